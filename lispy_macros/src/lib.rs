@@ -0,0 +1,103 @@
+// #[lispy_fn] turns a plain Rust function into a lispy builtin. Next to the
+// function it generates `register_<name>(env)`, which binds a native function
+// converting each argument with FromLispy and the result with ToLispy:
+//
+//   #[lispy_fn(name = "str/repeat")]
+//   fn repeat(text: String, times: i64) -> String { ... }
+//
+//   register_repeat(env);
+//
+// The lispy name defaults to the Rust one with `_` turned into `-`. Parameters
+// are owned FromLispy types, the arity is their count. The function returns a
+// ToLispy value or a Result<_, LispyType> whose error is raised as is.
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, AttributeArgs, FnArg, ItemFn, Lit, Meta, NestedMeta, ReturnType, Type,
+};
+
+#[proc_macro_attribute]
+pub fn lispy_fn(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as AttributeArgs);
+    let function = parse_macro_input!(item as ItemFn);
+
+    let mut name = function.sig.ident.to_string().replace('_', "-");
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("name") => match pair.lit {
+                Lit::Str(value) => name = value.value(),
+                other => return error(&other, "lispy_fn name must be a string"),
+            },
+            other => return error(&other, "lispy_fn only takes name = \"...\""),
+        }
+    }
+
+    let mut types = vec![];
+    for input in function.sig.inputs.iter() {
+        match input {
+            FnArg::Typed(pattern) => types.push(pattern.ty.as_ref().clone()),
+            FnArg::Receiver(receiver) => {
+                return error(receiver, "lispy_fn can't be used on methods")
+            }
+        }
+    }
+
+    let ident = &function.sig.ident;
+    let visibility = &function.vis;
+    let register = format_ident!("register_{}", ident);
+    let arity = types.len() as i32;
+    let bindings: Vec<_> = (0..types.len()).map(|index| format_ident!("arg{}", index)).collect();
+    let extractions = types.iter().enumerate().map(|(index, ty)| {
+        let binding = &bindings[index];
+        quote! {
+            let #binding = ::lispy::convert::argument::<#ty>(&args, #index, #name);
+            if #binding.is_err() {
+                return Err(#binding.err().unwrap());
+            }
+            let #binding = #binding.unwrap();
+        }
+    });
+    let conversion = if returns_result(&function.sig.output) {
+        quote! { result.map(|value| ::lispy::convert::ToLispy::to_lispy(&value)) }
+    } else {
+        quote! { Ok(::lispy::convert::ToLispy::to_lispy(&result)) }
+    };
+    let doc = format!("Binds `{}` to the `{}` builtin in `env`", ident, name);
+
+    quote! {
+        #function
+
+        #[doc = #doc]
+        #visibility fn #register(env: &mut ::lispy::env::LispyEnv) {
+            env.set(
+                #name,
+                ::lispy::types::LispyType::create_function(Some(#arity), |args| {
+                    #(#extractions)*
+                    let result = #ident(#(#bindings),*);
+                    #conversion
+                }),
+            );
+        }
+    }
+    .into()
+}
+
+// Going by the last path segment, so std::result::Result and a plain Result both count
+fn returns_result(output: &ReturnType) -> bool {
+    match output {
+        ReturnType::Type(_, ty) => match ty.as_ref() {
+            Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .map(|segment| segment.ident == "Result")
+                .unwrap_or(false),
+            _ => false,
+        },
+        ReturnType::Default => false,
+    }
+}
+
+fn error(tokens: &impl quote::ToTokens, message: &str) -> TokenStream {
+    syn::Error::new_spanned(tokens, message).to_compile_error().into()
+}
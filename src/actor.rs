@@ -0,0 +1,218 @@
+use crate::machine::apply_callable;
+use crate::types::LispyType;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub const ACTOR_KIND: &str = "actor";
+
+// Values can't cross threads yet, so an actor's mailbox is drained on the thread
+// that sends to it. Messages are still handled one at a time and in order: a send
+// from inside a handler is queued and picked up by the loop already running.
+pub struct Actor {
+    state: RefCell<LispyType>,
+    handler: LispyType,
+    mailbox: RefCell<VecDeque<LispyType>>,
+    processing: Cell<bool>,
+}
+
+pub fn create_actor(state: LispyType, handler: LispyType) -> LispyType {
+    LispyType::create_resource(
+        ACTOR_KIND,
+        Actor {
+            state: RefCell::new(state),
+            handler,
+            mailbox: RefCell::new(VecDeque::new()),
+            processing: Cell::new(false),
+        },
+    )
+}
+
+pub fn actor_arg(value: &LispyType) -> Result<&Actor, LispyType> {
+    match value.as_resource::<Actor>() {
+        Some(actor) => Ok(actor),
+        None => Err(LispyType::create_error(
+            format!("{} is not an actor", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+pub fn actor_state(actor: &Actor) -> LispyType {
+    actor.state.borrow().clone()
+}
+
+thread_local! {
+    // When the innermost ask! with a timeout gives up, None outside of any
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+// Restores the enclosing ask!'s deadline, even when the handler panics
+struct Deadline {
+    outer: Option<Instant>,
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        DEADLINE.with(|deadline| deadline.set(self.outer));
+    }
+}
+
+fn timeout_error(timeout: Duration) -> LispyType {
+    LispyType::create_error(
+        format!("Actor did not reply within {:?}", timeout).as_str(),
+        "TIMEOUT",
+    )
+}
+
+// Called by eval before every step, so a handler running past its ask!'s timeout
+// is stopped instead of being waited for
+pub fn check_deadline() -> Result<(), LispyType> {
+    match DEADLINE.with(|deadline| deadline.get()) {
+        Some(deadline) if Instant::now() > deadline => Err(LispyType::create_error(
+            "Actor did not reply in time",
+            "TIMEOUT",
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Runs the handler for `message` and keeps the state it returns. A failing
+// handler leaves the state as it was and drops whatever is still queued.
+fn handle(actor: &Actor, message: LispyType) -> Result<(), LispyType> {
+    let result = apply_callable(&actor.handler, vec![actor_state(actor), message]);
+    if result.is_err() {
+        actor.mailbox.borrow_mut().clear();
+        return Err(result.err().unwrap());
+    }
+    *actor.state.borrow_mut() = result.unwrap();
+    Ok(())
+}
+
+// Handles queued messages until the mailbox is empty
+fn drain(actor: &Actor) -> Result<(), LispyType> {
+    loop {
+        let message = actor.mailbox.borrow_mut().pop_front();
+        if message.is_none() {
+            return Ok(());
+        }
+        let handled = handle(actor, message.unwrap());
+        if handled.is_err() {
+            return Err(handled.err().unwrap());
+        }
+    }
+}
+
+pub fn send(actor: &Actor, message: LispyType) -> Result<LispyType, LispyType> {
+    actor.mailbox.borrow_mut().push_back(message);
+    if actor.processing.get() {
+        return Ok(LispyType::create_nil());
+    }
+
+    actor.processing.set(true);
+    let drained = drain(actor);
+    actor.processing.set(false);
+    if drained.is_err() {
+        return Err(drained.err().unwrap());
+    }
+
+    Ok(LispyType::create_nil())
+}
+
+// Replies with the state the handler produced for `message`. There is no mailbox
+// thread, the handler runs right away on the asking thread, so `timeout` bounds
+// the handler itself: once it is exceeded the handler is stopped, the state stays
+// as it was and the ask! fails with TIMEOUT. Messages the handler sent to the
+// actor are handled after the reply is taken, without the timeout.
+pub fn ask(
+    actor: &Actor,
+    message: LispyType,
+    timeout: Option<Duration>,
+) -> Result<LispyType, LispyType> {
+    if actor.processing.get() {
+        return Err(LispyType::create_error(
+            "ask! from inside the actor's own handler would never be answered",
+            "DEADLOCK",
+        ));
+    }
+
+    actor.processing.set(true);
+    let handled = match timeout {
+        Some(timeout) => {
+            let outer = DEADLINE.with(|deadline| deadline.get());
+            let _deadline = Deadline { outer };
+            let own = Instant::now() + timeout;
+            DEADLINE.with(|deadline| deadline.set(Some(outer.map_or(own, |outer| outer.min(own)))));
+            let previous = actor_state(actor);
+            let handled = handle(actor, message);
+            // Eval only notices the deadline between steps, a handler blocked in a
+            // builtin until after it may still have returned
+            if handled.is_ok() && Instant::now() > own {
+                *actor.state.borrow_mut() = previous;
+                actor.mailbox.borrow_mut().clear();
+                Err(timeout_error(timeout))
+            } else {
+                handled.map_err(|error| match error.as_error() {
+                    Some(thrown) if thrown.error_type == "TIMEOUT" && Instant::now() > own => {
+                        timeout_error(timeout)
+                    }
+                    _ => error,
+                })
+            }
+        }
+        None => handle(actor, message),
+    };
+    if handled.is_err() {
+        actor.processing.set(false);
+        return Err(handled.err().unwrap());
+    }
+    let reply = actor_state(actor);
+
+    let drained = drain(actor);
+    actor.processing.set(false);
+    if drained.is_err() {
+        return Err(drained.err().unwrap());
+    }
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::compile_source_code_to_ast;
+    use crate::machine::LispyMachine;
+    use crate::types::LispyType;
+
+    fn run(machine: &mut LispyMachine, source: &str) -> Result<LispyType, LispyType> {
+        let mut result = Ok(LispyType::create_nil());
+        for form in compile_source_code_to_ast(source).unwrap().iter() {
+            result = machine.evaluate(form);
+        }
+        result
+    }
+
+    const SLOW_COUNTER: &str = "(def! counter
+       (actor 0 (fn* (state message)
+         (if (= message :slow)
+           (loop (n 0) (if (< n 1000000) (recur (+ n 1)) (+ state 100)))
+           (+ state 1)))))";
+
+    #[test]
+    fn ask_stops_a_handler_running_past_its_timeout() {
+        let mut machine = LispyMachine::new();
+        run(&mut machine, SLOW_COUNTER).unwrap();
+        let error = run(&mut machine, "(ask! counter :slow 1)").err().unwrap();
+        assert_eq!(error.as_error().unwrap().error_type, "TIMEOUT");
+        let state = run(&mut machine, "(actor-state counter)");
+        assert_eq!(state.unwrap(), LispyType::create_number(0.0));
+    }
+
+    #[test]
+    fn ask_replies_with_the_state_for_its_message() {
+        let mut machine = LispyMachine::new();
+        run(&mut machine, SLOW_COUNTER).unwrap();
+        let reply = run(&mut machine, "(ask! counter :fast 10000)");
+        assert_eq!(reply.unwrap(), LispyType::create_number(1.0));
+        let reply = run(&mut machine, "(ask! counter :fast)");
+        assert_eq!(reply.unwrap(), LispyType::create_number(2.0));
+    }
+}
@@ -0,0 +1,294 @@
+use crate::env::LispyEnv;
+use crate::types::LispyType;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub const ARCHIVE_KIND: &str = "archive";
+
+// Builtins touching the file system, dropped when a machine is built without file io
+pub const ARCHIVE_BUILTINS: [&str; 7] = [
+    "archive/open",
+    "archive/zip",
+    "archive/unzip",
+    "archive/list",
+    "archive/tar",
+    "archive/untar",
+    "archive/tar-list",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Zip,
+    Tar,
+}
+
+impl Format {
+    fn name(&self) -> &'static str {
+        match self {
+            Format::Zip => "zip",
+            Format::Tar => "tar",
+        }
+    }
+}
+
+// What archive/open, archive/zip and archive/tar hand out: the archive's path and format
+pub struct Archive {
+    path: PathBuf,
+    format: Format,
+}
+
+fn system_error(path: &Path, error: impl ToString) -> LispyType {
+    LispyType::create_error(
+        format!("{}: {}", path.display(), error.to_string()).as_str(),
+        "SYSTEM_ERROR",
+    )
+}
+
+// An archive handle of `format`, or a path to one
+fn archive_arg(value: &LispyType, format: Format) -> Result<PathBuf, LispyType> {
+    if let Some(path) = value.as_string() {
+        return Ok(PathBuf::from(path));
+    }
+    match value.as_resource::<Archive>() {
+        Some(archive) if archive.format == format => Ok(archive.path.clone()),
+        Some(_) => Err(LispyType::create_error(
+            format!("Expected a {} archive, received {}", format.name(), value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+        None => Err(LispyType::create_error(
+            format!("Expected an archive or a path, received {}", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+fn path_arg(value: &LispyType) -> Result<PathBuf, LispyType> {
+    match value.as_string() {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => Err(LispyType::create_error(
+            format!("Expected a path, received {}", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+fn paths_arg(value: &LispyType) -> Result<Vec<PathBuf>, LispyType> {
+    let items = value.as_sequential();
+    if items.is_none() {
+        return Err(LispyType::create_error(
+            format!("Expected a list of paths, received {}", value).as_str(),
+            "INCORRECT_TYPE",
+        ));
+    }
+    items.unwrap().iter().map(path_arg).collect()
+}
+
+fn create_archive(path: PathBuf, format: Format) -> LispyType {
+    LispyType::create_resource(ARCHIVE_KIND, Archive { path, format })
+}
+
+// By extension, .zip or .tar
+fn open_archive(path: PathBuf) -> Result<LispyType, LispyType> {
+    let format = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("zip") => Format::Zip,
+        Some("tar") => Format::Tar,
+        _ => {
+            return Err(LispyType::create_error(
+                format!("{} is not a .zip or .tar file", path.display()).as_str(),
+                "INCORRECT_TYPE",
+            ))
+        }
+    };
+    if !path.is_file() {
+        return Err(LispyType::create_error(
+            format!("File {} not found", path.display()).as_str(),
+            "SYSTEM_ERROR",
+        ));
+    }
+    Ok(create_archive(path, format))
+}
+
+// {:name "dir/file.txt" :size 12 :dir false}
+fn entry_hash(name: &str, size: u64, is_dir: bool) -> LispyType {
+    let mut collection = HashMap::new();
+    collection.insert(LispyType::create_keyword(":name"), LispyType::create_string(name));
+    collection.insert(LispyType::create_keyword(":size"), LispyType::create_number(size as f64));
+    collection.insert(LispyType::create_keyword(":dir"), LispyType::create_bool(is_dir));
+    LispyType::Hash {
+        collection: Box::from(collection),
+        meta: HashMap::new(),
+    }
+}
+
+// The file name an input is stored under, directories keep their contents below it
+fn entry_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string())
+}
+
+fn write_zip(target: &Path, inputs: &[PathBuf]) -> io::Result<()> {
+    let mut zip = zip::ZipWriter::new(File::create(target)?);
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let mut pending: Vec<(PathBuf, String)> =
+        inputs.iter().map(|input| (input.clone(), entry_name(input))).collect();
+    while let Some((path, name)) = pending.pop() {
+        if path.is_dir() {
+            zip.add_directory(name.as_str(), options)?;
+            for child in fs::read_dir(&path)? {
+                let child = child?.path();
+                let child_name = format!("{}/{}", name, entry_name(&child));
+                pending.push((child, child_name));
+            }
+        } else {
+            zip.start_file(name.as_str(), options)?;
+            zip.write_all(&fs::read(&path)?)?;
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+fn list_zip(path: &Path) -> zip::result::ZipResult<Vec<LispyType>> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let mut entries = vec![];
+    for index in 0..archive.len() {
+        let entry = archive.by_index(index)?;
+        entries.push(entry_hash(entry.name(), entry.size(), entry.is_dir()));
+    }
+    Ok(entries)
+}
+
+// Entries that would land outside `destination` make the whole extraction fail
+fn unzip(path: &Path, destination: &Path) -> zip::result::ZipResult<Vec<LispyType>> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    archive.extract(destination)?;
+    Ok(archive
+        .file_names()
+        .map(|name| LispyType::create_string(destination.join(name).to_string_lossy().as_ref()))
+        .collect())
+}
+
+fn write_tar(target: &Path, inputs: &[PathBuf]) -> io::Result<()> {
+    let mut builder = tar::Builder::new(File::create(target)?);
+    for input in inputs {
+        if input.is_dir() {
+            builder.append_dir_all(entry_name(input), input)?;
+        } else {
+            builder.append_path_with_name(input, entry_name(input))?;
+        }
+    }
+    builder.finish()
+}
+
+fn list_tar(path: &Path) -> io::Result<Vec<LispyType>> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut entries = vec![];
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let header = entry.header();
+        entries.push(entry_hash(name.as_str(), header.size()?, header.entry_type().is_dir()));
+    }
+    Ok(entries)
+}
+
+// Entries reaching outside `destination` through .. or absolute paths are skipped
+fn untar(path: &Path, destination: &Path) -> io::Result<Vec<LispyType>> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut extracted = vec![];
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_path_buf();
+        if entry.unpack_in(destination)? {
+            extracted.push(LispyType::create_string(destination.join(name).to_string_lossy().as_ref()));
+        }
+    }
+    Ok(extracted)
+}
+
+// (archive/zip "backup.zip" ["notes" "todo.txt"]) and (archive/tar ...) alike
+fn pack(args: &[LispyType], format: Format) -> Result<LispyType, LispyType> {
+    let target = path_arg(&args[0]);
+    if target.is_err() {
+        return Err(target.err().unwrap());
+    }
+    let inputs = paths_arg(&args[1]);
+    if inputs.is_err() {
+        return Err(inputs.err().unwrap());
+    }
+    let (target, inputs) = (target.unwrap(), inputs.unwrap());
+    let written = match format {
+        Format::Zip => write_zip(&target, &inputs),
+        Format::Tar => write_tar(&target, &inputs),
+    };
+    if written.is_err() {
+        return Err(system_error(&target, written.err().unwrap()));
+    }
+    Ok(create_archive(target, format))
+}
+
+// (archive/unzip archive "out/") and (archive/untar ...), the extracted paths
+fn unpack(args: &[LispyType], format: Format) -> Result<LispyType, LispyType> {
+    let path = archive_arg(&args[0], format);
+    if path.is_err() {
+        return Err(path.err().unwrap());
+    }
+    let destination = path_arg(&args[1]);
+    if destination.is_err() {
+        return Err(destination.err().unwrap());
+    }
+    let (path, destination) = (path.unwrap(), destination.unwrap());
+    let extracted = match format {
+        Format::Zip => unzip(&path, &destination).map_err(|error| system_error(&path, error)),
+        Format::Tar => untar(&path, &destination).map_err(|error| system_error(&path, error)),
+    };
+    extracted.map(LispyType::create_list)
+}
+
+fn list(args: &[LispyType], format: Format) -> Result<LispyType, LispyType> {
+    let path = archive_arg(&args[0], format);
+    if path.is_err() {
+        return Err(path.err().unwrap());
+    }
+    let path = path.unwrap();
+    let entries = match format {
+        Format::Zip => list_zip(&path).map_err(|error| system_error(&path, error)),
+        Format::Tar => list_tar(&path).map_err(|error| system_error(&path, error)),
+    };
+    entries.map(LispyType::create_list)
+}
+
+pub fn apply_archive_ns(env: &mut LispyEnv) {
+    env.set(
+        "archive/open",
+        LispyType::create_function(Some(1), |args| path_arg(&args[0]).and_then(open_archive)),
+    );
+    env.set(
+        "archive/zip",
+        LispyType::create_function(Some(2), |args| pack(&args, Format::Zip)),
+    );
+    env.set(
+        "archive/unzip",
+        LispyType::create_function(Some(2), |args| unpack(&args, Format::Zip)),
+    );
+    env.set(
+        "archive/list",
+        LispyType::create_function(Some(1), |args| list(&args, Format::Zip)),
+    );
+    env.set(
+        "archive/tar",
+        LispyType::create_function(Some(2), |args| pack(&args, Format::Tar)),
+    );
+    env.set(
+        "archive/untar",
+        LispyType::create_function(Some(2), |args| unpack(&args, Format::Tar)),
+    );
+    env.set(
+        "archive/tar-list",
+        LispyType::create_function(Some(1), |args| list(&args, Format::Tar)),
+    );
+}
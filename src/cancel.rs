@@ -0,0 +1,57 @@
+use crate::types::LispyType;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Set from any thread to stop the evaluation of the machine the token belongs to.
+// Eval checks it before every step and fails with INTERRUPTED.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    // Makes this the token eval checks until the guard is dropped, which puts back
+    // the one of whichever machine was evaluating before
+    pub fn install(&self) -> InstalledToken {
+        InstalledToken {
+            previous: CURRENT.with(|current| current.replace(self.clone())),
+        }
+    }
+}
+
+pub struct InstalledToken {
+    previous: CancellationToken,
+}
+
+impl Drop for InstalledToken {
+    fn drop(&mut self) {
+        let previous = self.previous.clone();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+thread_local! {
+    // The token of the machine evaluating on this thread
+    static CURRENT: RefCell<CancellationToken> = RefCell::new(CancellationToken::default());
+}
+
+pub fn is_cancelled() -> bool {
+    CURRENT.with(|token| token.borrow().is_cancelled())
+}
+
+pub fn interrupted() -> LispyType {
+    LispyType::create_error("Evaluation was interrupted", "INTERRUPTED")
+}
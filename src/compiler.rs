@@ -1,27 +1,80 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
-use crate::LexerToken;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::lexer::LexerToken;
 use crate::types::LispyType;
 use logos::Logos;
 
-struct TokenReader {
+// Why reading failed and where, line and column are 1 based
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub error_type: String,
+    pub line: usize,
+    pub column: usize,
+    pub lexeme: String,
+}
+
+impl ParseError {
+    // The same error as a Lispy value, for code reading at runtime (compile-string)
+    pub fn to_lispy_error(&self) -> LispyType {
+        LispyType::create_error(format!("{}", self).as_str(), self.error_type.as_str())
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.line, self.column)?;
+        if !self.lexeme.is_empty() {
+            write!(f, " near `{}`", self.lexeme)?;
+        }
+        Ok(())
+    }
+}
+
+struct TokenReader<'a> {
     index: usize,
-    data: Vec<LexerToken>,
+    data: Vec<(LexerToken, Range<usize>)>,
+    source: &'a str,
+    // Byte offsets at which each line starts
+    line_starts: Vec<usize>,
+    file: Option<&'a str>,
 }
 
-impl TokenReader {
-    pub fn new(data: Vec<LexerToken>) -> Self {
+impl<'a> TokenReader<'a> {
+    pub fn new(source: &'a str, file: Option<&'a str>) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
         Self {
             index: 0,
-            data,
+            data: LexerToken::lexer(source).spanned().collect(),
+            source,
+            line_starts,
+            file,
         }
     }
 
-    pub fn peek(&self) -> LexerToken {
-        self.data[self.index].clone()
+    // Line and column of the token with `index`, or of the end of the source past the last one
+    pub fn position(&self, index: usize) -> (usize, usize) {
+        let offset = match self.data.get(index) {
+            Some((_, span)) => span.start,
+            None => self.source.len(),
+        };
+        let line = self.line_starts.partition_point(|start| *start <= offset);
+        let column = self.source[self.line_starts[line - 1]..offset].chars().count() + 1;
+        (line, column)
     }
 
-    pub fn grab(&mut self) -> LexerToken {
-        let token = self.peek().clone();
+    // None once all tokens are read
+    pub fn peek(&self) -> Option<LexerToken> {
+        self.data.get(self.index).map(|(token, _)| token.clone())
+    }
+
+    pub fn grab(&mut self) -> Option<LexerToken> {
+        let token = self.peek();
         self.index += 1;
         token
     }
@@ -29,58 +82,382 @@ impl TokenReader {
     pub fn is_empty(&self) -> bool {
         self.index >= self.data.len()
     }
+
+    // Error located at the token with `index`, or at the end of the source past the last one
+    pub fn error_at(&self, index: usize, message: &str, error_type: &str) -> ParseError {
+        let (line, column) = self.position(index);
+        let lexeme = match self.data.get(index) {
+            Some((_, span)) => self.source[span.clone()].to_string(),
+            None => String::new(),
+        };
+        ParseError {
+            message: message.to_string(),
+            error_type: error_type.to_string(),
+            line,
+            column,
+            lexeme,
+        }
+    }
+
+    pub fn error(&self, message: &str, error_type: &str) -> ParseError {
+        self.error_at(self.index, message, error_type)
+    }
 }
 
-fn build_any_form(reader: &mut TokenReader) -> LispyType {
-    match reader.peek() {
-        LexerToken::Quote => {
-            reader.grab();
-            LispyType::create_list(
-                vec![
-                    LispyType::create_symbol("quote"),
-                    build_any_form(reader),
-                ]
-            )
-        }
-        LexerToken::QuasiQuote => {
-            reader.grab();
-            LispyType::create_list(
-                vec![
-                    LispyType::create_symbol("quasi-quote"),
-                    build_any_form(reader),
-                ]
-            )
-        }
-        LexerToken::Unquote => {
-            reader.grab();
-            LispyType::create_list(
-                vec![
-                    LispyType::create_symbol("unquote"),
-                    build_any_form(reader),
-                ]
-            )
-        }
-        LexerToken::SpliceUnquote => {
+// Nesting the recursive readers accept by default. A level of the reader takes
+// several KiB of stack in debug builds, this stays well within the 2 MiB of a
+// spawned thread so deeply nested input fails with LIMIT_EXCEEDED instead of
+// overflowing the stack.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+#[derive(Clone, Debug)]
+pub struct ParseLimits {
+    pub max_depth: Option<usize>,
+    pub max_collection_size: Option<usize>,
+    pub max_string_length: Option<usize>,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_collection_size: None,
+            max_string_length: None,
+        }
+    }
+}
+
+thread_local! {
+    // Limits applied when reading data at runtime (compile-string and friends)
+    static RUNTIME_LIMITS: RefCell<ParseLimits> = RefCell::new(ParseLimits::default());
+}
+
+// Every distinct symbol and keyword name the reader produced, with how often it was
+// read, exposed for inspecting memory use. Keyword names are shared through the
+// interner, symbol names are not shared between values yet.
+#[derive(Default)]
+pub struct LiteralTable {
+    pub symbols: HashMap<String, usize>,
+    pub keywords: HashMap<String, usize>,
+}
+
+thread_local! {
+    static LITERALS: RefCell<LiteralTable> = RefCell::new(LiteralTable::default());
+}
+
+pub fn with_literals<T>(f: impl FnOnce(&LiteralTable) -> T) -> T {
+    LITERALS.with(|literals| f(&literals.borrow()))
+}
+
+fn record_literal(name: &str, keyword: bool) {
+    LITERALS.with(|literals| {
+        let mut literals = literals.borrow_mut();
+        let table = if keyword { &mut literals.keywords } else { &mut literals.symbols };
+        match table.get_mut(name) {
+            Some(count) => *count += 1,
+            None => {
+                table.insert(name.to_string(), 1);
+            }
+        }
+    });
+}
+
+pub fn runtime_limits() -> ParseLimits {
+    RUNTIME_LIMITS.with(|limits| limits.borrow().clone())
+}
+
+pub fn set_runtime_limits(limits: ParseLimits) {
+    RUNTIME_LIMITS.with(|current| *current.borrow_mut() = limits);
+}
+
+// Makes `limits` the runtime limits until the guard is dropped, which puts back
+// the previous ones, see LispyMachine::install
+pub fn install_runtime_limits(limits: ParseLimits) -> InstalledLimits {
+    let previous = RUNTIME_LIMITS.with(|current| current.replace(limits));
+    InstalledLimits { previous: Some(previous) }
+}
+
+pub struct InstalledLimits {
+    previous: Option<ParseLimits>,
+}
+
+impl Drop for InstalledLimits {
+    fn drop(&mut self) {
+        set_runtime_limits(self.previous.take().unwrap_or_default());
+    }
+}
+
+fn limit_error(reader: &TokenReader, message: String) -> ParseError {
+    reader.error(message.as_str(), "LIMIT_EXCEEDED")
+}
+
+fn syntax_error(reader: &TokenReader, message: &str) -> ParseError {
+    reader.error(message, "SYNTAX_ERROR")
+}
+
+fn closing_token(opening: &LexerToken) -> (LexerToken, &'static str) {
+    match opening {
+        LexerToken::VectorStart => (LexerToken::VectorEnd, "]"),
+        LexerToken::HashStart => (LexerToken::HashEnd, "}"),
+        _ => (LexerToken::ListEnd, ")"),
+    }
+}
+
+fn is_closing(token: &LexerToken) -> bool {
+    matches!(token, LexerToken::ListEnd | LexerToken::VectorEnd | LexerToken::HashEnd)
+}
+
+// Feature keywords a reader conditional branch can be selected by on this machine
+fn platform_features() -> Vec<&'static str> {
+    let mut features = vec![":lispy"];
+    if cfg!(unix) {
+        features.push(":unix");
+    }
+    if cfg!(windows) {
+        features.push(":windows");
+    }
+    if cfg!(target_os = "linux") {
+        features.push(":linux");
+    }
+    if cfg!(target_os = "macos") {
+        features.push(":macos");
+    }
+    features
+}
+
+// Reads `#?(:feature form ...)` and keeps the form of the first matching feature,
+// falling back to :default. Returns None when no branch applies so the caller can
+// drop the whole expression.
+fn read_conditional(
+    reader: &mut TokenReader,
+    limits: &ParseLimits,
+    depth: usize,
+) -> Result<Option<LispyType>, ParseError> {
+    let items = read_items(reader, limits, depth, 1);
+    if items.is_err() {
+        return Err(items.err().unwrap());
+    }
+    let items = items.unwrap();
+    let features = platform_features();
+
+    if !items.len().is_multiple_of(2) || items.iter().step_by(2).any(|feature| !feature.is_keyword()) {
+        return Err(reader.error_at(
+            reader.index - 1,
+            "Reader conditionals expect :feature form pairs",
+            "READER_ERROR",
+        ));
+    }
+    Ok(items.chunks(2).find_map(|pair| {
+        let feature = pair[0].as_keyword().unwrap().as_str();
+        if feature == ":default" || features.contains(&feature) {
+            Some(pair[1].clone())
+        } else {
+            None
+        }
+    }))
+}
+
+// Reads the items of the collection opened by the current token up to its matching
+// closing token. Reader conditionals splice their selected form in place.
+// `items_per_entry` is 2 for hashes, so size limits count entries rather than items.
+fn read_items(
+    reader: &mut TokenReader,
+    limits: &ParseLimits,
+    depth: usize,
+    items_per_entry: usize,
+) -> Result<Vec<LispyType>, ParseError> {
+    let opened_at = reader.index;
+    let (closing, closing_text) = closing_token(&reader.grab().unwrap());
+    let mut collection = vec![];
+
+    loop {
+        match reader.peek() {
+            None => {
+                return Err(reader.error_at(
+                    opened_at,
+                    format!("Unbalanced parenthesis, missing {}", closing_text).as_str(),
+                    "SYNTAX_ERROR",
+                ));
+            }
+            Some(token) if token == closing => {
+                reader.grab();
+                return Ok(collection);
+            }
+            Some(token) if is_closing(&token) => {
+                let opening = reader.error_at(opened_at, "", "");
+                return Err(syntax_error(
+                    reader,
+                    format!(
+                        "Expected {} to close {} from line {}, column {}",
+                        closing_text, opening.lexeme, opening.line, opening.column
+                    )
+                    .as_str(),
+                ));
+            }
+            Some(LexerToken::ReaderConditionalStart) => {
+                let selected = read_conditional(reader, limits, depth + 1);
+                if selected.is_err() {
+                    return Err(selected.err().unwrap());
+                }
+                collection.extend(selected.unwrap());
+            }
+            Some(_) => {
+                let item = build_any_form(reader, limits, depth + 1);
+                if item.is_err() {
+                    return Err(item.err().unwrap());
+                }
+                collection.push(item.unwrap());
+            }
+        }
+        if exceeds_collection_size(limits, collection.len() / items_per_entry) {
+            return Err(limit_error(
+                reader,
+                format!(
+                    "Collection size exceeds the limit of {}",
+                    limits.max_collection_size.unwrap()
+                ),
+            ));
+        }
+    }
+}
+
+// Resolves the escapes of a string literal, the surrounding quotes are already stripped
+fn unescape_string(raw: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            result.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('0') => result.push('\0'),
+            Some('\\') => result.push('\\'),
+            Some('"') => result.push('"'),
+            Some(other) => return Err(format!("Unknown escape sequence \\{} in string", other)),
+            None => return Err("String ends with an unfinished escape sequence".to_string()),
+        }
+    }
+    Ok(result)
+}
+
+// Numbers gensyms apart, shared by every machine so generated names never collide
+static GENSYM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// A symbol name no script has written: prefix followed by a fresh number
+pub fn gensym(prefix: &str) -> String {
+    format!("{}{}", prefix, GENSYM_COUNTER.fetch_add(1, Ordering::Relaxed) + 1)
+}
+
+// Inside `(...) every x# becomes the same fresh x__N__auto__ symbol, so macro
+// bindings can't capture the caller's names. Unquoted code is left alone.
+fn auto_gensym(form: &mut LispyType, names: &mut HashMap<String, String>) {
+    match form {
+        LispyType::Symbol { value, .. } if value.len() > 1 && value.ends_with('#') => {
+            let name = names
+                .entry(value.clone())
+                .or_insert_with(|| gensym(&format!("{}__", &value[..value.len() - 1])) + "__auto__")
+                .clone();
+            *form = LispyType::create_symbol(name.as_str());
+        }
+        LispyType::List { collection, .. } => {
+            let unquoted = collection
+                .first()
+                .map(|head| head.is_symbol_containing("unquote") || head.is_symbol_containing("splice-unquote"))
+                .unwrap_or(false);
+            if !unquoted {
+                collection.iter_mut().for_each(|item| auto_gensym(item, names));
+            }
+        }
+        LispyType::Vector { collection, .. } => {
+            collection.iter_mut().for_each(|item| auto_gensym(item, names));
+        }
+        _ => {}
+    }
+}
+
+// The form a reader shorthand like 'x or @x expands to
+fn shorthand_form(token: &LexerToken) -> &'static str {
+    match token {
+        LexerToken::Quote => "quote",
+        LexerToken::QuasiQuote => "quasi-quote",
+        LexerToken::Unquote => "unquote",
+        LexerToken::SpliceUnquote => "splice-unquote",
+        LexerToken::Deref => "deref",
+        _ => unreachable!(),
+    }
+}
+
+fn build_any_form(
+    reader: &mut TokenReader,
+    limits: &ParseLimits,
+    depth: usize,
+) -> Result<LispyType, ParseError> {
+    if let Some(max_depth) = limits.max_depth.filter(|max_depth| depth > *max_depth) {
+        return Err(limit_error(reader, format!(
+            "Nesting depth exceeds the limit of {}",
+            max_depth
+        )));
+    }
+    if reader.peek().is_none() {
+        return Err(syntax_error(reader, "Unexpected end of input"));
+    }
+    let start = reader.index;
+
+    let mut form = match reader.peek().unwrap() {
+        LexerToken::Quote
+        | LexerToken::QuasiQuote
+        | LexerToken::Unquote
+        | LexerToken::SpliceUnquote
+        | LexerToken::Deref => {
+            let name = shorthand_form(&reader.grab().unwrap());
+            let wrapped = build_any_form(reader, limits, depth + 1);
+            if wrapped.is_err() {
+                return Err(wrapped.err().unwrap());
+            }
+            let mut wrapped = wrapped.unwrap();
+            if name == "quasi-quote" {
+                auto_gensym(&mut wrapped, &mut HashMap::new());
+            }
+            LispyType::create_list(vec![LispyType::create_symbol(name), wrapped])
+        }
+        LexerToken::ArgsSpread => {
             reader.grab();
-            LispyType::create_list(
-                vec![
-                    LispyType::create_symbol("splice-unquote"),
-                    build_any_form(reader),
-                ]
-            )
+            LispyType::create_symbol("&")
         }
         LexerToken::Nil => {
             reader.grab();
             LispyType::Nil { meta: HashMap::new() }
         }
+        LexerToken::ReaderConditionalStart => {
+            let selected = read_conditional(reader, limits, depth);
+            if selected.is_err() {
+                return Err(selected.err().unwrap());
+            }
+            selected.unwrap().unwrap_or_else(LispyType::create_nil)
+        }
 
         LexerToken::Boolean(val) => {
             reader.grab();
             LispyType::Bool { value: val.clone(), meta: HashMap::new() }
         }
         LexerToken::String(val) => {
+            let value = unescape_string(&val[1..val.len() - 1]);
+            if value.is_err() {
+                return Err(reader.error(value.err().unwrap().as_str(), "READER_ERROR"));
+            }
+            let value = value.unwrap();
+            if let Some(max_length) = limits.max_string_length.filter(|max_length| value.len() > *max_length) {
+                return Err(limit_error(reader, format!(
+                    "String length exceeds the limit of {}",
+                    max_length
+                )));
+            }
             reader.grab();
-            LispyType::String { value: val.clone()[1..val.len() - 1].to_string().clone(), meta: HashMap::new() }
+            LispyType::String { value, meta: HashMap::new() }
         }
         LexerToken::Number(val) => {
             reader.grab();
@@ -88,55 +465,160 @@ fn build_any_form(reader: &mut TokenReader) -> LispyType {
         }
         LexerToken::Keyword(val) => {
             reader.grab();
-            LispyType::Keyword { value: val.clone(), meta: HashMap::new() }
+            record_literal(&val, true);
+            LispyType::create_keyword(&val)
         }
         LexerToken::Symbol(val) => {
             reader.grab();
-            LispyType::Symbol { value: val.clone(), meta: HashMap::new() }
+            record_literal(&val, false);
+            LispyType::create_symbol(val.as_str())
         }
 
         LexerToken::ListStart => {
-            reader.grab();
-            let mut collection = vec![];
-
-            while reader.peek() != LexerToken::ListEnd {
-                collection.push(build_any_form(reader));
+            let collection = read_items(reader, limits, depth, 1);
+            if collection.is_err() {
+                return Err(collection.err().unwrap());
             }
-
-            reader.grab();
-            LispyType::List { collection: Box::from(collection), meta: HashMap::new() }
+            LispyType::List { collection: Box::from(collection.unwrap()), meta: HashMap::new() }
+        }
+        LexerToken::VectorStart => {
+            let collection = read_items(reader, limits, depth, 1);
+            if collection.is_err() {
+                return Err(collection.err().unwrap());
+            }
+            LispyType::Vector { collection: Box::from(collection.unwrap()), meta: HashMap::new() }
         }
         LexerToken::HashStart => {
-            reader.grab();
-            let mut collection = HashMap::new();
-
-            while reader.peek() != LexerToken::HashEnd {
-                let key = build_any_form(reader);
-                let value = build_any_form(reader);
-                collection.insert(key, value);
+            let opened_at = reader.index;
+            let items = read_items(reader, limits, depth, 2);
+            if items.is_err() {
+                return Err(items.err().unwrap());
             }
-
-            reader.grab();
+            let items = items.unwrap();
+            if !items.len().is_multiple_of(2) {
+                return Err(reader.error_at(
+                    opened_at,
+                    "Hash literal needs an even number of forms",
+                    "SYNTAX_ERROR",
+                ));
+            }
+            if items.iter().step_by(2).any(|key| !key.is_hashable()) {
+                return Err(reader.error_at(
+                    opened_at,
+                    "Hash literal keys must be nil, booleans, numbers, symbols, keywords or strings",
+                    "SYNTAX_ERROR",
+                ));
+            }
+            let collection = items
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect::<HashMap<_, _>>();
             LispyType::Hash { collection: Box::from(collection), meta: HashMap::new() }
         }
 
-        _ => panic!("Unknown token {:?}", reader.peek())
+        LexerToken::ListEnd | LexerToken::VectorEnd | LexerToken::HashEnd => {
+            return Err(syntax_error(reader, "Unbalanced parenthesis, nothing to close"));
+        }
+        LexerToken::Error => return Err(syntax_error(reader, "Unexpected input")),
+    };
+
+    let (line, col) = reader.position(start);
+    let meta = form.meta_mut();
+    meta.insert("line".to_string(), LispyType::create_number(line as f64));
+    meta.insert("col".to_string(), LispyType::create_number(col as f64));
+    if let Some(file) = reader.file {
+        meta.insert("file".to_string(), LispyType::create_string(file));
     }
+    Ok(form)
+}
+
+fn exceeds_collection_size(limits: &ParseLimits, size: usize) -> bool {
+    limits.max_collection_size.is_some() && size > limits.max_collection_size.unwrap()
 }
 
-fn build_from_tokens(reader: &mut TokenReader) -> Vec<LispyType> {
+fn build_from_tokens(reader: &mut TokenReader, limits: &ParseLimits) -> Result<Vec<LispyType>, ParseError> {
     let mut ast = vec![];
 
     while !reader.is_empty() {
-        ast.push(build_any_form(reader));
+        if reader.peek() == Some(LexerToken::ReaderConditionalStart) {
+            let selected = read_conditional(reader, limits, 0);
+            if selected.is_err() {
+                return Err(selected.err().unwrap());
+            }
+            ast.extend(selected.unwrap());
+            continue;
+        }
+        let form = build_any_form(reader, limits, 0);
+        if form.is_err() {
+            return Err(form.err().unwrap());
+        }
+        ast.push(form.unwrap());
+        if exceeds_collection_size(limits, ast.len()) {
+            return Err(limit_error(reader, format!(
+                "Collection size exceeds the limit of {}",
+                limits.max_collection_size.unwrap()
+            )));
+        }
     }
 
-    ast
+    Ok(ast)
 }
 
 
-pub fn compile_source_code_to_ast(source_code: &str) -> Vec<LispyType> {
-    let tokens: Vec<LexerToken> = LexerToken::lexer(source_code).collect();
-    let mut reader = TokenReader::new(tokens);
-    build_from_tokens(&mut reader)
-}
\ No newline at end of file
+pub fn compile_source_code_to_ast(source_code: &str) -> Result<Vec<LispyType>, ParseError> {
+    compile_with_limits(source_code, &ParseLimits::default())
+}
+
+pub fn compile_with_limits(source_code: &str, limits: &ParseLimits) -> Result<Vec<LispyType>, ParseError> {
+    let mut reader = TokenReader::new(source_code, None);
+    build_from_tokens(&mut reader, limits)
+}
+
+// Like compile_source_code_to_ast, forms also record `file` next to their line and col
+pub fn compile_source_file(source_code: &str, file: &str) -> Result<Vec<LispyType>, ParseError> {
+    let mut reader = TokenReader::new(source_code, Some(file));
+    build_from_tokens(&mut reader, &ParseLimits::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::{compile_source_code_to_ast, compile_with_limits, ParseLimits};
+
+    fn limit_exceeded(source: &str, limits: &ParseLimits) -> bool {
+        compile_with_limits(source, limits).err().is_some_and(|error| error.error_type == "LIMIT_EXCEEDED")
+    }
+
+    #[test]
+    fn hash_literals_with_unhashable_keys_are_syntax_errors() {
+        for source in ["{(list 1) 2}", "{'s 1}", "{[1] 2}", "{{} 1}"] {
+            assert!(compile_source_code_to_ast(source).is_err(), "{} should not parse", source);
+        }
+        assert!(compile_source_code_to_ast("{:a 1 \"b\" 2 3 4 nil 5}").is_ok());
+    }
+
+    #[test]
+    fn deep_nesting_exceeds_the_default_depth_instead_of_the_stack() {
+        let source = format!("{}{}", "(".repeat(20_000), ")".repeat(20_000));
+        assert!(limit_exceeded(&source, &ParseLimits::default()));
+        let vectors = format!("{}{}", "[".repeat(20_000), "]".repeat(20_000));
+        assert!(limit_exceeded(&vectors, &ParseLimits::default()));
+        assert!(compile_source_code_to_ast("((((((((((1))))))))))").is_ok());
+    }
+
+    #[test]
+    fn reading_enforces_each_limit() {
+        let depth = ParseLimits { max_depth: Some(2), ..ParseLimits::default() };
+        assert!(limit_exceeded("(1 (2 (3)))", &depth));
+        assert!(!limit_exceeded("(1 (2))", &depth));
+
+        let size = ParseLimits { max_collection_size: Some(2), ..ParseLimits::default() };
+        assert!(limit_exceeded("(1 2 3)", &size));
+        assert!(limit_exceeded("{:a 1 :b 2 :c 3}", &size));
+        assert!(limit_exceeded("1 2 3", &size));
+        assert!(!limit_exceeded("(1 2) {:a 1 :b 2}", &size));
+
+        let length = ParseLimits { max_string_length: Some(3), ..ParseLimits::default() };
+        assert!(limit_exceeded("\"abcd\"", &length));
+        assert!(!limit_exceeded("\"abc\"", &length));
+    }
+}
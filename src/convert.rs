@@ -0,0 +1,204 @@
+use crate::types::LispyType;
+use std::collections::HashMap;
+
+// Rust values a lispy value can be built from, for passing data into scripts
+// without assembling LispyType trees by hand
+pub trait ToLispy {
+    fn to_lispy(&self) -> LispyType;
+}
+
+// Rust values a lispy value can be read back into. Errors are COERCION_FAILED
+// lispy errors, the same a script gets from to-number and friends.
+pub trait FromLispy: Sized {
+    fn from_lispy(value: &LispyType) -> Result<Self, LispyType>;
+}
+
+fn conversion_error(value: &LispyType, target: &str) -> LispyType {
+    LispyType::create_error(
+        format!("Could not convert {} to {}", value.to_readable_string(), target).as_str(),
+        "COERCION_FAILED",
+    )
+}
+
+impl ToLispy for LispyType {
+    fn to_lispy(&self) -> LispyType {
+        self.clone()
+    }
+}
+
+impl FromLispy for LispyType {
+    fn from_lispy(value: &LispyType) -> Result<Self, LispyType> {
+        Ok(value.clone())
+    }
+}
+
+// Argument `index` of the native function `name`, how #[lispy_fn] builtins read
+// their parameters. The error says which function and argument did not convert.
+pub fn argument<T: FromLispy>(args: &[LispyType], index: usize, name: &str) -> Result<T, LispyType> {
+    T::from_lispy(&args[index]).map_err(|error| {
+        let message = match &error {
+            LispyType::Error { message, .. } => message.clone(),
+            other => other.to_string(),
+        };
+        LispyType::create_error(
+            format!("{} argument {}: {}", name, index + 1, message).as_str(),
+            "INCORRECT_TYPE",
+        )
+    })
+}
+
+// Functions returning nothing return nil
+impl ToLispy for () {
+    fn to_lispy(&self) -> LispyType {
+        LispyType::create_nil()
+    }
+}
+
+impl ToLispy for i64 {
+    fn to_lispy(&self) -> LispyType {
+        LispyType::create_number(*self as f64)
+    }
+}
+
+// Only whole numbers in range, 2.5 is not silently truncated
+impl FromLispy for i64 {
+    fn from_lispy(value: &LispyType) -> Result<Self, LispyType> {
+        match value.as_number() {
+            Some(number) if number.fract() == 0.0 && number.abs() <= i64::MAX as f64 => Ok(*number as i64),
+            _ => Err(conversion_error(value, "i64")),
+        }
+    }
+}
+
+impl ToLispy for f64 {
+    fn to_lispy(&self) -> LispyType {
+        LispyType::create_number(*self)
+    }
+}
+
+impl FromLispy for f64 {
+    fn from_lispy(value: &LispyType) -> Result<Self, LispyType> {
+        value.as_number().copied().ok_or_else(|| conversion_error(value, "f64"))
+    }
+}
+
+impl ToLispy for bool {
+    fn to_lispy(&self) -> LispyType {
+        LispyType::create_bool(*self)
+    }
+}
+
+impl FromLispy for bool {
+    fn from_lispy(value: &LispyType) -> Result<Self, LispyType> {
+        value.as_bool().copied().ok_or_else(|| conversion_error(value, "bool"))
+    }
+}
+
+impl ToLispy for str {
+    fn to_lispy(&self) -> LispyType {
+        LispyType::create_string(self)
+    }
+}
+
+impl ToLispy for String {
+    fn to_lispy(&self) -> LispyType {
+        LispyType::create_string(self.as_str())
+    }
+}
+
+impl FromLispy for String {
+    fn from_lispy(value: &LispyType) -> Result<Self, LispyType> {
+        value.as_string().cloned().ok_or_else(|| conversion_error(value, "String"))
+    }
+}
+
+impl<T: ToLispy> ToLispy for Vec<T> {
+    fn to_lispy(&self) -> LispyType {
+        LispyType::create_list(self.iter().map(ToLispy::to_lispy).collect())
+    }
+}
+
+// From lists and vectors alike
+impl<T: FromLispy> FromLispy for Vec<T> {
+    fn from_lispy(value: &LispyType) -> Result<Self, LispyType> {
+        let items = value.as_sequential();
+        if items.is_none() {
+            return Err(conversion_error(value, "Vec"));
+        }
+        items.unwrap().iter().map(T::from_lispy).collect()
+    }
+}
+
+// Keys become strings, the way a script writing {"name" ...} would
+impl<T: ToLispy> ToLispy for HashMap<String, T> {
+    fn to_lispy(&self) -> LispyType {
+        LispyType::Hash {
+            collection: Box::new(
+                self.iter()
+                    .map(|(key, value)| (LispyType::create_string(key.as_str()), value.to_lispy()))
+                    .collect(),
+            ),
+            meta: HashMap::new(),
+        }
+    }
+}
+
+// String and keyword keys, keywords without their colon so {:name ...} and
+// {"name" ...} read the same
+impl<T: FromLispy> FromLispy for HashMap<String, T> {
+    fn from_lispy(value: &LispyType) -> Result<Self, LispyType> {
+        let entries = value.as_hash();
+        if entries.is_none() {
+            return Err(conversion_error(value, "HashMap"));
+        }
+        let mut map = HashMap::new();
+        for (key, item) in entries.unwrap().iter() {
+            let name = key
+                .as_string()
+                .cloned()
+                .or_else(|| key.as_keyword().map(|keyword| keyword.trim_start_matches(':').to_string()));
+            if name.is_none() {
+                return Err(conversion_error(key, "String key"));
+            }
+            let item = T::from_lispy(item);
+            if item.is_err() {
+                return Err(item.err().unwrap());
+            }
+            map.insert(name.unwrap(), item.unwrap());
+        }
+        Ok(map)
+    }
+}
+
+impl<T: ToLispy> ToLispy for Option<T> {
+    fn to_lispy(&self) -> LispyType {
+        match self {
+            Some(value) => value.to_lispy(),
+            None => LispyType::create_nil(),
+        }
+    }
+}
+
+// nil is None, anything else has to convert to T
+impl<T: FromLispy> FromLispy for Option<T> {
+    fn from_lispy(value: &LispyType) -> Result<Self, LispyType> {
+        if value.is_nil() {
+            return Ok(None);
+        }
+        T::from_lispy(value).map(Some)
+    }
+}
+
+impl<T: ToLispy + ?Sized> ToLispy for &T {
+    fn to_lispy(&self) -> LispyType {
+        (**self).to_lispy()
+    }
+}
+
+// A list of the converted items: `LispyType::from_iter(vec![1i64, 2])` or
+// `value.iter_seq()?.filter(..).collect::<LispyType>()`
+impl<T: ToLispy> FromIterator<T> for LispyType {
+    fn from_iter<I: IntoIterator<Item = T>>(items: I) -> Self {
+        LispyType::create_list(items.into_iter().map(|item| item.to_lispy()).collect())
+    }
+}
@@ -1,14 +1,43 @@
-use crate::compile_source_code_to_ast;
-use crate::env::LispyEnv;
-use crate::types::LispyType;
+use crate::actor::{actor_arg, actor_state, ask, create_actor, send, ACTOR_KIND};
+use crate::compiler::{
+    compile_with_limits, gensym, runtime_limits, set_runtime_limits, with_literals, ParseLimits,
+};
+use crate::env::{collect_cycles, LispyEnv};
+use crate::future::{await_future, create_future, future_arg, is_realized, FUTURE_KIND};
+use crate::generator::{
+    create_generator, is_finished, next_value, yield_value, Generator, GENERATOR_KIND,
+};
+use crate::inspector::inspect;
+use crate::lispy_fn;
+use crate::lock::{create_lock, is_locked, lock_arg, LOCK_KIND};
+use crate::machine::apply_callable;
+use crate::queue::{create_queue, queue_arg, QUEUE_KIND};
+use crate::protocols::{dispatch, extend_type, type_tag, OVERLOADABLE};
+use crate::sandbox;
+use crate::stacktrace;
+use crate::template::eval_template;
+use crate::types::{compare_keys, set_print_ratios, sorted_hash_entries, LispyType};
+use crate::watchers::{add_watch, remove_watches, unwatch, watch};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fs;
+use std::rc::Rc;
+use std::time::Duration;
+
+thread_local! {
+    // When set, `/` by zero raises DIVISION_BY_ZERO instead of returning ##Inf / ##NaN
+    static DIVISION_BY_ZERO_RAISES: Cell<bool> = const { Cell::new(false) };
+}
 
 pub fn apply_core_ns(env: &mut LispyEnv) {
     //#region Math
     env.set(
         "+",
         LispyType::create_function(Some(2), |args| {
+            if let Some(result) = dispatch("+", &args) {
+                return result;
+            }
             return Ok(args[0].clone() + args[1].clone());
         }),
     );
@@ -30,10 +59,49 @@ pub fn apply_core_ns(env: &mut LispyEnv) {
     env.set(
         "/",
         LispyType::create_function(Some(2), |args| {
+            if args[1].as_number() == Some(&0.0)
+                && DIVISION_BY_ZERO_RAISES.with(|raises| raises.get())
+            {
+                return Err(LispyType::create_error("Division by zero", "DIVISION_BY_ZERO"));
+            }
             return Ok(args[0].clone() / args[1].clone());
         }),
     );
 
+    env.set(
+        "set-division-by-zero!",
+        LispyType::create_function(Some(1), |args| {
+            let raises = match args[0].as_keyword().map(|keyword| keyword.as_str()) {
+                Some(":inf") => false,
+                Some(":error") => true,
+                _ => {
+                    return Err(LispyType::create_error(
+                        format!("Division by zero mode must be :inf or :error. Received {}", args[0])
+                            .as_str(),
+                        "INCORRECT_TYPE",
+                    ))
+                }
+            };
+            DIVISION_BY_ZERO_RAISES.with(|cell| cell.set(raises));
+            Ok(LispyType::create_nil())
+        }),
+    );
+
+    env.set(
+        "set-print-ratios!",
+        LispyType::create_function(Some(1), |args| {
+            let enabled = args[0].as_bool();
+            if enabled.is_none() {
+                return Err(LispyType::create_error(
+                    format!("set-print-ratios! expects true or false. Received {}", args[0]).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            set_print_ratios(*enabled.unwrap());
+            Ok(LispyType::create_nil())
+        }),
+    );
+
     //#endregion
     //#region Utility
     env.set(
@@ -62,6 +130,40 @@ pub fn apply_core_ns(env: &mut LispyEnv) {
         }),
     );
 
+    env.set(
+        "str",
+        LispyType::create_function(None, |args| {
+            let parts: Vec<String> = args.iter().map(|item| format!("{}", item)).collect();
+            Ok(LispyType::create_string(parts.concat().as_str()))
+        }),
+    );
+
+    env.set(
+        "pr-str",
+        LispyType::create_function(None, |args| {
+            let parts: Vec<String> = args.iter().map(|item| item.to_readable_string()).collect();
+            Ok(LispyType::create_string(parts.join(" ").as_str()))
+        }),
+    );
+
+    // Human readable counterpart of pr-str, arguments are separated by spaces
+    env.set(
+        "print-str",
+        LispyType::create_function(None, |args| {
+            let parts: Vec<String> = args.iter().map(|item| format!("{}", item)).collect();
+            Ok(LispyType::create_string(parts.join(" ").as_str()))
+        }),
+    );
+
+    env.set(
+        "prn",
+        LispyType::create_function(None, |args| {
+            let parts: Vec<String> = args.iter().map(|item| item.to_readable_string()).collect();
+            println!("{}", parts.join(" "));
+            Ok(LispyType::create_nil())
+        }),
+    );
+
     env.set(
         "list",
         LispyType::create_function(None, |args| {
@@ -71,9 +173,57 @@ pub fn apply_core_ns(env: &mut LispyEnv) {
             })
         }),
     );
+    // (range end), (range start end) or (range start end step), end is exclusive
+    env.set(
+        "range",
+        LispyType::create_function(None, |args| {
+            if args.is_empty() || args.len() > 3 {
+                return Err(LispyType::create_error(
+                    format!("Expected arity 1 to 3, received {}", args.len()).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            if args.iter().any(|arg| !arg.is_number()) {
+                return Err(LispyType::create_error(
+                    "range expects number arguments",
+                    "INCORRECT_TYPE",
+                ));
+            }
+            let numbers: Vec<f64> = args.iter().map(|arg| *arg.as_number().unwrap()).collect();
+            let (start, end, step) = match numbers.as_slice() {
+                [end] => (0.0, *end, 1.0),
+                [start, end] => (*start, *end, 1.0),
+                _ => (numbers[0], numbers[1], numbers[2]),
+            };
+            if step == 0.0 || !step.is_finite() {
+                return Err(LispyType::create_error(
+                    "range step must be a finite non zero number",
+                    "INCORRECT_TYPE",
+                ));
+            }
+            let sized = sandbox::check_size(((end - start) / step).ceil() as usize);
+            if sized.is_err() {
+                return Err(sized.err().unwrap());
+            }
+
+            let mut collection = vec![];
+            let mut current = start;
+            while (step > 0.0 && current < end) || (step < 0.0 && current > end) {
+                collection.push(LispyType::create_number(current));
+                current += step;
+            }
+            Ok(LispyType::create_list(collection))
+        }),
+    );
     env.set(
         "count",
         LispyType::create_function(Some(1), |args| {
+            if let Some(result) = dispatch("count", &args) {
+                return result;
+            }
+            if let Ok(queue) = queue_arg(&args[0]) {
+                return Ok(LispyType::create_number(queue.items().len() as f64));
+            }
             let res = args[0].len();
             if res.is_error() {
                 return Err(res.clone());
@@ -84,16 +234,21 @@ pub fn apply_core_ns(env: &mut LispyEnv) {
     env.set(
         "cons",
         LispyType::create_function(None, |args| {
-            let mut collection = vec![];
-
-            for i in 0..args.len() - 1 {
-                collection.push(args.get(i).unwrap().clone());
+            if args.is_empty() {
+                return Err(LispyType::create_error(
+                    "Expected arity at least 1, received 0",
+                    "INCORRECT_ARITY",
+                ));
             }
-            let last = args.get(args.len() - 1).unwrap().as_list().unwrap().clone();
-
-            for item in last.iter() {
-                collection.push(item.clone());
+            let last = args.last().unwrap();
+            if last.as_sequential().is_none() {
+                return Err(LispyType::create_error(
+                    format!("cons expects a list or vector last. Received {}", last).as_str(),
+                    "INCORRECT_TYPE",
+                ));
             }
+            let mut collection = args[..args.len() - 1].to_vec();
+            collection.extend(last.as_sequential().unwrap().iter().cloned());
 
             Ok(LispyType::List {
                 collection: Box::new(collection),
@@ -107,9 +262,14 @@ pub fn apply_core_ns(env: &mut LispyEnv) {
             let mut collection = vec![];
 
             for x in args {
-                for item in x.as_list().unwrap().iter() {
-                    collection.push(item.clone());
+                let items = x.as_sequential();
+                if items.is_none() {
+                    return Err(LispyType::create_error(
+                        format!("concat expects lists or vectors. Received {}", x).as_str(),
+                        "INCORRECT_TYPE",
+                    ));
                 }
+                collection.extend(items.unwrap().iter().cloned());
             }
 
             Ok(LispyType::List {
@@ -135,6 +295,115 @@ pub fn apply_core_ns(env: &mut LispyEnv) {
             args[0].nth(args[1].as_number().unwrap().clone() as usize)
         }),
     );
+
+    // (apply f a b (list c d)) calls (f a b c d)
+    env.set(
+        "apply",
+        LispyType::create_function(None, |args| {
+            if args.is_empty() {
+                return Err(LispyType::create_error(
+                    "Expected arity at least 1, received 0",
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let mut arguments: Vec<LispyType> = vec![];
+            if args.len() > 1 {
+                arguments.extend(args[1..args.len() - 1].iter().cloned());
+                match args.last().unwrap() {
+                    LispyType::Nil { .. } => {}
+                    last => match last.as_sequential() {
+                        Some(items) => arguments.extend(items.iter().cloned()),
+                        None => {
+                            return Err(LispyType::create_error(
+                                format!("{} is not a list", last).as_str(),
+                                "INCORRECT_TYPE",
+                            ))
+                        }
+                    },
+                }
+            }
+            apply_callable(&args[0], arguments)
+        }),
+    );
+    env.set(
+        "vector",
+        LispyType::create_function(None, |args| Ok(LispyType::create_vector(args))),
+    );
+    env.set(
+        "vec",
+        LispyType::create_function(Some(1), |args| match &args[0] {
+            LispyType::Nil { .. } => Ok(LispyType::create_vector(vec![])),
+            LispyType::List { collection, .. } | LispyType::Vector { collection, .. } => {
+                Ok(LispyType::create_vector(collection.to_vec()))
+            }
+            _ => Err(LispyType::create_error(
+                format!("Could not create a vector from {}", args[0]).as_str(),
+                "INCORRECT_TYPE",
+            )),
+        }),
+    );
+    // (get coll key) or (get coll key default): keys for hashes, indexes for lists and vectors
+    env.set(
+        "get",
+        LispyType::create_function(None, |args| {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(LispyType::create_error(
+                    format!("Expected arity 2 or 3, received {}", args.len()).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let found = match (&args[0], &args[1]) {
+                (LispyType::Hash { collection, .. }, key) if key.is_hashable() => {
+                    collection.get(key).cloned()
+                }
+                (
+                    LispyType::List { collection, .. } | LispyType::Vector { collection, .. },
+                    LispyType::Number { value, .. },
+                ) if *value >= 0.0 => collection.get(*value as usize).cloned(),
+                (LispyType::Nil { .. }, _) => None,
+                (LispyType::Hash { .. }, _)
+                | (LispyType::List { .. }, _)
+                | (LispyType::Vector { .. }, _) => None,
+                _ => {
+                    return Err(LispyType::create_error(
+                        format!("Could not get from {}", args[0]).as_str(),
+                        "INCORRECT_TYPE",
+                    ))
+                }
+            };
+            Ok(found.unwrap_or_else(|| {
+                args.get(2).cloned().unwrap_or_else(LispyType::create_nil)
+            }))
+        }),
+    );
+    // Adds items where it is cheapest: the end of a vector, the front of a list
+    env.set(
+        "conj",
+        LispyType::create_function(None, |args| {
+            if args.is_empty() {
+                return Ok(LispyType::create_vector(vec![]));
+            }
+            match &args[0] {
+                LispyType::Vector { collection, .. } => {
+                    let mut collection = collection.to_vec();
+                    collection.extend(args[1..].iter().cloned());
+                    Ok(LispyType::create_vector(collection))
+                }
+                LispyType::List { collection, .. } => {
+                    let mut items: Vec<LispyType> = args[1..].iter().rev().cloned().collect();
+                    items.extend(collection.iter().cloned());
+                    Ok(LispyType::create_list(items))
+                }
+                LispyType::Nil { .. } => Ok(LispyType::create_list(
+                    args[1..].iter().rev().cloned().collect(),
+                )),
+                _ => Err(LispyType::create_error(
+                    format!("Could not conj onto {}", args[0]).as_str(),
+                    "INCORRECT_TYPE",
+                )),
+            }
+        }),
+    );
     //#endregion
     //#region is_?
     env.set(
@@ -159,6 +428,22 @@ pub fn apply_core_ns(env: &mut LispyEnv) {
             Ok(LispyType::create_bool(args[0].is_number()))
         }),
     );
+    env.set(
+        "nan?",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_bool(
+                args[0].as_number().is_some_and(|number| number.is_nan()),
+            ))
+        }),
+    );
+    env.set(
+        "infinite?",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_bool(
+                args[0].as_number().is_some_and(|number| number.is_infinite()),
+            ))
+        }),
+    );
     env.set(
         "string?",
         LispyType::create_function(Some(1), |args| {
@@ -177,6 +462,12 @@ pub fn apply_core_ns(env: &mut LispyEnv) {
             Ok(LispyType::create_bool(args[0].is_bool()))
         }),
     );
+    env.set(
+        "vector?",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_bool(args[0].is_vector()))
+        }),
+    );
     env.set(
         "hash?",
         LispyType::create_function(Some(1), |args| {
@@ -189,6 +480,12 @@ pub fn apply_core_ns(env: &mut LispyEnv) {
             Ok(LispyType::create_bool(args[0].is_function()))
         }),
     );
+    env.set(
+        "resource?",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_bool(args[0].is_resource()))
+        }),
+    );
     env.set(
         "macro?",
         LispyType::create_function(Some(1), |args| {
@@ -196,13 +493,116 @@ pub fn apply_core_ns(env: &mut LispyEnv) {
         }),
     );
     //#endregion
+    //#region Coercion
+    env.set(
+        "to-string",
+        LispyType::create_function(Some(1), |args| args[0].coerce_to_string()),
+    );
+    env.set(
+        "to-number",
+        LispyType::create_function(Some(1), |args| args[0].coerce_to_number()),
+    );
+    env.set(
+        "to-bool",
+        LispyType::create_function(Some(1), |args| args[0].coerce_to_bool()),
+    );
+    env.set(
+        "to-list",
+        LispyType::create_function(Some(1), |args| args[0].coerce_to_list()),
+    );
+    env.set(
+        "to-hash",
+        LispyType::create_function(Some(1), |args| args[0].coerce_to_hash()),
+    );
+    env.set(
+        "to-string-or-nil",
+        LispyType::create_function(Some(1), |args| {
+            Ok(args[0]
+                .coerce_to_string()
+                .unwrap_or_else(|_| LispyType::create_nil()))
+        }),
+    );
+    env.set(
+        "to-number-or-nil",
+        LispyType::create_function(Some(1), |args| {
+            Ok(args[0]
+                .coerce_to_number()
+                .unwrap_or_else(|_| LispyType::create_nil()))
+        }),
+    );
+    env.set(
+        "to-bool-or-nil",
+        LispyType::create_function(Some(1), |args| {
+            Ok(args[0]
+                .coerce_to_bool()
+                .unwrap_or_else(|_| LispyType::create_nil()))
+        }),
+    );
+    env.set(
+        "to-list-or-nil",
+        LispyType::create_function(Some(1), |args| {
+            Ok(args[0]
+                .coerce_to_list()
+                .unwrap_or_else(|_| LispyType::create_nil()))
+        }),
+    );
+    env.set(
+        "to-hash-or-nil",
+        LispyType::create_function(Some(1), |args| {
+            Ok(args[0]
+                .coerce_to_hash()
+                .unwrap_or_else(|_| LispyType::create_nil()))
+        }),
+    );
+    //#endregion
     //#region Compare
     env.set(
         "=",
         LispyType::create_function(Some(2), |args| {
+            if let Some(result) = dispatch("=", &args) {
+                return result;
+            }
             Ok(LispyType::create_bool(args[0] == args[1]))
         }),
     );
+    // |a - b| <= epsilon, what `=` on computed floats usually means. NaN is never approx= anything.
+    env.set(
+        "approx=",
+        LispyType::create_function(Some(3), |args| {
+            let numbers: Vec<f64> = args.iter().filter_map(|arg| arg.as_number().copied()).collect();
+            if numbers.len() != 3 {
+                return Err(LispyType::create_error(
+                    format!("approx= expects numbers. Received {}, {} and {}", args[0], args[1], args[2])
+                        .as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            let (a, b, epsilon) = (numbers[0], numbers[1], numbers[2]);
+            Ok(LispyType::create_bool(a == b || (a - b).abs() <= epsilon))
+        }),
+    );
+    env.set(
+        "compare",
+        LispyType::create_function(Some(2), |args| {
+            if let Some(result) = dispatch("compare", &args) {
+                return result;
+            }
+            let ordering = match (&args[0], &args[1]) {
+                (LispyType::String { value: a, .. }, LispyType::String { value: b, .. }) => {
+                    Some(a.cmp(b))
+                }
+                (LispyType::Number { .. }, LispyType::Number { .. }) => args[0].partial_cmp(&args[1]),
+                _ => None,
+            };
+            match ordering {
+                Some(ordering) => Ok(LispyType::create_number(ordering as i32 as f64)),
+                None => Err(LispyType::create_error(
+                    format!("Could not compare {} and {}", args[0], args[1]).as_str(),
+                    "INCORRECT_TYPE",
+                )),
+            }
+        }),
+    );
     env.set(
         ">",
         LispyType::create_function(Some(2), |args| {
@@ -222,21 +622,141 @@ pub fn apply_core_ns(env: &mut LispyEnv) {
         }),
     );
     env.set(
-        "<",
+        "<=",
         LispyType::create_function(Some(2), |args| {
             Ok(LispyType::create_bool(args[0] <= args[1]))
         }),
     );
     //#endregion
+    //#region Protocols
+    env.set(
+        "extend-type",
+        LispyType::create_function(Some(3), |args| {
+            let tag = args[0].as_keyword();
+            if tag.is_none() {
+                return Err(LispyType::create_error(
+                    format!("extend-type first arg must be a keyword. Received {}", args[0])
+                        .as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            let operation = match &args[1] {
+                LispyType::Symbol { value, .. } | LispyType::String { value, .. } => value.clone(),
+                _ => "".to_string(),
+            };
+            if !OVERLOADABLE.contains(&operation.as_str()) {
+                return Err(LispyType::create_error(
+                    format!("{} could not be overloaded", args[1]).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            if !args[2].is_function() {
+                return Err(LispyType::create_error(
+                    format!("{} is not a function", args[2]).as_str(),
+                    "NOT_A_FUNCTION",
+                ));
+            }
+
+            extend_type(tag.unwrap().clone(), operation, args[2].clone());
+            Ok(LispyType::create_nil())
+        }),
+    );
+    env.set(
+        "type-tag",
+        LispyType::create_function(Some(1), |args| {
+            Ok(type_tag(&args[0])
+                .map(|tag| LispyType::create_keyword(tag.as_str()))
+                .unwrap_or_else(LispyType::create_nil))
+        }),
+    );
+    //#endregion
+    //#region Errors
+    // (make-error 'NOT_FOUND "No such user" {:id 7}), the type is a symbol or a
+    // string and matches (catch* NOT_FOUND e ...). The data is optional.
+    env.set(
+        "make-error",
+        LispyType::create_function(None, |args| {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(LispyType::create_error(
+                    format!("Expected arity 2 or 3, received {}", args.len()).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let error_type = args[0].as_symbol().or_else(|| args[0].as_string());
+            if error_type.is_none() || !args[1].is_string() {
+                return Err(LispyType::create_error(
+                    "make-error expects a type symbol or string and a message string",
+                    "INCORRECT_TYPE",
+                ));
+            }
+            let (error_type, message) = (error_type.unwrap(), args[1].as_string().unwrap());
+            Ok(match args.get(2) {
+                Some(data) => LispyType::create_error_with_data(message, error_type, data.clone()),
+                None => LispyType::create_error(message, error_type),
+            })
+        }),
+    );
+    env.set(
+        "error?",
+        LispyType::create_function(Some(1), |args| Ok(LispyType::create_bool(args[0].is_error()))),
+    );
+    env.set(
+        "error-type",
+        LispyType::create_function(Some(1), |args| {
+            error_arg(&args[0]).map(|(error_type, _, _)| LispyType::create_string(error_type))
+        }),
+    );
+    env.set(
+        "error-message",
+        LispyType::create_function(Some(1), |args| {
+            error_arg(&args[0]).map(|(_, message, _)| LispyType::create_string(message))
+        }),
+    );
+    // nil for errors thrown without data, like the builtin ones
+    env.set(
+        "error-data",
+        LispyType::create_function(Some(1), |args| {
+            error_arg(&args[0])
+                .map(|(_, _, data)| data.cloned().unwrap_or_else(LispyType::create_nil))
+        }),
+    );
+    //#endregion
     //#region Eval
+    // (gensym) or (gensym "prefix"), a fresh symbol for macros to bind
+    env.set(
+        "gensym",
+        LispyType::create_function(None, |args| {
+            if args.len() > 1 {
+                return Err(LispyType::create_error(
+                    format!("Expected arity 0 or 1, received {}", args.len()).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let prefix = match args.first() {
+                None => "G__",
+                Some(prefix) if prefix.is_string() => prefix.as_string().unwrap().as_str(),
+                Some(other) => {
+                    return Err(LispyType::create_error(
+                        format!("gensym prefix must be a string. Received {}", other).as_str(),
+                        "INCORRECT_TYPE",
+                    ))
+                }
+            };
+            Ok(LispyType::create_symbol(gensym(prefix).as_str()))
+        }),
+    );
     env.set(
         "compile-string",
         LispyType::create_function(Some(1), |args| {
-            let ast = compile_source_code_to_ast(args[0].clone().as_string().unwrap().as_str());
-            let start = vec![LispyType::Symbol {
-                value: "do".to_string(),
-                meta: HashMap::new(),
-            }];
+            let ast = compile_with_limits(
+                args[0].clone().as_string().unwrap().as_str(),
+                &runtime_limits(),
+            );
+            if ast.is_err() {
+                return Err(ast.err().unwrap().to_lispy_error());
+            }
+            let ast = ast.unwrap();
+            let start = vec![LispyType::create_symbol("do")];
 
             let collection = vec![start, ast].concat();
             Ok(LispyType::List {
@@ -245,21 +765,1673 @@ pub fn apply_core_ns(env: &mut LispyEnv) {
             })
         }),
     );
+    // (eval-template "port ~(+ base 1)" {:base 8000}), see template.rs
+    env.set(
+        "eval-template",
+        LispyType::create_function(Some(2), |args| {
+            if !args[0].is_string() {
+                return Err(LispyType::create_error(
+                    format!("eval-template expects a string. Received {}", args[0]).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            let bindings = hash_arg(&args[1]);
+            if bindings.is_err() {
+                return Err(bindings.err().unwrap());
+            }
+            let result = eval_template(args[0].as_string().unwrap(), bindings.unwrap());
+            if result.is_err() {
+                return Err(result.err().unwrap());
+            }
+            Ok(LispyType::create_string(result.unwrap().as_str()))
+        }),
+    );
+    env.set(
+        "set-parse-limits!",
+        LispyType::create_function(Some(1), |args| {
+            let options = hash_arg(&args[0]);
+            if options.is_err() {
+                return Err(options.err().unwrap());
+            }
+            let options = options.unwrap();
+
+            let mut limits = ParseLimits::default();
+            for (key, value) in options.iter() {
+                let limit = match value {
+                    LispyType::Nil { .. } => None,
+                    LispyType::Number { value, .. } if *value >= 0.0 => Some(*value as usize),
+                    _ => {
+                        return Err(LispyType::create_error(
+                            format!("Parse limits must be numbers or nil. Received {}", value)
+                                .as_str(),
+                            "INCORRECT_TYPE",
+                        ))
+                    }
+                };
+                match key.as_keyword().map(|keyword| keyword.as_str()) {
+                    Some(":max-depth") => limits.max_depth = limit,
+                    Some(":max-collection-size") => limits.max_collection_size = limit,
+                    Some(":max-string-length") => limits.max_string_length = limit,
+                    _ => {
+                        return Err(LispyType::create_error(
+                            format!("Unknown parse limit {}", key).as_str(),
+                            "INCORRECT_TYPE",
+                        ))
+                    }
+                }
+            }
+
+            set_runtime_limits(limits);
+            Ok(LispyType::create_nil())
+        }),
+    );
     //#endregion
-    //#region FS
+    //#region Features
     env.set(
-        "slurp",
+        "*features*",
+        LispyType::create_list(
+            enabled_features()
+                .into_iter()
+                .map(LispyType::create_keyword)
+                .collect(),
+        ),
+    );
+    env.set(
+        "feature?",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_bool(
+                args[0]
+                    .as_keyword()
+                    .is_some_and(|feature| enabled_features().contains(&feature.as_str())),
+            ))
+        }),
+    );
+    //#endregion
+    //#region Debug
+    env.set(
+        "watch!",
         LispyType::create_function(Some(1), |args| {
-            let path = args[0].as_string().unwrap();
-            let contents = fs::read_to_string(path);
-            if contents.is_err() {
+            if !args[0].is_symbol() {
                 return Err(LispyType::create_error(
-                    format!("File {} not found", path).as_str(),
-                    "SYSTEM_ERROR",
+                    format!("watch! expects a symbol. Received {}", args[0]).as_str(),
+                    "INCORRECT_TYPE",
                 ));
             }
-            Ok(LispyType::create_string(contents.unwrap().as_str()))
+            watch(args[0].as_symbol().unwrap());
+            Ok(LispyType::create_nil())
         }),
-    )
-    //#endregion
+    );
+    env.set(
+        "unwatch!",
+        LispyType::create_function(Some(1), |args| {
+            if !args[0].is_symbol() {
+                return Err(LispyType::create_error(
+                    format!("unwatch! expects a symbol. Received {}", args[0]).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            unwatch(args[0].as_symbol().unwrap());
+            Ok(LispyType::create_nil())
+        }),
+    );
+    // (add-watch 'sym (fn* (sym old new) ...)) runs on every later def! and undef! of
+    // sym in this machine's root env
+    env.set(
+        "add-watch",
+        LispyType::create_function(Some(2), |args| {
+            if !args[0].is_symbol() || !args[1].is_function() {
+                return Err(LispyType::create_error(
+                    format!(
+                        "add-watch expects a symbol and a function. Received {} {}",
+                        args[0], args[1]
+                    )
+                    .as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            add_watch(args[0].as_symbol().unwrap(), args[1].clone());
+            Ok(LispyType::create_nil())
+        }),
+    );
+    env.set(
+        "remove-watch",
+        LispyType::create_function(Some(1), |args| {
+            if !args[0].is_symbol() {
+                return Err(LispyType::create_error(
+                    format!("remove-watch expects a symbol. Received {}", args[0]).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            Ok(LispyType::create_bool(remove_watches(args[0].as_symbol().unwrap())))
+        }),
+    );
+    env.set(
+        "assert",
+        LispyType::create_function(None, |args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(LispyType::create_error(
+                    format!("Expected arity 1 or 2, received {}", args.len()).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            if args[0].is_truthy() {
+                return Ok(LispyType::create_bool(true));
+            }
+            let message = match args.get(1) {
+                Some(message) => format!("{}", message),
+                None => "Assertion failed".to_string(),
+            };
+            Err(LispyType::create_error(message.as_str(), "ASSERTION_FAILED"))
+        }),
+    );
+    env.set(
+        "assert=",
+        LispyType::create_function(Some(2), |args| {
+            if args[0] == args[1] {
+                return Ok(LispyType::create_bool(true));
+            }
+            let mut changes = vec![];
+            data_diff(&args[0], &args[1], &mut vec![], &mut changes);
+            let mut message = "Values are not equal".to_string();
+            for change in changes.iter() {
+                message += format!("\n  {}", describe_change(change)).as_str();
+            }
+            Err(LispyType::create_error(message.as_str(), "ASSERTION_FAILED"))
+        }),
+    );
+    env.set(
+        "inspect",
+        LispyType::create_function(Some(1), |args| Ok(inspect(args[0].clone()))),
+    );
+    env.set(
+        "gc!",
+        LispyType::create_function(Some(0), |_| {
+            Ok(LispyType::create_number(collect_cycles() as f64))
+        }),
+    );
+    // Sizes of the reader's symbol and keyword table, :bytes counts each name once
+    env.set(
+        "intern-stats",
+        LispyType::create_function(Some(0), |_| {
+            let stats = with_literals(|literals| {
+                let count = |table: &HashMap<String, usize>| {
+                    LispyType::create_number(table.len() as f64)
+                };
+                let references = |table: &HashMap<String, usize>| {
+                    LispyType::create_number(table.values().sum::<usize>() as f64)
+                };
+                let bytes: usize = literals
+                    .symbols
+                    .keys()
+                    .chain(literals.keywords.keys())
+                    .map(|name| name.len())
+                    .sum();
+                vec![
+                    (":symbols", count(&literals.symbols)),
+                    (":keywords", count(&literals.keywords)),
+                    (":symbol-references", references(&literals.symbols)),
+                    (":keyword-references", references(&literals.keywords)),
+                    (":bytes", LispyType::create_number(bytes as f64)),
+                ]
+            });
+            Ok(LispyType::Hash {
+                collection: Box::from(
+                    stats
+                        .into_iter()
+                        .map(|(key, value)| (LispyType::create_keyword(key), value))
+                        .collect::<HashMap<_, _>>(),
+                ),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    // Every keyword the reader has seen, sorted
+    env.set(
+        "keywords",
+        LispyType::create_function(Some(0), |_| {
+            let mut keywords = with_literals(|literals| {
+                literals.keywords.keys().cloned().collect::<Vec<String>>()
+            });
+            keywords.sort();
+            Ok(LispyType::create_list(
+                keywords.iter().map(|keyword| LispyType::create_keyword(keyword)).collect(),
+            ))
+        }),
+    );
+    // The functions being applied, innermost first
+    env.set(
+        "stacktrace",
+        LispyType::create_function(Some(0), |_| Ok(LispyType::create_list(stacktrace::current()))),
+    );
+    //#endregion
+    //#region Generators
+    env.set(
+        "generator",
+        LispyType::create_function(Some(1), |args| create_generator(args[0].clone())),
+    );
+    env.set(
+        "yield",
+        LispyType::create_function(Some(1), |args| yield_value(args[0].clone())),
+    );
+    env.set(
+        "next",
+        LispyType::create_function(Some(1), |args| match args[0].as_resource::<Generator>() {
+            Some(generator) => next_value(generator),
+            None => Err(LispyType::create_error(
+                format!("{} is not a generator", args[0]).as_str(),
+                "INCORRECT_TYPE",
+            )),
+        }),
+    );
+    env.set(
+        "done?",
+        LispyType::create_function(Some(1), |args| match args[0].as_resource::<Generator>() {
+            Some(generator) => Ok(LispyType::create_bool(is_finished(generator))),
+            None => Err(LispyType::create_error(
+                format!("{} is not a generator", args[0]).as_str(),
+                "INCORRECT_TYPE",
+            )),
+        }),
+    );
+    env.set(
+        "generator?",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_bool(args[0].is_resource_of(GENERATOR_KIND)))
+        }),
+    );
+    //#endregion
+    //#region Futures
+    env.set(
+        "future",
+        LispyType::create_function(Some(1), |args| {
+            if !args[0].is_function() {
+                return Err(LispyType::create_error(
+                    format!("{} is not a function", args[0]).as_str(),
+                    "NOT_A_FUNCTION",
+                ));
+            }
+            Ok(create_future(args[0].clone()))
+        }),
+    );
+    env.set(
+        "await",
+        LispyType::create_function(Some(1), |args| {
+            future_arg(&args[0]).and_then(await_future)
+        }),
+    );
+    env.set(
+        "realized?",
+        LispyType::create_function(Some(1), |args| {
+            future_arg(&args[0]).map(|future| LispyType::create_bool(is_realized(future)))
+        }),
+    );
+    env.set(
+        "future?",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_bool(args[0].is_resource_of(FUTURE_KIND)))
+        }),
+    );
+    env.set(
+        "all",
+        LispyType::create_function(Some(1), |args| {
+            if !args[0].is_list() {
+                return Err(LispyType::create_error(
+                    format!("{} is not a list", args[0]).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+
+            let mut results = vec![];
+            for item in args[0].as_list().unwrap().iter() {
+                let result = future_arg(item).and_then(await_future);
+                if result.is_err() {
+                    return Err(result.err().unwrap());
+                }
+                results.push(result.unwrap());
+            }
+            Ok(LispyType::create_list(results))
+        }),
+    );
+    // (race futures). Futures only run when awaited, so nothing actually races:
+    // this returns the first future already realized, or else awaits the first one
+    // in the list and leaves the others unstarted
+    env.set(
+        "race",
+        LispyType::create_function(Some(1), |args| {
+            let futures = args[0].as_list();
+            if futures.is_none() || futures.unwrap().is_empty() {
+                return Err(LispyType::create_error(
+                    "race expects a non-empty list of futures",
+                    "INCORRECT_TYPE",
+                ));
+            }
+
+            let mut winner = &futures.unwrap()[0];
+            for item in futures.unwrap().iter() {
+                let future = future_arg(item);
+                if future.is_err() {
+                    return Err(future.err().unwrap());
+                }
+                if is_realized(future.unwrap()) {
+                    winner = item;
+                    break;
+                }
+            }
+            future_arg(winner).and_then(await_future)
+        }),
+    );
+    env.set(
+        "lock",
+        LispyType::create_function(Some(0), |_| Ok(create_lock())),
+    );
+    env.set(
+        "locked?",
+        LispyType::create_function(Some(1), |args| {
+            lock_arg(&args[0]).map(|lock| LispyType::create_bool(is_locked(lock)))
+        }),
+    );
+    env.set(
+        "lock?",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_bool(args[0].is_resource_of(LOCK_KIND)))
+        }),
+    );
+    //#endregion
+    //#region Actors
+    env.set(
+        "actor",
+        LispyType::create_function(Some(2), |args| {
+            if !args[1].is_function() {
+                return Err(LispyType::create_error(
+                    format!("{} is not a function", args[1]).as_str(),
+                    "NOT_A_FUNCTION",
+                ));
+            }
+            Ok(create_actor(args[0].clone(), args[1].clone()))
+        }),
+    );
+    env.set(
+        "send!",
+        LispyType::create_function(Some(2), |args| {
+            actor_arg(&args[0]).and_then(|actor| send(actor, args[1].clone()))
+        }),
+    );
+    env.set(
+        "ask!",
+        LispyType::create_function(None, |args| {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(LispyType::create_error(
+                    format!("Expected arity 2 or 3, received {}", args.len()).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let timeout = match args.get(2) {
+                Some(LispyType::Number { value, .. }) if *value >= 0.0 => {
+                    Some(Duration::from_millis(*value as u64))
+                }
+                Some(other) => {
+                    return Err(LispyType::create_error(
+                        format!("ask! timeout must be milliseconds. Received {}", other)
+                            .as_str(),
+                        "INCORRECT_TYPE",
+                    ))
+                }
+                None => None,
+            };
+            actor_arg(&args[0]).and_then(|actor| ask(actor, args[1].clone(), timeout))
+        }),
+    );
+    env.set(
+        "actor-state",
+        LispyType::create_function(Some(1), |args| actor_arg(&args[0]).map(actor_state)),
+    );
+    env.set(
+        "actor?",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_bool(args[0].is_resource_of(ACTOR_KIND)))
+        }),
+    );
+    //#endregion
+    //#region Atoms
+    env.set(
+        "atom",
+        LispyType::create_function(Some(1), |args| Ok(LispyType::create_atom(args[0].clone()))),
+    );
+    env.set(
+        "atom?",
+        LispyType::create_function(Some(1), |args| Ok(LispyType::create_bool(args[0].is_atom()))),
+    );
+    // Works on futures too, where it waits for the value like `await`
+    env.set(
+        "deref",
+        LispyType::create_function(Some(1), |args| {
+            if args[0].is_resource_of(FUTURE_KIND) {
+                return future_arg(&args[0]).and_then(await_future);
+            }
+            atom_arg(&args[0]).map(|atom| atom.borrow().clone())
+        }),
+    );
+    env.set(
+        "reset!",
+        LispyType::create_function(Some(2), |args| {
+            atom_arg(&args[0]).map(|atom| {
+                *atom.borrow_mut() = args[1].clone();
+                args[1].clone()
+            })
+        }),
+    );
+    // (swap! atom f & args) stores (f current args...) and returns it
+    env.set(
+        "swap!",
+        LispyType::create_function(None, |args| {
+            if args.len() < 2 {
+                return Err(LispyType::create_error(
+                    format!("Expected arity at least 2, received {}", args.len()).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let atom = atom_arg(&args[0]);
+            if atom.is_err() {
+                return Err(atom.err().unwrap());
+            }
+            let atom = atom.unwrap();
+
+            let current = atom.borrow().clone();
+            let mut arguments = vec![current];
+            arguments.extend(args[2..].iter().cloned());
+            let updated = apply_callable(&args[1], arguments);
+            if updated.is_err() {
+                return Err(updated.err().unwrap());
+            }
+            *atom.borrow_mut() = updated.as_ref().unwrap().clone();
+            updated
+        }),
+    );
+    // Atoms belong to a single thread and the comparison runs no Lispy code, so
+    // nothing can change the atom between the check and the update
+    env.set(
+        "compare-and-set!",
+        LispyType::create_function(Some(3), |args| {
+            atom_arg(&args[0]).map(|atom| {
+                if *atom.borrow() != args[1] {
+                    return LispyType::create_bool(false);
+                }
+                *atom.borrow_mut() = args[2].clone();
+                LispyType::create_bool(true)
+            })
+        }),
+    );
+    //#endregion
+    //#region Walk
+    env.set(
+        "walk",
+        LispyType::create_function(Some(3), |args| {
+            let walked = walk_children(&args[2], &mut |child| {
+                apply_callable(&args[0], vec![child])
+            });
+            if walked.is_err() {
+                return Err(walked.err().unwrap());
+            }
+            apply_callable(&args[1], vec![walked.unwrap()])
+        }),
+    );
+    env.set(
+        "prewalk",
+        LispyType::create_function(Some(2), |args| prewalk(&args[0], args[1].clone())),
+    );
+    env.set(
+        "postwalk",
+        LispyType::create_function(Some(2), |args| postwalk(&args[0], args[1].clone())),
+    );
+    //#endregion
+    //#region Queues
+    env.set(
+        "queue",
+        LispyType::create_function(None, |args| Ok(create_queue(args.into_iter().collect()))),
+    );
+    env.set(
+        "queue?",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_bool(args[0].is_resource_of(QUEUE_KIND)))
+        }),
+    );
+    // Lists are stacks at the front, vectors at the end and queues push at the back
+    // and take from the front
+    env.set(
+        "push",
+        LispyType::create_function(Some(2), |args| match &args[0] {
+            LispyType::List { collection, .. } => {
+                let mut collection = collection.as_ref().clone();
+                collection.insert(0, args[1].clone());
+                Ok(LispyType::create_list(collection))
+            }
+            LispyType::Vector { collection, .. } => {
+                let mut collection = collection.as_ref().clone();
+                collection.push(args[1].clone());
+                Ok(LispyType::create_vector(collection))
+            }
+            _ => queue_arg(&args[0]).map(|queue| queue.push(args[1].clone())),
+        }),
+    );
+    // The item pop would remove, nil when empty
+    env.set(
+        "peek",
+        LispyType::create_function(Some(1), |args| {
+            let item = match &args[0] {
+                LispyType::List { collection, .. } => collection.first().cloned(),
+                LispyType::Vector { collection, .. } => collection.last().cloned(),
+                LispyType::Nil { .. } => None,
+                _ => {
+                    let queue = queue_arg(&args[0]);
+                    if queue.is_err() {
+                        return Err(queue.err().unwrap());
+                    }
+                    queue.unwrap().items().front().cloned()
+                }
+            };
+            Ok(item.unwrap_or_else(LispyType::create_nil))
+        }),
+    );
+    env.set(
+        "pop",
+        LispyType::create_function(Some(1), |args| {
+            let popped = match &args[0] {
+                LispyType::List { collection, .. } if !collection.is_empty() => {
+                    Some(LispyType::create_list(collection[1..].to_vec()))
+                }
+                LispyType::Vector { collection, .. } if !collection.is_empty() => {
+                    Some(LispyType::create_vector(collection[..collection.len() - 1].to_vec()))
+                }
+                LispyType::List { .. } | LispyType::Vector { .. } => None,
+                _ => {
+                    let queue = queue_arg(&args[0]);
+                    if queue.is_err() {
+                        return Err(queue.err().unwrap());
+                    }
+                    queue.unwrap().pop()
+                }
+            };
+            popped.ok_or_else(|| {
+                LispyType::create_error(
+                    format!("Can not pop from empty {}", args[0]).as_str(),
+                    "EMPTY_COLLECTION",
+                )
+            })
+        }),
+    );
+    //#endregion
+    //#region Sequences
+    // (map f coll ...) calls f with one item of every collection, stops at the shortest
+    env.set(
+        "map",
+        LispyType::create_function(None, |args| {
+            if args.len() < 2 {
+                return Err(LispyType::create_error(
+                    format!("Expected arity at least 2, received {}", args.len()).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let mut collections = vec![];
+            for collection in args[1..].iter() {
+                let items = sequence_items(collection);
+                if items.is_err() {
+                    return Err(items.err().unwrap());
+                }
+                collections.push(items.unwrap());
+            }
+
+            let size = collections.iter().map(|items| items.len()).min().unwrap();
+            let mut mapped = vec![];
+            for index in 0..size {
+                let call_args = collections.iter().map(|items| items[index].clone()).collect();
+                let result = apply_callable(&args[0], call_args);
+                if result.is_err() {
+                    return Err(result.err().unwrap());
+                }
+                mapped.push(result.unwrap());
+            }
+            Ok(LispyType::create_list(mapped))
+        }),
+    );
+    env.set(
+        "filter",
+        LispyType::create_function(Some(2), |args| {
+            let items = sequence_items(&args[1]);
+            if items.is_err() {
+                return Err(items.err().unwrap());
+            }
+
+            let mut kept = vec![];
+            for item in items.unwrap() {
+                let result = apply_callable(&args[0], vec![item.clone()]);
+                if result.is_err() {
+                    return Err(result.err().unwrap());
+                }
+                if result.unwrap().is_truthy() {
+                    kept.push(item);
+                }
+            }
+            Ok(LispyType::create_list(kept))
+        }),
+    );
+    // (reduce f coll) starts from the first item, (reduce f init coll) from init
+    env.set(
+        "reduce",
+        LispyType::create_function(None, |args| {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(LispyType::create_error(
+                    format!("Expected arity 2 or 3, received {}", args.len()).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let items = sequence_items(args.last().unwrap());
+            if items.is_err() {
+                return Err(items.err().unwrap());
+            }
+            let mut items = items.unwrap().into_iter();
+
+            let initial = if args.len() == 3 { Some(args[1].clone()) } else { items.next() };
+            if initial.is_none() {
+                // Like Clojure, reducing nothing calls f without arguments
+                return apply_callable(&args[0], vec![]);
+            }
+            let mut accumulator = initial.unwrap();
+            for item in items {
+                let result = apply_callable(&args[0], vec![accumulator, item]);
+                if result.is_err() {
+                    return Err(result.err().unwrap());
+                }
+                accumulator = result.unwrap();
+            }
+            Ok(accumulator)
+        }),
+    );
+    // (bsearch sorted x key-fn?) index of an item equal to x in a sorted list or vector,
+    // nil when there is none. With key-fn, x is the key looked for: (bsearch people 30 :age)
+    env.set(
+        "bsearch",
+        LispyType::create_function(None, |args| {
+            let position = sorted_position(&args, "bsearch", true);
+            if position.is_err() {
+                return Err(position.err().unwrap());
+            }
+            Ok(match position.unwrap() {
+                Ok(index) => LispyType::create_number(index as f64),
+                Err(_) => LispyType::create_nil(),
+            })
+        }),
+    );
+    // (insert-sorted sorted x key-fn?) adds x after any equal items, keeping the order
+    env.set(
+        "insert-sorted",
+        LispyType::create_function(None, |args| {
+            let position = sorted_position(&args, "insert-sorted", false);
+            if position.is_err() {
+                return Err(position.err().unwrap());
+            }
+            let mut index = match position.unwrap() {
+                Ok(index) | Err(index) => index,
+            };
+
+            let mut collection = args[0].as_sequential().unwrap().to_vec();
+            let key_fn = args.get(2);
+            // The search stops at any equal item, step past the rest of them
+            let target = sort_key(&args[1], key_fn).unwrap();
+            while index < collection.len() {
+                let key = sort_key(&collection[index], key_fn);
+                if key.is_err() {
+                    return Err(key.err().unwrap());
+                }
+                if compare_keys(&key.unwrap(), &target) != Ordering::Equal {
+                    break;
+                }
+                index += 1;
+            }
+            collection.insert(index, args[1].clone());
+            Ok(if args[0].is_vector() {
+                LispyType::create_vector(collection)
+            } else {
+                LispyType::create_list(collection)
+            })
+        }),
+    );
+    //#endregion
+    //#region Pipeline
+    env.set(
+        "mapping",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_list(vec![
+                LispyType::create_keyword(":mapping"),
+                args[0].clone(),
+            ]))
+        }),
+    );
+    env.set(
+        "filtering",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_list(vec![
+                LispyType::create_keyword(":filtering"),
+                args[0].clone(),
+            ]))
+        }),
+    );
+    env.set(
+        "taking",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_list(vec![
+                LispyType::create_keyword(":taking"),
+                args[0].clone(),
+            ]))
+        }),
+    );
+    env.set(
+        "dropping",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_list(vec![
+                LispyType::create_keyword(":dropping"),
+                args[0].clone(),
+            ]))
+        }),
+    );
+    env.set("pipeline", LispyType::create_function(None, run_pipeline));
+    //#endregion
+    //#region Hash
+    env.set(
+        "hash-map",
+        LispyType::create_function(None, |args| {
+            let collection = pairs_into(HashMap::new(), &args, "hash-map");
+            if collection.is_err() {
+                return Err(collection.err().unwrap());
+            }
+            Ok(LispyType::Hash {
+                collection: Box::from(collection.unwrap()),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    // (assoc hash key value ...) returns a new hash, the argument is left untouched
+    env.set(
+        "assoc",
+        LispyType::create_function(None, |args| {
+            if args.is_empty() {
+                return Err(LispyType::create_error(
+                    "Expected at least 1 arg, received 0",
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let hash = if args[0].is_nil() {
+                Ok(HashMap::new())
+            } else {
+                hash_arg(&args[0]).cloned()
+            };
+            if hash.is_err() {
+                return Err(hash.err().unwrap());
+            }
+            let collection = pairs_into(hash.unwrap(), &args[1..], "assoc");
+            if collection.is_err() {
+                return Err(collection.err().unwrap());
+            }
+            Ok(LispyType::Hash {
+                collection: Box::from(collection.unwrap()),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    env.set(
+        "dissoc",
+        LispyType::create_function(None, |args| {
+            if args.is_empty() {
+                return Err(LispyType::create_error(
+                    "Expected at least 1 arg, received 0",
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let hash = hash_arg(&args[0]);
+            if hash.is_err() {
+                return Err(hash.err().unwrap());
+            }
+            let mut collection = hash.unwrap().clone();
+            // Keys that can't be hashed can't be in the hash either
+            for key in args[1..].iter().filter(|key| key.is_hashable()) {
+                collection.remove(key);
+            }
+            Ok(LispyType::Hash {
+                collection: Box::from(collection),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    // keys and vals list entries in the same (key) order, so they can be zipped
+    env.set(
+        "keys",
+        LispyType::create_function(Some(1), |args| {
+            let hash = hash_arg(&args[0]);
+            if hash.is_err() {
+                return Err(hash.err().unwrap());
+            }
+            let keys = sorted_hash_entries(hash.unwrap()).into_iter().map(|(key, _)| key.clone());
+            Ok(LispyType::create_list(keys.collect()))
+        }),
+    );
+    env.set(
+        "vals",
+        LispyType::create_function(Some(1), |args| {
+            let hash = hash_arg(&args[0]);
+            if hash.is_err() {
+                return Err(hash.err().unwrap());
+            }
+            let vals = sorted_hash_entries(hash.unwrap()).into_iter().map(|(_, value)| value.clone());
+            Ok(LispyType::create_list(vals.collect()))
+        }),
+    );
+    // Hashes always iterate and print in key order, sorted-map only makes that explicit
+    env.set(
+        "sorted-map",
+        LispyType::create_function(None, |args| {
+            let collection = pairs_into(HashMap::new(), &args, "sorted-map");
+            if collection.is_err() {
+                return Err(collection.err().unwrap());
+            }
+            Ok(LispyType::Hash {
+                collection: Box::from(collection.unwrap()),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    // (key value) of the smallest key, nil for an empty hash
+    env.set(
+        "first-entry",
+        LispyType::create_function(Some(1), |args| {
+            let hash = hash_arg(&args[0]);
+            if hash.is_err() {
+                return Err(hash.err().unwrap());
+            }
+            Ok(entry_list(sorted_hash_entries(hash.unwrap()).first()))
+        }),
+    );
+    env.set(
+        "last-entry",
+        LispyType::create_function(Some(1), |args| {
+            let hash = hash_arg(&args[0]);
+            if hash.is_err() {
+                return Err(hash.err().unwrap());
+            }
+            Ok(entry_list(sorted_hash_entries(hash.unwrap()).last()))
+        }),
+    );
+    // Keys for hashes, indexes for lists and vectors, like get
+    env.set(
+        "contains?",
+        LispyType::create_function(Some(2), |args| {
+            let found = match (&args[0], &args[1]) {
+                (LispyType::Hash { collection, .. }, key) if key.is_hashable() => {
+                    collection.contains_key(key)
+                }
+                (
+                    LispyType::List { collection, .. } | LispyType::Vector { collection, .. },
+                    LispyType::Number { value, .. },
+                ) => *value >= 0.0 && (*value as usize) < collection.len(),
+                (LispyType::Nil { .. }, _) => false,
+                (LispyType::Hash { .. } | LispyType::List { .. } | LispyType::Vector { .. }, _) => false,
+                _ => {
+                    return Err(LispyType::create_error(
+                        format!("Could not look up keys in {}", args[0]).as_str(),
+                        "INCORRECT_TYPE",
+                    ))
+                }
+            };
+            Ok(LispyType::create_bool(found))
+        }),
+    );
+    env.set(
+        "group-by",
+        LispyType::create_function(Some(2), |args| {
+            let mut groups: HashMap<LispyType, LispyType> = HashMap::new();
+            let keyed = key_items(&args[0], &args[1]);
+            if keyed.is_err() {
+                return Err(keyed.err().unwrap());
+            }
+
+            for (key, item) in keyed.unwrap() {
+                let group = groups
+                    .entry(key)
+                    .or_insert_with(|| LispyType::create_list(vec![]));
+                if let LispyType::List { collection, .. } = group {
+                    collection.push(item);
+                }
+            }
+
+            Ok(LispyType::Hash {
+                collection: Box::from(groups),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    env.set(
+        "index-by",
+        LispyType::create_function(Some(2), |args| {
+            let keyed = key_items(&args[0], &args[1]);
+            if keyed.is_err() {
+                return Err(keyed.err().unwrap());
+            }
+
+            Ok(LispyType::Hash {
+                collection: Box::from(keyed.unwrap().into_iter().collect::<HashMap<_, _>>()),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    env.set(
+        "update",
+        LispyType::create_function(None, |args| {
+            if args.len() < 3 {
+                return Err(LispyType::create_error(
+                    format!("Expected at least 3 args, received {}", args.len()).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let hash = hash_arg(&args[0]);
+            if hash.is_err() {
+                return Err(hash.err().unwrap());
+            }
+            if !args[1].is_hashable() {
+                return Err(LispyType::create_error(
+                    format!("{} could not be used as a hash key", args[1]).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+
+            let mut collection = hash.unwrap().clone();
+            let current = collection
+                .get(&args[1])
+                .cloned()
+                .unwrap_or_else(LispyType::create_nil);
+            let call_args = [vec![current], args[3..].to_vec()].concat();
+            let updated = apply_callable(&args[2], call_args);
+            if updated.is_err() {
+                return Err(updated.err().unwrap());
+            }
+            collection.insert(args[1].clone(), updated.unwrap());
+
+            Ok(LispyType::Hash {
+                collection: Box::from(collection),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    env.set(
+        "update-keys",
+        LispyType::create_function(Some(2), |args| {
+            let hash = hash_arg(&args[0]);
+            if hash.is_err() {
+                return Err(hash.err().unwrap());
+            }
+
+            let mut collection = HashMap::new();
+            for (key, value) in hash.unwrap().iter() {
+                let key = apply_callable(&args[1], vec![key.clone()]);
+                if key.is_err() {
+                    return Err(key.err().unwrap());
+                }
+                let key = key.unwrap();
+                if !key.is_hashable() {
+                    return Err(LispyType::create_error(
+                        format!("{} could not be used as a hash key", key).as_str(),
+                        "INCORRECT_TYPE",
+                    ));
+                }
+                collection.insert(key, value.clone());
+            }
+
+            Ok(LispyType::Hash {
+                collection: Box::from(collection),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    env.set(
+        "update-vals",
+        LispyType::create_function(Some(2), |args| {
+            let hash = hash_arg(&args[0]);
+            if hash.is_err() {
+                return Err(hash.err().unwrap());
+            }
+
+            let mut collection = HashMap::new();
+            for (key, value) in hash.unwrap().iter() {
+                let value = apply_callable(&args[1], vec![value.clone()]);
+                if value.is_err() {
+                    return Err(value.err().unwrap());
+                }
+                collection.insert(key.clone(), value.unwrap());
+            }
+
+            Ok(LispyType::Hash {
+                collection: Box::from(collection),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    env.set(
+        "select-keys",
+        LispyType::create_function(Some(2), |args| {
+            let hash = hash_arg(&args[0]);
+            if hash.is_err() {
+                return Err(hash.err().unwrap());
+            }
+            if !args[1].is_list() {
+                return Err(LispyType::create_error(
+                    format!("{} is not a list", args[1]).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+
+            let hash = hash.unwrap();
+            let mut collection = HashMap::new();
+            for key in args[1].as_list().unwrap().iter() {
+                if !key.is_hashable() {
+                    continue;
+                }
+                if let Some(value) = hash.get(key) {
+                    collection.insert(key.clone(), value.clone());
+                }
+            }
+
+            Ok(LispyType::Hash {
+                collection: Box::from(collection),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    env.set(
+        "rename-keys",
+        LispyType::create_function(Some(2), |args| {
+            let hash = hash_arg(&args[0]);
+            if hash.is_err() {
+                return Err(hash.err().unwrap());
+            }
+            let renames = hash_arg(&args[1]);
+            if renames.is_err() {
+                return Err(renames.err().unwrap());
+            }
+
+            let hash = hash.unwrap();
+            let renames = renames.unwrap();
+            let mut collection = HashMap::new();
+            for (key, value) in hash.iter() {
+                if !renames.contains_key(key) {
+                    collection.insert(key.clone(), value.clone());
+                }
+            }
+            for (from, to) in renames.iter() {
+                if !to.is_hashable() {
+                    return Err(LispyType::create_error(
+                        format!("{} could not be used as a hash key", to).as_str(),
+                        "INCORRECT_TYPE",
+                    ));
+                }
+                if let Some(value) = hash.get(from) {
+                    collection.insert(to.clone(), value.clone());
+                }
+            }
+
+            Ok(LispyType::Hash {
+                collection: Box::from(collection),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    // (merge-with f hash ...) keeps the later value, or (f earlier later) when both have the key
+    env.set(
+        "merge-with",
+        LispyType::create_function(None, |args| {
+            if args.is_empty() {
+                return Err(LispyType::create_error(
+                    "Expected at least 1 arg, received 0",
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let mut collection: HashMap<LispyType, LispyType> = HashMap::new();
+            for hash in args[1..].iter().filter(|hash| !hash.is_nil()) {
+                let hash = hash_arg(hash);
+                if hash.is_err() {
+                    return Err(hash.err().unwrap());
+                }
+                for (key, value) in hash.unwrap().iter() {
+                    let merged = match collection.get(key) {
+                        Some(existing) => {
+                            apply_callable(&args[0], vec![existing.clone(), value.clone()])
+                        }
+                        None => Ok(value.clone()),
+                    };
+                    if merged.is_err() {
+                        return Err(merged.err().unwrap());
+                    }
+                    collection.insert(key.clone(), merged.unwrap());
+                }
+            }
+
+            Ok(LispyType::Hash {
+                collection: Box::from(collection),
+                meta: HashMap::new(),
+            })
+        }),
+    );
+    // Nested hashes are merged key by key, any other value is replaced by the later one
+    env.set(
+        "deep-merge",
+        LispyType::create_function(None, |args| {
+            let mut merged = LispyType::Hash {
+                collection: Box::from(HashMap::new()),
+                meta: HashMap::new(),
+            };
+            for hash in args.iter().filter(|hash| !hash.is_nil()) {
+                if !hash.is_hash() {
+                    return Err(LispyType::create_error(
+                        format!("{} is not a hash", hash).as_str(),
+                        "INCORRECT_TYPE",
+                    ));
+                }
+                merged = deep_merge(&merged, hash);
+            }
+            Ok(merged)
+        }),
+    );
+    //#endregion
+    //#region Diff
+    env.set(
+        "data-diff",
+        LispyType::create_function(Some(2), |args| {
+            let mut changes = vec![];
+            data_diff(&args[0], &args[1], &mut vec![], &mut changes);
+            Ok(LispyType::create_list(changes))
+        }),
+    );
+    //#endregion
+    //#region FS
+    register_slurp(env);
+    //#endregion
+}
+
+#[lispy_fn]
+fn slurp(path: String) -> Result<String, LispyType> {
+    fs::read_to_string(&path).map_err(|_| {
+        LispyType::create_error(format!("File {} not found", path).as_str(), "SYSTEM_ERROR")
+    })
+}
+
+// Cargo features this binary was built with, as keywords
+fn enabled_features() -> Vec<&'static str> {
+    let mut features = vec![];
+    if cfg!(feature = "matrix") {
+        features.push(":matrix");
+    }
+    if cfg!(feature = "decimal") {
+        features.push(":decimal");
+    }
+    if cfg!(feature = "serde") {
+        features.push(":serde");
+    }
+    if cfg!(feature = "msgpack") {
+        features.push(":msgpack");
+    }
+    if cfg!(feature = "image") {
+        features.push(":image");
+    }
+    if cfg!(feature = "archive") {
+        features.push(":archive");
+    }
+    features
+}
+
+// Rebuilds a list or hash by passing every child through `inner`. Hash keys and
+// values are walked separately, anything else is returned untouched.
+fn walk_children(
+    form: &LispyType,
+    inner: &mut dyn FnMut(LispyType) -> Result<LispyType, LispyType>,
+) -> Result<LispyType, LispyType> {
+    match form {
+        LispyType::List { collection, meta } => {
+            let mut walked = vec![];
+            for item in collection.iter() {
+                let result = inner(item.clone());
+                if result.is_err() {
+                    return Err(result.err().unwrap());
+                }
+                walked.push(result.unwrap());
+            }
+            Ok(LispyType::List {
+                collection: Box::from(walked),
+                meta: meta.clone(),
+            })
+        }
+        LispyType::Vector { collection, meta } => {
+            let mut walked = vec![];
+            for item in collection.iter() {
+                let result = inner(item.clone());
+                if result.is_err() {
+                    return Err(result.err().unwrap());
+                }
+                walked.push(result.unwrap());
+            }
+            Ok(LispyType::Vector {
+                collection: Box::from(walked),
+                meta: meta.clone(),
+            })
+        }
+        LispyType::Hash { collection, meta } => {
+            let mut walked = HashMap::new();
+            for (key, value) in collection.iter() {
+                let key = inner(key.clone());
+                if key.is_err() {
+                    return Err(key.err().unwrap());
+                }
+                let value = inner(value.clone());
+                if value.is_err() {
+                    return Err(value.err().unwrap());
+                }
+                let key = key.unwrap();
+                if !key.is_hashable() {
+                    return Err(LispyType::create_error(
+                        format!("Walked hash key {} can't be used as a hash key", key.to_readable_string())
+                            .as_str(),
+                        "INCORRECT_TYPE",
+                    ));
+                }
+                walked.insert(key, value.unwrap());
+            }
+            Ok(LispyType::Hash {
+                collection: Box::from(walked),
+                meta: meta.clone(),
+            })
+        }
+        _ => Ok(form.clone()),
+    }
+}
+
+fn prewalk(func: &LispyType, form: LispyType) -> Result<LispyType, LispyType> {
+    let form = apply_callable(func, vec![form]);
+    if form.is_err() {
+        return Err(form.err().unwrap());
+    }
+    walk_children(form.as_ref().unwrap(), &mut |child| prewalk(func, child))
+}
+
+fn postwalk(func: &LispyType, form: LispyType) -> Result<LispyType, LispyType> {
+    let walked = walk_children(&form, &mut |child| postwalk(func, child));
+    if walked.is_err() {
+        return Err(walked.err().unwrap());
+    }
+    apply_callable(func, vec![walked.unwrap()])
+}
+
+struct PipelineStep {
+    kind: String,
+    arg: LispyType,
+    seen: usize,
+}
+
+// Pushes every element of the collection through all steps before moving on to
+// the next one, so no intermediate lists are built and `taking` stops early.
+fn run_pipeline(args: Vec<LispyType>) -> Result<LispyType, LispyType> {
+    if args.is_empty() || !args[0].is_list() {
+        return Err(LispyType::create_error(
+            "pipeline first arg must be a list",
+            "INCORRECT_TYPE",
+        ));
+    }
+
+    let mut steps = vec![];
+    for step in args[1..].iter() {
+        let parts = step.as_list();
+        if parts.is_none() || parts.unwrap().len() != 2 || !parts.unwrap()[0].is_keyword() {
+            return Err(LispyType::create_error(
+                format!("{} is not a pipeline step", step).as_str(),
+                "INCORRECT_TYPE",
+            ));
+        }
+        let parts = parts.unwrap();
+        let kind = parts[0].as_keyword().unwrap().clone();
+        if (kind == ":taking" || kind == ":dropping") && !parts[1].is_number() {
+            return Err(LispyType::create_error(
+                format!("{} expects a number. Received {}", kind, parts[1]).as_str(),
+                "INCORRECT_TYPE",
+            ));
+        }
+        steps.push(PipelineStep {
+            kind,
+            arg: parts[1].clone(),
+            seen: 0,
+        });
+    }
+
+    let mut result = vec![];
+    'items: for item in args[0].as_list().unwrap().iter() {
+        let mut value = item.clone();
+        let mut exhausted = false;
+
+        for step in steps.iter_mut() {
+            match step.kind.as_str() {
+                ":mapping" => {
+                    let mapped = apply_callable(&step.arg, vec![value]);
+                    if mapped.is_err() {
+                        return Err(mapped.err().unwrap());
+                    }
+                    value = mapped.unwrap();
+                }
+                ":filtering" => {
+                    let keep = apply_callable(&step.arg, vec![value.clone()]);
+                    if keep.is_err() {
+                        return Err(keep.err().unwrap());
+                    }
+                    if !keep.unwrap().is_truthy() {
+                        continue 'items;
+                    }
+                }
+                ":taking" => {
+                    let limit = *step.arg.as_number().unwrap() as usize;
+                    if step.seen >= limit {
+                        break 'items;
+                    }
+                    step.seen += 1;
+                    exhausted = exhausted || step.seen >= limit;
+                }
+                ":dropping" => {
+                    let limit = *step.arg.as_number().unwrap() as usize;
+                    if step.seen < limit {
+                        step.seen += 1;
+                        continue 'items;
+                    }
+                }
+                _ => {
+                    return Err(LispyType::create_error(
+                        format!("Unknown pipeline step {}", step.kind).as_str(),
+                        "INCORRECT_TYPE",
+                    ))
+                }
+            }
+        }
+
+        result.push(value);
+        if exhausted {
+            break;
+        }
+    }
+
+    Ok(LispyType::create_list(result))
+}
+
+fn entry_list(entry: Option<&(&LispyType, &LispyType)>) -> LispyType {
+    match entry {
+        Some((key, value)) => LispyType::create_list(vec![(*key).clone(), (*value).clone()]),
+        None => LispyType::create_nil(),
+    }
+}
+
+fn deep_merge(base: &LispyType, overlay: &LispyType) -> LispyType {
+    match (base, overlay) {
+        (LispyType::Hash { collection: base, .. }, LispyType::Hash { collection: overlay, .. }) => {
+            let mut collection = base.as_ref().clone();
+            for (key, value) in overlay.iter() {
+                let merged = match collection.get(key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => value.clone(),
+                };
+                collection.insert(key.clone(), merged);
+            }
+            LispyType::Hash {
+                collection: Box::from(collection),
+                meta: HashMap::new(),
+            }
+        }
+        _ => overlay.clone(),
+    }
+}
+
+fn sort_key(item: &LispyType, key_fn: Option<&LispyType>) -> Result<LispyType, LispyType> {
+    match key_fn {
+        Some(key_fn) => apply_callable(key_fn, vec![item.clone()]),
+        None => Ok(item.clone()),
+    }
+}
+
+// Binary search shared by bsearch and insert-sorted over (sorted x key-fn?) arguments,
+// `x_is_key` skips applying key-fn to x. Ok(index) of a match, or Err(index) where x
+// would have to be inserted.
+fn sorted_position(
+    args: &[LispyType],
+    name: &str,
+    x_is_key: bool,
+) -> Result<Result<usize, usize>, LispyType> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(LispyType::create_error(
+            format!("Expected arity 2 or 3, received {}", args.len()).as_str(),
+            "INCORRECT_ARITY",
+        ));
+    }
+    let collection = args[0].as_sequential();
+    if collection.is_none() {
+        return Err(LispyType::create_error(
+            format!("{} expects a sorted list or vector. Received: {}", name, args[0]).as_str(),
+            "INCORRECT_TYPE",
+        ));
+    }
+    let collection = collection.unwrap();
+    let key_fn = args.get(2);
+    let target = if x_is_key { Ok(args[1].clone()) } else { sort_key(&args[1], key_fn) };
+    if target.is_err() {
+        return Err(target.err().unwrap());
+    }
+    let target = target.unwrap();
+
+    let (mut low, mut high) = (0, collection.len());
+    while low < high {
+        let middle = (low + high) / 2;
+        let key = sort_key(&collection[middle], key_fn);
+        if key.is_err() {
+            return Err(key.err().unwrap());
+        }
+        match compare_keys(&key.unwrap(), &target) {
+            Ordering::Less => low = middle + 1,
+            Ordering::Greater => high = middle,
+            Ordering::Equal => return Ok(Ok(middle)),
+        }
+    }
+    Ok(Err(low))
+}
+
+// Items of anything to-list accepts (lists, vectors, hashes, strings and nil) and queues
+fn sequence_items(value: &LispyType) -> Result<Vec<LispyType>, LispyType> {
+    if let Ok(queue) = queue_arg(value) {
+        return Ok(queue.items().iter().cloned().collect());
+    }
+    value.iter_seq().map(|items| items.collect())
+}
+
+fn atom_arg(value: &LispyType) -> Result<&Rc<RefCell<LispyType>>, LispyType> {
+    match value.as_atom() {
+        Some(atom) => Ok(atom),
+        None => Err(LispyType::create_error(
+            format!("{} is not an atom", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+// The type, message and data of an error value
+fn error_arg(value: &LispyType) -> Result<(&String, &String, Option<&LispyType>), LispyType> {
+    match value {
+        LispyType::Error {
+            error_type,
+            message,
+            data,
+            ..
+        } => Ok((error_type, message, data.as_deref())),
+        _ => Err(LispyType::create_error(
+            format!("{} is not an error", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+fn hash_arg(value: &LispyType) -> Result<&HashMap<LispyType, LispyType>, LispyType> {
+    match value.as_hash() {
+        Some(collection) => Ok(collection),
+        None => Err(LispyType::create_error(
+            format!("{} is not a hash", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+// Inserts `key value ...` arguments into `collection`
+fn pairs_into(
+    mut collection: HashMap<LispyType, LispyType>,
+    pairs: &[LispyType],
+    name: &str,
+) -> Result<HashMap<LispyType, LispyType>, LispyType> {
+    if !pairs.len().is_multiple_of(2) {
+        return Err(LispyType::create_error(
+            format!("{} expects key value pairs, received {} args", name, pairs.len()).as_str(),
+            "INCORRECT_ARITY",
+        ));
+    }
+    for pair in pairs.chunks(2) {
+        if !pair[0].is_hashable() {
+            return Err(LispyType::create_error(
+                format!("{} could not be used as a hash key", pair[0]).as_str(),
+                "INCORRECT_TYPE",
+            ));
+        }
+        collection.insert(pair[0].clone(), pair[1].clone());
+    }
+    Ok(collection)
+}
+
+// Pairs every element of `collection` with the result of calling `key_fn` on it.
+fn key_items(
+    key_fn: &LispyType,
+    collection: &LispyType,
+) -> Result<Vec<(LispyType, LispyType)>, LispyType> {
+    if !collection.is_list() {
+        return Err(LispyType::create_error(
+            format!("{} is not a list", collection).as_str(),
+            "INCORRECT_TYPE",
+        ));
+    }
+
+    let mut keyed = vec![];
+    for item in collection.as_list().unwrap().iter() {
+        let key = apply_callable(key_fn, vec![item.clone()]);
+        if key.is_err() {
+            return Err(key.err().unwrap());
+        }
+        let key = key.unwrap();
+        if !key.is_hashable() {
+            return Err(LispyType::create_error(
+                format!("{} could not be used as a hash key", key).as_str(),
+                "INCORRECT_TYPE",
+            ));
+        }
+        keyed.push((key, item.clone()));
+    }
+
+    Ok(keyed)
+}
+
+fn change(op: &str, path: &[LispyType], values: Vec<(&str, LispyType)>) -> LispyType {
+    let mut collection = HashMap::new();
+    collection.insert(
+        LispyType::create_keyword(":op"),
+        LispyType::create_keyword(op),
+    );
+    collection.insert(
+        LispyType::create_keyword(":path"),
+        LispyType::create_list(path.to_vec()),
+    );
+    for (key, value) in values {
+        collection.insert(LispyType::create_keyword(key), value);
+    }
+    LispyType::Hash {
+        collection: Box::from(collection),
+        meta: HashMap::new(),
+    }
+}
+
+// Collects :added / :removed / :changed entries describing how to get from `a` to `b`.
+// Hashes are compared key by key and lists index by index, anything else as a whole.
+fn data_diff(
+    a: &LispyType,
+    b: &LispyType,
+    path: &mut Vec<LispyType>,
+    changes: &mut Vec<LispyType>,
+) {
+    match (a, b) {
+        (LispyType::Hash { collection: a, .. }, LispyType::Hash { collection: b, .. }) => {
+            let mut keys: Vec<&LispyType> = a
+                .keys()
+                .chain(b.keys().filter(|key| !a.contains_key(key)))
+                .collect();
+            keys.sort_by_key(|key| format!("{}", key));
+            for key in keys {
+                path.push(key.clone());
+                match (a.get(key), b.get(key)) {
+                    (Some(from), Some(to)) => data_diff(from, to, path, changes),
+                    (Some(from), None) => {
+                        changes.push(change(":removed", path, vec![(":value", from.clone())]))
+                    }
+                    (None, Some(to)) => {
+                        changes.push(change(":added", path, vec![(":value", to.clone())]))
+                    }
+                    (None, None) => {}
+                }
+                path.pop();
+            }
+        }
+        (LispyType::List { collection: a, .. }, LispyType::List { collection: b, .. }) => {
+            for index in 0..a.len().max(b.len()) {
+                path.push(LispyType::create_number(index as f64));
+                match (a.get(index), b.get(index)) {
+                    (Some(from), Some(to)) => data_diff(from, to, path, changes),
+                    (Some(from), None) => {
+                        changes.push(change(":removed", path, vec![(":value", from.clone())]))
+                    }
+                    (None, Some(to)) => {
+                        changes.push(change(":added", path, vec![(":value", to.clone())]))
+                    }
+                    (None, None) => {}
+                }
+                path.pop();
+            }
+        }
+        _ if a == b => {}
+        _ => changes.push(change(
+            ":changed",
+            path,
+            vec![(":from", a.clone()), (":to", b.clone())],
+        )),
+    }
+}
+
+fn describe_change(change: &LispyType) -> String {
+    let fields = change.as_hash().unwrap();
+    let field = |key: &str| fields.get(&LispyType::create_keyword(key)).cloned();
+    let path = field(":path").unwrap();
+    let path: Vec<String> = path
+        .as_list()
+        .unwrap()
+        .iter()
+        .map(|step| format!("{}", step))
+        .collect();
+    let path = format!("[{}]", path.join(" "));
+    match field(":op").unwrap().as_keyword().unwrap().as_str() {
+        ":added" => format!("{} added {}", path, field(":value").unwrap()),
+        ":removed" => format!("{} removed {}", path, field(":value").unwrap()),
+        _ => format!(
+            "{} expected {}, got {}",
+            path,
+            field(":from").unwrap(),
+            field(":to").unwrap()
+        ),
+    }
 }
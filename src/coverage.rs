@@ -0,0 +1,200 @@
+use crate::types::LispyType;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+
+// Line and column of a form, see compiler::build_any_form
+type Position = (usize, usize);
+
+// Whether each known form was evaluated, by position
+#[derive(Default)]
+struct FileCoverage {
+    forms: BTreeMap<Position, bool>,
+    branches: BTreeMap<Position, bool>,
+}
+
+impl FileCoverage {
+    fn covered(&self) -> (usize, usize) {
+        let forms = self.forms.values().chain(self.branches.values());
+        let total = self.forms.len() + self.branches.len();
+        (forms.filter(|hit| **hit).count(), total)
+    }
+}
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    static FILES: RefCell<BTreeMap<String, FileCoverage>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// `--coverage`: tracks the top-level forms and `if` branches of the files loaded from
+// now on. Forms are told apart by their source position, so code that did not come
+// from a file, or was built by a macro, is not counted.
+pub fn enable() {
+    ENABLED.with(|enabled| enabled.set(true));
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.with(|enabled| enabled.get())
+}
+
+fn position(form: &LispyType) -> Option<(String, Position)> {
+    let meta = form.meta();
+    let file = meta.get("file")?.as_string()?.clone();
+    let line = *meta.get("line")?.as_number()? as usize;
+    let col = *meta.get("col")?.as_number()? as usize;
+    Some((file, (line, col)))
+}
+
+// Walks a form for the branches of every `if` in it. Quoted forms are data and
+// quasi-quoted ones templates, neither is run as written.
+fn collect_branches(form: &LispyType, branches: &mut BTreeMap<Position, bool>) {
+    let items = match form.as_sequential() {
+        Some(items) => items,
+        None => return,
+    };
+    let head = items.first().and_then(|head| head.as_symbol());
+    if form.is_list() && matches!(head.map(String::as_str), Some("quote" | "quasi-quote")) {
+        return;
+    }
+    if form.is_list() && head.map(String::as_str) == Some("if") {
+        for branch in items.iter().skip(2) {
+            if let Some((_, at)) = position(branch) {
+                branches.insert(at, false);
+            }
+        }
+    }
+    for item in items.iter() {
+        collect_branches(item, branches);
+    }
+}
+
+pub fn register_file(file: &str, ast: &[LispyType]) {
+    if !is_enabled() {
+        return;
+    }
+    let mut coverage = FileCoverage::default();
+    for form in ast {
+        if let Some((_, at)) = position(form) {
+            coverage.forms.insert(at, false);
+        }
+        collect_branches(form, &mut coverage.branches);
+    }
+    FILES.with(|files| files.borrow_mut().insert(file.to_string(), coverage));
+}
+
+fn hit(form: &LispyType, branch: bool) {
+    let (file, at) = match position(form) {
+        Some(position) => position,
+        None => return,
+    };
+    FILES.with(|files| {
+        if let Some(coverage) = files.borrow_mut().get_mut(&file) {
+            let known = if branch { &mut coverage.branches } else { &mut coverage.forms };
+            if let Some(hit) = known.get_mut(&at) {
+                *hit = true;
+            }
+        }
+    });
+}
+
+pub fn hit_form(form: &LispyType) {
+    if is_enabled() {
+        hit(form, false);
+    }
+}
+
+pub fn hit_branch(form: &LispyType) {
+    if is_enabled() {
+        hit(form, true);
+    }
+}
+
+// Percentage of forms and branches covered per file, for the test runner summary
+pub fn summary() -> LispyType {
+    let collection = FILES.with(|files| {
+        files
+            .borrow()
+            .iter()
+            .map(|(file, coverage)| {
+                let (covered, total) = coverage.covered();
+                let percent = if total == 0 { 100.0 } else { covered as f64 * 100.0 / total as f64 };
+                (LispyType::create_string(file), LispyType::create_number(percent))
+            })
+            .collect::<HashMap<_, _>>()
+    });
+    LispyType::Hash {
+        collection: Box::from(collection),
+        meta: HashMap::new(),
+    }
+}
+
+// One block per file: the totals, then every form and branch that never ran
+pub fn report() -> String {
+    FILES.with(|files| {
+        let mut report = String::new();
+        for (file, coverage) in files.borrow().iter() {
+            let count = |known: &BTreeMap<Position, bool>| known.values().filter(|hit| **hit).count();
+            let (covered, total) = coverage.covered();
+            report.push_str(&format!(
+                "{}: {}/{} forms, {}/{} branches, {:.1}%\n",
+                file,
+                count(&coverage.forms),
+                coverage.forms.len(),
+                count(&coverage.branches),
+                coverage.branches.len(),
+                if total == 0 { 100.0 } else { covered as f64 * 100.0 / total as f64 }
+            ));
+            for ((line, col), hit) in coverage.forms.iter() {
+                if !hit {
+                    report.push_str(&format!("  form never evaluated at {}:{}\n", line, col));
+                }
+            }
+            for ((line, col), hit) in coverage.branches.iter() {
+                if !hit {
+                    report.push_str(&format!("  branch never taken at {}:{}\n", line, col));
+                }
+            }
+        }
+        report
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coverage;
+    use crate::machine::LispyMachine;
+    use std::fs;
+
+    #[test]
+    fn reports_files_loaded_by_the_program() {
+        let directory = std::env::temp_dir().join(format!("lispy-coverage-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let lib = directory.join("lib.lispy");
+        let main = directory.join("main.lispy");
+        fs::write(&lib, "(def! sign (fn* (x) (if (< x 0) -1 1)))\n(def! unused 1)\n").unwrap();
+        fs::write(&main, format!("(load-file \"{}\")\n(sign 5)\n", lib.display())).unwrap();
+
+        let mut machine = LispyMachine::new();
+        coverage::enable();
+        machine.evaluate_file(main.to_str().unwrap()).unwrap();
+        let report = coverage::report();
+        fs::remove_dir_all(&directory).unwrap();
+
+        let main_line = format!("{}: 2/2 forms, 0/0 branches, 100.0%", main.display());
+        let lib_line = format!("{}: 2/2 forms, 1/2 branches, 75.0%", lib.display());
+        assert!(report.contains(&main_line), "{}", report);
+        assert!(report.contains(&lib_line), "{}", report);
+        assert!(report.contains("branch never taken at 1:33"), "{}", report);
+    }
+
+    #[test]
+    fn ignores_files_while_disabled() {
+        let directory = std::env::temp_dir().join(format!("lispy-coverage-off-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let main = directory.join("main.lispy");
+        fs::write(&main, "(def! x 1)\n").unwrap();
+        LispyMachine::new().evaluate_file(main.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(coverage::report(), "");
+    }
+}
+
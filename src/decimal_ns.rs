@@ -0,0 +1,412 @@
+use crate::env::LispyEnv;
+use crate::types::{format_number, LispyType};
+use std::cmp::Ordering;
+
+const DECIMAL_KIND: &str = "decimal";
+const DEFAULT_DIV_SCALE: u32 = 10;
+
+// Exact base-10 number: `units / 10^scale`
+pub struct Decimal {
+    units: i128,
+    scale: u32,
+}
+
+#[derive(Clone, Copy)]
+enum Rounding {
+    Down,
+    Up,
+    Floor,
+    Ceiling,
+    HalfUp,
+    HalfDown,
+    HalfEven,
+}
+
+impl Rounding {
+    fn from_arg(value: Option<&LispyType>) -> Result<Self, LispyType> {
+        let keyword = match value {
+            None => return Ok(Rounding::HalfEven),
+            Some(value) => value.as_keyword(),
+        };
+        match keyword.map(|keyword| keyword.as_str()) {
+            Some(":down") => Ok(Rounding::Down),
+            Some(":up") => Ok(Rounding::Up),
+            Some(":floor") => Ok(Rounding::Floor),
+            Some(":ceiling") => Ok(Rounding::Ceiling),
+            Some(":half-up") => Ok(Rounding::HalfUp),
+            Some(":half-down") => Ok(Rounding::HalfDown),
+            Some(":half-even") => Ok(Rounding::HalfEven),
+            _ => Err(LispyType::create_error(
+                format!("{} is not a rounding mode", value.unwrap()).as_str(),
+                "INCORRECT_TYPE",
+            )),
+        }
+    }
+}
+
+fn overflow_error() -> LispyType {
+    LispyType::create_error("Decimal value is out of range", "DECIMAL_OVERFLOW")
+}
+
+fn pow10(exponent: u32) -> Result<i128, LispyType> {
+    10i128.checked_pow(exponent).ok_or_else(overflow_error)
+}
+
+// Integer division of `numerator / denominator` rounded according to `mode`
+fn round_div(numerator: i128, denominator: i128, mode: Rounding) -> i128 {
+    let (numerator, denominator) = if denominator < 0 {
+        (-numerator, -denominator)
+    } else {
+        (numerator, denominator)
+    };
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    if remainder == 0 {
+        return quotient;
+    }
+
+    let sign = numerator.signum();
+    let away = quotient + sign;
+    let half = (remainder.abs() * 2).cmp(&denominator);
+    match mode {
+        Rounding::Down => quotient,
+        Rounding::Up => away,
+        Rounding::Floor if sign < 0 => away,
+        Rounding::Floor => quotient,
+        Rounding::Ceiling if sign > 0 => away,
+        Rounding::Ceiling => quotient,
+        Rounding::HalfUp if half != Ordering::Less => away,
+        Rounding::HalfDown if half == Ordering::Greater => away,
+        Rounding::HalfEven if half == Ordering::Greater => away,
+        Rounding::HalfEven if half == Ordering::Equal && quotient % 2 != 0 => away,
+        _ => quotient,
+    }
+}
+
+impl Decimal {
+    fn parse(source: &str) -> Option<Self> {
+        let source = source.trim();
+        let (negative, digits) = match source.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, source.strip_prefix('+').unwrap_or(source)),
+        };
+        let (whole, fraction) = match digits.split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (digits, ""),
+        };
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        if !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let mut units: i128 = 0;
+        for digit in whole.chars().chain(fraction.chars()) {
+            units = units
+                .checked_mul(10)?
+                .checked_add(digit.to_digit(10).unwrap() as i128)?;
+        }
+        Some(Self {
+            units: if negative { -units } else { units },
+            scale: fraction.len() as u32,
+        })
+    }
+
+    fn rescale(&self, scale: u32, mode: Rounding) -> Result<Self, LispyType> {
+        let units = if scale >= self.scale {
+            let factor = pow10(scale - self.scale);
+            if factor.is_err() {
+                return Err(factor.err().unwrap());
+            }
+            self.units.checked_mul(factor.unwrap()).ok_or_else(overflow_error)
+        } else {
+            pow10(self.scale - scale).map(|factor| round_div(self.units, factor, mode))
+        };
+        units.map(|units| Self { units, scale })
+    }
+
+    // Drops trailing fractional zeros, but never below `min_scale`
+    fn trim(mut self, min_scale: u32) -> Self {
+        while self.scale > min_scale && self.units % 10 == 0 {
+            self.units /= 10;
+            self.scale -= 1;
+        }
+        self
+    }
+
+    fn aligned(&self, other: &Decimal) -> Result<(i128, i128, u32), LispyType> {
+        let scale = self.scale.max(other.scale);
+        let a = self.rescale(scale, Rounding::Down);
+        if a.is_err() {
+            return Err(a.err().unwrap());
+        }
+        let b = other.rescale(scale, Rounding::Down);
+        if b.is_err() {
+            return Err(b.err().unwrap());
+        }
+        Ok((a.unwrap().units, b.unwrap().units, scale))
+    }
+
+    fn add(&self, other: &Decimal) -> Result<Self, LispyType> {
+        self.aligned(other).and_then(|(a, b, scale)| {
+            a.checked_add(b)
+                .map(|units| Self { units, scale })
+                .ok_or_else(overflow_error)
+        })
+    }
+
+    fn sub(&self, other: &Decimal) -> Result<Self, LispyType> {
+        self.aligned(other).and_then(|(a, b, scale)| {
+            a.checked_sub(b)
+                .map(|units| Self { units, scale })
+                .ok_or_else(overflow_error)
+        })
+    }
+
+    fn mul(&self, other: &Decimal) -> Result<Self, LispyType> {
+        self.units
+            .checked_mul(other.units)
+            .map(|units| Self {
+                units,
+                scale: self.scale + other.scale,
+            })
+            .ok_or_else(overflow_error)
+    }
+
+    fn div(&self, other: &Decimal, scale: u32, mode: Rounding) -> Result<Self, LispyType> {
+        if other.units == 0 {
+            return Err(LispyType::create_error(
+                "Division by zero",
+                "DIVISION_BY_ZERO",
+            ));
+        }
+        // (a / 10^sa) / (b / 10^sb) = a * 10^(sb + scale) / (b * 10^sa) in units of 10^-scale
+        let numerator = pow10(other.scale + scale)
+            .and_then(|factor| self.units.checked_mul(factor).ok_or_else(overflow_error));
+        if numerator.is_err() {
+            return Err(numerator.err().unwrap());
+        }
+        let denominator = pow10(self.scale)
+            .and_then(|factor| other.units.checked_mul(factor).ok_or_else(overflow_error));
+        if denominator.is_err() {
+            return Err(denominator.err().unwrap());
+        }
+        Ok(Self {
+            units: round_div(numerator.unwrap(), denominator.unwrap(), mode),
+            scale,
+        })
+    }
+
+    fn to_number(&self) -> f64 {
+        format!("{}", self).parse().unwrap()
+    }
+}
+
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = self.units.unsigned_abs().to_string();
+        let sign = if self.units < 0 { "-" } else { "" };
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        write!(f, "{}{}.{}", sign, whole, fraction)
+    }
+}
+
+fn decimal_arg(value: &LispyType) -> Result<&Decimal, LispyType> {
+    match value.as_resource::<Decimal>() {
+        Some(decimal) => Ok(decimal),
+        None => Err(LispyType::create_error(
+            format!("{} is not a decimal", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+fn scale_arg(value: &LispyType) -> Result<u32, LispyType> {
+    match value.as_number() {
+        Some(number) if *number >= 0.0 && number.fract() == 0.0 => Ok(*number as u32),
+        _ => Err(LispyType::create_error(
+            format!("{} is not a valid scale", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+fn wrap(decimal: Decimal) -> LispyType {
+    LispyType::create_resource(DECIMAL_KIND, decimal)
+}
+
+fn binary(
+    args: &[LispyType],
+    op: fn(&Decimal, &Decimal) -> Result<Decimal, LispyType>,
+) -> Result<LispyType, LispyType> {
+    let a = decimal_arg(&args[0]);
+    if a.is_err() {
+        return Err(a.err().unwrap());
+    }
+    let b = decimal_arg(&args[1]);
+    if b.is_err() {
+        return Err(b.err().unwrap());
+    }
+    op(a.unwrap(), b.unwrap()).map(wrap)
+}
+
+pub fn apply_decimal_ns(env: &mut LispyEnv) {
+    env.set(
+        "decimal",
+        LispyType::create_function(Some(1), |args| {
+            let source = match &args[0] {
+                LispyType::String { value, .. } => value.clone(),
+                LispyType::Number { value, .. } if value.is_finite() => format_number(*value),
+                _ if args[0].is_resource_of(DECIMAL_KIND) => return Ok(args[0].clone()),
+                _ => {
+                    return Err(LispyType::create_error(
+                        format!("Cannot create a decimal from {}", args[0]).as_str(),
+                        "INCORRECT_TYPE",
+                    ))
+                }
+            };
+            match Decimal::parse(&source) {
+                Some(decimal) => Ok(wrap(decimal)),
+                None => Err(LispyType::create_error(
+                    format!("\"{}\" is not a valid decimal", source).as_str(),
+                    "INCORRECT_TYPE",
+                )),
+            }
+        }),
+    );
+    env.set(
+        "decimal?",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_bool(args[0].is_resource_of(DECIMAL_KIND)))
+        }),
+    );
+    env.set(
+        "decimal/add",
+        LispyType::create_function(Some(2), |args| binary(&args, Decimal::add)),
+    );
+    env.set(
+        "decimal/sub",
+        LispyType::create_function(Some(2), |args| binary(&args, Decimal::sub)),
+    );
+    env.set(
+        "decimal/mul",
+        LispyType::create_function(Some(2), |args| binary(&args, Decimal::mul)),
+    );
+    // (decimal/div a b) or (decimal/div a b scale rounding-mode)
+    env.set(
+        "decimal/div",
+        LispyType::create_function(None, |args| {
+            if args.len() < 2 || args.len() > 4 {
+                return Err(LispyType::create_error(
+                    "decimal/div expects 2 to 4 arguments",
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let a = decimal_arg(&args[0]);
+            if a.is_err() {
+                return Err(a.err().unwrap());
+            }
+            let b = decimal_arg(&args[1]);
+            if b.is_err() {
+                return Err(b.err().unwrap());
+            }
+            let (a, b) = (a.unwrap(), b.unwrap());
+            let mode = Rounding::from_arg(args.get(3));
+            if mode.is_err() {
+                return Err(mode.err().unwrap());
+            }
+            match args.get(2) {
+                Some(scale) => scale_arg(scale)
+                    .and_then(|scale| a.div(b, scale, mode.unwrap()))
+                    .map(wrap),
+                None => a
+                    .div(b, DEFAULT_DIV_SCALE, mode.unwrap())
+                    .map(|result| wrap(result.trim(a.scale.max(b.scale)))),
+            }
+        }),
+    );
+    // (decimal/round d scale) or (decimal/round d scale rounding-mode), half-even by default
+    env.set(
+        "decimal/round",
+        LispyType::create_function(None, |args| {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(LispyType::create_error(
+                    "decimal/round expects 2 or 3 arguments",
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let decimal = decimal_arg(&args[0]);
+            if decimal.is_err() {
+                return Err(decimal.err().unwrap());
+            }
+            let scale = scale_arg(&args[1]);
+            if scale.is_err() {
+                return Err(scale.err().unwrap());
+            }
+            let mode = Rounding::from_arg(args.get(2));
+            if mode.is_err() {
+                return Err(mode.err().unwrap());
+            }
+            decimal
+                .unwrap()
+                .rescale(scale.unwrap(), mode.unwrap())
+                .map(wrap)
+        }),
+    );
+    env.set(
+        "decimal/compare",
+        LispyType::create_function(Some(2), |args| {
+            let a = decimal_arg(&args[0]);
+            if a.is_err() {
+                return Err(a.err().unwrap());
+            }
+            let b = decimal_arg(&args[1]);
+            if b.is_err() {
+                return Err(b.err().unwrap());
+            }
+            a.unwrap().aligned(b.unwrap()).map(|(a, b, _)| {
+                LispyType::create_number(match a.cmp(&b) {
+                    Ordering::Less => -1.0,
+                    Ordering::Equal => 0.0,
+                    Ordering::Greater => 1.0,
+                })
+            })
+        }),
+    );
+    // (decimal/format d) prints the exact value, (decimal/format d scale) pads or
+    // rounds (half-even) to a fixed number of fractional digits
+    env.set(
+        "decimal/format",
+        LispyType::create_function(None, |args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(LispyType::create_error(
+                    "decimal/format expects 1 or 2 arguments",
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let decimal = decimal_arg(&args[0]);
+            if decimal.is_err() {
+                return Err(decimal.err().unwrap());
+            }
+            let decimal = decimal.unwrap();
+            match args.get(1) {
+                None => Ok(LispyType::create_string(format!("{}", decimal).as_str())),
+                Some(scale) => scale_arg(scale)
+                    .and_then(|scale| decimal.rescale(scale, Rounding::HalfEven))
+                    .map(|rounded| LispyType::create_string(format!("{}", rounded).as_str())),
+            }
+        }),
+    );
+    env.set(
+        "decimal/to-number",
+        LispyType::create_function(Some(1), |args| {
+            decimal_arg(&args[0]).map(|decimal| LispyType::create_number(decimal.to_number()))
+        }),
+    );
+}
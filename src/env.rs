@@ -1,54 +1,438 @@
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Formatter};
+use std::rc::{Rc, Weak};
+#[cfg(feature = "archive")]
+use crate::archive_ns::apply_archive_ns;
 use crate::core_ns::apply_core_ns;
+#[cfg(feature = "decimal")]
+use crate::decimal_ns::apply_decimal_ns;
+use crate::fs_ns::apply_fs_ns;
+#[cfg(feature = "image")]
+use crate::image_ns::apply_image_ns;
+use crate::json_ns::apply_json_ns;
+#[cfg(feature = "matrix")]
+use crate::matrix_ns::apply_matrix_ns;
+#[cfg(feature = "msgpack")]
+use crate::serde_ns::apply_msgpack_ns;
+#[cfg(feature = "serde")]
+use crate::serde_ns::apply_serde_ns;
+use crate::spec_ns::apply_spec_ns;
+use crate::string_ns::apply_string_ns;
 use crate::types::LispyType;
 
-#[derive(Clone, Debug)]
-pub struct LispyEnv {
+// Every env ever created on this thread, so `collect_cycles` can find the ones
+// that only keep each other alive
+thread_local! {
+    static ENVS: RefCell<Vec<Weak<RefCell<EnvInner>>>> = const { RefCell::new(Vec::new()) };
+}
+
+const ENV_PRUNE_INTERVAL: usize = 4096;
+
+// Bumped whenever a root env is created or one of its bindings changes, which
+// invalidates every cached global lookup at once
+thread_local! {
+    static ROOT_GENERATION: Cell<u64> = const { Cell::new(0) };
+}
+
+fn bump_root_generation() {
+    ROOT_GENERATION.with(|generation| generation.set(generation.get() + 1));
+}
+
+// A global binding remembered by the symbol node that looked it up
+#[derive(Debug, Clone)]
+pub struct CachedBinding {
+    generation: u64,
+    root: usize,
+    value: LispyType,
+}
+
+pub type SymbolCache = Rc<RefCell<Option<CachedBinding>>>;
+
+struct EnvInner {
     store: HashMap<String, LispyType>,
-    parent: Option<Box<LispyEnv>>,
+    parent: Option<LispyEnv>,
+    protected: HashSet<String>,
+    constants: HashSet<String>,
+    // Special forms this root refuses, see LispyMachineBuilder::without_file_io
+    denied: HashSet<String>,
+}
+
+// A scope shared by reference: clones point at the same bindings, so definitions
+// made through any handle (closures, nested evals) are visible to all of them
+#[derive(Clone)]
+pub struct LispyEnv {
+    inner: Rc<RefCell<EnvInner>>,
+}
+
+// Closures stored in an env usually point back at it, so the contents are not printed
+impl Debug for LispyEnv {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "LispyEnv({} bindings)", self.inner.borrow().store.len())
+    }
 }
 
 impl LispyEnv {
-    pub fn root() -> Self {
-        let mut this = Self {
+    fn new(parent: Option<LispyEnv>) -> Self {
+        let parent_is_none = parent.is_none();
+        let inner = Rc::new(RefCell::new(EnvInner {
             store: HashMap::new(),
-            parent: None,
-        };
+            parent,
+            protected: HashSet::new(),
+            constants: HashSet::new(),
+            denied: HashSet::new(),
+        }));
+        ENVS.with(|envs| {
+            let mut envs = envs.borrow_mut();
+            envs.push(Rc::downgrade(&inner));
+            if envs.len() % ENV_PRUNE_INTERVAL == 0 {
+                envs.retain(|env| env.strong_count() > 0);
+            }
+        });
+        if parent_is_none {
+            bump_root_generation();
+        }
+        Self { inner }
+    }
+
+    pub fn root() -> Self {
+        let mut this = Self::new(None);
         apply_core_ns(&mut this);
+        apply_spec_ns(&mut this);
+        apply_json_ns(&mut this);
+        apply_fs_ns(&mut this);
+        #[cfg(feature = "matrix")]
+        apply_matrix_ns(&mut this);
+        #[cfg(feature = "decimal")]
+        apply_decimal_ns(&mut this);
+        #[cfg(feature = "serde")]
+        apply_serde_ns(&mut this);
+        #[cfg(feature = "msgpack")]
+        apply_msgpack_ns(&mut this);
+        #[cfg(feature = "image")]
+        apply_image_ns(&mut this);
+        #[cfg(feature = "archive")]
+        apply_archive_ns(&mut this);
+        apply_string_ns(&mut this);
         this
     }
 
+    // A root without any builtins, for sandboxes that bind only what they allow
+    pub fn empty() -> Self {
+        Self::new(None)
+    }
+
     pub fn child(parent: &mut LispyEnv) -> Self {
-        Self {
-            store: HashMap::new(),
-            parent: Some(Box::from(parent.clone())),
+        Self::new(Some(parent.clone()))
+    }
+
+    pub fn child_lambda(parent: &LispyEnv) -> Self {
+        Self::new(Some(parent.clone()))
+    }
+
+    // The outermost env this one descends from
+    pub fn global(&self) -> LispyEnv {
+        match &self.inner.borrow().parent {
+            Some(parent) => parent.global(),
+            None => self.clone(),
         }
     }
 
-    pub fn child_lambda(parent: Box<LispyEnv>) -> Self {
-        Self {
-            store: HashMap::new(),
-            parent: Some(Box::from(parent.clone())),
+    // Whether both handles point at the same scope
+    pub fn ptr_eq(&self, other: &LispyEnv) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    // Detached copy of the own bindings, used to compare before/after states
+    pub fn snapshot(&self) -> LispyEnv {
+        let inner = self.inner.borrow();
+        let copy = Self::new(inner.parent.clone());
+        copy.inner.borrow_mut().store = inner.store.clone();
+        copy.inner.borrow_mut().protected = inner.protected.clone();
+        copy.inner.borrow_mut().constants = inner.constants.clone();
+        copy.inner.borrow_mut().denied = inner.denied.clone();
+        copy
+    }
+
+    pub fn get_item(&self, key: &String) -> Option<LispyType> {
+        let inner = self.inner.borrow();
+        let result = inner.store.get(key);
+
+        if result.is_some() { return result.cloned(); }
+        inner.parent.as_ref()?.get_item(key)
+    }
+
+    // Runs `f` on the binding of `key` without cloning it, e.g. to read one entry of a large hash
+    pub fn with_item<T>(&self, key: &str, f: impl FnOnce(&LispyType) -> T) -> Option<T> {
+        let inner = self.inner.borrow();
+        match inner.store.get(key) {
+            Some(value) => Some(f(value)),
+            None => inner.parent.as_ref()?.with_item(key, f),
         }
     }
 
-    pub fn get_item(&self, key: &String) -> Option<&LispyType> {
-        let result = self.store.get(key);
+    // Same as get_item, but a binding found in the root env is remembered in
+    // `cache` so the next lookup through the same symbol node skips the root
+    // hash lookup. Local scopes are still searched first, so shadowing works.
+    pub fn get_item_cached(&self, key: &String, cache: &SymbolCache) -> Option<LispyType> {
+        let inner = self.inner.borrow();
+        if let Some(parent) = &inner.parent {
+            let result = inner.store.get(key);
+            if result.is_some() { return result.cloned(); }
+            return parent.get_item_cached(key, cache);
+        }
+
+        let generation = ROOT_GENERATION.with(|generation| generation.get());
+        let root = Rc::as_ptr(&self.inner) as usize;
+        if let Some(cached) = cache.borrow().as_ref() {
+            if cached.generation == generation && cached.root == root {
+                return Some(cached.value.clone());
+            }
+        }
+
+        let result = inner.store.get(key).cloned();
+        if result.as_ref().is_some_and(|value| value.is_function()) {
+            *cache.borrow_mut() = Some(CachedBinding {
+                generation,
+                root,
+                value: result.clone().unwrap(),
+            });
+        }
+        result
+    }
 
-        if result.is_some() { return result; }
-        if self.parent.is_some() { return self.parent.as_ref().unwrap().get_item(key); }
-        None
+    fn touch(&self) {
+        if self.inner.borrow().parent.is_none() {
+            bump_root_generation();
+        }
     }
 
     pub fn set_item(&mut self, key: String, value: LispyType) {
-        self.store.insert(key, value);
+        self.touch();
+        self.inner.borrow_mut().store.insert(key, value);
     }
 
     pub fn set(&mut self, key: &str, value: LispyType) {
-        self.store.insert(key.to_string(), value);
+        self.touch();
+        self.inner.borrow_mut().store.insert(key.to_string(), value);
+    }
+
+    // Names bound directly in this env, parents are not included
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.inner.borrow().store.keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<LispyType> {
+        self.touch();
+        self.inner.borrow_mut().store.remove(key)
+    }
+
+    // Makes every own binding also reachable as `prefix/name`, so it stays
+    // accessible after user code shadows the plain name
+    pub fn alias_namespace(&mut self, prefix: &str) {
+        self.touch();
+        let mut inner = self.inner.borrow_mut();
+        let aliases: Vec<(String, LispyType)> = inner
+            .store
+            .iter()
+            .filter(|(key, _)| !key.contains('/'))
+            .map(|(key, value)| (format!("{}/{}", prefix, key), value.clone()))
+            .collect();
+        inner.store.extend(aliases);
+    }
+
+    // Freezes every current binding of a root env, def! and friends then refuse
+    // to overwrite them and only redefine! can
+    pub fn protect_all(&mut self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.protected = inner.store.keys().cloned().collect();
+    }
+
+    pub fn protect(&mut self, key: &str) {
+        self.inner.borrow_mut().protected.insert(key.to_string());
+    }
+
+    pub fn is_protected(&self, key: &str) -> bool {
+        let inner = self.inner.borrow();
+        inner.parent.is_none() && inner.protected.contains(key)
     }
 
-    pub fn modify_with(&mut self, from: &LispyEnv) {
-        self.store = from.store.clone();
+    pub fn deny_form(&mut self, name: &str) {
+        self.inner.borrow_mut().denied.insert(name.to_string());
     }
-}
\ No newline at end of file
+
+    // Whether the root this env descends from refuses the special form `name`
+    pub fn is_denied(&self, name: &str) -> bool {
+        let inner = self.inner.borrow();
+        match &inner.parent {
+            Some(parent) => parent.is_denied(name),
+            None => inner.denied.contains(name),
+        }
+    }
+
+    // Constants can't be rebound in the scope they were defined in, not even with
+    // redefine!, but inner scopes may still shadow them
+    pub fn mark_constant(&mut self, key: &str) {
+        self.inner.borrow_mut().constants.insert(key.to_string());
+    }
+
+    pub fn is_constant(&self, key: &str) -> bool {
+        self.inner.borrow().constants.contains(key)
+    }
+
+    // Names whose own bindings differ between the two envs. Functions can't be
+    // compared, so rebinding one function to another is not reported.
+    pub fn changed_keys(&self, other: &LispyEnv) -> Vec<String> {
+        let (this, other) = (self.inner.borrow(), other.inner.borrow());
+        let mut changed: Vec<String> = this
+            .store
+            .keys()
+            .chain(other.store.keys())
+            .filter(|key| match (this.store.get(*key), other.store.get(*key)) {
+                (Some(a), Some(b)) => !(a.is_function() && b.is_function()) && a != b,
+                _ => true,
+            })
+            .cloned()
+            .collect();
+        changed.sort();
+        changed.dedup();
+        changed
+    }
+}
+
+// Envs referenced from inside `value`, through the lambdas it holds
+fn referenced_envs(value: &LispyType, found: &mut Vec<Rc<RefCell<EnvInner>>>) {
+    match value {
+        LispyType::Lambda { env, .. } => found.push(env.inner.clone()),
+        LispyType::List { collection, .. } | LispyType::Vector { collection, .. } => {
+            collection.iter().for_each(|item| referenced_envs(item, found))
+        }
+        LispyType::Hash { collection, .. } => collection.iter().for_each(|(key, item)| {
+            referenced_envs(key, found);
+            referenced_envs(item, found);
+        }),
+        _ => {}
+    }
+}
+
+fn env_references(env: &Rc<RefCell<EnvInner>>) -> Vec<Rc<RefCell<EnvInner>>> {
+    let inner = env.borrow();
+    let mut found = vec![];
+    if let Some(parent) = &inner.parent {
+        found.push(parent.inner.clone());
+    }
+    inner.store.values().for_each(|value| referenced_envs(value, &mut found));
+    found
+}
+
+// Frees envs that are only kept alive by reference cycles between envs, e.g. a
+// lambda stored in the very env it closes over. Envs referenced from anywhere
+// else (the machine, the Rust stack, opaque resources) are treated as roots, so
+// a missed reference can only keep garbage alive, never free a live env.
+// Returns the number of envs that were cleared.
+pub fn collect_cycles() -> usize {
+    let envs: Vec<Rc<RefCell<EnvInner>>> = ENVS.with(|envs| {
+        let mut envs = envs.borrow_mut();
+        envs.retain(|env| env.strong_count() > 0);
+        envs.iter().filter_map(|env| env.upgrade()).collect()
+    });
+    let indexes: HashMap<*const RefCell<EnvInner>, usize> = envs
+        .iter()
+        .enumerate()
+        .map(|(index, env)| (Rc::as_ptr(env), index))
+        .collect();
+
+    let edges: Vec<Vec<usize>> = envs
+        .iter()
+        .map(|env| {
+            env_references(env)
+                .iter()
+                .filter_map(|target| indexes.get(&Rc::as_ptr(target)).copied())
+                .collect()
+        })
+        .collect();
+
+    let mut internal = vec![0; envs.len()];
+    edges.iter().flatten().for_each(|target| internal[*target] += 1);
+
+    // Our own `envs` vector holds one strong reference to each of them
+    let mut reachable: Vec<bool> = envs
+        .iter()
+        .enumerate()
+        .map(|(index, env)| Rc::strong_count(env) - 1 > internal[index])
+        .collect();
+    let mut pending: Vec<usize> = (0..envs.len()).filter(|index| reachable[*index]).collect();
+    while let Some(index) = pending.pop() {
+        for target in edges[index].iter() {
+            if !reachable[*target] {
+                reachable[*target] = true;
+                pending.push(*target);
+            }
+        }
+    }
+
+    let mut collected = 0;
+    bump_root_generation();
+    for (index, env) in envs.iter().enumerate() {
+        if !reachable[index] {
+            // Dropped only after the borrow ends, values may hold other envs
+            let (store, parent) = {
+                let mut inner = env.borrow_mut();
+                (std::mem::take(&mut inner.store), inner.parent.take())
+            };
+            drop((store, parent));
+            collected += 1;
+        }
+    }
+    collected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile_source_code_to_ast;
+    use crate::machine::eval;
+
+    fn run(source: &str, env: &mut LispyEnv) -> LispyType {
+        let mut result = LispyType::create_nil();
+        for form in compile_source_code_to_ast(source).unwrap().iter() {
+            result = eval(form, env).unwrap();
+        }
+        result
+    }
+
+    fn closure_env(value: &LispyType) -> Weak<RefCell<EnvInner>> {
+        match value {
+            LispyType::Lambda { env, .. } => Rc::downgrade(&env.inner),
+            other => panic!("expected a closure, got {}", other),
+        }
+    }
+
+    const MAKE_CYCLE: &str = "(def! make (fn* () (let* (f (fn* () f)) f)))";
+
+    #[test]
+    fn collects_envs_only_kept_alive_by_their_own_closures() {
+        let mut root = LispyEnv::root();
+        run(MAKE_CYCLE, &mut root);
+        collect_cycles();
+
+        let env = closure_env(&run("(make)", &mut root));
+        assert!(env.upgrade().is_some(), "the let* env leaks through its closure");
+        assert!(collect_cycles() >= 1);
+        assert!(env.upgrade().is_none());
+        assert_eq!(collect_cycles(), 0);
+    }
+
+    #[test]
+    fn keeps_cycles_reachable_from_a_live_env() {
+        let mut root = LispyEnv::root();
+        run(MAKE_CYCLE, &mut root);
+        let env = closure_env(&run("(def! kept (make))", &mut root));
+
+        collect_cycles();
+        assert!(env.upgrade().is_some());
+        assert!(run("(kept)", &mut root).is_function());
+    }
+}
@@ -0,0 +1,363 @@
+use crate::cancel;
+use crate::env::LispyEnv;
+use crate::fs_watch;
+use crate::lispy_fn;
+use crate::machine::apply_callable;
+use crate::types::LispyType;
+use std::collections::HashMap;
+use std::fs::{self, File, Permissions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// How long fs/with-lock waits for a held lock by default, and between attempts
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+// How often fs/run-watchers rescans by default
+const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+// Tells apart the temp paths one process creates within the same nanosecond
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Dropped along with slurp when a machine is built without file io
+pub const FS_BUILTINS: [&str; 14] = [
+    "fs/stat",
+    "fs/set-permissions",
+    "fs/copy",
+    "fs/rename",
+    "fs/touch",
+    "fs/remove",
+    "fs/temp-file",
+    "fs/temp-dir",
+    "fs/write-atomic",
+    "fs/with-lock",
+    "fs/watch",
+    "fs/unwatch",
+    "fs/poll",
+    "fs/run-watchers",
+];
+
+fn fs_error(path: &str, error: io::Error) -> LispyType {
+    LispyType::create_error(format!("{}: {}", path, error).as_str(), "SYSTEM_ERROR")
+}
+
+fn millis_since_epoch(time: io::Result<SystemTime>) -> LispyType {
+    time.ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|elapsed| LispyType::create_number(elapsed.as_millis() as f64))
+        .unwrap_or_else(LispyType::create_nil)
+}
+
+#[cfg(unix)]
+fn mode_of(permissions: &Permissions) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(permissions.mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn mode_of(_: &Permissions) -> Option<u32> {
+    None
+}
+
+// (fs/stat "notes.txt") => {:kind :file :size 120 :modified 1700000000000 :readonly false :mode 420}
+// Symlinks are reported as :symlink rather than followed. :modified is in ms since
+// the epoch, :mode only exists on unix.
+#[lispy_fn(name = "fs/stat")]
+fn fs_stat(path: String) -> Result<LispyType, LispyType> {
+    let metadata = fs::symlink_metadata(&path);
+    if metadata.is_err() {
+        return Err(fs_error(&path, metadata.err().unwrap()));
+    }
+    let metadata = metadata.unwrap();
+    let kind = if metadata.file_type().is_symlink() {
+        ":symlink"
+    } else if metadata.is_dir() {
+        ":dir"
+    } else {
+        ":file"
+    };
+
+    let mut collection = HashMap::new();
+    collection.insert(LispyType::create_keyword(":kind"), LispyType::create_keyword(kind));
+    collection.insert(
+        LispyType::create_keyword(":size"),
+        LispyType::create_number(metadata.len() as f64),
+    );
+    collection.insert(
+        LispyType::create_keyword(":modified"),
+        millis_since_epoch(metadata.modified()),
+    );
+    collection.insert(
+        LispyType::create_keyword(":readonly"),
+        LispyType::create_bool(metadata.permissions().readonly()),
+    );
+    if let Some(mode) = mode_of(&metadata.permissions()) {
+        collection.insert(LispyType::create_keyword(":mode"), LispyType::create_number(mode as f64));
+    }
+    Ok(LispyType::Hash {
+        collection: Box::from(collection),
+        meta: HashMap::new(),
+    })
+}
+
+// (fs/set-permissions "run.sh" 0o755). Outside of unix only the write bits
+// count: without any the file becomes read only.
+#[lispy_fn(name = "fs/set-permissions")]
+fn fs_set_permissions(path: String, mode: i64) -> Result<(), LispyType> {
+    let metadata = fs::metadata(&path);
+    if metadata.is_err() {
+        return Err(fs_error(&path, metadata.err().unwrap()));
+    }
+    let mut permissions = metadata.unwrap().permissions();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(mode as u32);
+    }
+    #[cfg(not(unix))]
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(&path, permissions).map_err(|error| fs_error(&path, error))
+}
+
+// (fs/copy from to), the number of bytes copied. Overwrites `to`.
+#[lispy_fn(name = "fs/copy")]
+fn fs_copy(from: String, to: String) -> Result<i64, LispyType> {
+    fs::copy(&from, &to)
+        .map(|bytes| bytes as i64)
+        .map_err(|error| fs_error(&from, error))
+}
+
+#[lispy_fn(name = "fs/rename")]
+fn fs_rename(from: String, to: String) -> Result<(), LispyType> {
+    fs::rename(&from, &to).map_err(|error| fs_error(&from, error))
+}
+
+// Creates an empty file, or moves the modification time of an existing one to now
+#[lispy_fn(name = "fs/touch")]
+fn fs_touch(path: String) -> Result<(), LispyType> {
+    File::options()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|file| file.set_modified(SystemTime::now()))
+        .map_err(|error| fs_error(&path, error))
+}
+
+// A file or a whole directory tree
+#[lispy_fn(name = "fs/remove")]
+fn fs_remove(path: String) -> Result<(), LispyType> {
+    let removed = match fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&path),
+        Ok(_) => fs::remove_file(&path),
+        Err(error) => Err(error),
+    };
+    removed.map_err(|error| fs_error(&path, error))
+}
+
+// Creates a fresh path in the system temp directory with `create`, retrying on
+// the rare name collision
+fn create_temp(create: impl Fn(&PathBuf) -> io::Result<()>) -> Result<String, LispyType> {
+    let directory = std::env::temp_dir();
+    loop {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or(0);
+        let name = format!(
+            "lispy-{}-{}-{}",
+            process::id(),
+            nanos,
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = directory.join(name);
+        match create(&path) {
+            Ok(()) => return Ok(path.to_string_lossy().to_string()),
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(fs_error(&directory.to_string_lossy(), error)),
+        }
+    }
+}
+
+// (fs/temp-file), the path of a new empty file. with-temp-file removes it again.
+#[lispy_fn(name = "fs/temp-file")]
+fn fs_temp_file() -> Result<String, LispyType> {
+    create_temp(|path| File::options().write(true).create_new(true).open(path).map(|_| ()))
+}
+
+// (fs/temp-dir), the path of a new empty directory. with-temp-dir removes it again.
+#[lispy_fn(name = "fs/temp-dir")]
+fn fs_temp_dir() -> Result<String, LispyType> {
+    create_temp(|path| fs::create_dir(path))
+}
+
+// Writes a sibling temp file and renames it over `path`, so readers see either
+// the old contents or the new ones and never a half written file
+#[lispy_fn(name = "fs/write-atomic")]
+fn fs_write_atomic(path: String, contents: String) -> Result<(), LispyType> {
+    let target = Path::new(&path);
+    let name = target.file_name().map(|name| name.to_string_lossy().to_string());
+    if name.is_none() {
+        return Err(LispyType::create_error(
+            format!("{} is not a file path", path).as_str(),
+            "INCORRECT_TYPE",
+        ));
+    }
+    let temp = target.with_file_name(format!(
+        ".{}.tmp-{}-{}",
+        name.unwrap(),
+        process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let written = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, target));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+        return Err(fs_error(&path, written.err().unwrap()));
+    }
+    Ok(())
+}
+
+// Creates the lock file, waiting while another process holds it
+fn acquire_lock(path: &str, timeout: Duration) -> Result<(), LispyType> {
+    let started = Instant::now();
+    loop {
+        match File::options().write(true).create_new(true).open(path) {
+            Ok(mut file) => {
+                let _ = write!(file, "{}", process::id());
+                return Ok(());
+            }
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                if cancel::is_cancelled() {
+                    return Err(cancel::interrupted());
+                }
+                if started.elapsed() >= timeout {
+                    return Err(LispyType::create_error(
+                        format!("Lock {} is still held after {}ms", path, timeout.as_millis()).as_str(),
+                        "LOCK_TIMEOUT",
+                    ));
+                }
+                thread::sleep(LOCK_RETRY_INTERVAL);
+            }
+            Err(error) => return Err(fs_error(path, error)),
+        }
+    }
+}
+
+// (fs/with-lock "counter.lock" (fn* () ...)) or with a timeout in ms as the third
+// argument, 10s by default. The lock file exists while f runs and is removed
+// once it returns or throws.
+fn with_lock(args: Vec<LispyType>) -> Result<LispyType, LispyType> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(LispyType::create_error(
+            format!("Expected arity 2 or 3, received {}", args.len()).as_str(),
+            "INCORRECT_ARITY",
+        ));
+    }
+    let path = args[0].as_string();
+    let timeout = match args.get(2) {
+        None => Some(DEFAULT_LOCK_TIMEOUT),
+        Some(value) => value
+            .as_number()
+            .filter(|ms| **ms >= 0.0)
+            .map(|ms| Duration::from_millis(*ms as u64)),
+    };
+    if path.is_none() || timeout.is_none() {
+        return Err(LispyType::create_error(
+            "fs/with-lock expects a lock file path, a function and optionally a timeout in ms",
+            "INCORRECT_TYPE",
+        ));
+    }
+    let path = path.unwrap();
+    let acquired = acquire_lock(path, timeout.unwrap());
+    if acquired.is_err() {
+        return Err(acquired.err().unwrap());
+    }
+    let result = apply_callable(&args[1], vec![]);
+    let released = fs::remove_file(path);
+    if result.is_ok() && released.is_err() {
+        return Err(fs_error(path, released.err().unwrap()));
+    }
+    result
+}
+
+// (fs/watch "src" (fn* (event) ...)), a watcher calling the handler with
+// {:kind :create :path "src/new.lispy"} for every :create, :modify and :delete
+// under the path. Events are delivered by fs/poll and fs/run-watchers.
+fn watch(args: Vec<LispyType>) -> Result<LispyType, LispyType> {
+    let path = args[0].as_string();
+    if path.is_none() || !args[1].is_function() {
+        return Err(LispyType::create_error(
+            "fs/watch expects a path and a handler function",
+            "INCORRECT_TYPE",
+        ));
+    }
+    Ok(fs_watch::create_watcher(path.unwrap(), args[1].clone()))
+}
+
+// (fs/poll watcher), rescans once and returns how many events were delivered
+fn poll(args: Vec<LispyType>) -> Result<LispyType, LispyType> {
+    fs_watch::watcher_arg(&args[0])
+        .and_then(fs_watch::poll)
+        .map(|count| LispyType::create_number(count as f64))
+}
+
+fn unwatch(args: Vec<LispyType>) -> Result<LispyType, LispyType> {
+    fs_watch::watcher_arg(&args[0]).map(|watcher| {
+        fs_watch::unwatch(watcher);
+        LispyType::create_nil()
+    })
+}
+
+// (fs/run-watchers) or with the interval between scans in ms, 250 by default.
+// Blocks until every watcher is unwatched, a handler throws, which unwatches its
+// watcher, or the machine is cancelled.
+fn run_watchers(args: Vec<LispyType>) -> Result<LispyType, LispyType> {
+    if args.len() > 1 {
+        return Err(LispyType::create_error(
+            format!("Expected arity 0 or 1, received {}", args.len()).as_str(),
+            "INCORRECT_ARITY",
+        ));
+    }
+    let interval = match args.first() {
+        None => Some(DEFAULT_WATCH_INTERVAL),
+        Some(value) => value
+            .as_number()
+            .filter(|ms| **ms >= 0.0)
+            .map(|ms| Duration::from_millis(*ms as u64)),
+    };
+    if interval.is_none() {
+        return Err(LispyType::create_error(
+            "fs/run-watchers expects an interval in ms",
+            "INCORRECT_TYPE",
+        ));
+    }
+    fs_watch::run_watchers(interval.unwrap())
+}
+
+pub fn apply_fs_ns(env: &mut LispyEnv) {
+    register_fs_stat(env);
+    register_fs_set_permissions(env);
+    register_fs_copy(env);
+    register_fs_rename(env);
+    register_fs_touch(env);
+    register_fs_remove(env);
+    register_fs_temp_file(env);
+    register_fs_temp_dir(env);
+    register_fs_write_atomic(env);
+    env.set("fs/with-lock", LispyType::create_function(None, with_lock));
+    env.set("fs/watch", LispyType::create_function(Some(2), watch));
+    env.set("fs/unwatch", LispyType::create_function(Some(1), unwatch));
+    env.set("fs/poll", LispyType::create_function(Some(1), poll));
+    env.set("fs/run-watchers", LispyType::create_function(None, run_watchers));
+}
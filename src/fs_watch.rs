@@ -0,0 +1,157 @@
+use crate::cancel;
+use crate::machine::apply_callable;
+use crate::types::LispyType;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+pub const WATCHER_KIND: &str = "watcher";
+
+// Values can't cross threads yet, so there is no background thread noticing
+// changes: a watcher remembers what its path looked like and compares on every
+// poll, on the thread that polls it. fs/run-watchers is the loop doing that.
+pub struct Watcher {
+    path: PathBuf,
+    handler: LispyType,
+    snapshot: RefCell<Snapshot>,
+    active: Cell<bool>,
+}
+
+// Modification time and size of every file and directory under the watched path
+type Snapshot = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+thread_local! {
+    // Watchers fs/run-watchers polls, until they are unwatched or their handler fails
+    static WATCHERS: RefCell<Vec<LispyType>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn watcher_arg(value: &LispyType) -> Result<&Watcher, LispyType> {
+    match value.as_resource::<Watcher>() {
+        Some(watcher) => Ok(watcher),
+        None => Err(LispyType::create_error(
+            format!("{} is not a watcher", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+// Symlinks are recorded but not followed. Entries vanishing mid walk are skipped,
+// the next poll reports them as deleted.
+fn take_snapshot(path: &Path, snapshot: &mut Snapshot) {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+    snapshot.insert(path.to_path_buf(), (metadata.modified().ok(), metadata.len()));
+    if metadata.is_dir() {
+        if let Ok(entries) = fs::read_dir(path) {
+            for entry in entries.flatten() {
+                take_snapshot(&entry.path(), snapshot);
+            }
+        }
+    }
+}
+
+pub fn create_watcher(path: &str, handler: LispyType) -> LispyType {
+    let mut snapshot = Snapshot::new();
+    take_snapshot(Path::new(path), &mut snapshot);
+    let watcher = LispyType::create_resource(
+        WATCHER_KIND,
+        Watcher {
+            path: PathBuf::from(path),
+            handler,
+            snapshot: RefCell::new(snapshot),
+            active: Cell::new(true),
+        },
+    );
+    WATCHERS.with(|watchers| watchers.borrow_mut().push(watcher.clone()));
+    watcher
+}
+
+pub fn unwatch(watcher: &Watcher) {
+    watcher.active.set(false);
+    WATCHERS.with(|watchers| {
+        watchers.borrow_mut().retain(|other| {
+            other
+                .as_resource::<Watcher>()
+                .is_some_and(|other| !std::ptr::eq(other, watcher))
+        })
+    });
+}
+
+// {:kind :modify :path "src/main.lispy"}
+fn event(kind: &str, path: &Path) -> LispyType {
+    let mut collection = HashMap::new();
+    collection.insert(LispyType::create_keyword(":kind"), LispyType::create_keyword(kind));
+    collection.insert(
+        LispyType::create_keyword(":path"),
+        LispyType::create_string(path.to_string_lossy().as_ref()),
+    );
+    LispyType::Hash {
+        collection: Box::from(collection),
+        meta: HashMap::new(),
+    }
+}
+
+// Compares the path with the last snapshot and calls the handler once per
+// :create, :modify and :delete event, in path order. Returns how many were found.
+// A handler error unwatches the watcher, so fs/run-watchers doesn't fail on it forever.
+pub fn poll(watcher: &Watcher) -> Result<usize, LispyType> {
+    if !watcher.active.get() {
+        return Ok(0);
+    }
+    let mut current = Snapshot::new();
+    take_snapshot(&watcher.path, &mut current);
+    let previous = watcher.snapshot.replace(current.clone());
+
+    let mut events = vec![];
+    for (path, state) in current.iter() {
+        match previous.get(path) {
+            None => events.push((path.clone(), ":create")),
+            Some(old) if old != state => events.push((path.clone(), ":modify")),
+            _ => {}
+        }
+    }
+    for path in previous.keys().filter(|path| !current.contains_key(*path)) {
+        events.push((path.clone(), ":delete"));
+    }
+    events.sort();
+
+    for (path, kind) in events.iter() {
+        // The handler may unwatch, the rest of the events are dropped then
+        if !watcher.active.get() {
+            break;
+        }
+        let handled = apply_callable(&watcher.handler, vec![event(kind, path)]);
+        if handled.is_err() {
+            unwatch(watcher);
+            return Err(handled.err().unwrap());
+        }
+    }
+    Ok(events.len())
+}
+
+// Polls every watcher each `interval` until none is left or the machine is
+// cancelled. A handler error stops the loop and is returned, the other watchers
+// are polled by the next call.
+pub fn run_watchers(interval: Duration) -> Result<LispyType, LispyType> {
+    loop {
+        let watchers = WATCHERS.with(|watchers| watchers.borrow().clone());
+        if watchers.is_empty() {
+            return Ok(LispyType::create_nil());
+        }
+        for watcher in watchers.iter() {
+            let polled = poll(watcher.as_resource::<Watcher>().unwrap());
+            if polled.is_err() {
+                return Err(polled.err().unwrap());
+            }
+        }
+        if cancel::is_cancelled() {
+            return Err(cancel::interrupted());
+        }
+        thread::sleep(interval);
+    }
+}
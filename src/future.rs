@@ -0,0 +1,45 @@
+use crate::machine::apply_callable;
+use crate::types::LispyType;
+use std::cell::RefCell;
+
+pub const FUTURE_KIND: &str = "future";
+
+// Values are not thread-safe yet, so a future is a memoized thunk that runs on
+// the first await instead of on a worker thread.
+pub struct Future {
+    thunk: LispyType,
+    result: RefCell<Option<Result<LispyType, LispyType>>>,
+}
+
+pub fn create_future(thunk: LispyType) -> LispyType {
+    LispyType::create_resource(
+        FUTURE_KIND,
+        Future {
+            thunk,
+            result: RefCell::new(None),
+        },
+    )
+}
+
+pub fn future_arg(value: &LispyType) -> Result<&Future, LispyType> {
+    match value.as_resource::<Future>() {
+        Some(future) => Ok(future),
+        None => Err(LispyType::create_error(
+            format!("{} is not a future", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+pub fn is_realized(future: &Future) -> bool {
+    future.result.borrow().is_some()
+}
+
+pub fn await_future(future: &Future) -> Result<LispyType, LispyType> {
+    if let Some(result) = future.result.borrow().as_ref() {
+        return result.clone();
+    }
+    let result = apply_callable(&future.thunk, vec![]);
+    *future.result.borrow_mut() = Some(result.clone());
+    result
+}
@@ -0,0 +1,534 @@
+use crate::env::LispyEnv;
+use crate::machine::{
+    apply_callable, check_recur, destructure, eval, is_macro_call, macro_expand, recur_error,
+};
+use crate::types::LispyType;
+use std::cell::{Cell, RefCell};
+
+pub const GENERATOR_KIND: &str = "generator";
+
+// Special forms a yield can't be stepped through, it has to be moved out of them
+const UNSTEPPABLE_FORMS: [&str; 31] = [
+    "def!", "defn!", "doc", "redefine!", "refer", "ns", "require", "load-file",
+    "load-file-force", "safe-eval", "defconst", "eval-when-compile", "defmacro!", "undef!",
+    "deferror!", "for", "fn*", "eval", "quote", "quasi-quote-expand", "quasi-quote", "block",
+    "return-from", "locking", "deftest", "run-tests", "macro-expand", "macro-expand-1",
+    "macro-expand-all", "throw", "try*",
+];
+
+// A generator walks its function's body on a stack of frames kept here instead
+// of on the Rust stack, so it can stop at a yield and carry on from there on the
+// next `next`, on the caller's thread. Forms without a yield (or a recur back into
+// a stepped loop) are handed to eval whole. Like in Python, yield belongs to the
+// generator's own body: it can sit in if, do, let*, let, loop/recur, while, macros
+// expanding to those and the arguments of calls, but not in a function defined
+// inside the body.
+enum Frame {
+    // Items of a do or a loop body still to run, the last value is the result
+    Do {
+        items: Vec<LispyType>,
+        next: usize,
+        env: LispyEnv,
+    },
+    // Waiting for the condition
+    If {
+        then: LispyType,
+        otherwise: Option<LispyType>,
+        env: LispyEnv,
+    },
+    // Waiting for the value of the binding at `next`. let evaluates the values in
+    // `outer`, let* and loop in `env`, which collects the bindings either way.
+    Let {
+        form: String,
+        bindings: Vec<LispyType>,
+        next: usize,
+        body: Vec<LispyType>,
+        outer: LispyEnv,
+        env: LispyEnv,
+    },
+    // Where a recur lands: the loop's patterns, body and the env it was entered from
+    Loop {
+        patterns: Vec<LispyType>,
+        body: Vec<LispyType>,
+        outer: LispyEnv,
+    },
+    // The recur form and its values so far
+    Recur {
+        form: LispyType,
+        values: Vec<LispyType>,
+        env: LispyEnv,
+    },
+    // Waiting for the condition
+    While {
+        condition: LispyType,
+        body: Vec<LispyType>,
+        env: LispyEnv,
+    },
+    // Callee and arguments evaluated so far
+    Call {
+        items: Vec<LispyType>,
+        values: Vec<LispyType>,
+        env: LispyEnv,
+    },
+}
+
+enum Control {
+    Eval(LispyType, LispyEnv),
+    Return(LispyType),
+    // Stop here, `next` hands the value out
+    Yield(LispyType),
+}
+
+pub struct Generator {
+    // None while the generator runs and once it is exhausted
+    state: RefCell<Option<(Vec<Frame>, Control)>>,
+    finished: Cell<bool>,
+}
+
+fn generator_error(message: String) -> LispyType {
+    LispyType::create_error(message.as_str(), "GENERATOR_ERROR")
+}
+
+// Whether `form` has to be stepped through: it yields, or recurs into a loop that
+// does. Nested functions and quoted forms never do, a loop without a yield
+// handles its own recur.
+fn needs_step(form: &LispyType) -> bool {
+    let items = match form.as_list() {
+        Some(items) if !items.is_empty() => items,
+        _ => return false,
+    };
+    match items[0].as_symbol().map(|head| head.as_str()) {
+        Some("fn*") | Some("quote") | Some("quasi-quote") => false,
+        Some("yield") | Some("recur") => true,
+        Some("loop") => items[1..].iter().any(yields),
+        _ => items.iter().any(needs_step),
+    }
+}
+
+fn yields(form: &LispyType) -> bool {
+    let items = match form.as_list() {
+        Some(items) if !items.is_empty() => items,
+        _ => return false,
+    };
+    match items[0].as_symbol().map(|head| head.as_str()) {
+        Some("fn*") | Some("quote") | Some("quasi-quote") => false,
+        Some("yield") => true,
+        _ => items.iter().any(yields),
+    }
+}
+
+fn bindings_of(form: &str, items: &[LispyType]) -> Result<Vec<LispyType>, LispyType> {
+    match items.get(1).and_then(|bindings| bindings.as_sequential()) {
+        Some(bindings) if bindings.len() % 2 == 0 => Ok(bindings.to_vec()),
+        _ => Err(LispyType::create_error(
+            format!("{} first arg must be a list or vector of key value pairs", form).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+pub fn create_generator(func: LispyType) -> Result<LispyType, LispyType> {
+    let start = match &func {
+        LispyType::Lambda {
+            bindings,
+            to_eval,
+            env,
+            is_macro: false,
+            ..
+        } if bindings.is_empty() => Control::Eval(
+            to_eval.as_ref().clone(),
+            LispyEnv::child_lambda(env),
+        ),
+        _ => {
+            return Err(LispyType::create_error(
+                format!("generator expects a fn* without parameters, received {}", func).as_str(),
+                "INCORRECT_TYPE",
+            ))
+        }
+    };
+    Ok(LispyType::create_resource(
+        GENERATOR_KIND,
+        Generator {
+            state: RefCell::new(Some((vec![], start))),
+            finished: Cell::new(false),
+        },
+    ))
+}
+
+// yield is only ever reached through eval when it isn't in a generator's own body
+pub fn yield_value(_: LispyType) -> Result<LispyType, LispyType> {
+    Err(LispyType::create_error(
+        "yield can only be used in the body of a generator's function",
+        "NOT_IN_GENERATOR",
+    ))
+}
+
+// Resumes the generator until its next yield. Returns nil once it is exhausted,
+// an error ends it.
+pub fn next_value(generator: &Generator) -> Result<LispyType, LispyType> {
+    if generator.finished.get() {
+        return Ok(LispyType::create_nil());
+    }
+    let state = generator.state.borrow_mut().take();
+    if state.is_none() {
+        return Err(generator_error("Generator is already running".to_string()));
+    }
+    let (mut stack, control) = state.unwrap();
+    match run(&mut stack, control) {
+        Ok(Some(value)) => {
+            // yield itself evaluates to nil once the generator is resumed
+            *generator.state.borrow_mut() = Some((stack, Control::Return(LispyType::create_nil())));
+            Ok(value)
+        }
+        Ok(None) => {
+            generator.finished.set(true);
+            Ok(LispyType::create_nil())
+        }
+        Err(error) => {
+            generator.finished.set(true);
+            Err(error)
+        }
+    }
+}
+
+pub fn is_finished(generator: &Generator) -> bool {
+    generator.finished.get()
+}
+
+// Steps until a yield, Some of its value, or the end of the body
+fn run(stack: &mut Vec<Frame>, mut control: Control) -> Result<Option<LispyType>, LispyType> {
+    loop {
+        let next = match control {
+            Control::Eval(form, env) => enter(form, env, stack),
+            Control::Yield(value) => return Ok(Some(value)),
+            Control::Return(value) => match stack.pop() {
+                Some(frame) => resume(frame, value, stack),
+                None => return Ok(None),
+            },
+        };
+        if next.is_err() {
+            return Err(next.err().unwrap());
+        }
+        control = next.unwrap();
+    }
+}
+
+// Works `form` down to where eval can take over or a frame waits for a value
+fn enter(form: LispyType, mut env: LispyEnv, stack: &mut Vec<Frame>) -> Result<Control, LispyType> {
+    if !needs_step(&form) {
+        return eval(&form, &mut env).map(Control::Return);
+    }
+    if is_macro_call(&form, &env) {
+        return macro_expand(&form, &env).map(|expanded| Control::Eval(expanded, env));
+    }
+    let items = form.as_list().unwrap().to_vec();
+    let head = items[0].as_symbol().cloned().unwrap_or_default();
+    match head.as_str() {
+        "do" => Ok(run_body(items[1..].to_vec(), env, stack)),
+        "if" => {
+            if items.len() < 3 || items.len() > 4 {
+                return Err(LispyType::create_error(
+                    format!("if expects a condition, a branch and an optional else. Received: {}", form).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            stack.push(Frame::If {
+                then: items[2].clone(),
+                otherwise: items.get(3).cloned(),
+                env: env.clone(),
+            });
+            Ok(Control::Eval(items[1].clone(), env))
+        }
+        "let*" | "let" | "loop" => {
+            let bindings = bindings_of(&head, &items);
+            if bindings.is_err() {
+                return Err(bindings.err().unwrap());
+            }
+            if head == "loop" {
+                for index in 2..items.len() {
+                    let checked = check_recur(&items[index], index == items.len() - 1, &env);
+                    if checked.is_err() {
+                        return Err(checked.err().unwrap());
+                    }
+                }
+            }
+            let n_env = LispyEnv::child(&mut env);
+            Ok(bind_next(head, bindings.unwrap(), 0, items[2..].to_vec(), env, n_env, stack))
+        }
+        "recur" => {
+            if items.len() == 1 {
+                return rebind_loop(&form, vec![], stack);
+            }
+            stack.push(Frame::Recur {
+                form: form.clone(),
+                values: vec![],
+                env: env.clone(),
+            });
+            Ok(Control::Eval(items[1].clone(), env))
+        }
+        "while" => {
+            if items.len() < 2 {
+                return Err(LispyType::create_error("while expects a condition", "INCORRECT_ARITY"));
+            }
+            stack.push(Frame::While {
+                condition: items[1].clone(),
+                body: items[2..].to_vec(),
+                env: env.clone(),
+            });
+            Ok(Control::Eval(items[1].clone(), env))
+        }
+        name if UNSTEPPABLE_FORMS.contains(&name) => Err(generator_error(format!(
+            "yield can't be used inside {} in a generator body. Received: {}",
+            name, form
+        ))),
+        _ => {
+            stack.push(Frame::Call {
+                items: items.clone(),
+                values: vec![],
+                env: env.clone(),
+            });
+            Ok(Control::Eval(items[0].clone(), env))
+        }
+    }
+}
+
+// Hands `value` to `frame`, the one that was on top of the stack
+fn resume(frame: Frame, value: LispyType, stack: &mut Vec<Frame>) -> Result<Control, LispyType> {
+    match frame {
+        Frame::Do { items, next, env } => {
+            if next == items.len() {
+                return Ok(Control::Return(value));
+            }
+            let item = items[next].clone();
+            stack.push(Frame::Do {
+                items,
+                next: next + 1,
+                env: env.clone(),
+            });
+            Ok(Control::Eval(item, env))
+        }
+        Frame::If {
+            then,
+            otherwise,
+            env,
+        } => Ok(match (value.is_truthy(), otherwise) {
+            (true, _) => Control::Eval(then, env),
+            (false, Some(otherwise)) => Control::Eval(otherwise, env),
+            (false, None) => Control::Return(LispyType::create_nil()),
+        }),
+        Frame::Let {
+            form,
+            bindings,
+            next,
+            body,
+            outer,
+            mut env,
+        } => {
+            let bound = destructure(&form, &bindings[next], value, &mut env);
+            if bound.is_err() {
+                return Err(bound.err().unwrap());
+            }
+            Ok(bind_next(form, bindings, next + 2, body, outer, env, stack))
+        }
+        Frame::Loop { .. } => Ok(Control::Return(value)),
+        Frame::Recur {
+            form,
+            mut values,
+            env,
+        } => {
+            values.push(value);
+            let items = form.as_list().unwrap();
+            if values.len() + 1 < items.len() {
+                let item = items[values.len() + 1].clone();
+                stack.push(Frame::Recur { form, values, env: env.clone() });
+                return Ok(Control::Eval(item, env));
+            }
+            rebind_loop(&form, values, stack)
+        }
+        Frame::While {
+            condition,
+            body,
+            env,
+        } => {
+            if !value.is_truthy() {
+                return Ok(Control::Return(LispyType::create_nil()));
+            }
+            // The body runs above a Do that evaluates the condition again once it is done
+            stack.push(Frame::While {
+                condition: condition.clone(),
+                body: body.clone(),
+                env: env.clone(),
+            });
+            stack.push(Frame::Do {
+                items: vec![condition],
+                next: 0,
+                env: env.clone(),
+            });
+            Ok(run_body(body, env, stack))
+        }
+        Frame::Call {
+            items,
+            mut values,
+            env,
+        } => {
+            values.push(value);
+            if values.len() < items.len() {
+                let item = items[values.len()].clone();
+                stack.push(Frame::Call {
+                    items,
+                    values,
+                    env: env.clone(),
+                });
+                return Ok(Control::Eval(item, env));
+            }
+            if items[0].is_symbol_containing("yield") {
+                if values.len() != 2 {
+                    return Err(LispyType::create_error(
+                        format!("Expected arity 1, received {}", values.len() - 1).as_str(),
+                        "INCORRECT_ARITY",
+                    ));
+                }
+                return Ok(Control::Yield(values.pop().unwrap()));
+            }
+            let callee = values.remove(0);
+            apply_callable(&callee, values).map(Control::Return)
+        }
+    }
+}
+
+// Runs `items` in order, the last one's value is the result
+fn run_body(items: Vec<LispyType>, env: LispyEnv, stack: &mut Vec<Frame>) -> Control {
+    stack.push(Frame::Do { items, next: 0, env });
+    Control::Return(LispyType::create_nil())
+}
+
+// Evaluates the value of the binding at `next`, or runs the body once all are bound
+fn bind_next(
+    form: String,
+    bindings: Vec<LispyType>,
+    next: usize,
+    body: Vec<LispyType>,
+    outer: LispyEnv,
+    env: LispyEnv,
+    stack: &mut Vec<Frame>,
+) -> Control {
+    if next < bindings.len() {
+        let item = bindings[next + 1].clone();
+        let scope = if form == "let" { outer.clone() } else { env.clone() };
+        stack.push(Frame::Let {
+            form,
+            bindings,
+            next,
+            body,
+            outer,
+            env,
+        });
+        return Control::Eval(item, scope);
+    }
+    if form == "loop" {
+        stack.push(Frame::Loop {
+            patterns: bindings.iter().step_by(2).cloned().collect(),
+            body: body.clone(),
+            outer,
+        });
+    }
+    run_body(body, env, stack)
+}
+
+// Drops the frames between a recur and its loop, then runs the loop's body again
+// with `values` bound to its patterns
+fn rebind_loop(form: &LispyType, values: Vec<LispyType>, stack: &mut Vec<Frame>) -> Result<Control, LispyType> {
+    loop {
+        match stack.pop() {
+            None => return Err(recur_error(form)),
+            Some(Frame::Loop {
+                patterns,
+                body,
+                mut outer,
+            }) => {
+                if values.len() != patterns.len() {
+                    return Err(LispyType::create_error(
+                        format!("recur expects {} arguments, got {}", patterns.len(), values.len()).as_str(),
+                        "INCORRECT_ARITY",
+                    ));
+                }
+                let mut n_env = LispyEnv::child(&mut outer);
+                for (pattern, value) in patterns.iter().zip(values) {
+                    let bound = destructure("recur", pattern, value, &mut n_env);
+                    if bound.is_err() {
+                        return Err(bound.err().unwrap());
+                    }
+                }
+                stack.push(Frame::Loop {
+                    patterns,
+                    body: body.clone(),
+                    outer,
+                });
+                return Ok(run_body(body, n_env, stack));
+            }
+            Some(_) => continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::compile_source_code_to_ast;
+    use crate::machine::LispyMachine;
+    use crate::types::LispyType;
+
+    fn run(machine: &mut LispyMachine, source: &str) -> Result<LispyType, LispyType> {
+        let mut result = Ok(LispyType::create_nil());
+        for form in compile_source_code_to_ast(source).unwrap().iter() {
+            result = machine.evaluate(form);
+        }
+        result
+    }
+
+    #[test]
+    fn yields_values_in_order_then_nil() {
+        let mut machine = LispyMachine::new();
+        let values = run(
+            &mut machine,
+            "(def! g (generator (fn* () (let* (a 1) (do (yield a) (if (= a 1) (yield (+ a 1))))))))
+             (pr-str (list (next g) (next g) (next g) (done? g)))",
+        );
+        assert_eq!(values.unwrap().as_string().unwrap(), "(1 2 nil true)");
+    }
+
+    #[test]
+    fn resumes_loops_without_growing_the_stack() {
+        let mut machine = LispyMachine::new();
+        let last = run(
+            &mut machine,
+            "(def! nat (generator (fn* () (loop (i 0) (yield i) (recur (+ i 1))))))
+             (loop (i 0 last nil) (if (< i 20000) (recur (+ i 1) (next nat)) last))",
+        );
+        assert_eq!(last.unwrap(), LispyType::create_number(19999.0));
+    }
+
+    #[test]
+    fn body_runs_in_step_with_next() {
+        let mut machine = LispyMachine::new();
+        let seen = run(
+            &mut machine,
+            "(def! seen (atom []))
+             (def! g (generator (fn* () (while (< (count @seen) 3) (swap! seen conj (count @seen)) (yield @seen)))))
+             (next g)
+             (pr-str (list @seen (next g) (next g) (next g)))",
+        );
+        assert_eq!(seen.unwrap().as_string().unwrap(), "([0] [0 1] [0 1 2] nil)");
+    }
+
+    #[test]
+    fn yield_outside_the_generator_body_is_an_error() {
+        let mut machine = LispyMachine::new();
+        let nested = run(
+            &mut machine,
+            "(next (generator (fn* () (map (fn* (x) (yield x)) (list 1 2)))))",
+        );
+        assert_eq!(nested.unwrap_err().as_error().unwrap().error_type, "NOT_IN_GENERATOR");
+        let in_try = run(&mut machine, "(next (generator (fn* () (try* (yield 1) (catch* _ e e)))))");
+        assert_eq!(in_try.unwrap_err().as_error().unwrap().error_type, "GENERATOR_ERROR");
+    }
+}
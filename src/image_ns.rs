@@ -0,0 +1,291 @@
+use crate::env::LispyEnv;
+use crate::lispy_fn;
+use crate::types::LispyType;
+use std::collections::HashMap;
+use std::fs;
+
+// Builtins reading files, dropped along with slurp when a machine is built without file io
+pub const IMAGE_BUILTINS: [&str; 3] = ["image/format", "image/dimensions", "image/exif"];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Png,
+    Jpeg,
+    Gif,
+    Bmp,
+    Webp,
+}
+
+impl Format {
+    fn name(&self) -> &'static str {
+        match self {
+            Format::Png => ":png",
+            Format::Jpeg => ":jpeg",
+            Format::Gif => ":gif",
+            Format::Bmp => ":bmp",
+            Format::Webp => ":webp",
+        }
+    }
+}
+
+fn image_error(path: &str, message: &str) -> LispyType {
+    LispyType::create_error(format!("{}: {}", path, message).as_str(), "INCORRECT_TYPE")
+}
+
+fn read_image(path: &str) -> Result<(Format, Vec<u8>), LispyType> {
+    let bytes = fs::read(path);
+    if bytes.is_err() {
+        return Err(LispyType::create_error(
+            format!("File {} not found", path).as_str(),
+            "SYSTEM_ERROR",
+        ));
+    }
+    let bytes = bytes.unwrap();
+    match detect_format(&bytes) {
+        Some(format) => Ok((format, bytes)),
+        None => Err(image_error(path, "not a png, jpeg, gif, bmp or webp image")),
+    }
+}
+
+// By the magic bytes the file starts with, the extension is not trusted
+fn detect_format(bytes: &[u8]) -> Option<Format> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(Format::Png)
+    } else if bytes.starts_with(&[0xFF, 0xD8]) {
+        Some(Format::Jpeg)
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some(Format::Gif)
+    } else if bytes.starts_with(b"BM") {
+        Some(Format::Bmp)
+    } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some(Format::Webp)
+    } else {
+        None
+    }
+}
+
+fn u16_be(bytes: &[u8], at: usize) -> Option<u32> {
+    bytes.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]]) as u32)
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> Option<u32> {
+    bytes.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as u32)
+}
+
+fn u24_le(bytes: &[u8], at: usize) -> Option<u32> {
+    bytes.get(at..at + 3).map(|b| u32::from_le_bytes([b[0], b[1], b[2], 0]))
+}
+
+fn u32_be(bytes: &[u8], at: usize) -> Option<u32> {
+    bytes.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+// Width and height from the header, None when it is cut short
+fn dimensions(format: Format, bytes: &[u8]) -> Option<(u32, u32)> {
+    match format {
+        Format::Png => Some((u32_be(bytes, 16)?, u32_be(bytes, 20)?)),
+        Format::Gif => Some((u16_le(bytes, 6)?, u16_le(bytes, 8)?)),
+        // Negative heights mark rows stored top down
+        Format::Bmp => {
+            let width = bytes.get(18..22)?;
+            let height = bytes.get(22..26)?;
+            Some((
+                i32::from_le_bytes([width[0], width[1], width[2], width[3]]).unsigned_abs(),
+                i32::from_le_bytes([height[0], height[1], height[2], height[3]]).unsigned_abs(),
+            ))
+        }
+        Format::Webp => match bytes.get(12..16)? {
+            b"VP8 " => Some((u16_le(bytes, 26)? & 0x3FFF, u16_le(bytes, 28)? & 0x3FFF)),
+            b"VP8L" => {
+                let b = bytes.get(21..25)?;
+                let (b0, b1, b2, b3) = (b[0] as u32, b[1] as u32, b[2] as u32, b[3] as u32);
+                Some((
+                    1 + (((b1 & 0x3F) << 8) | b0),
+                    1 + (((b3 & 0x0F) << 10) | (b2 << 2) | ((b1 & 0xC0) >> 6)),
+                ))
+            }
+            b"VP8X" => Some((1 + u24_le(bytes, 24)?, 1 + u24_le(bytes, 27)?)),
+            _ => None,
+        },
+        Format::Jpeg => jpeg_segments(bytes)
+            .into_iter()
+            .find(|(marker, _)| is_start_of_frame(*marker))
+            .and_then(|(_, at)| Some((u16_be(bytes, at + 3)?, u16_be(bytes, at + 1)?))),
+    }
+}
+
+// SOF0 to SOF15, apart from the DHT, JPG and DAC markers sharing the range
+fn is_start_of_frame(marker: u8) -> bool {
+    (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker)
+}
+
+// The marker and payload offset of every JPEG segment up to the image data
+fn jpeg_segments(bytes: &[u8]) -> Vec<(u8, usize)> {
+    let mut segments = vec![];
+    let mut at = 2;
+    while at + 4 <= bytes.len() && bytes[at] == 0xFF {
+        let marker = bytes[at + 1];
+        if marker == 0xFF {
+            at += 1;
+            continue;
+        }
+        if marker == 0xD9 || marker == 0xDA {
+            break;
+        }
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            at += 2;
+            continue;
+        }
+        let length = u16_be(bytes, at + 2).unwrap() as usize;
+        segments.push((marker, at + 4));
+        at += 2 + length;
+    }
+    segments
+}
+
+// A TIFF block as found in a JPEG's Exif segment, offsets are relative to its start
+struct Tiff<'a> {
+    bytes: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(bytes: &'a [u8]) -> Option<Self> {
+        let little_endian = match bytes.get(0..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        let tiff = Tiff { bytes, little_endian };
+        if tiff.u16(2)? != 42 {
+            return None;
+        }
+        Some(tiff)
+    }
+
+    fn u16(&self, at: usize) -> Option<u32> {
+        if self.little_endian {
+            u16_le(self.bytes, at)
+        } else {
+            u16_be(self.bytes, at)
+        }
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let b = self.bytes.get(at..at + 4)?;
+        Some(if self.little_endian {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        })
+    }
+
+    // Tag to the offset of its 12 byte entry, for the IFD at `at`
+    fn entries(&self, at: usize) -> HashMap<u32, usize> {
+        let mut entries = HashMap::new();
+        let count = self.u16(at).unwrap_or(0) as usize;
+        for index in 0..count {
+            let entry = at + 2 + index * 12;
+            if let Some(tag) = self.u16(entry) {
+                entries.insert(tag, entry);
+            }
+        }
+        entries
+    }
+
+    // ASCII values longer than 4 bytes live at the offset the entry holds
+    fn ascii(&self, entry: usize) -> Option<String> {
+        let count = self.u32(entry + 4)? as usize;
+        let at = if count <= 4 { entry + 8 } else { self.u32(entry + 8)? as usize };
+        let raw = self.bytes.get(at..at + count)?;
+        let text = String::from_utf8_lossy(raw);
+        Some(text.trim_end_matches(|c: char| c == '\0' || c.is_whitespace()).to_string())
+    }
+}
+
+const EXIF_IFD_POINTER: u32 = 0x8769;
+
+// Tag, the keyword it is reported under and whether it is text (or a SHORT)
+const EXIF_TAGS: [(u32, &str, bool); 5] = [
+    (0x010F, ":make", true),
+    (0x0110, ":model", true),
+    (0x0112, ":orientation", false),
+    (0x0132, ":date-time", true),
+    (0x9003, ":date-time-original", true),
+];
+
+// The EXIF_TAGS a JPEG carries, an empty hash for other formats or no Exif segment
+fn exif(format: Format, bytes: &[u8]) -> LispyType {
+    let mut collection = HashMap::new();
+    let tiff = match format {
+        Format::Jpeg => jpeg_segments(bytes)
+            .into_iter()
+            .filter(|(marker, _)| *marker == 0xE1)
+            .find(|(_, at)| bytes[*at..].starts_with(b"Exif\0\0"))
+            .and_then(|(_, at)| Tiff::new(&bytes[at + 6..])),
+        _ => None,
+    };
+    if let Some(tiff) = tiff {
+        let mut entries = tiff.u32(4).map(|at| tiff.entries(at as usize)).unwrap_or_default();
+        let exif_ifd = entries.get(&EXIF_IFD_POINTER).and_then(|entry| tiff.u32(entry + 8));
+        if let Some(at) = exif_ifd {
+            entries.extend(tiff.entries(at as usize));
+        }
+        for (tag, name, text) in EXIF_TAGS {
+            let value = entries.get(&tag).and_then(|entry| {
+                if text {
+                    tiff.ascii(*entry).map(|value| LispyType::create_string(value.as_str()))
+                } else {
+                    tiff.u16(entry + 8).map(|value| LispyType::create_number(value as f64))
+                }
+            });
+            if let Some(value) = value {
+                collection.insert(LispyType::create_keyword(name), value);
+            }
+        }
+    }
+    LispyType::Hash {
+        collection: Box::from(collection),
+        meta: HashMap::new(),
+    }
+}
+
+// (image/format "photo.jpg") => :jpeg
+#[lispy_fn(name = "image/format")]
+fn image_format(path: String) -> Result<LispyType, LispyType> {
+    read_image(&path).map(|(format, _)| LispyType::create_keyword(format.name()))
+}
+
+// (image/dimensions "photo.jpg") => {:width 4032 :height 3024}
+#[lispy_fn(name = "image/dimensions")]
+fn image_dimensions(path: String) -> Result<LispyType, LispyType> {
+    let image = read_image(&path);
+    if image.is_err() {
+        return Err(image.err().unwrap());
+    }
+    let (format, bytes) = image.unwrap();
+    let size = dimensions(format, &bytes);
+    if size.is_none() {
+        return Err(image_error(&path, "image header is truncated"));
+    }
+    let (width, height) = size.unwrap();
+    let mut collection = HashMap::new();
+    collection.insert(LispyType::create_keyword(":width"), LispyType::create_number(width as f64));
+    collection.insert(LispyType::create_keyword(":height"), LispyType::create_number(height as f64));
+    Ok(LispyType::Hash {
+        collection: Box::from(collection),
+        meta: HashMap::new(),
+    })
+}
+
+// (image/exif "photo.jpg") => {:make "Canon" :model "..." :orientation 1 :date-time-original "..."}
+#[lispy_fn(name = "image/exif")]
+fn image_exif(path: String) -> Result<LispyType, LispyType> {
+    read_image(&path).map(|(format, bytes)| exif(format, &bytes))
+}
+
+pub fn apply_image_ns(env: &mut LispyEnv) {
+    register_image_format(env);
+    register_image_dimensions(env);
+    register_image_exif(env);
+}
@@ -0,0 +1,111 @@
+use crate::types::LispyType;
+use std::io::{self, BufRead, Write};
+
+const PAGE_SIZE: usize = 20;
+const PREVIEW_LENGTH: usize = 60;
+
+// One line preview of a value, nested collections are collapsed to their size
+fn summary(value: &LispyType) -> String {
+    match value {
+        LispyType::List { collection, .. } => format!("({} items)", collection.len()),
+        LispyType::Hash { collection, .. } => format!("{{{} keys}}", collection.len()),
+        _ => {
+            let text = format!("{}", value);
+            if text.chars().count() > PREVIEW_LENGTH {
+                format!("{}...", text.chars().take(PREVIEW_LENGTH).collect::<String>())
+            } else {
+                text
+            }
+        }
+    }
+}
+
+fn key_label(key: &LispyType) -> String {
+    match key {
+        LispyType::Keyword { value, .. } => value.to_string(),
+        LispyType::String { value, .. } => format!("\"{}\"", value),
+        _ => format!("{}", key),
+    }
+}
+
+fn children(value: &LispyType) -> Vec<(String, LispyType)> {
+    match value {
+        LispyType::List { collection, .. } => collection
+            .iter()
+            .enumerate()
+            .map(|(index, item)| (index.to_string(), item.clone()))
+            .collect(),
+        LispyType::Hash { collection, .. } => {
+            let mut entries: Vec<(String, LispyType)> = collection
+                .iter()
+                .map(|(key, item)| (key_label(key), item.clone()))
+                .collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            entries
+        }
+        _ => vec![],
+    }
+}
+
+fn render(path: &[String], value: &LispyType, page: usize) {
+    let entries = children(value);
+    let pages = entries.len().div_ceil(PAGE_SIZE);
+    println!("--- /{} {}", path.join("/"), summary(value));
+    if entries.is_empty() {
+        println!("  {}", value);
+    }
+    for (index, (key, item)) in entries
+        .iter()
+        .enumerate()
+        .skip(page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+    {
+        println!("  [{}] {} => {}", index, key, summary(item));
+    }
+    if pages > 1 {
+        println!("  page {}/{}", page + 1, pages);
+    }
+    print!("number: expand, u: up, n/p: next/previous page, q: quit > ");
+    let _ = io::stdout().flush();
+}
+
+// Walks `value` interactively on stdin/stdout. Returns the value that was open
+// when the user quit, so `(inspect data)` can be used to pick out a nested part.
+pub fn inspect(value: LispyType) -> LispyType {
+    // (key of the opened child, parent it was opened from)
+    let mut stack: Vec<(String, LispyType)> = vec![];
+    let mut current = value;
+    let mut page = 0;
+    let stdin = io::stdin();
+
+    loop {
+        let path: Vec<String> = stack.iter().map(|(key, _)| key.clone()).collect();
+        render(&path, &current, page);
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            println!();
+            return current;
+        }
+        let entries = children(&current);
+        match line.trim() {
+            "q" => return current,
+            "u" => {
+                if let Some((_, parent)) = stack.pop() {
+                    current = parent;
+                    page = 0;
+                }
+            }
+            "n" if (page + 1) * PAGE_SIZE < entries.len() => page += 1,
+            "p" if page > 0 => page -= 1,
+            input => match input.parse::<usize>().ok().and_then(|index| entries.get(index)) {
+                Some((key, item)) => {
+                    stack.push((key.clone(), current));
+                    current = item.clone();
+                    page = 0;
+                }
+                None => println!("Unknown command {}", input),
+            },
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+struct Name {
+    text: String,
+    // Hash of `text`, computed once. DefaultHasher::new() is unkeyed, so equal names
+    // interned on different threads (one machine each) still hash the same.
+    hash: u64,
+}
+
+// The name of a keyword, shared by every keyword spelled the same on this thread.
+// Cloning is a reference count bump, hashing writes the precomputed hash and
+// equality is a pointer comparison unless the names come from different threads.
+#[derive(Clone)]
+pub struct Interned(Rc<Name>);
+
+thread_local! {
+    // Keywords are few and long-lived, so names are never dropped from the table
+    static NAMES: RefCell<HashMap<String, Interned>> = RefCell::new(HashMap::new());
+}
+
+pub fn intern(text: &str) -> Interned {
+    NAMES.with(|names| {
+        if let Some(interned) = names.borrow().get(text) {
+            return interned.clone();
+        }
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        let interned = Interned(Rc::new(Name {
+            text: text.to_string(),
+            hash: hasher.finish(),
+        }));
+        names.borrow_mut().insert(text.to_string(), interned.clone());
+        interned
+    })
+}
+
+impl Deref for Interned {
+    type Target = String;
+
+    fn deref(&self) -> &String {
+        &self.0.text
+    }
+}
+
+impl PartialEq for Interned {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0) || (self.0.hash == other.0.hash && self.0.text == other.0.text)
+    }
+}
+
+impl Eq for Interned {}
+
+impl Hash for Interned {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash);
+    }
+}
+
+impl Debug for Interned {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.0.text, f)
+    }
+}
+
+impl Display for Interned {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.text)
+    }
+}
@@ -0,0 +1,423 @@
+use crate::compiler::{runtime_limits, ParseLimits};
+use crate::env::LispyEnv;
+use crate::types::{format_number, sorted_hash_entries, LispyType};
+use std::collections::HashMap;
+
+const INDENT: &str = "  ";
+
+fn json_error(message: String) -> LispyType {
+    LispyType::create_error(message.as_str(), "SYNTAX_ERROR")
+}
+
+fn limit_error(message: String) -> LispyType {
+    LispyType::create_error(message.as_str(), "LIMIT_EXCEEDED")
+}
+
+// Recursive descent over the bytes of a JSON document. Nesting, collection sizes
+// and string lengths are bounded by the runtime parse limits, like compile-string.
+struct JsonReader<'a> {
+    source: &'a str,
+    index: usize,
+    limits: ParseLimits,
+}
+
+impl<'a> JsonReader<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.source.as_bytes().get(self.index).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.index += 1;
+        }
+    }
+
+    fn error(&self, message: &str) -> LispyType {
+        let mut end = self.index.min(self.source.len());
+        while !self.source.is_char_boundary(end) {
+            end -= 1;
+        }
+        let before = &self.source[..end];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |newline| newline + 1) + 1;
+        json_error(format!("{} at line {}, column {}", message, line, column))
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), LispyType> {
+        if !self.source[self.index..].starts_with(literal) {
+            return Err(self.error(format!("Expected {}", literal).as_str()));
+        }
+        self.index += literal.len();
+        Ok(())
+    }
+
+    fn read_value(&mut self, depth: usize) -> Result<LispyType, LispyType> {
+        if let Some(max_depth) = self.limits.max_depth.filter(|max_depth| depth > *max_depth) {
+            return Err(limit_error(format!(
+                "Nesting depth exceeds the limit of {}",
+                max_depth
+            )));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(self.error("Unexpected end of input")),
+            Some(b'{') => self.read_object(depth),
+            Some(b'[') => self.read_array(depth),
+            Some(b'"') => self.read_string().map(|value| LispyType::create_string(value.as_str())),
+            Some(b't') => self.expect("true").map(|_| LispyType::create_bool(true)),
+            Some(b'f') => self.expect("false").map(|_| LispyType::create_bool(false)),
+            Some(b'n') => self.expect("null").map(|_| LispyType::create_nil()),
+            Some(b'-' | b'0'..=b'9') => self.read_number(),
+            Some(_) => Err(self.error("Unexpected character")),
+        }
+    }
+
+    fn check_size(&self, size: usize) -> Result<(), LispyType> {
+        if let Some(max_size) = self.limits.max_collection_size.filter(|max_size| size > *max_size) {
+            return Err(limit_error(format!(
+                "Collection size exceeds the limit of {}",
+                max_size
+            )));
+        }
+        Ok(())
+    }
+
+    fn read_array(&mut self, depth: usize) -> Result<LispyType, LispyType> {
+        self.index += 1;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.index += 1;
+            return Ok(LispyType::create_list(items));
+        }
+        loop {
+            let item = self.read_value(depth + 1);
+            if item.is_err() {
+                return Err(item.err().unwrap());
+            }
+            items.push(item.unwrap());
+            let size = self.check_size(items.len());
+            if size.is_err() {
+                return Err(size.err().unwrap());
+            }
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.index += 1,
+                Some(b']') => {
+                    self.index += 1;
+                    return Ok(LispyType::create_list(items));
+                }
+                _ => return Err(self.error("Expected , or ] in array")),
+            }
+        }
+    }
+
+    fn read_object(&mut self, depth: usize) -> Result<LispyType, LispyType> {
+        self.index += 1;
+        let mut collection = HashMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.index += 1;
+            return Ok(LispyType::Hash { collection: Box::new(collection), meta: HashMap::new() });
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("Expected a string key in object"));
+            }
+            let key = self.read_string();
+            if key.is_err() {
+                return Err(key.err().unwrap());
+            }
+            self.skip_whitespace();
+            let colon = self.expect(":");
+            if colon.is_err() {
+                return Err(colon.err().unwrap());
+            }
+            let value = self.read_value(depth + 1);
+            if value.is_err() {
+                return Err(value.err().unwrap());
+            }
+            // A repeated key keeps its last value
+            collection.insert(LispyType::create_string(key.unwrap().as_str()), value.unwrap());
+            let size = self.check_size(collection.len());
+            if size.is_err() {
+                return Err(size.err().unwrap());
+            }
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.index += 1,
+                Some(b'}') => {
+                    self.index += 1;
+                    return Ok(LispyType::Hash { collection: Box::new(collection), meta: HashMap::new() });
+                }
+                _ => return Err(self.error("Expected , or } in object")),
+            }
+        }
+    }
+
+    fn read_hex_escape(&mut self) -> Result<u32, LispyType> {
+        let digits = self.source.get(self.index..self.index + 4);
+        let code = digits.and_then(|digits| u32::from_str_radix(digits, 16).ok());
+        if code.is_none() {
+            return Err(self.error("Expected 4 hex digits after \\u"));
+        }
+        self.index += 4;
+        Ok(code.unwrap())
+    }
+
+    // \uXXXX, joining a surrogate pair into one char
+    fn read_unicode_escape(&mut self) -> Result<char, LispyType> {
+        let high = self.read_hex_escape();
+        if high.is_err() {
+            return Err(high.err().unwrap());
+        }
+        let high = high.unwrap();
+        let code = if (0xD800..0xDC00).contains(&high) {
+            let low = self.expect("\\u").and_then(|_| self.read_hex_escape());
+            match low {
+                Ok(low) if (0xDC00..0xE000).contains(&low) => 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00),
+                _ => return Err(self.error("Unpaired surrogate in \\u escape")),
+            }
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("Invalid \\u escape"))
+    }
+
+    fn read_string(&mut self) -> Result<String, LispyType> {
+        self.index += 1;
+        let mut value = String::new();
+        loop {
+            let rest = &self.source[self.index..];
+            let char = rest.chars().next();
+            if char.is_none() {
+                return Err(self.error("Unterminated string"));
+            }
+            let char = char.unwrap();
+            self.index += char.len_utf8();
+            match char {
+                '"' => break,
+                '\\' => {
+                    let escaped = self.peek();
+                    self.index += 1;
+                    match escaped {
+                        Some(b'"') => value.push('"'),
+                        Some(b'\\') => value.push('\\'),
+                        Some(b'/') => value.push('/'),
+                        Some(b'b') => value.push('\u{8}'),
+                        Some(b'f') => value.push('\u{c}'),
+                        Some(b'n') => value.push('\n'),
+                        Some(b'r') => value.push('\r'),
+                        Some(b't') => value.push('\t'),
+                        Some(b'u') => {
+                            let unicode = self.read_unicode_escape();
+                            if unicode.is_err() {
+                                return Err(unicode.err().unwrap());
+                            }
+                            value.push(unicode.unwrap());
+                        }
+                        _ => return Err(self.error("Invalid escape in string")),
+                    }
+                }
+                char if char < ' ' => return Err(self.error("Control character in string")),
+                char => value.push(char),
+            }
+        }
+        if let Some(max_length) = self.limits.max_string_length.filter(|max_length| value.len() > *max_length) {
+            return Err(limit_error(format!(
+                "String length exceeds the limit of {}",
+                max_length
+            )));
+        }
+        Ok(value)
+    }
+
+    fn read_number(&mut self) -> Result<LispyType, LispyType> {
+        let start = self.index;
+        let bytes = self.source.as_bytes();
+        let digits = |index: &mut usize| {
+            let from = *index;
+            while bytes.get(*index).is_some_and(u8::is_ascii_digit) {
+                *index += 1;
+            }
+            *index > from
+        };
+        let mut index = start;
+        if bytes[index] == b'-' {
+            index += 1;
+        }
+        let whole_start = index;
+        let mut valid = digits(&mut index) && !(bytes[whole_start] == b'0' && index - whole_start > 1);
+        if valid && bytes.get(index) == Some(&b'.') {
+            index += 1;
+            valid = digits(&mut index);
+        }
+        if valid && matches!(bytes.get(index), Some(b'e' | b'E')) {
+            index += 1;
+            if matches!(bytes.get(index), Some(b'+' | b'-')) {
+                index += 1;
+            }
+            valid = digits(&mut index);
+        }
+        if !valid {
+            return Err(self.error("Invalid number"));
+        }
+        self.index = index;
+        Ok(LispyType::create_number(self.source[start..index].parse().unwrap()))
+    }
+}
+
+// Objects become hashes with string keys, arrays lists, null nil
+pub fn parse_json(source: &str) -> Result<LispyType, LispyType> {
+    let mut reader = JsonReader { source, index: 0, limits: runtime_limits() };
+    let value = reader.read_value(1);
+    if value.is_err() {
+        return Err(value.err().unwrap());
+    }
+    reader.skip_whitespace();
+    if reader.index < source.len() {
+        return Err(reader.error("Unexpected content after the JSON value"));
+    }
+    value
+}
+
+fn write_string(value: &str, out: &mut String) {
+    out.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            char if char < ' ' => out.push_str(format!("\\u{:04x}", char as u32).as_str()),
+            char => out.push(char),
+        }
+    }
+    out.push('"');
+}
+
+fn encode_error(value: &LispyType) -> LispyType {
+    LispyType::create_error(
+        format!("{} can't be written as JSON", value.to_readable_string()).as_str(),
+        "INCORRECT_TYPE",
+    )
+}
+
+// Object keys are strings, keywords and symbols are written as their names and
+// numbers as they print
+fn key_text(key: &LispyType) -> Result<String, LispyType> {
+    match key {
+        LispyType::String { value, .. } | LispyType::Symbol { value, .. } => Ok(value.clone()),
+        LispyType::Keyword { value, .. } => Ok(value.trim_start_matches(':').to_string()),
+        LispyType::Number { value, .. } if value.is_finite() => Ok(format_number(*value)),
+        _ => Err(encode_error(key)),
+    }
+}
+
+// Writes `items` between `open` and `close`, one per line when `indent` is set
+fn write_items<T>(
+    items: &[T],
+    open: char,
+    close: char,
+    indent: Option<usize>,
+    out: &mut String,
+    mut write_item: impl FnMut(&T, &mut String) -> Result<(), LispyType>,
+) -> Result<(), LispyType> {
+    out.push(open);
+    for (index, item) in items.iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        if let Some(level) = indent {
+            out.push('\n');
+            out.push_str(INDENT.repeat(level + 1).as_str());
+        }
+        let written = write_item(item, out);
+        if written.is_err() {
+            return Err(written.err().unwrap());
+        }
+    }
+    if let Some(level) = indent.filter(|_| !items.is_empty()) {
+        out.push('\n');
+        out.push_str(INDENT.repeat(level).as_str());
+    }
+    out.push(close);
+    Ok(())
+}
+
+fn write_value(value: &LispyType, indent: Option<usize>, out: &mut String) -> Result<(), LispyType> {
+    let nested = indent.map(|level| level + 1);
+    match value {
+        LispyType::Nil { .. } => out.push_str("null"),
+        LispyType::Bool { value, .. } => out.push_str(if *value { "true" } else { "false" }),
+        LispyType::Number { value: number, .. } if number.is_finite() => out.push_str(format_number(*number).as_str()),
+        LispyType::String { value, .. } | LispyType::Symbol { value, .. } => write_string(value, out),
+        LispyType::Keyword { value, .. } => write_string(value.trim_start_matches(':'), out),
+        LispyType::List { collection, .. } | LispyType::Vector { collection, .. } => {
+            return write_items(collection, '[', ']', indent, out, |item, out| write_value(item, nested, out));
+        }
+        LispyType::Hash { collection, .. } => {
+            let entries = sorted_hash_entries(collection);
+            return write_items(&entries, '{', '}', indent, out, |(key, item), out| {
+                let key = key_text(key);
+                if key.is_err() {
+                    return Err(key.err().unwrap());
+                }
+                write_string(key.unwrap().as_str(), out);
+                out.push_str(if indent.is_some() { ": " } else { ":" });
+                write_value(item, nested, out)
+            });
+        }
+        LispyType::Atom { value, .. } => return write_value(&value.borrow(), indent, out),
+        _ => return Err(encode_error(value)),
+    }
+    Ok(())
+}
+
+// Hash keys are written in key order so the same value always gives the same text
+pub fn stringify_json(value: &LispyType, pretty: bool) -> Result<String, LispyType> {
+    let mut out = String::new();
+    let written = write_value(value, if pretty { Some(0) } else { None }, &mut out);
+    written.map(|_| out)
+}
+
+pub fn apply_json_ns(env: &mut LispyEnv) {
+    env.set(
+        "json/parse",
+        LispyType::create_function(Some(1), |args| {
+            let source = args[0].as_string();
+            if source.is_none() {
+                return Err(LispyType::create_error(
+                    format!("json/parse expects a string. Received {}", args[0]).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            parse_json(source.unwrap())
+        }),
+    );
+    // (json/stringify value) or (json/stringify value {:pretty true}) for two-space indented output
+    env.set(
+        "json/stringify",
+        LispyType::create_function(None, |args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(LispyType::create_error(
+                    format!("Expected arity 1 or 2, received {}", args.len()).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let options = args.get(1).filter(|options| !options.is_nil());
+            if let Some(options) = options.filter(|options| !options.is_hash()) {
+                return Err(LispyType::create_error(
+                    format!("{} is not a hash of options", options).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            let pretty = options
+                .and_then(|options| options.as_hash().unwrap().get(&LispyType::create_keyword(":pretty")))
+                .is_some_and(|pretty| pretty.is_truthy());
+            stringify_json(&args[0], pretty).map(|json| LispyType::create_string(json.as_str()))
+        }),
+    );
+}
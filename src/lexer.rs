@@ -14,6 +14,12 @@ pub enum LexerToken {
     #[token("}")]
     HashEnd,
 
+    #[token("[")]
+    VectorStart,
+
+    #[token("]")]
+    VectorEnd,
+
     #[regex("true|false", | lex | lex.slice().parse())]
     Boolean(bool),
 
@@ -32,19 +38,30 @@ pub enum LexerToken {
     #[token("~@")]
     SpliceUnquote,
 
+    #[token("@")]
+    Deref,
+
     #[token("&")]
     ArgsSpread,
+
+    #[token("#?(")]
+    ReaderConditionalStart,
     
-    #[regex(r#""(\\"|[^"])*""#, | lex | lex.slice().parse())]
+    #[regex(r#""(\\.|[^"\\])*""#, | lex | lex.slice().parse())]
     String(String),
 
-    #[regex(r"[-]?((\d+(\.\d*)?)|(\.\d+))", | lex | lex.slice().parse(), priority = 2)]
+    #[regex(r"[-]?((\d(_?\d)*(\.(\d(_?\d)*)?)?)|(\.\d(_?\d)*))", | lex | parse_decimal(lex.slice()), priority = 2)]
+    #[regex(r"[-]?0x[0-9a-fA-F](_?[0-9a-fA-F])*", | lex | parse_radix(lex.slice(), 16), priority = 3)]
+    #[regex(r"[-]?0o[0-7](_?[0-7])*", | lex | parse_radix(lex.slice(), 8), priority = 3)]
+    #[regex(r"[-]?0b[01](_?[01])*", | lex | parse_radix(lex.slice(), 2), priority = 3)]
+    #[regex(r"[-]?\d(_?\d)*/\d(_?\d)*", | lex | parse_ratio(lex.slice()), priority = 3)]
+    #[regex(r"##(NaN|Inf|-Inf)", | lex | parse_special_number(lex.slice()), priority = 3)]
     Number(f64),
 
     #[regex(r":(:|\w)[\w\-!@#$+?~]*", | lex | lex.slice().parse())]
     Keyword(String),
 
-    #[regex(r"[\w+\-*/$&#=][\w\-!@#$+?~*=]*", | lex | lex.slice().parse())]
+    #[regex(r"[\w+\-*/$&#=<>][\w\-!@#$+?~*=/<>.]*", | lex | lex.slice().parse())]
     Symbol(String),
 
     #[error]
@@ -52,3 +69,39 @@ pub enum LexerToken {
     #[regex(r";.*\n", logos::skip)]
     Error,
 }
+
+fn parse_special_number(slice: &str) -> Option<f64> {
+    match slice {
+        "##NaN" => Some(f64::NAN),
+        "##Inf" => Some(f64::INFINITY),
+        "##-Inf" => Some(f64::NEG_INFINITY),
+        _ => None,
+    }
+}
+
+// `1_000_000`: underscores only group digits
+fn parse_decimal(slice: &str) -> Option<f64> {
+    slice.replace('_', "").parse().ok()
+}
+
+// `1/3`, the form numbers print in after `(set-print-ratios! true)`. A zero
+// denominator is not a number.
+fn parse_ratio(slice: &str) -> Option<f64> {
+    let (numerator, denominator) = slice.split_once('/')?;
+    let denominator = parse_decimal(denominator)?;
+    if denominator == 0.0 {
+        return None;
+    }
+    Some(parse_decimal(numerator)? / denominator)
+}
+
+// `0xFF`, `0o755` and `0b1010`, optionally negative and grouped with underscores.
+// Numbers are floats until there is an integer type, so large literals lose precision.
+fn parse_radix(slice: &str, radix: u32) -> Option<f64> {
+    let (negative, digits) = match slice.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, slice),
+    };
+    let value = u64::from_str_radix(&digits[2..].replace('_', ""), radix).ok()? as f64;
+    Some(if negative { -value } else { value })
+}
@@ -0,0 +1,51 @@
+// Lispy as a library, for embedding it as a scripting language. The binary in
+// main.rs is one such embedder. See machine::LispyMachineBuilder for configuring a machine.
+// Builtins can be written as plain Rust functions with #[lispy_fn], which refers
+// to this crate as ::lispy even from inside it.
+extern crate self as lispy;
+
+pub use lispy_macros::lispy_fn;
+
+mod actor;
+#[cfg(feature = "archive")]
+mod archive_ns;
+mod cancel;
+pub mod compiler;
+pub mod convert;
+mod core_ns;
+pub mod coverage;
+#[cfg(feature = "decimal")]
+mod decimal_ns;
+pub mod env;
+mod fs_ns;
+mod fs_watch;
+mod future;
+mod generator;
+#[cfg(feature = "image")]
+mod image_ns;
+mod inspector;
+pub mod interner;
+mod json_ns;
+mod lexer;
+pub mod lint;
+mod lock;
+pub mod machine;
+#[cfg(feature = "matrix")]
+mod matrix_ns;
+mod namespace;
+mod protocols;
+mod queue;
+pub mod record;
+pub mod repl;
+mod sandbox;
+#[cfg(feature = "serde")]
+mod serde_ns;
+pub mod server;
+mod spec_ns;
+mod stacktrace;
+mod stdlib;
+mod string_ns;
+mod template;
+mod testing;
+pub mod types;
+mod watchers;
@@ -0,0 +1,83 @@
+use crate::compiler::{compile_source_file, ParseError};
+use crate::types::LispyType;
+use std::fmt::{Display, Formatter};
+
+// Comparisons by exact equality
+const EQUALITY: [&str; 2] = ["=", "assert="];
+
+// Arithmetic whose result is a float as soon as one operand is
+const ARITHMETIC: [&str; 3] = ["+", "-", "*"];
+
+pub struct Warning {
+    pub location: String,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: warning: {}", self.location, self.message)
+    }
+}
+
+// Reads `source` without evaluating it and reports suspicious forms
+pub fn lint_source(source: &str, file: &str) -> Result<Vec<Warning>, ParseError> {
+    let ast = compile_source_file(source, file);
+    if ast.is_err() {
+        return Err(ast.err().unwrap());
+    }
+    let mut warnings = vec![];
+    for form in ast.unwrap().iter() {
+        check(form, &mut warnings);
+    }
+    Ok(warnings)
+}
+
+fn head_symbol(items: &[LispyType]) -> Option<&str> {
+    items.first().and_then(|head| head.as_symbol()).map(String::as_str)
+}
+
+// Whether `form` is a float literal or arithmetic on one. Every number is a float
+// at runtime, the ones with a fractional part are those rounding bites.
+fn is_float(form: &LispyType) -> bool {
+    match form {
+        LispyType::Number { value, .. } => value.fract() != 0.0,
+        LispyType::List { collection, .. } => match head_symbol(collection) {
+            Some("/") => true,
+            Some(operator) if ARITHMETIC.contains(&operator) => collection[1..].iter().any(is_float),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn check(form: &LispyType, warnings: &mut Vec<Warning>) {
+    let items = match form {
+        LispyType::List { collection, .. } => collection.as_slice(),
+        LispyType::Vector { collection, .. } => collection.as_slice(),
+        _ => return,
+    };
+    let head = if form.is_list() { head_symbol(items) } else { None };
+    if matches!(head, Some("quote") | Some("quasi-quote")) {
+        return;
+    }
+    if head == Some("catch*") && items.len() == 3 {
+        warnings.push(Warning {
+            location: form.source_location().unwrap_or_default(),
+            message: "(catch* TYPE handler) is deprecated, use (catch* TYPE e handler)".to_string(),
+        });
+    }
+    if let Some(operator) = head.filter(|head| EQUALITY.contains(head)) {
+        if items[1..].iter().any(is_float) {
+            warnings.push(Warning {
+                location: form.source_location().unwrap_or_default(),
+                message: format!(
+                    "{} on floating point values is rarely true after rounding, use (approx= a b epsilon)",
+                    operator
+                ),
+            });
+        }
+    }
+    for item in items.iter() {
+        check(item, warnings);
+    }
+}
@@ -0,0 +1,44 @@
+use crate::types::LispyType;
+use std::cell::Cell;
+
+pub const LOCK_KIND: &str = "lock";
+
+// Values never leave the thread of the machine that made them and that machine
+// evaluates one thing at a time (generators included), so there is nothing for a
+// lock to exclude: `locking` never waits and a lock only counts how many times it
+// is currently held, for locked?. Holding it again from inside its own body is
+// allowed. Real exclusion is needed once values can be shared between threads.
+pub struct Lock {
+    holds: Cell<usize>,
+}
+
+pub fn create_lock() -> LispyType {
+    LispyType::create_resource(
+        LOCK_KIND,
+        Lock {
+            holds: Cell::new(0),
+        },
+    )
+}
+
+pub fn lock_arg(value: &LispyType) -> Result<&Lock, LispyType> {
+    match value.as_resource::<Lock>() {
+        Some(lock) => Ok(lock),
+        None => Err(LispyType::create_error(
+            format!("{} is not a lock", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+pub fn acquire(lock: &Lock) {
+    lock.holds.set(lock.holds.get() + 1);
+}
+
+pub fn release(lock: &Lock) {
+    lock.holds.set(lock.holds.get().saturating_sub(1));
+}
+
+pub fn is_locked(lock: &Lock) -> bool {
+    lock.holds.get() > 0
+}
@@ -1,24 +1,171 @@
-use crate::compile_source_code_to_ast;
+use crate::actor;
+use crate::cancel::{self, CancellationToken, InstalledToken};
+use crate::compiler::{
+    compile_source_file, install_runtime_limits, runtime_limits, InstalledLimits, ParseError, ParseLimits,
+};
+use crate::convert::{FromLispy, ToLispy};
+use crate::coverage;
 use crate::env::LispyEnv;
+use crate::lock::{acquire, lock_arg, release};
+use crate::namespace::{self, InstalledRegistry, NamespaceRegistry};
+use crate::record::Recorder;
+use crate::sandbox;
+use crate::stacktrace::{self, format_trace, Frame};
+use crate::stdlib::SearchPath;
+use crate::testing::{register_test, run_tests};
 use crate::types::LispyType;
+use crate::watchers::{self, InstalledWatchers, Watchers};
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::fs;
+use std::ops::{Deref, Range};
+use std::path::PathBuf;
+use std::rc::Rc;
 
 pub struct LispyMachine {
     env: LispyEnv,
+    recorder: Option<Recorder>,
+    namespaces: NamespaceRegistry,
+    // Applied to data read at runtime, e.g. by json/parse, and changed by set-parse-limits!
+    parse_limits: ParseLimits,
+    watchers: Watchers,
+    trace_macros: bool,
+    cancellation: CancellationToken,
+}
+
+// Configures a machine before the stdlib is loaded into it, for embedding lispy:
+//   LispyMachine::builder().with_native_fn("log", log).without_file_io().build()
+pub struct LispyMachineBuilder {
+    search_path: SearchPath,
+    stdlib: bool,
+    file_io: bool,
+    natives: Vec<(String, LispyType)>,
+    globals: Vec<(String, LispyType)>,
+    removed: Vec<String>,
+    parse_limits: ParseLimits,
+}
+
+impl LispyMachineBuilder {
+    // Searched for the stdlib and for required namespaces before the LISPY_PATH
+    // directories, in the order added
+    pub fn with_search_path(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.search_path.add(directory.into());
+        self
+    }
+
+    // Only the native builtins, core.lispy is not loaded
+    pub fn without_stdlib(mut self) -> Self {
+        self.stdlib = false;
+        self
+    }
+
+    // Protected like the builtins and also reachable as core/name
+    pub fn with_native_fn(
+        mut self,
+        name: &str,
+        func: impl Fn(Vec<LispyType>) -> Result<LispyType, LispyType> + 'static,
+    ) -> Self {
+        self.natives.push((name.to_string(), LispyType::create_function(None, func)));
+        self
+    }
+
+    // A plain root binding scripts may redefine, like *command-line-args*
+    pub fn with_global(mut self, name: &str, value: LispyType) -> Self {
+        self.globals.push((name.to_string(), value));
+        self
+    }
+
+    // Drops a builtin, e.g. one the scripts must not reach
+    pub fn without_builtin(mut self, name: &str) -> Self {
+        self.removed.push(name.to_string());
+        self
+    }
+
+    // No reading files from scripts: slurp is dropped and load-file, load-file-force
+    // and require fail with SANDBOXED. Files the embedder evaluates are unaffected.
+    pub fn without_file_io(mut self) -> Self {
+        self.file_io = false;
+        self
+    }
+
+    // Limits for data the scripts read at runtime, with compile-string, json/parse
+    // and the like, until they call set-parse-limits! themselves
+    pub fn with_parse_limits(mut self, limits: ParseLimits) -> Self {
+        self.parse_limits = limits;
+        self
+    }
+
+    pub fn build(self) -> LispyMachine {
+        let env = LispyEnv::root();
+        let namespaces = NamespaceRegistry::new(&env, self.search_path.clone());
+        let watchers = Watchers::new(&env);
+        let mut machine = LispyMachine {
+            env,
+            recorder: None,
+            namespaces,
+            parse_limits: self.parse_limits,
+            watchers,
+            trace_macros: false,
+            cancellation: CancellationToken::default(),
+        };
+
+        if self.stdlib {
+            let core = self.search_path.stdlib_source("core.lispy");
+            let (source, file) = core.expect("The stdlib is embedded, core.lispy can't be missing");
+            let loaded = machine.evaluate_source(source.as_str(), file.as_str());
+            if loaded.is_err() {
+                panic!("core.lispy failed to load: {}", loaded.err().unwrap());
+            }
+        }
+        for (name, func) in self.natives {
+            machine.env.set(name.as_str(), func);
+        }
+        machine.env.alias_namespace("core");
+
+        let mut removed = self.removed;
+        if !self.file_io {
+            removed.extend(sandbox::file_builtins().iter().map(|name| name.to_string()));
+            for form in sandbox::FILE_FORMS {
+                machine.env.deny_form(form);
+            }
+        }
+        for name in removed {
+            machine.env.remove(&name);
+            machine.env.remove(&format!("core/{}", name));
+        }
+        machine.env.protect_all();
+
+        for (name, value) in self.globals {
+            machine.env.set(name.as_str(), value);
+        }
+        machine
+    }
+}
+
+// " at file:line:col" for messages about `form`, empty when it was not read from source
+fn location_of(form: &LispyType) -> String {
+    form.source_location()
+        .map(|location| format!(" at {}", location))
+        .unwrap_or_default()
 }
 
 fn eval_ast(expression: &LispyType, env: &mut LispyEnv) -> Result<LispyType, LispyType> {
     match expression {
-        LispyType::Symbol { .. } => {
-            let result = env.get_item(expression.as_symbol().unwrap());
+        LispyType::Symbol { value, cache, .. } => {
+            let result = env
+                .get_item_cached(value, cache)
+                .or_else(|| namespace::resolve_qualified(value, env));
             return if result.is_some() {
                 Ok(result.unwrap().clone())
             } else {
                 Err(LispyType::Error {
-                    message: format!("Symbol {} is not defined", expression.as_symbol().unwrap())
-                        .to_string(),
+                    message: format!(
+                        "Symbol {} is not defined{}",
+                        expression.as_symbol().unwrap(),
+                        location_of(expression)
+                    ),
                     error_type: "NOT_DEFINED".to_string(),
+                    data: None,
                     meta: HashMap::new(),
                 })
             };
@@ -28,7 +175,7 @@ fn eval_ast(expression: &LispyType, env: &mut LispyEnv) -> Result<LispyType, Lis
             let mut collection: Vec<LispyType> = vec![];
 
             for index in 0..items.len() {
-                let evaluated = eval(&items.get(index).unwrap().clone(), env);
+                let evaluated = eval(items.get(index).unwrap(), env);
 
                 if evaluated.is_err() {
                     return evaluated;
@@ -42,6 +189,17 @@ fn eval_ast(expression: &LispyType, env: &mut LispyEnv) -> Result<LispyType, Lis
                 meta: HashMap::new(),
             })
         }
+        LispyType::Vector { collection: items, .. } => {
+            let mut collection: Vec<LispyType> = vec![];
+            for item in items.iter() {
+                let evaluated = eval(item, env);
+                if evaluated.is_err() {
+                    return Err(evaluated.err().unwrap());
+                }
+                collection.push(evaluated.unwrap());
+            }
+            Ok(LispyType::create_vector(collection))
+        }
         LispyType::Hash { .. } => {
             let mut collection = HashMap::new();
             for (key, value) in expression.as_hash().unwrap().iter() {
@@ -57,18 +215,312 @@ fn eval_ast(expression: &LispyType, env: &mut LispyEnv) -> Result<LispyType, Lis
                 meta: HashMap::new(),
             })
         }
+        // Literals evaluate to plain values, the source position stays with the code
+        LispyType::Number { value, .. } => Ok(LispyType::create_number(*value)),
+        LispyType::String { value, .. } => Ok(LispyType::create_string(value)),
+        LispyType::Keyword { value, .. } => Ok(LispyType::Keyword {
+            value: value.clone(),
+            meta: HashMap::new(),
+        }),
         _ => Ok(expression.clone()),
     }
 }
 
+fn protected_error(name: &str) -> LispyType {
+    LispyType::Error {
+        message: format!(
+            "{} is a protected core binding. Use (redefine! '{} ...) to overwrite it",
+            name, name
+        ),
+        error_type: "PROTECTED_BINDING".to_string(),
+        data: None,
+        meta: HashMap::new(),
+    }
+}
+
+fn constant_error(name: &str) -> LispyType {
+    LispyType::Error {
+        message: format!("{} is a constant and can't be redefined", name),
+        error_type: "CONSTANT_BINDING".to_string(),
+        data: None,
+        meta: HashMap::new(),
+    }
+}
+
+fn eval_when_compile_arity_error(received: usize) -> LispyType {
+    LispyType::create_error(
+        format!("eval-when-compile expects exactly one form, received {}", received).as_str(),
+        "INCORRECT_ARITY",
+    )
+}
+
+// Pre-pass run on every top-level form before it is evaluated: each
+// `(eval-when-compile expr)` is evaluated once and replaced by its quoted result,
+// so e.g. lookup tables inside a function body are not rebuilt on every call.
+// Rebuilt collections keep their meta and with it their source position.
+pub fn expand_compile_time(form: &LispyType, env: &mut LispyEnv) -> Result<LispyType, LispyType> {
+    match form {
+        LispyType::List { collection, meta } => {
+            match collection.first() {
+                Some(head) if head.is_symbol_containing("quote") => return Ok(form.clone()),
+                Some(head) if head.is_symbol_containing("eval-when-compile") => {
+                    if collection.len() != 2 {
+                        return Err(eval_when_compile_arity_error(collection.len() - 1));
+                    }
+                    let result = eval(collection.get(1).unwrap(), env);
+                    if result.is_err() {
+                        return Err(result.err().unwrap());
+                    }
+                    return Ok(LispyType::create_list(vec![
+                        LispyType::create_symbol("quote"),
+                        result.unwrap(),
+                    ]));
+                }
+                _ => {}
+            }
+            let mut expanded = vec![];
+            for item in collection.iter() {
+                let item = expand_compile_time(item, env);
+                if item.is_err() {
+                    return Err(item.err().unwrap());
+                }
+                expanded.push(item.unwrap());
+            }
+            Ok(LispyType::List {
+                collection: Box::from(expanded),
+                meta: meta.clone(),
+            })
+        }
+        LispyType::Vector { collection, meta } => {
+            let mut expanded = vec![];
+            for item in collection.iter() {
+                let item = expand_compile_time(item, env);
+                if item.is_err() {
+                    return Err(item.err().unwrap());
+                }
+                expanded.push(item.unwrap());
+            }
+            Ok(LispyType::Vector {
+                collection: Box::from(expanded),
+                meta: meta.clone(),
+            })
+        }
+        LispyType::Hash { collection, meta } => {
+            let mut expanded = HashMap::new();
+            for (key, value) in collection.iter() {
+                let value = expand_compile_time(value, env);
+                if value.is_err() {
+                    return Err(value.err().unwrap());
+                }
+                expanded.insert(key.clone(), value.unwrap());
+            }
+            Ok(LispyType::Hash {
+                collection: Box::from(expanded),
+                meta: meta.clone(),
+            })
+        }
+        _ => Ok(form.clone()),
+    }
+}
+
+// Binds a definition, then reports it to whatever watches `name`
+fn define(env: &mut LispyEnv, name: &String, value: LispyType) -> Result<(), LispyType> {
+    let previous = if watchers::is_watched(env, name) { Some(env.get_item(name)) } else { None };
+    env.set_item(name.clone(), value.clone());
+    match previous {
+        Some(previous) => watchers::notify(name, previous, Some(value)),
+        None => Ok(()),
+    }
+}
+
+fn destructure_error(form: &str, pattern: &LispyType, value: &LispyType) -> LispyType {
+    LispyType::create_error(
+        format!("{} can't destructure {} with {}", form, value, pattern).as_str(),
+        "INCORRECT_TYPE",
+    )
+}
+
+// Binds `pattern` to `value` in `env` for `let*`. A list or vector pattern binds its
+// items positionally, `& rest` taking the remaining ones, and a hash pattern binds
+// `{:keys (x y)}` to the :x and :y entries and `{name key}` to the entry at key.
+// Missing items and entries bind to nil, sequential patterns nest.
+pub fn destructure(
+    form: &str,
+    pattern: &LispyType,
+    value: LispyType,
+    env: &mut LispyEnv,
+) -> Result<(), LispyType> {
+    if pattern.is_symbol() {
+        env.set_item(pattern.as_symbol().unwrap().clone(), value);
+        return Ok(());
+    }
+
+    if let Some(targets) = pattern.as_sequential() {
+        let items = match value.as_sequential() {
+            Some(items) => items.to_vec(),
+            None if value.is_nil() => vec![],
+            None => return Err(destructure_error(form, pattern, &value)),
+        };
+        let spread = targets.iter().position(|target| target.is_symbol_containing("&"));
+        if spread.is_some() && spread.unwrap() + 2 != targets.len() {
+            return Err(LispyType::create_error(
+                format!("& must be followed by exactly one rest binding. Received {}", pattern).as_str(),
+                "INCORRECT_TYPE",
+            ));
+        }
+        let fixed = spread.unwrap_or(targets.len());
+        for (index, target) in targets.iter().take(fixed).enumerate() {
+            let item = items.get(index).cloned().unwrap_or_else(LispyType::create_nil);
+            let bound = destructure(form, target, item, env);
+            if bound.is_err() {
+                return Err(bound.err().unwrap());
+            }
+        }
+        if spread.is_some() {
+            let rest = items.get(fixed..).unwrap_or(&[]).to_vec();
+            return destructure(form, targets.last().unwrap(), LispyType::create_list(rest), env);
+        }
+        return Ok(());
+    }
+
+    if let Some(targets) = pattern.as_hash() {
+        let entries = match value.as_hash() {
+            Some(entries) => entries.as_ref().clone(),
+            None if value.is_nil() => HashMap::new(),
+            None => return Err(destructure_error(form, pattern, &value)),
+        };
+        let entry = |key: &LispyType| entries.get(key).cloned().unwrap_or_else(LispyType::create_nil);
+        for (target, key) in targets.iter() {
+            if target.as_keyword().map(String::as_str) != Some(":keys") {
+                let bound = destructure(form, target, entry(key), env);
+                if bound.is_err() {
+                    return Err(bound.err().unwrap());
+                }
+                continue;
+            }
+            let names = key.as_sequential().filter(|names| names.iter().all(|name| name.is_symbol()));
+            if names.is_none() {
+                return Err(LispyType::create_error(
+                    format!("{} :keys expects a list of symbols. Received: {}", form, key).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            for name in names.unwrap().iter() {
+                let name = name.as_symbol().unwrap();
+                let value = entry(&LispyType::create_keyword(format!(":{}", name).as_str()));
+                env.set_item(name.clone(), value);
+            }
+        }
+        return Ok(());
+    }
+
+    Err(LispyType::create_error(
+        format!("{} bindings key must be a symbol, list, vector or hash. Received: {}", form, pattern).as_str(),
+        "INCORRECT_TYPE",
+    ))
+}
+
+fn for_error(message: String) -> LispyType {
+    LispyType::create_error(message.as_str(), "INCORRECT_TYPE")
+}
+
+// Walks the clauses of `(for (clauses ...) body)` depth first, every combination of
+// bound values that passes the :when filters adds one evaluated body to `results`
+fn eval_for(
+    clauses: &[LispyType],
+    body: &LispyType,
+    env: &mut LispyEnv,
+    results: &mut Vec<LispyType>,
+) -> Result<(), LispyType> {
+    if clauses.is_empty() {
+        let evaluated = eval(body, env);
+        if evaluated.is_err() {
+            return Err(evaluated.err().unwrap());
+        }
+        results.push(evaluated.unwrap());
+        return Ok(());
+    }
+
+    if clauses[0].is_keyword() {
+        let modifier = clauses[0].as_keyword().unwrap().as_str();
+        let argument = clauses.get(1);
+        if argument.is_none() {
+            return Err(for_error(format!("for {} expects an argument", modifier)));
+        }
+        let argument = argument.unwrap();
+        return match modifier {
+            ":when" => {
+                let condition = eval(argument, env);
+                if condition.is_err() {
+                    return Err(condition.err().unwrap());
+                }
+                if !condition.unwrap().is_truthy() {
+                    return Ok(());
+                }
+                eval_for(&clauses[2..], body, env, results)
+            }
+            ":let" => {
+                let bindings = argument.as_sequential();
+                if bindings.is_none() || !bindings.unwrap().len().is_multiple_of(2) {
+                    return Err(for_error(format!(
+                        "for :let expects a list of key value pairs. Received: {}",
+                        argument
+                    )));
+                }
+                let mut n_env = LispyEnv::child(env);
+                for pair in bindings.unwrap().chunks(2) {
+                    if !pair[0].is_symbol() {
+                        return Err(for_error(format!(
+                            "for :let key must be a symbol. Received: {}",
+                            pair[0]
+                        )));
+                    }
+                    let evaluated = eval(&pair[1], &mut n_env);
+                    if evaluated.is_err() {
+                        return Err(evaluated.err().unwrap());
+                    }
+                    n_env.set_item(pair[0].as_symbol().unwrap().clone(), evaluated.unwrap());
+                }
+                eval_for(&clauses[2..], body, &mut n_env, results)
+            }
+            _ => Err(for_error(format!("Unknown for modifier {}", modifier))),
+        };
+    }
+
+    let binding = clauses[0].as_sequential();
+    if binding.is_none() || binding.unwrap().len() != 2 || !binding.unwrap()[0].is_symbol() {
+        return Err(for_error(format!(
+            "for binding must be a (symbol collection) pair. Received: {}",
+            clauses[0]
+        )));
+    }
+    let binding = binding.unwrap();
+    let collection = eval(&binding[1], env);
+    if collection.is_err() {
+        return Err(collection.err().unwrap());
+    }
+    let collection = collection.unwrap().coerce_to_list();
+    if collection.is_err() {
+        return Err(collection.err().unwrap());
+    }
+    for item in collection.unwrap().as_list().unwrap().iter() {
+        let mut n_env = LispyEnv::child(env);
+        n_env.set_item(binding[0].as_symbol().unwrap().clone(), item.clone());
+        let result = eval_for(&clauses[1..], body, &mut n_env, results);
+        if result.is_err() {
+            return Err(result.err().unwrap());
+        }
+    }
+    Ok(())
+}
+
 pub fn quasi_quote(ast: &LispyType) -> LispyType {
     if ast.is_list()
         && ast
             .as_list()
             .unwrap()
-            .get(0)
-            .unwrap()
-            .is_symbol_containing("unquote")
+            .first()
+            .is_some_and(|first| first.is_symbol_containing("unquote"))
     {
         return ast.as_list().unwrap().get(1).unwrap().clone();
     }
@@ -79,9 +531,8 @@ pub fn quasi_quote(ast: &LispyType) -> LispyType {
                 && elt
                     .as_list()
                     .unwrap()
-                    .get(0)
-                    .unwrap()
-                    .is_symbol_containing("splice-unquote")
+                    .first()
+                    .is_some_and(|first| first.is_symbol_containing("splice-unquote"))
             {
                 result = vec![
                     LispyType::create_symbol("concat"),
@@ -98,6 +549,12 @@ pub fn quasi_quote(ast: &LispyType) -> LispyType {
         }
         return LispyType::create_list(result);
     }
+    if ast.is_vector() {
+        return LispyType::create_list(vec![
+            LispyType::create_symbol("vec"),
+            quasi_quote(&LispyType::create_list(ast.as_vector().unwrap().to_vec())),
+        ]);
+    }
     if ast.is_hash() || ast.is_symbol() {
         return LispyType::create_list(vec![LispyType::create_symbol("quote"), ast.clone()]);
     }
@@ -112,31 +569,78 @@ pub fn is_macro_call(ast: &LispyType, env: &LispyEnv) -> bool {
         return false;
     }
 
-    let item = ast.as_list().unwrap().first().unwrap().clone();
+    let item = ast.as_list().unwrap().first().unwrap();
     if !item.is_symbol() {
         return false;
     }
-    let in_env = env.get_item(item.as_symbol().unwrap());
+    let in_env = match item {
+        LispyType::Symbol { value, cache, .. } => env.get_item_cached(value, cache),
+        _ => None,
+    };
     if in_env.is_none() {
         return false;
     }
     in_env.unwrap().is_macro()
 }
 
+thread_local! {
+    // --trace-macros of the machine evaluating on this thread: every expansion is
+    // printed to stderr as it happens
+    static TRACE_MACROS: Cell<bool> = const { Cell::new(false) };
+}
+
+// Puts back the flag of whichever machine was evaluating before
+struct InstalledTraceMacros {
+    previous: bool,
+}
+
+impl Drop for InstalledTraceMacros {
+    fn drop(&mut self) {
+        TRACE_MACROS.with(|trace| trace.set(self.previous));
+    }
+}
+
+fn install_trace_macros(trace: bool) -> InstalledTraceMacros {
+    InstalledTraceMacros {
+        previous: TRACE_MACROS.with(|cell| cell.replace(trace)),
+    }
+}
+
+// Expands `ast` once when it is a macro call, returns it unchanged otherwise
+pub fn macro_expand_1(ast: &LispyType, env: &LispyEnv) -> Result<LispyType, LispyType> {
+    if !is_macro_call(ast, env) {
+        return Ok(ast.clone());
+    }
+    let callee_symbol = ast.as_list().unwrap().first().unwrap().clone();
+    let callee = env.get_item(callee_symbol.as_symbol().unwrap()).unwrap();
+    let len = ast.as_list().unwrap().len();
+    let args: Vec<LispyType> = ast.as_list().unwrap().get(1..len).unwrap().into();
+    let result = callee.apply_lambda(args);
+    if result.is_err() {
+        return Err(result.err().unwrap());
+    }
+    let mut unwrapped = result.unwrap();
+    let result = eval(&unwrapped.0, &mut unwrapped.1);
+    if result.is_err() {
+        return Err(result.err().unwrap());
+    }
+    let expanded = result.unwrap();
+    if TRACE_MACROS.with(|trace| trace.get()) {
+        eprintln!(
+            "macro-expand: {} => {}",
+            ast.to_readable_string(),
+            expanded.to_readable_string()
+        );
+    }
+    Ok(expanded)
+}
+
+// Expands `ast` until its head is no longer a macro, nested forms are left alone
 pub fn macro_expand(ast: &LispyType, passed_env: &LispyEnv) -> Result<LispyType, LispyType> {
     let mut ast = ast.clone();
     let env = passed_env.clone();
     while is_macro_call(&ast, &env) {
-        let callee_symbol = ast.as_list().unwrap().first().unwrap().clone();
-        let callee = env.get_item(callee_symbol.as_symbol().unwrap()).unwrap();
-        let len = ast.as_list().unwrap().len();
-        let args: Vec<LispyType> = ast.as_list().unwrap().get(1..len).unwrap().into();
-        let result = callee.apply_lambda(args);
-        if result.is_err() {
-            return Err(result.err().unwrap());
-        }
-        let mut unwrapped = result.unwrap();
-        let result = eval(&unwrapped.0, &mut unwrapped.1);
+        let result = macro_expand_1(&ast, &env);
         if result.is_err() {
             return result;
         }
@@ -146,33 +650,328 @@ pub fn macro_expand(ast: &LispyType, passed_env: &LispyEnv) -> Result<LispyType,
     Ok(ast)
 }
 
-pub fn eval(
+// Expands `ast` and then every form nested in it. Quoted data, fn* parameters
+// and the names bound by let*, let and loop are not expanded.
+pub fn macro_expand_all(ast: &LispyType, env: &LispyEnv) -> Result<LispyType, LispyType> {
+    let expanded = macro_expand(ast, env);
+    if expanded.is_err() {
+        return Err(expanded.err().unwrap());
+    }
+    let expanded = expanded.unwrap();
+    let (items, meta) = match &expanded {
+        LispyType::List { collection, meta } if !collection.is_empty() => (collection, meta),
+        LispyType::Vector { collection, meta } => {
+            let mut walked = vec![];
+            for item in collection.iter() {
+                let result = macro_expand_all(item, env);
+                if result.is_err() {
+                    return Err(result.err().unwrap());
+                }
+                walked.push(result.unwrap());
+            }
+            return Ok(LispyType::Vector {
+                collection: Box::new(walked),
+                meta: meta.clone(),
+            });
+        }
+        _ => return Ok(expanded),
+    };
+
+    let head = items[0].as_symbol().map(|head| head.as_str());
+    if head == Some("quote") || head == Some("quasi-quote") {
+        return Ok(expanded);
+    }
+    let mut walked = vec![];
+    for (index, item) in items.iter().enumerate() {
+        let result = match head {
+            Some("fn*") if index == 1 => Ok(item.clone()),
+            Some("let*") | Some("let") | Some("loop") if index == 1 && item.as_sequential().is_some() => {
+                expand_binding_values(item, env)
+            }
+            _ => macro_expand_all(item, env),
+        };
+        if result.is_err() {
+            return Err(result.err().unwrap());
+        }
+        walked.push(result.unwrap());
+    }
+    Ok(LispyType::List {
+        collection: Box::new(walked),
+        meta: meta.clone(),
+    })
+}
+
+// The values of a `(name value ...)` binding list, names are kept as written
+fn expand_binding_values(bindings: &LispyType, env: &LispyEnv) -> Result<LispyType, LispyType> {
+    let mut walked = vec![];
+    for (index, item) in bindings.as_sequential().unwrap().iter().enumerate() {
+        if index % 2 == 0 {
+            walked.push(item.clone());
+            continue;
+        }
+        let result = macro_expand_all(item, env);
+        if result.is_err() {
+            return Err(result.err().unwrap());
+        }
+        walked.push(result.unwrap());
+    }
+    Ok(match bindings {
+        LispyType::Vector { meta, .. } => LispyType::Vector {
+            collection: Box::new(walked),
+            meta: meta.clone(),
+        },
+        _ => LispyType::create_list(walked),
+    })
+}
+
+// The form eval is working on: the caller's until a tail position replaces it.
+// Lambda bodies are shared with the lambda instead of copied on every call, and
+// tail positions inside a form are reached by their path in it instead of copied out.
+#[derive(Clone)]
+enum Form<'a> {
+    Borrowed(&'a LispyType),
+    Shared(Rc<LispyType>, Vec<usize>),
+}
+
+impl<'a> Form<'a> {
+    fn owned(form: LispyType) -> Self {
+        Form::Shared(Rc::new(form), vec![])
+    }
+
+    // Item `index` of this list form
+    fn into_item(self, index: usize) -> Self {
+        match self {
+            Form::Borrowed(form) => Form::Borrowed(&form.as_list().unwrap()[index]),
+            Form::Shared(form, mut path) => {
+                path.push(index);
+                Form::Shared(form, path)
+            }
+        }
+    }
+}
+
+impl Deref for Form<'_> {
+    type Target = LispyType;
+
+    fn deref(&self) -> &LispyType {
+        match self {
+            Form::Borrowed(form) => form,
+            Form::Shared(form, path) => path
+                .iter()
+                .fold(form.as_ref(), |form, index| &form.as_list().unwrap()[*index]),
+        }
+    }
+}
+
+fn catch_error(clause: &LispyType) -> LispyType {
+    LispyType::create_error(
+        format!(
+            "try* clauses must be (catch* TYPE e handler) or a last (finally ...). Received: {}",
+            clause
+        )
+        .as_str(),
+        "SYNTAX_ERROR",
+    )
+}
+
+fn is_finally_clause(clause: &LispyType) -> bool {
+    clause
+        .as_list()
+        .and_then(|parts| parts.first())
+        .is_some_and(|head| head.is_symbol_containing("finally"))
+}
+
+// Whether the catch* `clause` handles `error`. TYPE is the error type's name, as
+// given to deferror!, or _ for anything thrown. The deprecated three item form
+// still evaluates TYPE and compares it with the error.
+fn catch_matches(clause: &LispyType, error: &LispyType, env: &mut LispyEnv) -> Result<bool, LispyType> {
+    let parts = match clause.as_list() {
+        Some(parts) if (parts.len() == 3 || parts.len() == 4) && parts[0].is_symbol_containing("catch*") => parts,
+        _ => return Err(catch_error(clause)),
+    };
+    if parts.len() == 3 {
+        let expected = eval_ast(&parts[1], env);
+        if expected.is_err() {
+            return Err(expected.err().unwrap());
+        }
+        return Ok(expected.unwrap() == *error);
+    }
+    let name = parts[1].as_symbol().or_else(|| parts[1].as_string());
+    if name.is_none() {
+        return Err(catch_error(clause));
+    }
+    let name = name.unwrap();
+    Ok(name == "_" || error.as_error().is_some_and(|error| &error.error_type == name))
+}
+
+// Index of the first try* item in `clauses` handling `error`. return-from only
+// unwinds as an error, no catch* may stop it before its block does.
+fn find_catch(
+    items: &[LispyType],
+    clauses: Range<usize>,
+    error: &LispyType,
+    env: &mut LispyEnv,
+) -> Result<Option<usize>, LispyType> {
+    if error.as_error().is_some_and(|error| error.error_type == "RETURN_FROM") {
+        return Ok(None);
+    }
+    for index in clauses {
+        let matched = catch_matches(&items[index], error, env);
+        if matched.is_err() {
+            return Err(matched.err().unwrap());
+        }
+        if matched.unwrap() {
+            return Ok(Some(index));
+        }
+    }
+    Ok(None)
+}
+
+// The env a catch* handler runs in and the handler's position in the clause. The
+// deprecated (catch* TYPE handler) binds nothing and runs in the try*'s own env.
+fn bind_caught(
+    clause: &LispyType,
+    error: LispyType,
+    env: &mut LispyEnv,
+) -> Result<(LispyEnv, usize), LispyType> {
+    let parts = clause.as_list().unwrap();
+    if parts.len() == 3 {
+        return Ok((env.clone(), 2));
+    }
+    let mut n_env = LispyEnv::child(env);
+    let bound = destructure("catch*", &parts[2], error, &mut n_env);
+    if bound.is_err() {
+        return Err(bound.err().unwrap());
+    }
+    Ok((n_env, 3))
+}
+
+// The innermost loop a tail position recur jumps back to: the loop form, its
+// binding patterns and the env the loop was entered from
+struct LoopFrame<'a> {
+    form: Form<'a>,
+    patterns: Vec<LispyType>,
+    outer: LispyEnv,
+}
+
+pub fn recur_error(form: &LispyType) -> LispyType {
+    LispyType::create_error(
+        format!("recur must be in tail position of a loop. Received: {}", form).as_str(),
+        "SYNTAX_ERROR",
+    )
+}
+
+// Rejects any recur in `form` outside of a tail position, macro calls are expanded
+// first. A nested loop's body is checked when that loop is entered.
+pub fn check_recur(form: &LispyType, tail: bool, env: &LispyEnv) -> Result<(), LispyType> {
+    if is_macro_call(form, env) {
+        let expanded = macro_expand(form, env);
+        if expanded.is_err() {
+            return Err(expanded.err().unwrap());
+        }
+        return check_recur(&expanded.unwrap(), tail, env);
+    }
+    if form.is_vector() {
+        for item in form.as_sequential().unwrap().iter() {
+            let checked = check_recur(item, false, env);
+            if checked.is_err() {
+                return Err(checked.err().unwrap());
+            }
+        }
+        return Ok(());
+    }
+    let items = match form.as_list() {
+        Some(items) if !items.is_empty() => items,
+        _ => return Ok(()),
+    };
+    let last = items.len() - 1;
+    let in_tail: Vec<bool> = match items[0].as_symbol().map(|head| head.as_str()) {
+        Some("quote") | Some("quasi-quote") => return Ok(()),
+        Some("recur") if !tail => return Err(recur_error(form)),
+        Some("loop") => return check_recur(&items[1], false, env),
+        Some("if") => (0..items.len()).map(|index| tail && index >= 2).collect(),
+        Some("do") => (0..items.len()).map(|index| tail && index == last).collect(),
+        Some("let*") | Some("let") => (0..items.len()).map(|index| tail && index == 2).collect(),
+        _ => vec![false; items.len()],
+    };
+    for (item, tail) in items.iter().zip(in_tail) {
+        let checked = check_recur(item, tail, env);
+        if checked.is_err() {
+            return Err(checked.err().unwrap());
+        }
+    }
+    Ok(())
+}
+
+// Evaluates a loop body up to its tail form, which is left for eval to continue with
+fn loop_body<'a>(frame: &LoopFrame<'a>, env: &mut LispyEnv) -> Result<Option<Form<'a>>, LispyType> {
+    let last = frame.form.as_list().unwrap().len() - 1;
+    if last < 2 {
+        return Ok(None);
+    }
+    for index in 2..last {
+        let evaluated = eval(&frame.form.as_list().unwrap()[index], env);
+        if evaluated.is_err() {
+            return Err(evaluated.err().unwrap());
+        }
+    }
+    Ok(Some(frame.form.clone().into_item(last)))
+}
+
+pub fn eval(expression: &LispyType, env: &mut LispyEnv) -> Result<LispyType, LispyType> {
+    let depth = stacktrace::depth();
+    let result = eval_form(expression, env, depth).map_err(|error| stacktrace::attach(error, None));
+    stacktrace::leave(depth);
+    result
+}
+
+// Applications made here own the call stack frame at `depth`
+fn eval_form(
     passed_expression: &LispyType,
     passed_env: &mut LispyEnv,
+    depth: usize,
 ) -> Result<LispyType, LispyType> {
     let mut env = passed_env.clone();
-    let mut expression = passed_expression.clone();
+    let mut expression = Form::Borrowed(passed_expression);
+    let mut recur_target: Option<LoopFrame> = None;
 
     loop {
-        match expression {
+        if cancel::is_cancelled() {
+            return Err(cancel::interrupted());
+        }
+        let stepped = sandbox::step();
+        if stepped.is_err() {
+            return Err(stepped.err().unwrap());
+        }
+        let timely = actor::check_deadline();
+        if timely.is_err() {
+            return Err(timely.err().unwrap());
+        }
+        match *expression {
             LispyType::List { .. } => {
-                let macro_expand_result = macro_expand(&expression, &mut env);
+                if is_macro_call(&expression, &env) {
+                    let macro_expand_result = macro_expand(&expression, &mut env);
 
-                if macro_expand_result.is_err() {
-                    return macro_expand_result;
-                }
+                    if macro_expand_result.is_err() {
+                        return Err(macro_expand_result.err().unwrap());
+                    }
 
-                if !macro_expand_result.as_ref().unwrap().is_list() {
-                    return eval_ast(macro_expand_result.as_ref().unwrap(), &mut env);
+                    if !macro_expand_result.as_ref().unwrap().is_list() {
+                        return eval_ast(macro_expand_result.as_ref().unwrap(), &mut env);
+                    }
+                    expression = Form::owned(macro_expand_result.unwrap());
                 }
-                expression = macro_expand_result.unwrap();
 
                 if expression.as_list().unwrap().is_empty() {
-                    return Ok(expression.clone());
+                    return Ok(LispyType::clone(&expression));
                 }
 
                 let first = expression.as_list().unwrap().first().unwrap();
                 if first.is_symbol() {
+                    let allowed = sandbox::check_form(first.as_symbol().unwrap(), &env);
+                    if allowed.is_err() {
+                        return Err(allowed.err().unwrap());
+                    }
                     match first.as_symbol().unwrap().as_str() {
                         "def!" => {
                             let key = expression.as_list().unwrap().get(1).unwrap().clone();
@@ -185,36 +984,408 @@ pub fn eval(
                                         key
                                     ),
                                     error_type: "INCORRECT_TYPE".to_string(),
+                                    data: None,
                                     meta: HashMap::new(),
                                 });
                             }
 
+                            let name = key.as_symbol().unwrap();
+                            if env.is_protected(name) {
+                                return Err(protected_error(name));
+                            }
+                            if env.is_constant(name) {
+                                return Err(constant_error(name));
+                            }
+
                             let evaluated = eval(&value, &mut env);
                             if evaluated.is_err() {
                                 return evaluated;
                             }
 
-                            env.set_item(
-                                key.as_symbol().unwrap().clone(),
-                                evaluated.as_ref().unwrap().clone(),
-                            );
-                            passed_env.modify_with(&env);
+                            if !name.contains('/')
+                                && env.get_item(&format!("core/{}", name)).is_some()
+                            {
+                                eprintln!("WARNING: {} shadows core/{}", name, name);
+                            }
+
+                            let defined = define(&mut env, name, evaluated.as_ref().unwrap().clone());
+                            if defined.is_err() {
+                                return Err(defined.err().unwrap());
+                            }
                             return evaluated;
                         }
-                        "defmacro!" => {
-                            let key = expression.as_list().unwrap().get(1).unwrap().clone();
-                            let value = expression.as_list().unwrap().get(2).unwrap().clone();
+                        "defn!" => {
+                            // (defn! name "docstring"? (params) body...)
+                            let items = expression.as_list().unwrap();
+                            let name = items.get(1).and_then(|name| name.as_symbol());
+                            if name.is_none() {
+                                return Err(LispyType::create_error(
+                                    "defn! first arg must be a symbol",
+                                    "INCORRECT_TYPE",
+                                ));
+                            }
+                            let name = name.unwrap();
+                            if env.is_protected(name) {
+                                return Err(protected_error(name));
+                            }
+                            if env.is_constant(name) {
+                                return Err(constant_error(name));
+                            }
+
+                            let doc = items.get(2).filter(|doc| doc.is_string());
+                            let params_index = if doc.is_some() { 3 } else { 2 };
+                            let bindings = items.get(params_index).and_then(|params| params.as_sequential());
+                            if bindings.is_none() || items.len() == params_index + 1 {
+                                return Err(LispyType::create_error(
+                                    format!("defn! {} expects a parameter list and a body", name).as_str(),
+                                    "INCORRECT_TYPE",
+                                ));
+                            }
+                            // Several body forms run in a do, which takes the position of the defn!
+                            let body = &items[params_index + 1..];
+                            let to_eval = if body.len() == 1 {
+                                body[0].clone()
+                            } else {
+                                let mut forms = vec![LispyType::create_symbol("do")];
+                                forms.extend(body.iter().cloned());
+                                LispyType::List {
+                                    collection: Box::from(forms),
+                                    meta: expression.meta().clone(),
+                                }
+                            };
 
+                            let mut meta = HashMap::new();
+                            if let Some(doc) = doc {
+                                meta.insert("doc".to_string(), doc.clone());
+                            }
+                            let lambda = LispyType::Lambda {
+                                bindings: Rc::new(bindings.unwrap().to_vec()),
+                                to_eval: Rc::new(to_eval),
+                                env: Box::new(env.clone()),
+                                meta,
+                                is_macro: false,
+                            };
+                            let defined = define(&mut env, name, lambda.clone());
+                            if defined.is_err() {
+                                return Err(defined.err().unwrap());
+                            }
+                            return Ok(lambda);
+                        }
+                        "doc" => {
+                            // Prints the name, the parameters of lambdas and the docstring of defn!
+                            let key = expression.as_list().unwrap().get(1).cloned();
+                            if key.as_ref().is_none_or(|key| !key.is_symbol()) {
+                                return Err(LispyType::create_error(
+                                    "doc expects a symbol",
+                                    "INCORRECT_TYPE",
+                                ));
+                            }
+                            let key = key.unwrap();
+                            let value = eval_ast(&key, &mut env);
+                            if value.is_err() {
+                                return Err(value.err().unwrap());
+                            }
+                            let value = value.unwrap();
+                            println!("{}", key.as_symbol().unwrap());
+                            if let LispyType::Lambda { bindings, .. } = &value {
+                                println!("{}", LispyType::create_list(bindings.to_vec()).to_readable_string());
+                            }
+                            match value.meta().get("doc").and_then(|doc| doc.as_string()) {
+                                Some(doc) => println!("  {}", doc),
+                                None => println!("  No documentation"),
+                            }
+                            return Ok(LispyType::create_nil());
+                        }
+                        "redefine!" => {
+                            if expression.as_list().unwrap().len() != 3 {
+                                return Err(LispyType::create_error(
+                                    "redefine! expects a symbol and a value",
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            let key = expression.as_list().unwrap().get(1).unwrap().clone();
+                            let key = eval(&key, &mut env);
+                            if key.is_err() {
+                                return Err(key.err().unwrap());
+                            }
+                            let key = key.unwrap();
                             if !key.is_symbol() {
                                 return Err(LispyType::Error {
                                     message: format!(
-                                        "defmacro! first arg must be a symbol. Received: {}",
+                                        "redefine! first arg must be a symbol. Received: {}",
                                         key
                                     ),
                                     error_type: "INCORRECT_TYPE".to_string(),
+                                    data: None,
                                     meta: HashMap::new(),
                                 });
                             }
+                            if env.is_constant(key.as_symbol().unwrap()) {
+                                return Err(constant_error(key.as_symbol().unwrap()));
+                            }
+
+                            let value = expression.as_list().unwrap().get(2).unwrap().clone();
+                            let evaluated = eval(&value, &mut env);
+                            if evaluated.is_err() {
+                                return Err(evaluated.err().unwrap());
+                            }
+
+                            let defined = define(
+                                &mut env,
+                                key.as_symbol().unwrap(),
+                                evaluated.as_ref().unwrap().clone(),
+                            );
+                            if defined.is_err() {
+                                return Err(defined.err().unwrap());
+                            }
+                            return evaluated;
+                        }
+                        "refer" => {
+                            if expression.as_list().unwrap().len() < 2 {
+                                return Err(LispyType::create_error(
+                                    "refer expects a namespace",
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            let namespace = expression.as_list().unwrap().get(1).unwrap().clone();
+                            if !namespace.is_symbol() {
+                                return Err(LispyType::Error {
+                                    message: format!(
+                                        "refer first arg must be a symbol. Received: {}",
+                                        namespace
+                                    ),
+                                    error_type: "INCORRECT_TYPE".to_string(),
+                                    data: None,
+                                    meta: HashMap::new(),
+                                });
+                            }
+                            let namespace = namespace.as_symbol().unwrap();
+
+                            // (refer core :only (count map) :rename {first head})
+                            let mut referrals = vec![];
+                            let options = expression.as_list().unwrap()[2..].to_vec();
+                            for option in options.chunks(2) {
+                                let names = option.get(1);
+                                match (option[0].as_keyword().map(|k| k.as_str()), names) {
+                                    (Some(":only"), Some(LispyType::List { collection, .. })) => {
+                                        for name in collection.iter() {
+                                            referrals.push((name.clone(), name.clone()));
+                                        }
+                                    }
+                                    (Some(":rename"), Some(LispyType::Hash { collection, .. })) => {
+                                        for (from, to) in collection.iter() {
+                                            referrals.push((from.clone(), to.clone()));
+                                        }
+                                    }
+                                    _ => {
+                                        return Err(LispyType::Error {
+                                            message: format!(
+                                                "refer expects :only (symbols) or :rename {{symbol symbol}}. Received: {}",
+                                                option[0]
+                                            ),
+                                            error_type: "INCORRECT_TYPE".to_string(),
+                                            data: None,
+                                            meta: HashMap::new(),
+                                        });
+                                    }
+                                }
+                            }
+
+                            for (from, to) in referrals {
+                                if !from.is_symbol() || !to.is_symbol() {
+                                    return Err(LispyType::Error {
+                                        message: format!(
+                                            "refer names must be symbols. Received: {} {}",
+                                            from, to
+                                        ),
+                                        error_type: "INCORRECT_TYPE".to_string(),
+                                        data: None,
+                                        meta: HashMap::new(),
+                                    });
+                                }
+                                let qualified =
+                                    format!("{}/{}", namespace, from.as_symbol().unwrap());
+                                let value = env
+                                    .get_item(&qualified)
+                                    .or_else(|| namespace::resolve_qualified(&qualified, &env));
+                                if value.is_none() {
+                                    return Err(LispyType::Error {
+                                        message: format!(
+                                            "Symbol {} is not defined{}",
+                                            qualified,
+                                            location_of(&from)
+                                        ),
+                                        error_type: "NOT_DEFINED".to_string(),
+                                        data: None,
+                                        meta: HashMap::new(),
+                                    });
+                                }
+                                env.set_item(to.as_symbol().unwrap().clone(), value.unwrap());
+                            }
+
+                            return Ok(LispyType::create_nil());
+                        }
+                        "ns" => {
+                            let name = expression.as_list().unwrap().get(1).cloned();
+                            if name.as_ref().is_none_or(|name| !name.is_symbol()) {
+                                return Err(LispyType::create_error(
+                                    "ns expects a symbol",
+                                    "INCORRECT_TYPE",
+                                ));
+                            }
+                            let switched = namespace::switch_namespace(name.unwrap().as_symbol().unwrap());
+                            if switched.is_err() {
+                                return Err(switched.err().unwrap());
+                            }
+                            return Ok(LispyType::create_nil());
+                        }
+                        "require" | "load-file" | "load-file-force" => {
+                            let form = first.as_symbol().unwrap().clone();
+                            let argument = expression.as_list().unwrap().get(1).cloned();
+                            if argument.is_none() {
+                                return Err(LispyType::create_error(
+                                    format!("{} expects an argument", form).as_str(),
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            let argument = eval(&argument.unwrap(), &mut env);
+                            if argument.is_err() {
+                                return Err(argument.err().unwrap());
+                            }
+                            let argument = argument.unwrap();
+                            if form != "require" {
+                                if !argument.is_string() {
+                                    return Err(LispyType::create_error(
+                                        format!("{} expects a path. Received {}", form, argument).as_str(),
+                                        "INCORRECT_TYPE",
+                                    ));
+                                }
+                                let force = form == "load-file-force";
+                                return namespace::load_file(argument.as_string().unwrap(), force);
+                            }
+                            if !argument.is_symbol() {
+                                return Err(LispyType::create_error(
+                                    format!("require expects a symbol. Received {}", argument).as_str(),
+                                    "INCORRECT_TYPE",
+                                ));
+                            }
+                            let required = namespace::require(argument.as_symbol().unwrap());
+                            if required.is_err() {
+                                return Err(required.err().unwrap());
+                            }
+                            return Ok(LispyType::create_nil());
+                        }
+                        "safe-eval" => {
+                            // (safe-eval form allowed-symbols max-steps?)
+                            let mut args = vec![];
+                            for arg in expression.as_list().unwrap()[1..].iter() {
+                                let evaluated = eval(arg, &mut env);
+                                if evaluated.is_err() {
+                                    return Err(evaluated.err().unwrap());
+                                }
+                                args.push(evaluated.unwrap());
+                            }
+                            if args.len() < 2 || args.len() > 3 {
+                                return Err(LispyType::create_error(
+                                    format!("safe-eval expects 2 or 3 arguments, received {}", args.len()).as_str(),
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            let allowed = args[1].as_sequential().filter(|names| names.iter().all(|name| name.is_symbol()));
+                            let steps = match args.get(2) {
+                                Some(steps) => steps.as_number().filter(|steps| **steps >= 0.0).map(|steps| *steps as u64),
+                                None => Some(sandbox::DEFAULT_STEP_LIMIT),
+                            };
+                            if allowed.is_none() || steps.is_none() {
+                                return Err(LispyType::create_error(
+                                    "safe-eval expects a list of symbols and a step count",
+                                    "INCORRECT_TYPE",
+                                ));
+                            }
+
+                            // Only the allowed bindings, as the caller sees them, not even builtins
+                            let mut sandbox_env = LispyEnv::empty();
+                            for name in allowed.unwrap().iter() {
+                                let value = eval_ast(name, &mut env);
+                                if value.is_err() {
+                                    return Err(value.err().unwrap());
+                                }
+                                sandbox_env.set_item(name.as_symbol().unwrap().clone(), value.unwrap());
+                            }
+                            return sandbox::run(steps.unwrap(), || eval(&args[0], &mut sandbox_env));
+                        }
+                        "defconst" => {
+                            if expression.as_list().unwrap().len() != 3 {
+                                return Err(LispyType::create_error(
+                                    "defconst expects a name and a value",
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            let key = expression.as_list().unwrap()[1].clone();
+                            let value = expression.as_list().unwrap()[2].clone();
+
+                            if !key.is_symbol() {
+                                return Err(LispyType::Error {
+                                    message: format!(
+                                        "defconst first arg must be a symbol. Received: {}",
+                                        key
+                                    ),
+                                    error_type: "INCORRECT_TYPE".to_string(),
+                                    data: None,
+                                    meta: HashMap::new(),
+                                });
+                            }
+                            let name = key.as_symbol().unwrap();
+                            if env.is_protected(name) {
+                                return Err(protected_error(name));
+                            }
+                            if env.is_constant(name) {
+                                return Err(constant_error(name));
+                            }
+
+                            let evaluated = eval(&value, &mut env);
+                            if evaluated.is_err() {
+                                return Err(evaluated.err().unwrap());
+                            }
+
+                            let defined = define(&mut env, name, evaluated.as_ref().unwrap().clone());
+                            if defined.is_err() {
+                                return Err(defined.err().unwrap());
+                            }
+                            env.mark_constant(name);
+                            return evaluated;
+                        }
+                        "eval-when-compile" => {
+                            // Only reached for forms that skipped the pre-pass, e.g. built by
+                            // macros at runtime; those are evaluated in place
+                            let length = expression.as_list().unwrap().len();
+                            if length != 2 {
+                                return Err(eval_when_compile_arity_error(length - 1));
+                            }
+                            expression = expression.into_item(1);
+                            continue;
+                        }
+                        "defmacro!" => {
+                            let key = expression.as_list().unwrap().get(1).unwrap().clone();
+                            let value = expression.as_list().unwrap().get(2).unwrap().clone();
+
+                            if !key.is_symbol() {
+                                return Err(LispyType::Error {
+                                    message: format!(
+                                        "defmacro! first arg must be a symbol. Received: {}",
+                                        key
+                                    ),
+                                    error_type: "INCORRECT_TYPE".to_string(),
+                                    data: None,
+                                    meta: HashMap::new(),
+                                });
+                            }
+                            if env.is_protected(key.as_symbol().unwrap()) {
+                                return Err(protected_error(key.as_symbol().unwrap()));
+                            }
+                            if env.is_constant(key.as_symbol().unwrap()) {
+                                return Err(constant_error(key.as_symbol().unwrap()));
+                            }
 
                             let evaluated = eval(&value, &mut env);
                             if evaluated.is_err() {
@@ -227,31 +1398,79 @@ pub fn eval(
                                         key
                                     ),
                                     error_type: "INCORRECT_TYPE".to_string(),
+                                    data: None,
                                     meta: HashMap::new(),
                                 });
                             }
 
                             let evaluated = evaluated.unwrap().convert_to_macro();
 
-                            env.set_item(key.as_symbol().unwrap().clone(), evaluated.clone());
-                            passed_env.modify_with(&env);
+                            let defined = define(&mut env, key.as_symbol().unwrap(), evaluated.clone());
+                            if defined.is_err() {
+                                return Err(defined.err().unwrap());
+                            }
                             return Ok(evaluated);
                         }
+                        "undef!" => {
+                            if expression.as_list().unwrap().len() != 2 {
+                                return Err(LispyType::create_error(
+                                    "undef! expects a symbol",
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            let key = expression.as_list().unwrap().get(1).unwrap().clone();
+                            let key = eval(&key, &mut env);
+                            if key.is_err() {
+                                return Err(key.err().unwrap());
+                            }
+                            let key = key.unwrap();
+                            if !key.is_symbol() {
+                                return Err(LispyType::Error {
+                                    message: format!(
+                                        "undef! first arg must be a symbol. Received: {}",
+                                        key
+                                    ),
+                                    error_type: "INCORRECT_TYPE".to_string(),
+                                    data: None,
+                                    meta: HashMap::new(),
+                                });
+                            }
+
+                            if env.is_protected(key.as_symbol().unwrap()) {
+                                return Err(protected_error(key.as_symbol().unwrap()));
+                            }
+                            if env.is_constant(key.as_symbol().unwrap()) {
+                                return Err(constant_error(key.as_symbol().unwrap()));
+                            }
+
+                            let watched = watchers::is_watched(&env, key.as_symbol().unwrap());
+                            let removed = env.remove(key.as_symbol().unwrap());
+                            if watched && removed.is_some() {
+                                let notified = watchers::notify(key.as_symbol().unwrap(), removed.clone(), None);
+                                if notified.is_err() {
+                                    return Err(notified.err().unwrap());
+                                }
+                            }
+                            return Ok(LispyType::create_bool(removed.is_some()));
+                        }
                         "deferror!" => {
-                            let symbol = expression
-                                .as_list()
-                                .unwrap()
-                                .get(1)
-                                .unwrap()
-                                .as_symbol()
-                                .unwrap();
-                            let error_type = expression
-                                .as_list()
-                                .unwrap()
-                                .get(2)
-                                .unwrap()
-                                .as_string()
-                                .unwrap();
+                            let items = expression.as_list().unwrap();
+                            let symbol = items.get(1).and_then(|symbol| symbol.as_symbol());
+                            let error_type = items.get(2).and_then(|error_type| error_type.as_string());
+                            if items.len() != 3 || symbol.is_none() || error_type.is_none() {
+                                return Err(LispyType::create_error(
+                                    "deferror! expects a symbol and a message string",
+                                    "INCORRECT_TYPE",
+                                ));
+                            }
+                            let symbol = symbol.unwrap();
+                            let error_type = error_type.unwrap();
+                            if env.is_protected(symbol) {
+                                return Err(protected_error(symbol));
+                            }
+                            if env.is_constant(symbol) {
+                                return Err(constant_error(symbol));
+                            }
 
                             env.set_item(
                                 symbol.clone(),
@@ -260,89 +1479,243 @@ pub fn eval(
                                     &symbol.clone().as_str(),
                                 ),
                             );
-                            passed_env.modify_with(&env);
                             return Ok(LispyType::create_nil());
                         }
-                        "let*" => {
+                        // (for ((x xs) (y ys) :when cond :let (z expr)) body) collects a list
+                        "for" => {
+                            let clauses = expression.as_list().unwrap().get(1).cloned();
+                            let body = expression.as_list().unwrap().get(2).cloned();
+                            if clauses.is_none()
+                                || clauses.as_ref().unwrap().as_sequential().is_none()
+                                || body.is_none()
+                            {
+                                return Err(for_error(
+                                    "for expects a list of clauses and a body".to_string(),
+                                ));
+                            }
+
+                            let mut results = vec![];
+                            let result = eval_for(
+                                clauses.unwrap().as_sequential().unwrap(),
+                                &body.unwrap(),
+                                &mut env,
+                                &mut results,
+                            );
+                            if result.is_err() {
+                                return Err(result.err().unwrap());
+                            }
+                            return Ok(LispyType::create_list(results));
+                        }
+                        // let* binds sequentially, each value sees the bindings before it.
+                        // let binds in parallel, every value is evaluated in the outer scope.
+                        "let*" | "let" => {
+                            let form = first.as_symbol().unwrap().clone();
+                            if expression.as_list().unwrap().len() < 3 {
+                                return Err(LispyType::create_error(
+                                    format!("{} expects bindings and a body", form).as_str(),
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
                             let mut n_env = LispyEnv::child(&mut env);
                             let bindings = expression.as_list().unwrap().get(1).unwrap().clone();
-                            let to_eval = expression.as_list().unwrap().get(2).unwrap().clone();
 
-                            if !bindings.is_list() || bindings.as_list().unwrap().len() % 2 != 0 {
+                            if bindings.as_sequential().is_none()
+                                || bindings.as_sequential().unwrap().len() % 2 != 0
+                            {
                                 return Err(LispyType::Error {
-                                    message: format!("let* first arg must be a list of key value pairs. Received: {}", bindings),
+                                    message: format!("{} first arg must be a list or vector of key value pairs. Received: {}", form, bindings),
                                     error_type: "INCORRECT_TYPE".to_string(),
+                                    data: None,
                                     meta: HashMap::new(),
                                 });
                             }
 
-                            for index in (0..bindings.as_list().unwrap().len()).step_by(2) {
-                                let key = bindings.as_list().unwrap().get(index).unwrap().clone();
-                                let value =
-                                    bindings.as_list().unwrap().get(index + 1).unwrap().clone();
-                                if !key.is_symbol() {
-                                    return Err(LispyType::Error {
-                                        message: format!(
-                                            "let* bindings key must be a symbol. Received: {}",
-                                            bindings
-                                        ),
-                                        error_type: "INCORRECT_TYPE".to_string(),
-                                        meta: HashMap::new(),
-                                    });
-                                }
-                                let evaluated = eval(&value, &mut n_env);
+                            for index in (0..bindings.as_sequential().unwrap().len()).step_by(2) {
+                                let key =
+                                    bindings.as_sequential().unwrap().get(index).unwrap().clone();
+                                let value = bindings
+                                    .as_sequential()
+                                    .unwrap()
+                                    .get(index + 1)
+                                    .unwrap()
+                                    .clone();
+                                let evaluated = if form == "let" {
+                                    eval(&value, &mut env)
+                                } else {
+                                    eval(&value, &mut n_env)
+                                };
                                 if evaluated.is_err() {
                                     return evaluated;
                                 }
-                                n_env
-                                    .set_item(key.as_symbol().unwrap().clone(), evaluated.unwrap());
+                                let bound = destructure(&form, &key, evaluated.unwrap(), &mut n_env);
+                                if bound.is_err() {
+                                    return Err(bound.err().unwrap());
+                                }
                             }
 
                             env = n_env;
-                            expression = to_eval;
+                            expression = expression.into_item(2);
                             continue;
                         }
+                        // (loop (bindings ...) body ...) binds like let*, a recur in tail
+                        // position of the body rebinds them and runs the body again
+                        "loop" => {
+                            let bindings = expression.as_list().unwrap().get(1).cloned();
+                            if bindings.is_none()
+                                || bindings.as_ref().unwrap().as_sequential().is_none()
+                                || bindings.as_ref().unwrap().as_sequential().unwrap().len() % 2 != 0
+                            {
+                                return Err(LispyType::create_error(
+                                    format!("loop first arg must be a list or vector of key value pairs. Received: {}", *expression).as_str(),
+                                    "INCORRECT_TYPE",
+                                ));
+                            }
+                            let bindings = bindings.unwrap();
+                            let pairs = bindings.as_sequential().unwrap();
+                            for index in 2..expression.as_list().unwrap().len() {
+                                let last = index == expression.as_list().unwrap().len() - 1;
+                                let checked =
+                                    check_recur(&expression.as_list().unwrap()[index], last, &env);
+                                if checked.is_err() {
+                                    return Err(checked.err().unwrap());
+                                }
+                            }
+
+                            let mut n_env = LispyEnv::child(&mut env);
+                            for index in (0..pairs.len()).step_by(2) {
+                                let evaluated = eval(&pairs[index + 1], &mut n_env);
+                                if evaluated.is_err() {
+                                    return Err(evaluated.err().unwrap());
+                                }
+                                let bound = destructure("loop", &pairs[index], evaluated.unwrap(), &mut n_env);
+                                if bound.is_err() {
+                                    return Err(bound.err().unwrap());
+                                }
+                            }
+
+                            let frame = LoopFrame {
+                                form: expression.clone(),
+                                patterns: pairs.iter().step_by(2).cloned().collect(),
+                                outer: env.clone(),
+                            };
+                            env = n_env;
+                            let tail = loop_body(&frame, &mut env);
+                            if tail.is_err() {
+                                return Err(tail.err().unwrap());
+                            }
+                            match tail.unwrap() {
+                                Some(tail) => expression = tail,
+                                None => return Ok(LispyType::create_nil()),
+                            }
+                            recur_target = Some(frame);
+                            continue;
+                        }
+                        "recur" => {
+                            if recur_target.is_none() {
+                                return Err(recur_error(&expression));
+                            }
+                            let frame = recur_target.as_ref().unwrap();
+                            let values = &expression.as_list().unwrap()[1..];
+                            if values.len() != frame.patterns.len() {
+                                return Err(LispyType::create_error(
+                                    format!(
+                                        "recur expects {} arguments, got {}",
+                                        frame.patterns.len(),
+                                        values.len()
+                                    )
+                                    .as_str(),
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            let mut evaluated = vec![];
+                            for value in values {
+                                let result = eval(value, &mut env);
+                                if result.is_err() {
+                                    return Err(result.err().unwrap());
+                                }
+                                evaluated.push(result.unwrap());
+                            }
+
+                            let mut n_env = LispyEnv::child(&mut frame.outer.clone());
+                            for (pattern, value) in frame.patterns.iter().zip(evaluated) {
+                                let bound = destructure("recur", pattern, value, &mut n_env);
+                                if bound.is_err() {
+                                    return Err(bound.err().unwrap());
+                                }
+                            }
+                            env = n_env;
+                            let tail = loop_body(frame, &mut env);
+                            if tail.is_err() {
+                                return Err(tail.err().unwrap());
+                            }
+                            match tail.unwrap() {
+                                Some(tail) => expression = tail,
+                                None => return Ok(LispyType::create_nil()),
+                            }
+                            continue;
+                        }
+                        // (while condition body ...) runs the body until condition is falsy
+                        "while" => {
+                            let items = expression.as_list().unwrap();
+                            if items.len() < 2 {
+                                return Err(LispyType::create_error(
+                                    "while expects a condition",
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            loop {
+                                let condition = eval(&items[1], &mut env);
+                                if condition.is_err() {
+                                    return Err(condition.err().unwrap());
+                                }
+                                if !condition.unwrap().is_truthy() {
+                                    return Ok(LispyType::create_nil());
+                                }
+                                for item in &items[2..] {
+                                    let evaluated = eval(item, &mut env);
+                                    if evaluated.is_err() {
+                                        return Err(evaluated.err().unwrap());
+                                    }
+                                }
+                            }
+                        }
                         "do" => {
                             if expression.as_list().unwrap().len() == 1 {
                                 return Ok(LispyType::create_nil());
                             }
-                            if expression.as_list().unwrap().len() == 2 {
-                                let last_expr = expression
-                                    .as_list()
-                                    .unwrap()
-                                    .get(expression.as_list().unwrap().len() - 1)
-                                    .unwrap()
-                                    .clone();
-                                expression = last_expr;
-                                continue;
-                            }
-                            for index in 1..expression.as_list().unwrap().len() - 1 {
-                                let item =
-                                    expression.as_list().unwrap().get(index).unwrap().clone();
-                                let evaluated = eval(&item, &mut env);
+                            let last = expression.as_list().unwrap().len() - 1;
+                            for index in 1..last {
+                                let item = expression.as_list().unwrap().get(index).unwrap();
+                                let evaluated = eval(item, &mut env);
                                 if evaluated.is_err() {
                                     return evaluated;
                                 }
                             }
-                            let last_expr = expression
-                                .as_list()
-                                .unwrap()
-                                .get(expression.as_list().unwrap().len() - 1)
-                                .unwrap()
-                                .clone();
-                            expression = last_expr;
+                            expression = expression.into_item(last);
                             continue;
                         }
+                        // (if cond then) without an else is nil when cond is falsy
                         "if" => {
-                            let cond = expression.as_list().unwrap().get(1).unwrap().clone();
-                            let evaluated_condition = eval(&cond, &mut env);
-                            let to_eval = if evaluated_condition.unwrap().is_truthy() {
-                                expression.as_list().unwrap().get(2).unwrap().clone()
+                            let length = expression.as_list().unwrap().len();
+                            if !(3..=4).contains(&length) {
+                                return Err(LispyType::create_error(
+                                    format!("if expects a condition, a branch and an optional else. Received: {}", *expression).as_str(),
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            let cond = expression.as_list().unwrap().get(1).unwrap();
+                            let evaluated_condition = eval(cond, &mut env);
+                            if evaluated_condition.is_err() {
+                                return Err(evaluated_condition.err().unwrap());
+                            }
+                            let branch = if evaluated_condition.unwrap().is_truthy() { 2 } else { 3 };
+                            if let Some(taken) = expression.as_list().unwrap().get(branch) {
+                                coverage::hit_branch(taken);
                             } else {
-                                expression.as_list().unwrap().get(3).unwrap().clone()
-                            };
+                                return Ok(LispyType::create_nil());
+                            }
 
-                            expression = to_eval;
+                            expression = expression.into_item(branch);
                             continue;
                         }
                         "fn*" => {
@@ -352,13 +1725,13 @@ pub fn eval(
                                 .get(1)
                                 .unwrap()
                                 .clone()
-                                .as_list()
+                                .as_sequential()
                                 .unwrap()
-                                .clone();
+                                .to_vec();
                             let to_eval = expression.as_list().unwrap().get(2).unwrap().clone();
                             return Ok(LispyType::Lambda {
-                                bindings,
-                                to_eval: Box::new(to_eval),
+                                bindings: Rc::new(bindings),
+                                to_eval: Rc::new(to_eval),
                                 env: Box::new(env.clone()),
                                 meta: HashMap::new(),
                                 is_macro: false,
@@ -371,7 +1744,8 @@ pub fn eval(
                             if evaluated_expr.is_err() {
                                 return evaluated_expr;
                             }
-                            expression = evaluated_expr.unwrap();
+                            expression = Form::owned(evaluated_expr.unwrap());
+                            recur_target = None;
                             continue;
                         }
                         "quote" => {
@@ -383,96 +1757,287 @@ pub fn eval(
                             ));
                         }
                         "quasi-quote" => {
-                            expression =
-                                quasi_quote(&expression.as_list().unwrap().get(1).unwrap().clone());
+                            expression = Form::owned(quasi_quote(
+                                &expression.as_list().unwrap().get(1).unwrap().clone(),
+                            ));
                             continue;
                         }
-                        "macro-expand" => {
-                            return macro_expand(
-                                expression.as_list().unwrap().get(1).unwrap(),
-                                &env,
+                        "block" => {
+                            let name = expression.as_list().unwrap().get(1).cloned();
+                            if name.is_none() {
+                                return Err(LispyType::create_error(
+                                    "block expects a block name",
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            let name = name.unwrap();
+                            if !name.is_symbol() {
+                                return Err(LispyType::Error {
+                                    message: format!(
+                                        "block first arg must be a symbol. Received: {}",
+                                        name
+                                    ),
+                                    error_type: "INCORRECT_TYPE".to_string(),
+                                    data: None,
+                                    meta: HashMap::new(),
+                                });
+                            }
+
+                            let mut result = Ok(LispyType::create_nil());
+                            for item in expression.as_list().unwrap()[2..].iter() {
+                                result = eval(item, &mut env);
+                                if result.is_err() {
+                                    break;
+                                }
+                            }
+
+                            return match result {
+                                Err(LispyType::Error {
+                                    error_type, meta, ..
+                                }) if error_type == "RETURN_FROM"
+                                    && meta.get("block") == Some(&name) =>
+                                {
+                                    Ok(meta.get("value").unwrap().clone())
+                                }
+                                _ => result,
+                            };
+                        }
+                        "return-from" => {
+                            let name = expression.as_list().unwrap().get(1).cloned();
+                            if name.is_none() {
+                                return Err(LispyType::create_error(
+                                    "return-from expects a block name",
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            let name = name.unwrap();
+                            if !name.is_symbol() {
+                                return Err(LispyType::Error {
+                                    message: format!(
+                                        "return-from first arg must be a symbol. Received: {}",
+                                        name
+                                    ),
+                                    error_type: "INCORRECT_TYPE".to_string(),
+                                    data: None,
+                                    meta: HashMap::new(),
+                                });
+                            }
+                            let value = match expression.as_list().unwrap().get(2) {
+                                Some(value) => eval(value, &mut env),
+                                None => Ok(LispyType::create_nil()),
+                            };
+                            if value.is_err() {
+                                return Err(value.err().unwrap());
+                            }
+
+                            // Unwinds like an error until the enclosing block with that name
+                            let mut meta = HashMap::new();
+                            meta.insert("block".to_string(), name.clone());
+                            meta.insert("value".to_string(), value.unwrap());
+                            return Err(LispyType::Error {
+                                message: format!(
+                                    "return-from {} outside of its block",
+                                    name.as_symbol().unwrap()
+                                ),
+                                error_type: "RETURN_FROM".to_string(),
+                                data: None,
+                                meta,
+                            });
+                        }
+                        // (locking lock body ...) holds lock around body. It never has to
+                        // wait for it, see lock.rs
+                        "locking" => {
+                            let lock = expression.as_list().unwrap().get(1).cloned();
+                            if lock.is_none() {
+                                return Err(LispyType::create_error(
+                                    "locking expects a lock",
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            let lock = eval(&lock.unwrap(), &mut env);
+                            if lock.is_err() {
+                                return Err(lock.err().unwrap());
+                            }
+                            let lock = lock.unwrap();
+                            let held = lock_arg(&lock);
+                            if held.is_err() {
+                                return Err(held.err().unwrap());
+                            }
+
+                            acquire(held.as_ref().unwrap());
+                            let mut result = Ok(LispyType::create_nil());
+                            for item in expression.as_list().unwrap()[2..].iter() {
+                                result = eval(item, &mut env);
+                                if result.is_err() {
+                                    break;
+                                }
+                            }
+                            release(held.unwrap());
+                            return result;
+                        }
+                        "deftest" => {
+                            let name = expression.as_list().unwrap().get(1).cloned();
+                            if name.is_none() {
+                                return Err(LispyType::create_error(
+                                    "deftest expects a name and a body",
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            let name = name.unwrap();
+                            if !name.is_symbol() {
+                                return Err(LispyType::Error {
+                                    message: format!(
+                                        "deftest first arg must be a symbol. Received: {}",
+                                        name
+                                    ),
+                                    error_type: "INCORRECT_TYPE".to_string(),
+                                    data: None,
+                                    meta: HashMap::new(),
+                                });
+                            }
+
+                            let body = [
+                                vec![LispyType::create_symbol("do")],
+                                expression.as_list().unwrap()[2..].to_vec(),
+                            ]
+                            .concat();
+                            register_test(
+                                name.as_symbol().unwrap().clone(),
+                                LispyType::create_list(body),
                             );
+                            return Ok(LispyType::create_nil());
+                        }
+                        "run-tests" => {
+                            return Ok(run_tests(&mut env));
+                        }
+                        "macro-expand" => {
+                            let form = expression.as_list().unwrap().get(1);
+                            if form.is_none() {
+                                return Err(LispyType::create_error(
+                                    "macro-expand expects a form",
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            return macro_expand(form.unwrap(), &env);
+                        }
+                        "macro-expand-1" => {
+                            let form = expression.as_list().unwrap().get(1);
+                            if form.is_none() {
+                                return Err(LispyType::create_error(
+                                    "macro-expand-1 expects a form",
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            return macro_expand_1(form.unwrap(), &env);
+                        }
+                        "macro-expand-all" => {
+                            let form = expression.as_list().unwrap().get(1);
+                            if form.is_none() {
+                                return Err(LispyType::create_error(
+                                    "macro-expand-all expects a form",
+                                    "INCORRECT_ARITY",
+                                ));
+                            }
+                            return macro_expand_all(form.unwrap(), &env);
                         }
                         "throw" => {
-                            let error =
-                                eval_ast(expression.as_list().unwrap().get(1).unwrap(), &mut env);
+                            let error = eval(expression.as_list().unwrap().get(1).unwrap(), &mut env);
                             if error.is_err() {
                                 return error;
                             }
                             return Err(error.unwrap());
                         }
+                        // (try* expr (catch* TYPE e handler) ... (finally cleanup ...)), the
+                        // first clause whose TYPE matches runs with the error bound to e, _
+                        // matches anything. finally runs last either way, in the same env,
+                        // and the outcome before it is kept unless it throws itself.
                         "try*" => {
                             let result = eval(
                                 &expression.as_list().unwrap().get(1).unwrap().clone(),
                                 &mut env,
                             );
+                            let items = expression.as_list().unwrap();
+                            let finally = items
+                                .last()
+                                .filter(|clause| items.len() > 2 && is_finally_clause(clause))
+                                .cloned();
+                            let catch_end = items.len() - if finally.is_some() { 1 } else { 0 };
 
-                            if result.is_ok() {
+                            if result.is_ok() && finally.is_none() {
                                 return Ok(result.unwrap());
                             }
-
-                            let catch_clauses: Vec<LispyType> = expression
-                                .as_list()
-                                .unwrap()
-                                .get(2..expression.as_list().unwrap().len())
-                                .unwrap()
-                                .iter()
-                                .map(|item| item.clone())
-                                .collect();
-
-                            for catch_clause in catch_clauses.iter() {
-                                let gotten_error = eval_ast(
-                                    &catch_clause.as_list().unwrap().get(1).unwrap().clone(),
-                                    &mut env,
-                                );
-                                if gotten_error.is_err() {
-                                    return gotten_error;
+                            let outcome = match result {
+                                Ok(value) => Ok(value),
+                                Err(error) => {
+                                    let caught = find_catch(items, 2..catch_end, &error, &mut env);
+                                    if caught.is_err() {
+                                        return Err(caught.err().unwrap());
+                                    }
+                                    match caught.unwrap() {
+                                        None => Err(error),
+                                        Some(index) => {
+                                            let handler = bind_caught(&items[index], error, &mut env);
+                                            if handler.is_err() {
+                                                return Err(handler.err().unwrap());
+                                            }
+                                            let (mut handler_env, position) = handler.unwrap();
+                                            if finally.is_none() {
+                                                env = handler_env;
+                                                expression = expression.into_item(index).into_item(position);
+                                                continue;
+                                            }
+                                            eval(&items[index].as_list().unwrap()[position], &mut handler_env)
+                                        }
+                                    }
                                 }
-                                if gotten_error.unwrap() == result.as_ref().err().unwrap().clone() {
-                                    return eval(
-                                        &catch_clause.as_list().unwrap().get(2).unwrap().clone(),
-                                        &mut env,
-                                    );
+                            };
+
+                            if finally.is_none() {
+                                return outcome;
+                            }
+                            for cleanup in finally.unwrap().as_list().unwrap()[1..].iter() {
+                                let cleaned = eval(cleanup, &mut env);
+                                if cleaned.is_err() {
+                                    return Err(cleaned.err().unwrap());
                                 }
                             }
-                            return result;
+                            return outcome;
                         }
                         _ => {}
                     }
                 }
+                if let Some(found) = keyword_lookup(&expression, &env) {
+                    return Ok(found);
+                }
                 let evaluated = eval_ast(&expression, &mut env);
                 if evaluated.is_err() {
                     return evaluated;
                 }
-                let callee = evaluated
-                    .as_ref()
-                    .unwrap()
-                    .as_list()
-                    .unwrap()
-                    .get(0)
-                    .unwrap()
-                    .clone();
-                let len = evaluated.as_ref().unwrap().as_list().unwrap().len();
-                let arguments: Vec<LispyType> = evaluated
-                    .as_ref()
-                    .unwrap()
-                    .as_list()
-                    .unwrap()
-                    .get(1..len)
-                    .unwrap()
-                    .into();
+                // The evaluated list is ours, its items become the argument vector as is
+                let mut arguments = match evaluated.unwrap() {
+                    LispyType::List { collection, .. } => *collection,
+                    _ => unreachable!(),
+                };
+                let callee = arguments.remove(0);
 
+                if callee.is_keyword() || callee.is_hash() {
+                    return apply_lookup(&callee, arguments);
+                }
                 if callee.is_function() && !callee.is_lambda() {
-                    return callee.apply_function(arguments);
+                    return callee.apply_function(arguments).map_err(|error| {
+                        stacktrace::attach(error, Some(Frame::new(&expression, &callee)))
+                    });
                 }
+                stacktrace::enter(depth, Frame::new(&expression, &callee));
 
                 let parse = callee.apply_lambda(arguments);
                 if parse.is_err() {
                     return Err(parse.err().unwrap());
                 }
                 let unwrapped = parse.unwrap();
-                expression = unwrapped.0;
+                expression = Form::Shared(unwrapped.0, vec![]);
                 env = unwrapped.1;
+                recur_target = None;
                 continue;
             }
             _ => return eval_ast(&expression, &mut env),
@@ -480,15 +2045,164 @@ pub fn eval(
     }
 }
 
+// `(:key hash default?)` and `(hash :key default?)` look the key up like get
+// `(:key symbol)` with `symbol` bound to a hash reads the entry from the binding
+// instead of from a copy of the whole hash. Anything else is left to apply_lookup.
+fn keyword_lookup(expression: &LispyType, env: &LispyEnv) -> Option<LispyType> {
+    let items = expression.as_list().unwrap();
+    if items.len() != 2 || !items[0].is_keyword() {
+        return None;
+    }
+    let name = items[1].as_symbol()?;
+    env.with_item(name, |value| {
+        value
+            .as_hash()
+            .map(|collection| collection.get(&items[0]).cloned().unwrap_or_else(LispyType::create_nil))
+    })
+    .flatten()
+}
+
+fn apply_lookup(callee: &LispyType, arguments: Vec<LispyType>) -> Result<LispyType, LispyType> {
+    if arguments.is_empty() || arguments.len() > 2 {
+        return Err(LispyType::create_error(
+            format!("Expected arity 1 or 2, received {}", arguments.len()).as_str(),
+            "INCORRECT_ARITY",
+        ));
+    }
+    let (hash, key) = if callee.is_keyword() {
+        (&arguments[0], callee)
+    } else {
+        (callee, &arguments[0])
+    };
+    let found = hash
+        .as_hash()
+        .filter(|_| key.is_hashable())
+        .and_then(|collection| collection.get(key))
+        .cloned();
+    Ok(found.unwrap_or_else(|| arguments.get(1).cloned().unwrap_or_else(LispyType::create_nil)))
+}
+
+pub fn apply_callable(
+    callee: &LispyType,
+    arguments: Vec<LispyType>,
+) -> Result<LispyType, LispyType> {
+    if callee.is_function() && !callee.is_lambda() {
+        return callee.apply_function(arguments);
+    }
+    if callee.is_keyword() || callee.is_hash() {
+        return apply_lookup(callee, arguments);
+    }
+
+    let parse = callee.apply_lambda(arguments);
+    if parse.is_err() {
+        return Err(parse.err().unwrap());
+    }
+    let mut unwrapped = parse.unwrap();
+    let depth = stacktrace::depth();
+    stacktrace::enter(depth, Frame::callback(callee));
+    let result = eval(&unwrapped.0, &mut unwrapped.1);
+    stacktrace::leave(depth);
+    result
+}
+
+// What LispyMachine::install put in place, restored on drop
+struct Installed {
+    _registry: InstalledRegistry,
+    _limits: InstalledLimits,
+    _watchers: InstalledWatchers,
+    _trace_macros: InstalledTraceMacros,
+    _cancellation: InstalledToken,
+}
+
+impl Default for LispyMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl LispyMachine {
     pub fn new() -> Self {
-        let mut this = Self {
-            env: LispyEnv::root(),
-        };
+        Self::builder().build()
+    }
+
+    pub fn builder() -> LispyMachineBuilder {
+        LispyMachineBuilder {
+            search_path: SearchPath::from_env(),
+            stdlib: true,
+            file_io: true,
+            natives: vec![],
+            globals: vec![],
+            removed: vec![],
+            parse_limits: ParseLimits::default(),
+        }
+    }
 
-        this.evaluate_file("lispy_std/core.lispy");
+    // Prints every macro expansion this machine does from now on to stderr, as
+    // `macro-expand: form => expansion`
+    pub fn set_trace_macros(&mut self, trace: bool) {
+        self.trace_macros = trace;
+    }
+
+    // Cancelling it interrupts whatever this machine is evaluating, from any thread
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
 
-        this
+    // Called with the symbol, previous and new value whenever def!, redefine!, defmacro!
+    // or defconst binds anything in this machine's root env, or undef! removes it with
+    // nil as the new value, e.g. to reload what depends on a redefined function
+    pub fn on_redefine(&mut self, hook: impl Fn(&str, &LispyType, &LispyType) + 'static) {
+        self.watchers.add_hook(Rc::new(hook));
+    }
+
+    // Binds a native function once the machine is running, protected and reachable
+    // as core/name like the ones given to LispyMachineBuilder::with_native_fn. The
+    // closure may capture host state, e.g. an Rc<RefCell<..>> shared with the embedder.
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        func: impl Fn(Vec<LispyType>) -> Result<LispyType, LispyType> + 'static,
+    ) {
+        let func = LispyType::create_function(None, func);
+        for key in [name.to_string(), format!("core/{}", name)] {
+            self.env.set(key.as_str(), func.clone());
+            self.env.protect(key.as_str());
+        }
+    }
+
+    // Calls the function bound to `name` in the current namespace, `ns/name` for
+    // another one, converting the arguments and the result:
+    //   let total: i64 = machine.call("sum", &[&vec![1i64, 2, 3]])?;
+    pub fn call<R: FromLispy>(&mut self, name: &str, args: &[&dyn ToLispy]) -> Result<R, LispyType> {
+        let _installed = self.install();
+        let env = self.namespaces.current_env();
+        let callee = env
+            .get_item(&name.to_string())
+            .or_else(|| namespace::resolve_qualified(name, &env));
+        if callee.is_none() {
+            return Err(LispyType::create_error(
+                format!("Symbol {} is not defined", name).as_str(),
+                "NOT_DEFINED",
+            ));
+        }
+        let result = apply_callable(&callee.unwrap(), args.iter().map(|arg| arg.to_lispy()).collect());
+        self.parse_limits = runtime_limits();
+        if result.is_err() {
+            return Err(result.err().unwrap());
+        }
+        R::from_lispy(&result.unwrap())
+    }
+
+    // Points the thread-wide state eval reads at this machine until the guard is
+    // dropped, so several machines can take turns on one thread
+    fn install(&self) -> Installed {
+        Installed {
+            _registry: self.namespaces.install(),
+            _limits: install_runtime_limits(self.parse_limits.clone()),
+            _watchers: self.watchers.install(),
+            _trace_macros: install_trace_macros(self.trace_macros),
+            _cancellation: self.cancellation.install(),
+        }
     }
 
     pub fn get_env_mut(&mut self) -> &mut LispyEnv {
@@ -499,24 +2213,525 @@ impl LispyMachine {
         &self.env
     }
 
-    pub fn execute(&mut self, input_code: &str) {
-        let ast = compile_source_code_to_ast(input_code);
+    // Top-level forms evaluated from now on are appended to the log at `path`
+    pub fn record_to(&mut self, path: &str) -> std::io::Result<()> {
+        self.recorder = Some(Recorder::open(path)?);
+        Ok(())
+    }
+
+    // Evaluates in the current namespace, the root env unless an (ns ...) switched it
+    pub fn evaluate(&mut self, expression: &LispyType) -> Result<LispyType, LispyType> {
+        let mut env = self.namespaces.current_env();
+        self.evaluate_in(expression, &mut env)
+    }
 
-        for expression in ast {
-            let result = eval(&expression, &mut self.env);
+    // Evaluates in `env` instead of the root, e.g. a child env isolating a remote session
+    pub fn evaluate_in(
+        &mut self,
+        expression: &LispyType,
+        env: &mut LispyEnv,
+    ) -> Result<LispyType, LispyType> {
+        let _installed = self.install();
+        let expanded = expand_compile_time(expression, env);
+        let result = match expanded {
+            Ok(expanded) => eval(&expanded, env),
+            Err(error) => Err(error),
+        };
+        self.parse_limits = runtime_limits();
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(expression, &result);
+        }
+        result
+    }
+
+    // Stops at a syntax error or the first uncaught error and returns it as the text
+    // to report, with its stack trace. Anything can be thrown, not only errors.
+    fn execute(&mut self, ast: Result<Vec<LispyType>, ParseError>) -> Result<(), String> {
+        if ast.is_err() {
+            return Err(format!("Syntax error: {}", ast.err().unwrap()));
+        }
+
+        for expression in ast.unwrap() {
+            coverage::hit_form(&expression);
+            let result = self.evaluate(&expression);
 
             if result.is_err() {
-                panic!(
-                    "Error: {:?}",
-                    result.err().unwrap().as_error().unwrap().message
-                );
+                let error = result.err().unwrap();
+                let message = match error.as_error() {
+                    Some(thrown) => thrown.message.clone(),
+                    None => error.to_string(),
+                };
+                return Err(format!("Error: {}{}", message, format_trace(&error)));
             }
         }
+        Ok(())
     }
 
-    pub fn evaluate_file(&mut self, filepath: &str) {
-        let contents =
-            fs::read_to_string(filepath).expect(format!("File {} not found", filepath).as_str());
-        self.execute(contents.as_str());
+    pub fn evaluate_file(&mut self, filepath: &str) -> Result<(), String> {
+        let contents = fs::read_to_string(filepath);
+        if contents.is_err() {
+            return Err(format!("Could not read {}: {}", filepath, contents.err().unwrap()));
+        }
+        self.evaluate_source(contents.unwrap().as_str(), filepath)
+    }
+
+    // `filepath` is only used for source positions and resolving requires
+    fn evaluate_source(&mut self, contents: &str, filepath: &str) -> Result<(), String> {
+        let ast = compile_source_file(contents, filepath);
+        if let Ok(ast) = &ast {
+            coverage::register_file(filepath, ast);
+        }
+        self.namespaces.enter_file(filepath);
+        let executed = self.execute(ast);
+        self.namespaces.leave_file();
+        executed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::{compile_source_code_to_ast, ParseLimits};
+    use crate::machine::{LispyMachine, TRACE_MACROS};
+    use crate::types::LispyType;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn run(source: &str) -> Result<LispyType, LispyType> {
+        let mut machine = LispyMachine::new();
+        let mut result = Ok(LispyType::create_nil());
+        for form in compile_source_code_to_ast(source).unwrap().iter() {
+            result = machine.evaluate(form);
+        }
+        result
+    }
+
+    #[test]
+    fn return_from_is_not_caught_by_catch() {
+        let caught = run("(block b (try* (return-from b 1) (catch* _ e 2)))");
+        assert_eq!(caught.unwrap(), LispyType::create_number(1.0));
+        let typed = run("(block b (try* (return-from b 1) (catch* RETURN_FROM e 2)))");
+        assert_eq!(typed.unwrap(), LispyType::create_number(1.0));
+    }
+
+    #[test]
+    fn locking_without_a_lock_is_an_error() {
+        assert!(run("(locking)").is_err());
+        assert!(run("(locking 1 2)").is_err());
+    }
+
+    #[test]
+    fn deftest_without_a_name_is_an_error() {
+        assert!(run("(deftest)").is_err());
+        assert!(run("(deftest \"name\" (= 1 1))").is_err());
+    }
+
+    #[test]
+    fn defconst_checks_its_arguments() {
+        for source in ["(defconst)", "(defconst x)", "(defconst x 1 2)", "(defconst 1 2)"] {
+            assert!(run(source).is_err(), "{} should fail", source);
+        }
+        assert_eq!(run("(defconst x 1) x").unwrap(), LispyType::create_number(1.0));
+    }
+
+    #[test]
+    fn deferror_checks_its_arguments() {
+        for source in ["(deferror!)", "(deferror! E)", "(deferror! \"E\" \"m\")", "(deferror! E 1)"] {
+            assert!(run(source).is_err(), "{} should fail", source);
+        }
+        assert!(run("(deferror! E \"m\")").is_ok());
+    }
+
+    #[test]
+    fn macro_expand_without_a_form_is_an_error() {
+        for source in ["(macro-expand)", "(macro-expand-1)", "(macro-expand-all)"] {
+            assert!(run(source).is_err(), "{} should fail", source);
+        }
+    }
+
+    #[test]
+    fn block_and_return_from_without_a_name_are_errors() {
+        for source in ["(block)", "(return-from)", "(block 1 2)"] {
+            assert!(run(source).is_err(), "{} should fail", source);
+        }
+    }
+
+    #[test]
+    fn return_from_still_runs_finally() {
+        let result = run(
+            "(def! log (atom (list)))
+             (block b (try* (return-from b 1) (catch* _ e 2) (finally (swap! log conj :done))))
+             (pr-str @log)",
+        );
+        assert_eq!(result.unwrap().as_string().unwrap(), "(:done)");
+    }
+
+    #[test]
+    fn unhashable_keys_are_never_in_a_hash() {
+        let result = run("(pr-str (list (contains? {:a 1} (list 1)) (dissoc {:a 1} (list 1) :b)))");
+        assert_eq!(result.unwrap().as_string().unwrap(), "(false {:a 1})");
+    }
+
+    #[test]
+    fn looking_up_an_unhashable_key_gives_the_default() {
+        let result = run("(pr-str (list ({:a 1} (list 1)) ({:a 1} (list 1) 2)))");
+        assert_eq!(result.unwrap().as_string().unwrap(), "(nil 2)");
+    }
+
+    #[test]
+    fn race_prefers_an_awaited_future() {
+        let result = run(
+            "(def! a (future (fn* () 1)))
+             (def! b (future (fn* () 2)))
+             (await b)
+             (pr-str (list (race (list a b)) (realized? a)))",
+        );
+        assert_eq!(result.unwrap().as_string().unwrap(), "(2 false)");
+    }
+
+    #[test]
+    fn let_star_binds_sequentially_and_let_in_parallel() {
+        let sequential = run("(def! x 1) (let* (x 10 y (+ x 1)) (pr-str (list x y)))");
+        assert_eq!(sequential.unwrap().as_string().unwrap(), "(10 11)");
+        let parallel = run("(def! x 1) (let (x 10 y (+ x 1)) (pr-str (list x y)))");
+        assert_eq!(parallel.unwrap().as_string().unwrap(), "(10 2)");
+        for source in ["(let)", "(let*)", "(let (x 1))", "(let* (x 1))"] {
+            assert!(run(source).is_err(), "{} should fail", source);
+        }
+    }
+
+    #[test]
+    fn let_bindings_see_the_enclosing_scope_and_shadow_it_locally() {
+        let result = run(
+            "(def! x 1)
+             (def! f (fn* (a) (let* (b (+ a x) x (* b 2)) (list a b x))))
+             (pr-str (list (f 5) x))",
+        );
+        assert_eq!(result.unwrap().as_string().unwrap(), "((5 6 12) 1)");
+    }
+
+    #[test]
+    fn let_values_cannot_see_their_siblings() {
+        let result = run("(let (a 1 b a) b)");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn without_file_io_only_denies_file_forms_in_its_own_machine() {
+        let mut open = LispyMachine::new();
+        let mut closed = LispyMachine::builder().without_file_io().build();
+        let form = &compile_source_code_to_ast("(load-file \"missing.lispy\")").unwrap()[0];
+        let denied = closed.evaluate(form).err().unwrap();
+        assert_eq!(denied.as_error().unwrap().error_type, "SANDBOXED");
+        let missing = open.evaluate(form).err().unwrap();
+        assert_ne!(missing.as_error().unwrap().error_type, "SANDBOXED");
+    }
+
+    #[test]
+    fn walking_a_hash_key_into_an_unhashable_value_is_an_error() {
+        let result = run("(postwalk (fn* (x) (if (= x :a) (list x) x)) {:a 1})");
+        assert_eq!(result.err().unwrap().as_error().unwrap().error_type, "INCORRECT_TYPE");
+    }
+
+    #[test]
+    fn eval_template_cannot_reach_the_file_system() {
+        let result = run("(eval-template \"~(fs/touch \\\"lispy-template-touch\\\")\" {})");
+        assert!(result.is_err());
+        assert!(!std::path::Path::new("lispy-template-touch").exists());
+        assert!(run("(eval-template \"~(fs/stat \\\".\\\")\" {})").is_err());
+        assert!(run("(eval-template \"~(user/slurp \\\"Cargo.toml\\\")\" {})").is_err());
+    }
+
+    #[test]
+    fn eval_template_only_reaches_pure_builtins() {
+        let result = run("(eval-template \"port ~(+ base 1) ~(pr-str (map (fn* (x) (+ x 1)) (list 1 2)))\" {:base 8000})");
+        assert_eq!(result.unwrap().as_string().unwrap(), "port 8001 (2 3)");
+        for name in [
+            "set-division-by-zero!", "gc!", "extend-type", "actor", "println", "slurp",
+            "user/slurp", "user/extend-type",
+        ] {
+            let result = run(format!("(eval-template \"~({})\" {{}})", name).as_str());
+            assert!(result.is_err(), "{} should not be reachable", name);
+        }
+    }
+
+    #[test]
+    fn cons_concat_and_splice_accept_vectors() {
+        let result = run(
+            "(let* (v [1 2])
+               (pr-str (list (cons 0 [1 2]) (concat [1] (list 2)) (quasi-quote (0 (splice-unquote v))))))",
+        );
+        assert_eq!(result.unwrap().as_string().unwrap(), "((0 1 2) (1 2) (0 1 2))");
+        for source in ["(cons)", "(cons 0 1)", "(concat (list 1) 2)"] {
+            assert!(run(source).is_err(), "{} should fail", source);
+        }
+    }
+
+    #[test]
+    fn undef_checks_its_arguments() {
+        for source in ["(undef!)", "(undef! 'x 'y)", "(undef! 1)"] {
+            assert!(run(source).is_err(), "{} should fail", source);
+        }
+        assert!(run("(def! x 1) (undef! 'x)").unwrap().as_bool().unwrap());
+    }
+
+    #[test]
+    fn redefine_checks_its_arguments() {
+        for source in ["(redefine!)", "(redefine! 'x)", "(redefine! 'x 1 2)", "(redefine! 1 2)"] {
+            assert!(run(source).is_err(), "{} should fail", source);
+        }
+        assert_eq!(run("(redefine! 'count 1) count").unwrap(), LispyType::create_number(1.0));
+    }
+
+    #[test]
+    fn refer_without_a_namespace_is_an_error() {
+        for source in ["(refer)", "(refer 1)", "(refer core :only)"] {
+            assert!(run(source).is_err(), "{} should fail", source);
+        }
+    }
+
+    #[test]
+    fn eval_when_compile_expects_one_form() {
+        for source in ["(eval-when-compile)", "(eval-when-compile 1 2)", "(eval (list 'eval-when-compile))"] {
+            assert!(run(source).is_err(), "{} should fail", source);
+        }
+        assert_eq!(run("(eval-when-compile (+ 1 2))").unwrap(), LispyType::create_number(3.0));
+    }
+
+    #[test]
+    fn evaluating_a_missing_file_is_an_error() {
+        let error = LispyMachine::new().evaluate_file("missing.lispy").err().unwrap();
+        assert!(error.starts_with("Could not read missing.lispy"), "{}", error);
+    }
+
+    #[test]
+    fn qualified_symbols_do_not_reach_past_a_sandbox_root() {
+        assert!(run("(def! secret 42) (= user/secret secret)").unwrap().as_bool().unwrap());
+        for source in [
+            "(eval-template \"~(user/slurp \\\"Cargo.toml\\\")\" {})",
+            "(safe-eval '(user/slurp \"Cargo.toml\") '())",
+            "(safe-eval '(user/gc!) '())",
+        ] {
+            let error = run(source).err().unwrap();
+            assert_eq!(error.as_error().unwrap().error_type, "NOT_DEFINED", "{}", source);
+        }
+    }
+
+    #[test]
+    fn machines_built_later_do_not_leak_into_a_sandboxed_machine() {
+        let mut closed = LispyMachine::builder().without_file_io().build();
+        let mut open = LispyMachine::new();
+        let define = &compile_source_code_to_ast("(def! secret 42)").unwrap()[0];
+        open.evaluate(define).unwrap();
+        for source in ["(user/slurp \"Cargo.toml\")", "user/secret"] {
+            let form = &compile_source_code_to_ast(source).unwrap()[0];
+            let error = closed.evaluate(form).err().unwrap();
+            assert_eq!(error.as_error().unwrap().error_type, "NOT_DEFINED", "{}", source);
+        }
+        for form in compile_source_code_to_ast("(ns other) (def! x 1)").unwrap().iter() {
+            closed.evaluate(form).unwrap();
+        }
+        let qualified = &compile_source_code_to_ast("user/secret").unwrap()[0];
+        assert_eq!(open.evaluate(qualified).unwrap(), LispyType::create_number(42.0));
+        let other = &compile_source_code_to_ast("other/x").unwrap()[0];
+        assert!(open.evaluate(other).is_err());
+    }
+
+    #[test]
+    fn eval_template_caps_what_one_builtin_allocates() {
+        let mut segments = vec!["(range 1000000000000)", "(str/pad-left \\\"\\\" 1000000000000)"];
+        if cfg!(feature = "matrix") {
+            segments.push("(matrix/zeros 100000 100000)");
+        }
+        for segment in segments {
+            let result = run(format!("(eval-template \"~{}\" {{}})", segment).as_str());
+            assert_eq!(result.err().unwrap().as_error().unwrap().error_type, "LIMIT_EXCEEDED", "{}", segment);
+        }
+        assert_eq!(run("(count (range 100))").unwrap(), LispyType::create_number(100.0));
+    }
+
+    #[test]
+    fn safe_eval_only_sees_the_allowed_bindings() {
+        assert_eq!(run("(def! x 2) (safe-eval '(+ x 1) '(+ x))").unwrap(), LispyType::create_number(3.0));
+        for source in [
+            "(def! x 2) (safe-eval '(- x 1) '(x))",
+            "(safe-eval '(user/slurp \"Cargo.toml\") '())",
+            "(safe-eval '(core/slurp \"Cargo.toml\") '())",
+        ] {
+            let error = run(source).err().unwrap();
+            assert_eq!(error.as_error().unwrap().error_type, "NOT_DEFINED", "{}", source);
+        }
+    }
+
+    #[test]
+    fn safe_eval_stops_at_its_step_limit() {
+        let result = run("(safe-eval '(loop (i 0) (recur (+ i 1))) '(+) 1000)");
+        assert_eq!(result.err().unwrap().as_error().unwrap().error_type, "STEP_LIMIT");
+        let nested = run("(safe-eval '(safe-eval '(loop (i 0) (recur (+ i 1))) '(+) 1000000) '(+) 1000)");
+        assert_eq!(nested.err().unwrap().as_error().unwrap().error_type, "STEP_LIMIT");
+    }
+
+    #[test]
+    fn operators_dispatch_on_type_tagged_hashes() {
+        let result = run(
+            "(extend-type :money '+ (fn* (a b) {:type :money :cents (+ (:cents a) (:cents b))}))
+             (extend-type :money 'compare (fn* (a b) (compare (:cents a) (:cents b))))
+             (extend-type :money 'count (fn* (m) (:cents m)))
+             (def! a {:type :money :cents 150})
+             (def! b {:type :money :cents 250})
+             (pr-str (list (:cents (+ a b)) (compare a b) (compare b a) (count a)))",
+        );
+        assert_eq!(result.unwrap().as_string().unwrap(), "(400 -1 1 150)");
+    }
+
+    #[test]
+    fn compare_rejects_mismatched_types() {
+        for source in ["(compare 1 \"a\")", "(compare \"a\" 1)", "(compare :a :b)", "(compare nil 1)"] {
+            let error = run(source).err().unwrap();
+            assert_eq!(error.as_error().unwrap().error_type, "INCORRECT_TYPE", "{}", source);
+        }
+        assert_eq!(run("(compare 1 2)").unwrap(), LispyType::create_number(-1.0));
+        assert_eq!(run("(compare \"b\" \"a\")").unwrap(), LispyType::create_number(1.0));
+    }
+
+    #[test]
+    fn if_takes_a_condition_a_branch_and_an_optional_else() {
+        for source in ["(if)", "(if true)", "(if true 1 2 3)"] {
+            let error = run(source).err().unwrap();
+            assert_eq!(error.as_error().unwrap().error_type, "INCORRECT_ARITY", "{}", source);
+        }
+        assert_eq!(run("(if false 1)").unwrap(), LispyType::create_nil());
+        assert_eq!(run("(if false 1 2)").unwrap(), LispyType::create_number(2.0));
+    }
+
+    #[test]
+    fn json_parse_enforces_each_limit() {
+        let deep = format!("{}{}", "[".repeat(20_000), "]".repeat(20_000));
+        let result = run(format!("(json/parse \"{}\")", deep).as_str());
+        assert_eq!(result.err().unwrap().as_error().unwrap().error_type, "LIMIT_EXCEEDED");
+        for (limit, json) in [
+            ("{:max-depth 2}", "[1, [2, [3]]]"),
+            ("{:max-collection-size 2}", "[1, 2, 3]"),
+            ("{:max-collection-size 2}", "{\\\"a\\\": 1, \\\"b\\\": 2, \\\"c\\\": 3}"),
+            ("{:max-string-length 3}", "\\\"abcd\\\""),
+        ] {
+            let result = run(format!("(set-parse-limits! {}) (json/parse \"{}\")", limit, json).as_str());
+            assert_eq!(result.err().unwrap().as_error().unwrap().error_type, "LIMIT_EXCEEDED", "{} {}", limit, json);
+        }
+    }
+
+    #[test]
+    fn parse_limits_given_to_the_builder_stay_with_their_machine() {
+        let limits = ParseLimits { max_collection_size: Some(2), ..ParseLimits::default() };
+        let mut limited = LispyMachine::builder().with_parse_limits(limits).build();
+        let mut open = LispyMachine::new();
+        let form = &compile_source_code_to_ast("(json/parse \"[1, 2, 3]\")").unwrap()[0];
+        let error = limited.evaluate(form).err().unwrap();
+        assert_eq!(error.as_error().unwrap().error_type, "LIMIT_EXCEEDED");
+        assert!(open.evaluate(form).is_ok());
+
+        let raise = &compile_source_code_to_ast("(set-parse-limits! {:max-collection-size 5})").unwrap()[0];
+        limited.evaluate(raise).unwrap();
+        assert!(limited.evaluate(form).is_ok());
+    }
+
+    #[test]
+    fn eval_sees_local_bindings() {
+        assert_eq!(run("(let* (x 1) (eval 'x))").unwrap(), LispyType::create_number(1.0));
+        assert_eq!(run("((fn* (y) (eval '(+ y 1))) 2)").unwrap(), LispyType::create_number(3.0));
+    }
+
+    fn record_redefinitions(machine: &mut LispyMachine) -> Rc<RefCell<Vec<String>>> {
+        let seen = Rc::new(RefCell::new(vec![]));
+        let recorded = seen.clone();
+        machine.on_redefine(move |name, previous, value| {
+            recorded.borrow_mut().push(format!("{} {} {}", name, previous, value))
+        });
+        seen
+    }
+
+    #[test]
+    fn watchers_see_undef_but_not_sandboxed_definitions() {
+        let mut machine = LispyMachine::new();
+        let seen = record_redefinitions(&mut machine);
+        let source = "(watch! 'x) (def! x 1) (safe-eval '(def! x 2) '()) \
+                      (eval-template \"~(def! x 3)\" {}) (def! x 4) (undef! 'x) (undef! 'x)";
+        for form in compile_source_code_to_ast(source).unwrap().iter() {
+            machine.evaluate(form).unwrap();
+        }
+        assert_eq!(*seen.borrow(), vec!["x nil 1", "x 1 4", "x 4 nil"]);
+    }
+
+    #[test]
+    fn add_watch_callbacks_stay_with_their_machine() {
+        let mut watched = LispyMachine::new();
+        let mut other = LispyMachine::new();
+        let source = "(def! seen (atom ())) (def! x 1) \
+                      (add-watch 'x (fn* (sym old new) (swap! seen (fn* (s) (cons new s)))))";
+        for form in compile_source_code_to_ast(source).unwrap().iter() {
+            watched.evaluate(form).unwrap();
+        }
+        for form in compile_source_code_to_ast("(def! x 2) (undef! 'x)").unwrap().iter() {
+            other.evaluate(form).unwrap();
+        }
+        let mut result = Ok(LispyType::create_nil());
+        for form in compile_source_code_to_ast("(def! x 2) (undef! 'x) (= @seen '(nil 2))").unwrap().iter() {
+            result = watched.evaluate(form);
+        }
+        assert_eq!(result.unwrap(), LispyType::create_bool(true));
+    }
+
+    #[test]
+    fn trace_macros_stays_with_its_machine() {
+        let mut traced = LispyMachine::new();
+        let mut quiet = LispyMachine::new();
+        traced.set_trace_macros(true);
+        for machine in [&mut traced, &mut quiet] {
+            machine.register_fn("tracing?", |_| Ok(LispyType::create_bool(TRACE_MACROS.with(|trace| trace.get()))));
+        }
+        let tracing = compile_source_code_to_ast("(tracing?)").unwrap();
+        assert_eq!(traced.evaluate(&tracing[0]).unwrap(), LispyType::create_bool(true));
+        assert_eq!(quiet.evaluate(&tracing[0]).unwrap(), LispyType::create_bool(false));
+        assert!(!TRACE_MACROS.with(|trace| trace.get()));
+    }
+
+    #[test]
+    fn cancelling_one_machine_leaves_the_others_running() {
+        let mut cancelled = LispyMachine::new();
+        let mut running = LispyMachine::new();
+        cancelled.cancellation_token().cancel();
+        let form = &compile_source_code_to_ast("(+ 1 2)").unwrap()[0];
+        assert_eq!(running.evaluate(form).unwrap(), LispyType::create_number(3.0));
+        let interrupted = cancelled.evaluate(form).err().unwrap();
+        assert_eq!(interrupted.as_error().unwrap().error_type, "INTERRUPTED");
+        assert_eq!(running.evaluate(form).unwrap(), LispyType::create_number(3.0));
+    }
+
+    #[test]
+    fn fs_poll_reports_creates_modifies_and_deletes() {
+        let source = "(def! dir (fs/temp-dir)) (def! file (str dir \"/a.txt\")) (def! seen (atom ())) \
+                      (def! w (fs/watch dir (fn* (e) (if (= (get e :path) file) \
+                        (swap! seen (fn* (s) (cons (get e :kind) s))))))) \
+                      (fs/write-atomic file \"a\") (fs/poll w) \
+                      (fs/write-atomic file \"abc\") (fs/poll w) \
+                      (fs/remove file) (fs/poll w) \
+                      (fs/remove dir) \
+                      (= @seen '(:delete :modify :create))";
+        assert_eq!(run(source).unwrap(), LispyType::create_bool(true));
+    }
+
+    #[test]
+    fn fs_watchers_whose_handler_fails_are_unwatched() {
+        let mut machine = LispyMachine::new();
+        let mut result = Ok(LispyType::create_nil());
+        let source = "(def! dir (fs/temp-dir)) (def! w (fs/watch dir (fn* (e) (throw \"boom\")))) \
+                      (fs/touch (str dir \"/a.txt\")) (def! failed (try* (fs/run-watchers 0) (catch* _ e :failed))) \
+                      (fs/touch (str dir \"/b.txt\")) (fs/remove dir) \
+                      (list failed (fs/poll w) (fs/run-watchers 0))";
+        for form in compile_source_code_to_ast(source).unwrap().iter() {
+            result = machine.evaluate(form);
+        }
+        let expected = "(list :failed 0 nil)";
+        let expected = machine.evaluate(&compile_source_code_to_ast(expected).unwrap()[0]);
+        assert_eq!(result.unwrap(), expected.unwrap());
     }
 }
@@ -1,33 +1,287 @@
 extern crate core;
 
-use types::LispyType;
+use std::io::{self, BufRead};
+use std::time::Duration;
 
-use crate::compiler::compile_source_code_to_ast;
-use crate::lexer::LexerToken;
-use crate::machine::LispyMachine;
+use lispy::compiler::compile_source_code_to_ast;
+use lispy::machine::LispyMachine;
+use lispy::types::LispyType;
+use lispy::{coverage, lint, record, repl, server};
 
-mod compiler;
-mod core_ns;
-mod env;
-mod lexer;
-mod machine;
-mod types;
+// Evaluates `expression` once per stdin line with *line* and *line-number* bound,
+// printing every non-nil result, awk style.
+fn run_filter(lispy_machine: &mut LispyMachine, expression: &str) {
+    let ast = compile_source_code_to_ast(expression).unwrap_or_else(|error| {
+        eprintln!("Syntax error: {}", error);
+        std::process::exit(1);
+    });
+    let stdin = io::stdin();
 
-fn main() {
+    for (index, line) in stdin.lock().lines().enumerate() {
+        let line = line.expect("Could not read from stdin");
+        lispy_machine
+            .get_env_mut()
+            .set("*line*", LispyType::create_string(line.as_str()));
+        lispy_machine
+            .get_env_mut()
+            .set("*line-number*", LispyType::create_number((index + 1) as f64));
+
+        let mut result = Ok(LispyType::create_nil());
+        for form in ast.iter() {
+            result = lispy_machine.evaluate(form);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        match result {
+            Ok(value) if !value.is_nil() => println!("{}", value),
+            Ok(_) => {}
+            Err(error) => eprintln!("Error on line {}: {}", index + 1, error),
+        }
+    }
+}
+
+// Prints the warnings of every file without running them. Exits with 1 when there
+// are any and with 2 when a file can't be read or parsed.
+fn run_lint(paths: &[String]) -> i32 {
+    let mut status = 0;
+    for path in paths {
+        let source = std::fs::read_to_string(path);
+        if source.is_err() {
+            eprintln!("Could not read {}: {}", path, source.err().unwrap());
+            status = 2;
+            continue;
+        }
+        match lint::lint_source(source.unwrap().as_str(), path) {
+            Ok(warnings) => {
+                for warning in warnings.iter() {
+                    println!("{}", warning);
+                }
+                if !warnings.is_empty() && status == 0 {
+                    status = 1;
+                }
+            }
+            Err(error) => {
+                eprintln!("{}: {}", path, error);
+                status = 2;
+            }
+        }
+    }
+    status
+}
+
+const USAGE: &str = "Usage: lispy [--coverage] [--trace-macros] [FILE | -e EXPR] [-- ARGS...]
+       lispy filter EXPR
+       lispy lint FILE...
+       lispy repl [--listen PORT [--timeout MS]]
+       lispy replay LOG
+Every mode accepts --record LOG to append the evaluated forms and their results to LOG
+Directories in LISPY_PATH are searched for the stdlib and for required namespaces";
+
+enum Program {
+    File(String),
+    Expression(String),
+}
+
+struct CliOptions {
+    program: Program,
+    script_args: Vec<String>,
+    coverage: bool,
+    trace_macros: bool,
+}
+
+// Everything after `--` is handed to the script untouched as *command-line-args*.
+// Without a file or -e the old demo.lispy entry point is used.
+fn parse_cli_args(args: &[String]) -> Result<CliOptions, String> {
+    let mut program = None;
+    let mut script_args = vec![];
+    let mut coverage = false;
+    let mut trace_macros = false;
+    let mut index = 0;
+
+    while index < args.len() {
+        match args[index].as_str() {
+            "--" => {
+                script_args = args[index + 1..].to_vec();
+                break;
+            }
+            "-e" => {
+                if program.is_some() {
+                    return Err("Only one file or -e expression can be run".to_string());
+                }
+                match args.get(index + 1) {
+                    Some(expression) => program = Some(Program::Expression(expression.clone())),
+                    None => return Err("-e expects an expression".to_string()),
+                }
+                index += 1;
+            }
+            "--coverage" => coverage = true,
+            "--trace-macros" => trace_macros = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            flag if flag.starts_with('-') => return Err(format!("Unknown option {}", flag)),
+            path => {
+                if program.is_some() {
+                    return Err("Only one file or -e expression can be run".to_string());
+                }
+                program = Some(Program::File(path.to_string()));
+            }
+        }
+        index += 1;
+    }
+
+    Ok(CliOptions {
+        program: program.unwrap_or_else(|| Program::File("demo.lispy".to_string())),
+        script_args,
+        coverage,
+        trace_macros,
+    })
+}
+
+fn run_expression(lispy_machine: &mut LispyMachine, expression: &str) {
+    let ast = compile_source_code_to_ast(expression).unwrap_or_else(|error| {
+        eprintln!("Syntax error: {}", error);
+        std::process::exit(1);
+    });
+    let mut result = Ok(LispyType::create_nil());
+    for form in ast.iter() {
+        result = lispy_machine.evaluate(form);
+        if result.is_err() {
+            break;
+        }
+    }
+
+    match result {
+        Ok(value) if !value.is_nil() => println!("{}", value),
+        Ok(_) => {}
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+// `--record LOG` applies to every mode, so it is taken out before the mode is parsed.
+// Arguments after `--` belong to the script and are left alone.
+fn take_record_option(args: &mut Vec<String>) -> Result<Option<String>, String> {
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    let index = args[..end].iter().position(|arg| arg == "--record");
+    if index.is_none() {
+        return Ok(None);
+    }
+    let index = index.unwrap();
+    if index + 1 >= end {
+        return Err("--record expects a file".to_string());
+    }
+    let path = args.remove(index + 1);
+    args.remove(index);
+    Ok(Some(path))
+}
+
+fn new_machine(record: &Option<String>) -> LispyMachine {
     let mut lispy_machine = LispyMachine::new();
+    if let Some(path) = record {
+        let opened = lispy_machine.record_to(path);
+        if opened.is_err() {
+            eprintln!("Could not record to {}: {}", path, opened.err().unwrap());
+            std::process::exit(1);
+        }
+    }
     lispy_machine
-        .get_env_mut()
-        .set("message-to-print", LispyType::create_string("Hello"));
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    let record = take_record_option(&mut args).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(2);
+    });
+    if args.len() > 2 && args[1] == "filter" {
+        let mut lispy_machine = new_machine(&record);
+        run_filter(&mut lispy_machine, args[2].as_str());
+        return;
+    }
+    if args.len() > 2 && args[1] == "lint" {
+        std::process::exit(run_lint(&args[2..]));
+    }
+    if args.len() == 3 && args[1] == "replay" {
+        let mut lispy_machine = new_machine(&record);
+        if !record::replay(&mut lispy_machine, args[2].as_str()) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.len() == 2 && args[1] == "repl" {
+        let mut lispy_machine = new_machine(&record);
+        repl::run_repl(&mut lispy_machine);
+        return;
+    }
+    if (args.len() == 4 || args.len() == 6) && args[1] == "repl" && args[2] == "--listen" {
+        let port = args[3].parse::<u16>().unwrap_or_else(|_| {
+            eprintln!("--listen expects a port number, got {}", args[3]);
+            std::process::exit(2);
+        });
+        let timeout = match args.get(4).map(|flag| flag.as_str()) {
+            Some("--timeout") => match args[5].parse::<u64>() {
+                Ok(ms) => Some(Duration::from_millis(ms)),
+                Err(_) => {
+                    eprintln!("--timeout expects milliseconds, got {}", args[5]);
+                    std::process::exit(2);
+                }
+            },
+            Some(other) => {
+                eprintln!("Unknown option {}\n{}", other, USAGE);
+                std::process::exit(2);
+            }
+            None => None,
+        };
+        let mut lispy_machine = new_machine(&record);
+        server::run_server(&mut lispy_machine, port, timeout);
+        return;
+    }
 
-    lispy_machine.evaluate_file("demo.lispy");
+    let options = parse_cli_args(&args[1..]);
+    if options.is_err() {
+        eprintln!("{}", options.err().unwrap());
+        std::process::exit(2);
+    }
+    let options = options.unwrap();
 
-    println!(
-        "{}",
-        lispy_machine
-            .get_env()
-            .get_item(&"message-to-print".to_string())
-            .unwrap()
-            .as_string()
-            .unwrap()
+    let mut lispy_machine = new_machine(&record);
+    lispy_machine.get_env_mut().set(
+        "*command-line-args*",
+        LispyType::create_list(
+            options
+                .script_args
+                .iter()
+                .map(|arg| LispyType::create_string(arg.as_str()))
+                .collect(),
+        ),
     );
+
+    // Enabled once the stdlib is loaded so only the program's own files are reported
+    if options.coverage {
+        coverage::enable();
+    }
+    if options.trace_macros {
+        lispy_machine.set_trace_macros(true);
+    }
+
+    let mut failed = false;
+    match options.program {
+        Program::Expression(expression) => run_expression(&mut lispy_machine, &expression),
+        Program::File(path) => {
+            if let Err(error) = lispy_machine.evaluate_file(path.as_str()) {
+                eprintln!("{}", error);
+                failed = true;
+            }
+        }
+    }
+
+    if options.coverage {
+        eprint!("{}", coverage::report());
+    }
+    if failed {
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,489 @@
+use crate::env::LispyEnv;
+use crate::sandbox;
+use crate::types::LispyType;
+
+const MATRIX_KIND: &str = "matrix";
+
+// Cells one matrix may hold, 128 MiB of f64s
+const MAX_CELLS: usize = 1 << 24;
+
+// Dense row-major matrix stored in one contiguous buffer
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>,
+}
+
+impl Matrix {
+    fn zeros(rows: usize, cols: usize) -> Self {
+        Self {
+            rows,
+            cols,
+            data: vec![0.0; rows * cols],
+        }
+    }
+
+    fn identity(size: usize) -> Self {
+        let mut matrix = Self::zeros(size, size);
+        for index in 0..size {
+            matrix.data[index * size + index] = 1.0;
+        }
+        matrix
+    }
+
+    fn get(&self, row: usize, col: usize) -> f64 {
+        self.data[row * self.cols + col]
+    }
+
+    fn transpose(&self) -> Self {
+        let mut result = Self::zeros(self.cols, self.rows);
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                result.data[col * self.rows + row] = self.get(row, col);
+            }
+        }
+        result
+    }
+
+    fn matmul(&self, other: &Matrix) -> Option<Self> {
+        if self.cols != other.rows {
+            return None;
+        }
+        let mut result = Self::zeros(self.rows, other.cols);
+        for row in 0..self.rows {
+            for k in 0..self.cols {
+                let a = self.get(row, k);
+                for col in 0..other.cols {
+                    result.data[row * other.cols + col] += a * other.get(k, col);
+                }
+            }
+        }
+        Some(result)
+    }
+
+    fn zip_with(&self, other: &Matrix, op: fn(f64, f64) -> f64) -> Option<Self> {
+        if self.rows != other.rows || self.cols != other.cols {
+            return None;
+        }
+        Some(Self {
+            rows: self.rows,
+            cols: self.cols,
+            data: self
+                .data
+                .iter()
+                .zip(other.data.iter())
+                .map(|(a, b)| op(*a, *b))
+                .collect(),
+        })
+    }
+
+    // Gauss-Jordan elimination with partial pivoting
+    fn inverse(&self) -> Option<Self> {
+        if self.rows != self.cols {
+            return None;
+        }
+        let size = self.rows;
+        let mut work = Self {
+            rows: size,
+            cols: size,
+            data: self.data.clone(),
+        };
+        let mut result = Self::identity(size);
+
+        for col in 0..size {
+            let pivot = (col..size)
+                .max_by(|a, b| {
+                    work.get(*a, col)
+                        .abs()
+                        .partial_cmp(&work.get(*b, col).abs())
+                        .unwrap()
+                })
+                .unwrap();
+            if work.get(pivot, col).abs() < f64::EPSILON {
+                return None;
+            }
+            for k in 0..size {
+                work.data.swap(col * size + k, pivot * size + k);
+                result.data.swap(col * size + k, pivot * size + k);
+            }
+
+            let divisor = work.get(col, col);
+            for k in 0..size {
+                work.data[col * size + k] /= divisor;
+                result.data[col * size + k] /= divisor;
+            }
+
+            for row in 0..size {
+                if row == col {
+                    continue;
+                }
+                let factor = work.get(row, col);
+                for k in 0..size {
+                    work.data[row * size + k] -= factor * work.data[col * size + k];
+                    result.data[row * size + k] -= factor * result.data[col * size + k];
+                }
+            }
+        }
+
+        Some(result)
+    }
+
+    fn to_list(&self) -> LispyType {
+        LispyType::create_list(
+            (0..self.rows)
+                .map(|row| {
+                    LispyType::create_list(
+                        (0..self.cols)
+                            .map(|col| LispyType::create_number(self.get(row, col)))
+                            .collect(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    fn from_list(value: &LispyType) -> Result<Self, LispyType> {
+        let rows = value.as_list();
+        if rows.is_none() {
+            return Err(LispyType::create_error(
+                format!("{} is not a list of rows", value).as_str(),
+                "INCORRECT_TYPE",
+            ));
+        }
+        let rows = rows.unwrap();
+        let cols = rows
+            .first()
+            .and_then(|row| row.as_list())
+            .map(|row| row.len())
+            .unwrap_or(0);
+
+        let mut data = Vec::with_capacity(rows.len() * cols);
+        for row in rows.iter() {
+            let items = row.as_list();
+            if items.is_none() || items.unwrap().len() != cols {
+                return Err(LispyType::create_error(
+                    format!("Matrix rows must be lists of length {}", cols).as_str(),
+                    "INCORRECT_SHAPE",
+                ));
+            }
+            for item in items.unwrap().iter() {
+                match item.as_number() {
+                    Some(number) => data.push(*number),
+                    None => {
+                        return Err(LispyType::create_error(
+                            format!("Matrix elements must be numbers. Received {}", item)
+                                .as_str(),
+                            "INCORRECT_TYPE",
+                        ))
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            rows: rows.len(),
+            cols,
+            data,
+        })
+    }
+}
+
+fn matrix_arg(value: &LispyType) -> Result<&Matrix, LispyType> {
+    match value.as_resource::<Matrix>() {
+        Some(matrix) => Ok(matrix),
+        None => Err(LispyType::create_error(
+            format!("{} is not a matrix", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+fn size_arg(value: &LispyType) -> Result<usize, LispyType> {
+    match value.as_number() {
+        Some(number) if *number >= 0.0 && number.fract() == 0.0 => Ok(*number as usize),
+        _ => Err(LispyType::create_error(
+            format!("{} is not a valid size", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+// Checked before allocating a rows x cols matrix
+fn check_cells(rows: usize, cols: usize) -> Result<(), LispyType> {
+    match rows.checked_mul(cols) {
+        Some(cells) if cells <= MAX_CELLS => sandbox::check_size(cells),
+        _ => Err(LispyType::create_error(
+            format!("A {}x{} matrix has more than {} cells", rows, cols, MAX_CELLS).as_str(),
+            "LIMIT_EXCEEDED",
+        )),
+    }
+}
+
+fn wrap(matrix: Matrix) -> LispyType {
+    LispyType::create_resource(MATRIX_KIND, matrix)
+}
+
+fn shape_error(a: &Matrix, b: &Matrix) -> LispyType {
+    LispyType::create_error(
+        format!(
+            "Incompatible matrix shapes {}x{} and {}x{}",
+            a.rows, a.cols, b.rows, b.cols
+        )
+        .as_str(),
+        "INCORRECT_SHAPE",
+    )
+}
+
+fn element_wise(args: &[LispyType], op: fn(f64, f64) -> f64) -> Result<LispyType, LispyType> {
+    let a = matrix_arg(&args[0]);
+    if a.is_err() {
+        return Err(a.err().unwrap());
+    }
+    let b = matrix_arg(&args[1]);
+    if b.is_err() {
+        return Err(b.err().unwrap());
+    }
+    let (a, b) = (a.unwrap(), b.unwrap());
+    match a.zip_with(b, op) {
+        Some(result) => Ok(wrap(result)),
+        None => Err(shape_error(a, b)),
+    }
+}
+
+pub fn apply_matrix_ns(env: &mut LispyEnv) {
+    env.set(
+        "matrix?",
+        LispyType::create_function(Some(1), |args| {
+            Ok(LispyType::create_bool(args[0].is_resource_of(MATRIX_KIND)))
+        }),
+    );
+    env.set(
+        "matrix/from-list",
+        LispyType::create_function(Some(1), |args| Matrix::from_list(&args[0]).map(wrap)),
+    );
+    env.set(
+        "matrix/to-list",
+        LispyType::create_function(Some(1), |args| matrix_arg(&args[0]).map(|m| m.to_list())),
+    );
+    env.set(
+        "matrix/zeros",
+        LispyType::create_function(Some(2), |args| {
+            let rows = size_arg(&args[0]);
+            if rows.is_err() {
+                return Err(rows.err().unwrap());
+            }
+            let cols = size_arg(&args[1]);
+            if cols.is_err() {
+                return Err(cols.err().unwrap());
+            }
+            let (rows, cols) = (rows.unwrap(), cols.unwrap());
+            let sized = check_cells(rows, cols);
+            if sized.is_err() {
+                return Err(sized.err().unwrap());
+            }
+            Ok(wrap(Matrix::zeros(rows, cols)))
+        }),
+    );
+    env.set(
+        "matrix/identity",
+        LispyType::create_function(Some(1), |args| {
+            let size = size_arg(&args[0]);
+            if size.is_err() {
+                return Err(size.err().unwrap());
+            }
+            let size = size.unwrap();
+            let sized = check_cells(size, size);
+            if sized.is_err() {
+                return Err(sized.err().unwrap());
+            }
+            Ok(wrap(Matrix::identity(size)))
+        }),
+    );
+    env.set(
+        "matrix/shape",
+        LispyType::create_function(Some(1), |args| {
+            matrix_arg(&args[0]).map(|m| {
+                LispyType::create_list(vec![
+                    LispyType::create_number(m.rows as f64),
+                    LispyType::create_number(m.cols as f64),
+                ])
+            })
+        }),
+    );
+    env.set(
+        "matrix/get",
+        LispyType::create_function(Some(3), |args| {
+            let matrix = matrix_arg(&args[0]);
+            if matrix.is_err() {
+                return Err(matrix.err().unwrap());
+            }
+            let matrix = matrix.unwrap();
+            let row = size_arg(&args[1]);
+            let col = size_arg(&args[2]);
+            if row.is_err() || col.is_err() {
+                return Err(LispyType::create_error(
+                    "Matrix indices must be non-negative numbers",
+                    "INCORRECT_TYPE",
+                ));
+            }
+            let (row, col) = (row.unwrap(), col.unwrap());
+            if row >= matrix.rows || col >= matrix.cols {
+                return Err(LispyType::create_error(
+                    format!("Index {}x{} is out of bounds", row, col).as_str(),
+                    "OUT_OF_BOUNDS",
+                ));
+            }
+            Ok(LispyType::create_number(matrix.get(row, col)))
+        }),
+    );
+    env.set(
+        "matrix/add",
+        LispyType::create_function(Some(2), |args| element_wise(&args, |a, b| a + b)),
+    );
+    env.set(
+        "matrix/sub",
+        LispyType::create_function(Some(2), |args| element_wise(&args, |a, b| a - b)),
+    );
+    env.set(
+        "matrix/mul",
+        LispyType::create_function(Some(2), |args| element_wise(&args, |a, b| a * b)),
+    );
+    env.set(
+        "matrix/div",
+        LispyType::create_function(Some(2), |args| element_wise(&args, |a, b| a / b)),
+    );
+    env.set(
+        "matrix/scale",
+        LispyType::create_function(Some(2), |args| {
+            let matrix = matrix_arg(&args[0]);
+            if matrix.is_err() {
+                return Err(matrix.err().unwrap());
+            }
+            let matrix = matrix.unwrap();
+            let factor = args[1].as_number();
+            if factor.is_none() {
+                return Err(LispyType::create_error(
+                    format!("{} is not a number", args[1]).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            let factor = *factor.unwrap();
+            Ok(wrap(Matrix {
+                rows: matrix.rows,
+                cols: matrix.cols,
+                data: matrix.data.iter().map(|item| item * factor).collect(),
+            }))
+        }),
+    );
+    env.set(
+        "matrix/matmul",
+        LispyType::create_function(Some(2), |args| {
+            let a = matrix_arg(&args[0]);
+            if a.is_err() {
+                return Err(a.err().unwrap());
+            }
+            let b = matrix_arg(&args[1]);
+            if b.is_err() {
+                return Err(b.err().unwrap());
+            }
+            let (a, b) = (a.unwrap(), b.unwrap());
+            let sized = check_cells(a.rows, b.cols);
+            if sized.is_err() {
+                return Err(sized.err().unwrap());
+            }
+            match a.matmul(b) {
+                Some(result) => Ok(wrap(result)),
+                None => Err(shape_error(a, b)),
+            }
+        }),
+    );
+    env.set(
+        "matrix/transpose",
+        LispyType::create_function(Some(1), |args| {
+            matrix_arg(&args[0]).map(|m| wrap(m.transpose()))
+        }),
+    );
+    env.set(
+        "matrix/inverse",
+        LispyType::create_function(Some(1), |args| {
+            let matrix = matrix_arg(&args[0]);
+            if matrix.is_err() {
+                return Err(matrix.err().unwrap());
+            }
+            match matrix.unwrap().inverse() {
+                Some(result) => Ok(wrap(result)),
+                None => Err(LispyType::create_error(
+                    "Matrix is not invertible",
+                    "SINGULAR_MATRIX",
+                )),
+            }
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::compile_source_code_to_ast;
+    use crate::machine::LispyMachine;
+    use crate::types::LispyType;
+
+    fn run(source: &str) -> Result<LispyType, LispyType> {
+        let mut machine = LispyMachine::new();
+        let mut result = Ok(LispyType::create_nil());
+        for form in compile_source_code_to_ast(source).unwrap().iter() {
+            result = machine.evaluate(form);
+        }
+        result
+    }
+
+    fn printed(source: &str) -> String {
+        run(source).unwrap().as_string().unwrap().clone()
+    }
+
+    #[test]
+    fn converts_from_and_to_lists() {
+        let source = "(pr-str (matrix/to-list (matrix/transpose (matrix/from-list (list (list 1 2 3) (list 4 5 6))))))";
+        assert_eq!(printed(source), "((1 4) (2 5) (3 6))");
+        assert!(run("(matrix/from-list (list (list 1 2) (list 3)))").is_err());
+    }
+
+    #[test]
+    fn multiplies_and_inverts() {
+        let product = "(pr-str (matrix/to-list (matrix/matmul (matrix/from-list (list (list 1 2) (list 3 4)))
+                                                               (matrix/from-list (list (list 5 6) (list 7 8))))))";
+        assert_eq!(printed(product), "((19 22) (43 50))");
+        let inverse = "(pr-str (matrix/to-list (matrix/inverse (matrix/from-list (list (list 2 0) (list 0 4))))))";
+        assert_eq!(printed(inverse), "((0.5 0) (0 0.25))");
+        let singular = run("(matrix/inverse (matrix/from-list (list (list 1 2) (list 2 4))))");
+        assert_eq!(singular.err().unwrap().as_error().unwrap().error_type, "SINGULAR_MATRIX");
+    }
+
+    #[test]
+    fn rejects_sizes_that_are_not_counts() {
+        for source in ["(matrix/zeros 1.5 2)", "(matrix/zeros -1 2)", "(matrix/identity ##Inf)"] {
+            let error = run(source).err().unwrap();
+            assert_eq!(error.as_error().unwrap().error_type, "INCORRECT_TYPE", "{}", source);
+        }
+        assert_eq!(printed("(pr-str (matrix/shape (matrix/zeros 2 3)))"), "(2 3)");
+    }
+
+    #[test]
+    fn rejects_matrices_over_the_cell_limit() {
+        for source in [
+            "(matrix/zeros 10000000000 10000000000)",
+            "(matrix/zeros 100000 100000)",
+            "(matrix/identity 5000)",
+            "(matrix/matmul (matrix/zeros 5000 1) (matrix/zeros 1 5000))",
+        ] {
+            let error = run(source).err().unwrap();
+            assert_eq!(error.as_error().unwrap().error_type, "LIMIT_EXCEEDED", "{}", source);
+        }
+    }
+
+    #[test]
+    fn is_available_as_the_math_matrix_namespace() {
+        let source = "(require 'math.matrix)
+                      (pr-str (math.matrix/to-rows (math.matrix/eye 2)))";
+        assert_eq!(printed(source), "((1 0) (0 1))");
+    }
+}
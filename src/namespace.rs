@@ -0,0 +1,256 @@
+use crate::compiler::compile_source_file;
+use crate::coverage;
+use crate::env::LispyEnv;
+use crate::machine::eval;
+use crate::stdlib::SearchPath;
+use crate::types::LispyType;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// Where top-level forms go until an `(ns ...)`, it is the root env itself so
+// programs without namespaces behave as before
+pub const DEFAULT_NAMESPACE: &str = "user";
+
+struct Registry {
+    root: LispyEnv,
+    namespaces: HashMap<String, LispyEnv>,
+    current: String,
+    // Files being loaded, innermost last. `require` looks next to the innermost one.
+    loading: Vec<String>,
+    // What load-file returned for each file by canonical path, nil while it loads
+    loaded: HashMap<PathBuf, LispyType>,
+    search_path: SearchPath,
+}
+
+// Every namespace but the default one is a child env of the root: builtins and
+// the stdlib are shared, definitions are not. `(ns name)` switches the namespace
+// the following top-level forms are evaluated in, `name/symbol` reads from
+// another one and `(require 'a.b)` loads a/b.lispy into namespace a.b once.
+#[derive(Clone)]
+pub struct NamespaceRegistry {
+    inner: Rc<RefCell<Registry>>,
+}
+
+pub struct InstalledRegistry {
+    previous: Option<NamespaceRegistry>,
+}
+
+impl Drop for InstalledRegistry {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+thread_local! {
+    // The registry of the machine evaluating on this thread, for the special forms
+    static CURRENT: RefCell<Option<NamespaceRegistry>> = const { RefCell::new(None) };
+}
+
+impl NamespaceRegistry {
+    pub fn new(root: &LispyEnv, search_path: SearchPath) -> Self {
+        let mut namespaces = HashMap::new();
+        namespaces.insert(DEFAULT_NAMESPACE.to_string(), root.clone());
+        Self {
+            inner: Rc::new(RefCell::new(Registry {
+                root: root.clone(),
+                namespaces,
+                current: DEFAULT_NAMESPACE.to_string(),
+                loading: vec![],
+                loaded: HashMap::new(),
+                search_path,
+            })),
+        }
+    }
+
+    // Makes this the registry the special forms use until the guard is dropped,
+    // which puts back whichever was installed before, e.g. by a machine evaluating
+    // another one from a native function
+    pub fn install(&self) -> InstalledRegistry {
+        let previous = CURRENT.with(|current| current.borrow_mut().replace(self.clone()));
+        InstalledRegistry { previous }
+    }
+
+    pub fn current_env(&self) -> LispyEnv {
+        let inner = self.inner.borrow();
+        inner.namespaces.get(&inner.current).unwrap().clone()
+    }
+
+    fn current_name(&self) -> String {
+        self.inner.borrow().current.clone()
+    }
+
+    fn get(&self, name: &str) -> Option<LispyEnv> {
+        self.inner.borrow().namespaces.get(name).cloned()
+    }
+
+    // Makes `name` current, creating it on first use
+    fn switch(&self, name: &str) {
+        let mut inner = self.inner.borrow_mut();
+        if !inner.namespaces.contains_key(name) {
+            let env = LispyEnv::child(&mut inner.root);
+            inner.namespaces.insert(name.to_string(), env);
+        }
+        inner.current = name.to_string();
+    }
+
+    pub fn enter_file(&self, path: &str) {
+        self.inner.borrow_mut().loading.push(path.to_string());
+    }
+
+    pub fn leave_file(&self) {
+        self.inner.borrow_mut().loading.pop();
+    }
+
+    // Candidates for file `relative`: next to the file requiring it, then in the cwd.
+    // The search path comes after these, see `require`.
+    fn source_paths(&self, relative: &str) -> Vec<String> {
+        let mut paths = vec![];
+        let inner = self.inner.borrow();
+        let directory = inner.loading.last().and_then(|file| Path::new(file).parent());
+        if let Some(directory) = directory.filter(|directory| !directory.as_os_str().is_empty()) {
+            paths.push(directory.join(relative).to_string_lossy().to_string());
+        }
+        paths.push(relative.to_string());
+        paths
+    }
+}
+
+fn registry() -> Result<NamespaceRegistry, LispyType> {
+    CURRENT.with(|current| current.borrow().clone()).ok_or_else(|| {
+        LispyType::create_error("Namespaces need a running LispyMachine", "SYSTEM_ERROR")
+    })
+}
+
+pub fn switch_namespace(name: &str) -> Result<(), LispyType> {
+    registry().map(|registry| registry.switch(name))
+}
+
+// Looks up `namespace/symbol` in the namespace, for qualified symbols that are
+// not bound under their full name, the way core/... aliases are. Only code running
+// under the registry's root sees its namespaces, a sandbox env with a root of its
+// own can't reach past it.
+pub fn resolve_qualified(symbol: &str, env: &LispyEnv) -> Option<LispyType> {
+    let (namespace, name) = symbol.rsplit_once('/')?;
+    if namespace.is_empty() || name.is_empty() {
+        return None;
+    }
+    let registry = registry().ok()?;
+    if !registry.inner.borrow().root.ptr_eq(&env.global()) {
+        return None;
+    }
+    let env = registry.get(namespace)?;
+    env.get_item(&name.to_string())
+}
+
+fn not_found(path: &str) -> LispyType {
+    LispyType::create_error(format!("File {} not found", path).as_str(), "SYSTEM_ERROR")
+}
+
+// Evaluates the forms of a file one at a time, each in whatever namespace is current
+// when it is reached, so an `(ns ...)` in the file applies to the forms after it.
+// The namespace that was current before is restored afterwards.
+fn evaluate_source(registry: &NamespaceRegistry, source: &str, file: &str) -> Result<LispyType, LispyType> {
+    let ast = compile_source_file(source, file);
+    if ast.is_err() {
+        return Err(ast.err().unwrap().to_lispy_error());
+    }
+    let ast = ast.unwrap();
+    coverage::register_file(file, &ast);
+
+    let previous = registry.current_name();
+    registry.enter_file(file);
+    let mut result = Ok(LispyType::create_nil());
+    for form in ast.iter() {
+        coverage::hit_form(form);
+        result = eval(form, &mut registry.current_env());
+        if result.is_err() {
+            break;
+        }
+    }
+    registry.leave_file();
+    registry.switch(&previous);
+    result
+}
+
+// A file that was loaded before is not evaluated again unless `force`d, its first
+// result is returned instead
+pub fn load_file(path: &str, force: bool) -> Result<LispyType, LispyType> {
+    let registry = registry()?;
+    let canonical = fs::canonicalize(path);
+    if canonical.is_err() {
+        return Err(not_found(path));
+    }
+    let canonical = canonical.unwrap();
+    // Files loading each other in a cycle see nil for the one still loading
+    let cached = registry.inner.borrow().loaded.get(&canonical).cloned();
+    if let Some(cached) = cached.filter(|_| !force) {
+        return Ok(cached);
+    }
+
+    let contents = fs::read_to_string(path);
+    if contents.is_err() {
+        return Err(not_found(path));
+    }
+    registry.inner.borrow_mut().loaded.insert(canonical.clone(), LispyType::create_nil());
+    let result = evaluate_source(&registry, contents.unwrap().as_str(), path);
+    // A failed load is retried on the next load-file
+    let mut inner = registry.inner.borrow_mut();
+    match &result {
+        Ok(value) => inner.loaded.insert(canonical, value.clone()),
+        Err(_) => inner.loaded.remove(&canonical),
+    };
+    result
+}
+
+// Loads namespace `name` from its file unless it exists already, e.g. defined
+// earlier or currently being required further up a cycle. a.b is looked for as
+// a/b.lispy next to the requiring file, in the cwd, on the search path and last
+// among the embedded stdlib files. The file starts out in `name`, so it does not
+// need an `(ns ...)` of its own.
+pub fn require(name: &str) -> Result<(), LispyType> {
+    let registry = registry()?;
+    if registry.get(name).is_some() {
+        return Ok(());
+    }
+    let relative = format!("{}.lispy", name.replace('.', "/"));
+    let paths = registry.source_paths(&relative);
+    let path = paths.iter().find(|path| Path::new(path).is_file());
+    let stdlib = if path.is_none() {
+        registry.inner.borrow().search_path.stdlib_source(&relative)
+    } else {
+        None
+    };
+    if path.is_none() && stdlib.is_none() {
+        let searched = registry.inner.borrow().search_path.candidates(&relative);
+        let searched = searched.iter().map(|path| path.to_string_lossy().to_string());
+        return Err(LispyType::create_error(
+            format!(
+                "Namespace {} not found, looked for {}",
+                name,
+                paths.iter().cloned().chain(searched).collect::<Vec<_>>().join(", ")
+            )
+            .as_str(),
+            "SYSTEM_ERROR",
+        ));
+    }
+
+    let previous = registry.current_name();
+    registry.switch(name);
+    let loaded = match path {
+        Some(path) => load_file(path, true),
+        None => {
+            let (source, file) = stdlib.unwrap();
+            evaluate_source(&registry, &source, &file)
+        }
+    };
+    registry.switch(&previous);
+    // A namespace that failed to load is required again from scratch next time
+    if loaded.is_err() {
+        registry.inner.borrow_mut().namespaces.remove(name);
+    }
+    loaded.map(|_| ())
+}
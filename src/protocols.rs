@@ -0,0 +1,41 @@
+use crate::machine::apply_callable;
+use crate::types::LispyType;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+// Until proper records land, a "user type" is a hash with a keyword under `:type`.
+// Implementations for the overloadable operators are registered with `extend-type`
+// and looked up by the type of the first argument.
+pub const OVERLOADABLE: [&str; 4] = ["+", "=", "compare", "count"];
+
+thread_local! {
+    static IMPLEMENTATIONS: RefCell<HashMap<(String, String), LispyType>> =
+        RefCell::new(HashMap::new());
+}
+
+pub fn type_tag(value: &LispyType) -> Option<String> {
+    value
+        .as_hash()?
+        .get(&LispyType::create_keyword(":type"))?
+        .as_keyword()
+        .cloned()
+}
+
+pub fn extend_type(tag: String, operation: String, implementation: LispyType) {
+    IMPLEMENTATIONS.with(|implementations| {
+        implementations
+            .borrow_mut()
+            .insert((tag, operation), implementation);
+    });
+}
+
+pub fn dispatch(operation: &str, args: &[LispyType]) -> Option<Result<LispyType, LispyType>> {
+    let tag = type_tag(args.first()?)?;
+    let implementation = IMPLEMENTATIONS.with(|implementations| {
+        implementations
+            .borrow()
+            .get(&(tag, operation.to_string()))
+            .cloned()
+    })?;
+    Some(apply_callable(&implementation, args.to_vec()))
+}
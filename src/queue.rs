@@ -0,0 +1,45 @@
+use crate::types::LispyType;
+use std::collections::VecDeque;
+
+pub const QUEUE_KIND: &str = "queue";
+
+// FIFO queue value. Like the other collections it is immutable: push and pop
+// return a new queue, which for now copies the items.
+pub struct Queue {
+    items: VecDeque<LispyType>,
+}
+
+pub fn create_queue(items: VecDeque<LispyType>) -> LispyType {
+    LispyType::create_resource(QUEUE_KIND, Queue { items })
+}
+
+pub fn queue_arg(value: &LispyType) -> Result<&Queue, LispyType> {
+    match value.as_resource::<Queue>() {
+        Some(queue) => Ok(queue),
+        None => Err(LispyType::create_error(
+            format!("{} is not a queue", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+impl Queue {
+    pub fn items(&self) -> &VecDeque<LispyType> {
+        &self.items
+    }
+
+    pub fn push(&self, value: LispyType) -> LispyType {
+        let mut items = self.items.clone();
+        items.push_back(value);
+        create_queue(items)
+    }
+
+    pub fn pop(&self) -> Option<LispyType> {
+        if self.items.is_empty() {
+            return None;
+        }
+        let mut items = self.items.clone();
+        items.pop_front();
+        Some(create_queue(items))
+    }
+}
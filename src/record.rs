@@ -0,0 +1,112 @@
+use crate::compiler::compile_source_code_to_ast;
+use crate::machine::LispyMachine;
+use crate::types::LispyType;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+
+const RESULT_PREFIX: &str = ";; => ";
+const ERROR_PREFIX: &str = ";; !! ";
+
+// `--record FILE` appends every top-level form evaluated and how it ended to FILE:
+//   (+ 1 2)
+//   ;; => 3
+// with `;; !! ERROR_TYPE message` for errors. Outcomes are comments, so a log also
+// runs as a plain program, and `lispy replay FILE` re-executes it checking the outcomes.
+pub struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    pub fn record(&mut self, form: &LispyType, result: &Result<LispyType, LispyType>) {
+        let entry = format!("{}\n{}\n", form.to_readable_string(), outcome_line(result));
+        let written = self.file.write_all(entry.as_bytes());
+        if written.is_err() {
+            eprintln!("Could not record: {}", written.err().unwrap());
+        }
+    }
+}
+
+// Kept on one line so entries stay separable
+fn outcome_line(result: &Result<LispyType, LispyType>) -> String {
+    let (prefix, printed) = match result {
+        Ok(value) => (RESULT_PREFIX, value.to_readable_string()),
+        Err(error) => match error.as_error() {
+            Some(error) => (ERROR_PREFIX, format!("{} {}", error.error_type, error.message)),
+            None => (ERROR_PREFIX, format!("{}", error)),
+        },
+    };
+    format!("{}{}", prefix, printed.replace('\n', " "))
+}
+
+// Error messages point into the source they were raised from, which moves once
+// forms are recorded, so errors only have to agree on their type
+fn same_outcome(recorded: &str, replayed: &str) -> bool {
+    let error_type = |line: &str| {
+        let outcome = line.strip_prefix(ERROR_PREFIX)?;
+        outcome.split(' ').next().map(String::from)
+    };
+    match (error_type(recorded), error_type(replayed)) {
+        (Some(recorded), Some(replayed)) => recorded == replayed,
+        _ => recorded == replayed,
+    }
+}
+
+// Evaluates every entry of a recorded log in order, reporting entries whose outcome
+// differs from the recorded one. Forms after the last outcome, e.g. from a session
+// that crashed, are evaluated without a check. Returns whether all outcomes matched.
+pub fn replay(lispy_machine: &mut LispyMachine, path: &str) -> bool {
+    let log = fs::read_to_string(path).unwrap_or_else(|error| {
+        eprintln!("Could not read {}: {}", path, error);
+        std::process::exit(1);
+    });
+
+    let mut entries: Vec<(String, Option<&str>)> = vec![];
+    let mut source = String::new();
+    for line in log.lines() {
+        if line.starts_with(RESULT_PREFIX) || line.starts_with(ERROR_PREFIX) {
+            entries.push((std::mem::take(&mut source), Some(line)));
+        } else {
+            source.push_str(line);
+            source.push('\n');
+        }
+    }
+    if !source.trim().is_empty() {
+        entries.push((source, None));
+    }
+
+    let mut differed = 0;
+    for (index, (source, recorded)) in entries.iter().enumerate() {
+        let ast = compile_source_code_to_ast(source);
+        if ast.is_err() {
+            eprintln!("Entry {}: syntax error: {}", index + 1, ast.err().unwrap());
+            differed += 1;
+            continue;
+        }
+        let mut result = Ok(LispyType::create_nil());
+        for form in ast.unwrap().iter() {
+            result = lispy_machine.evaluate(form);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        let replayed = outcome_line(&result);
+        if recorded.is_some() && !same_outcome(recorded.unwrap(), &replayed) {
+            eprintln!(
+                "Entry {} `{}` differs\n  recorded {}\n  replayed {}",
+                index + 1,
+                source.trim(),
+                recorded.unwrap(),
+                replayed
+            );
+            differed += 1;
+        }
+    }
+    eprintln!("Replayed {} entries, {} differed", entries.len(), differed);
+    differed == 0
+}
@@ -0,0 +1,435 @@
+use crate::compiler::compile_source_code_to_ast;
+use crate::lexer::LexerToken;
+use crate::machine::LispyMachine;
+use logos::Logos;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const PROMPT: &str = "lispy> ";
+const CONTINUATION_PROMPT: &str = "   ...> ";
+const HISTORY_FILE: &str = ".lispy_history";
+const HISTORY_LIMIT: usize = 1000;
+
+enum ReadResult {
+    Line(String),
+    Interrupted,
+    Eof,
+}
+
+enum Search {
+    Accept(String),
+    Edit(Vec<char>),
+    Interrupted,
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    KillToEnd,
+    KillToStart,
+    Search,
+    Cancel,
+    Interrupt,
+    Eof,
+    Closed,
+    Escape,
+    Unknown,
+}
+
+// Puts the terminal into unbuffered, no echo mode for as long as it lives. There is
+// no termios binding in the dependencies, so this goes through stty like a shell would.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> Option<Self> {
+        let saved = Command::new("stty")
+            .arg("-g")
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        let saved = String::from_utf8_lossy(&saved.stdout).trim().to_string();
+        let status = Command::new("stty")
+            .args(["-icanon", "-echo", "-isig", "-ixon", "min", "1"])
+            .stdin(Stdio::inherit())
+            .status()
+            .ok()?;
+        if !status.success() {
+            return None;
+        }
+        Some(Self { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = Command::new("stty").arg(&self.saved).stdin(Stdio::inherit()).status();
+    }
+}
+
+struct LineEditor {
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+}
+
+impl LineEditor {
+    fn new() -> Self {
+        let history_path =
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        let history = history_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| {
+                let lines: Vec<String> = contents
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(|line| line.to_string())
+                    .collect();
+                lines[lines.len().saturating_sub(HISTORY_LIMIT)..].to_vec()
+            })
+            .unwrap_or_default();
+        Self {
+            history,
+            history_path,
+        }
+    }
+
+    // Multi-line entries are stored on one line so the history file stays line based
+    fn add_history(&mut self, entry: &str) {
+        let entry = entry.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if entry.is_empty() || self.history.last() == Some(&entry) {
+            return;
+        }
+        self.history.push(entry.clone());
+        if let Some(path) = &self.history_path {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", entry);
+            }
+        }
+    }
+
+    fn read_line(&mut self, prompt: &str) -> ReadResult {
+        let raw_mode = RawMode::enable();
+        if raw_mode.is_none() {
+            return read_plain_line(prompt);
+        }
+        self.edit(prompt)
+    }
+
+    fn edit(&mut self, prompt: &str) -> ReadResult {
+        let mut buffer: Vec<char> = vec![];
+        let mut cursor = 0;
+        // history.len() is the line being typed, it is kept while browsing older entries
+        let mut history_index = self.history.len();
+        let mut pending: Vec<char> = vec![];
+
+        redraw(prompt, &buffer, cursor);
+        loop {
+            match read_key() {
+                Key::Char(char) => {
+                    buffer.insert(cursor, char);
+                    cursor += 1;
+                }
+                Key::Enter => {
+                    print!("\r\n");
+                    let _ = io::stdout().flush();
+                    return ReadResult::Line(buffer.iter().collect());
+                }
+                Key::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    buffer.remove(cursor);
+                }
+                Key::Delete if cursor < buffer.len() => {
+                    buffer.remove(cursor);
+                }
+                Key::Left if cursor > 0 => cursor -= 1,
+                Key::Right if cursor < buffer.len() => cursor += 1,
+                Key::Home => cursor = 0,
+                Key::End => cursor = buffer.len(),
+                Key::KillToEnd => buffer.truncate(cursor),
+                Key::KillToStart => {
+                    buffer.drain(..cursor);
+                    cursor = 0;
+                }
+                Key::Up if history_index > 0 => {
+                    if history_index == self.history.len() {
+                        pending = buffer.clone();
+                    }
+                    history_index -= 1;
+                    buffer = self.history[history_index].chars().collect();
+                    cursor = buffer.len();
+                }
+                Key::Down if history_index < self.history.len() => {
+                    history_index += 1;
+                    buffer = if history_index == self.history.len() {
+                        pending.clone()
+                    } else {
+                        self.history[history_index].chars().collect()
+                    };
+                    cursor = buffer.len();
+                }
+                Key::Search => match self.search(&buffer) {
+                    Search::Accept(line) => {
+                        print!("\r{}{}\x1b[K\r\n", prompt, line);
+                        let _ = io::stdout().flush();
+                        return ReadResult::Line(line);
+                    }
+                    Search::Edit(line) => {
+                        buffer = line;
+                        cursor = buffer.len();
+                    }
+                    Search::Interrupted => {
+                        print!("^C\r\n");
+                        let _ = io::stdout().flush();
+                        return ReadResult::Interrupted;
+                    }
+                },
+                Key::Interrupt => {
+                    print!("^C\r\n");
+                    let _ = io::stdout().flush();
+                    return ReadResult::Interrupted;
+                }
+                Key::Closed => {
+                    print!("\r\n");
+                    let _ = io::stdout().flush();
+                    return ReadResult::Eof;
+                }
+                Key::Eof if buffer.is_empty() => {
+                    print!("\r\n");
+                    let _ = io::stdout().flush();
+                    return ReadResult::Eof;
+                }
+                Key::Eof if cursor < buffer.len() => {
+                    buffer.remove(cursor);
+                }
+                _ => {}
+            }
+            redraw(prompt, &buffer, cursor);
+        }
+    }
+
+    // Ctrl-R: incremental search backwards through history. Enter runs the match,
+    // Ctrl-R again looks further back, Ctrl-G or Escape go back to the original line
+    // and any other key keeps editing the match.
+    fn search(&self, original: &[char]) -> Search {
+        let mut query = String::new();
+        let mut found: Option<usize> = None;
+        loop {
+            let shown = found.map(|index| self.history[index].as_str()).unwrap_or("");
+            print!("\r(reverse-i-search)`{}': {}\x1b[K", query, shown);
+            let _ = io::stdout().flush();
+
+            match read_key() {
+                Key::Cancel | Key::Escape => return Search::Edit(original.to_vec()),
+                Key::Char(char) => {
+                    query.push(char);
+                    found = self.find(&query, self.history.len()).or(found);
+                }
+                Key::Backspace => {
+                    query.pop();
+                    found = self.find(&query, self.history.len());
+                }
+                Key::Search => {
+                    let start = found.unwrap_or(self.history.len());
+                    found = self.find(&query, start).or(found);
+                }
+                Key::Interrupt | Key::Closed => return Search::Interrupted,
+                Key::Enter => return Search::Accept(shown.to_string()),
+                _ => return Search::Edit(shown.chars().collect()),
+            }
+        }
+    }
+
+    fn find(&self, query: &str, before: usize) -> Option<usize> {
+        if query.is_empty() {
+            return None;
+        }
+        (0..before).rev().find(|index| self.history[*index].contains(query))
+    }
+}
+
+fn read_plain_line(prompt: &str) -> ReadResult {
+    print!("{}", prompt);
+    let _ = io::stdout().flush();
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => ReadResult::Eof,
+        Ok(_) => ReadResult::Line(line.trim_end_matches(['\n', '\r']).to_string()),
+    }
+}
+
+fn redraw(prompt: &str, buffer: &[char], cursor: usize) {
+    let line: String = buffer.iter().collect();
+    print!("\r{}{}\x1b[K", prompt, line);
+    if cursor < buffer.len() {
+        print!("\x1b[{}D", buffer.len() - cursor);
+    }
+    let _ = io::stdout().flush();
+}
+
+fn read_byte() -> Option<u8> {
+    let mut byte = [0u8; 1];
+    match io::stdin().lock().read(&mut byte) {
+        Ok(1) => Some(byte[0]),
+        _ => None,
+    }
+}
+
+fn read_key() -> Key {
+    let byte = match read_byte() {
+        Some(byte) => byte,
+        None => return Key::Closed,
+    };
+    match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x05 => Key::End,
+        0x0b => Key::KillToEnd,
+        0x15 => Key::KillToStart,
+        0x12 => Key::Search,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x1b => read_escape(),
+        0x07 => Key::Cancel,
+        byte if byte < 0x20 => Key::Unknown,
+        byte if byte < 0x80 => Key::Char(byte as char),
+        byte => read_utf8(byte),
+    }
+}
+
+// Arrow keys and friends arrive as ESC [ X or ESC O X
+fn read_escape() -> Key {
+    match read_byte() {
+        Some(b'[') | Some(b'O') => {}
+        _ => return Key::Escape,
+    }
+    match read_byte() {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        Some(digit @ b'0'..=b'9') => {
+            // ESC [ n ~ sequences, only delete (3), home (1, 7) and end (4, 8) are used
+            let mut code = vec![digit];
+            while let Some(byte) = read_byte() {
+                if byte == b'~' {
+                    break;
+                }
+                code.push(byte);
+            }
+            match code.as_slice() {
+                b"3" => Key::Delete,
+                b"1" | b"7" => Key::Home,
+                b"4" | b"8" => Key::End,
+                _ => Key::Unknown,
+            }
+        }
+        _ => Key::Unknown,
+    }
+}
+
+fn read_utf8(first: u8) -> Key {
+    let length = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Key::Unknown,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..length {
+        match read_byte() {
+            Some(byte) => bytes.push(byte),
+            None => return Key::Unknown,
+        }
+    }
+    match std::str::from_utf8(&bytes).ok().and_then(|text| text.chars().next()) {
+        Some(char) => Key::Char(char),
+        None => Key::Unknown,
+    }
+}
+
+// Whether every opened list, vector and hash in `source` is closed again, so the
+// REPL knows to keep reading on the next line. Unreadable input counts as complete
+// and is reported by the reader instead.
+fn is_complete(source: &str) -> bool {
+    let mut depth: i64 = 0;
+    for token in LexerToken::lexer(source) {
+        match token {
+            LexerToken::ListStart
+            | LexerToken::VectorStart
+            | LexerToken::HashStart
+            | LexerToken::ReaderConditionalStart => depth += 1,
+            LexerToken::ListEnd | LexerToken::VectorEnd | LexerToken::HashEnd => depth -= 1,
+            _ => {}
+        }
+    }
+    depth <= 0
+}
+
+fn evaluate_entry(lispy_machine: &mut LispyMachine, source: &str) {
+    let ast = compile_source_code_to_ast(source);
+    if ast.is_err() {
+        eprintln!("Syntax error: {}", ast.err().unwrap());
+        return;
+    }
+    let ast = ast.unwrap();
+    // A panic in the machine ends this entry, not the session
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        for form in ast.iter() {
+            match lispy_machine.evaluate(form) {
+                Ok(value) => println!("{}", value.to_readable_string()),
+                Err(error) => {
+                    eprintln!("Error: {}", error);
+                    break;
+                }
+            }
+        }
+    }));
+    if outcome.is_err() {
+        eprintln!("Error: evaluation aborted");
+    }
+}
+
+// Interactive session: line editing and history when stdin is a terminal, plain
+// line reading otherwise. Ctrl-C drops the current input, Ctrl-D on an empty line exits.
+pub fn run_repl(lispy_machine: &mut LispyMachine) {
+    let mut editor = LineEditor::new();
+    let mut entry = String::new();
+
+    loop {
+        let prompt = if entry.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+        match editor.read_line(prompt) {
+            ReadResult::Line(line) => {
+                if !entry.is_empty() {
+                    entry.push('\n');
+                }
+                entry.push_str(&line);
+                if !is_complete(&entry) {
+                    continue;
+                }
+                editor.add_history(&entry);
+                evaluate_entry(lispy_machine, &entry);
+                entry.clear();
+            }
+            ReadResult::Interrupted => entry.clear(),
+            ReadResult::Eof => break,
+        }
+    }
+}
@@ -0,0 +1,119 @@
+#[cfg(feature = "archive")]
+use crate::archive_ns;
+use crate::env::LispyEnv;
+use crate::fs_ns;
+#[cfg(feature = "image")]
+use crate::image_ns;
+use crate::types::LispyType;
+use std::cell::Cell;
+
+// Steps of `safe-eval` and `eval-template` unless told otherwise
+pub const DEFAULT_STEP_LIMIT: u64 = 100_000;
+
+// Items a single builtin may allocate while sandboxed, e.g. the length of a range
+// or the cells of a matrix, so a template can't exhaust the host's memory in one step
+pub const MAX_SANDBOX_ITEMS: usize = 1_000_000;
+
+// Special forms reading files, LispyMachineBuilder::without_file_io denies them in
+// its machine
+pub const FILE_FORMS: [&str; 3] = ["load-file", "load-file-force", "require"];
+
+// Builtins touching the file system, dropped by LispyMachineBuilder::without_file_io
+// and kept out of eval-template
+pub fn file_builtins() -> Vec<&'static str> {
+    let mut names = vec!["slurp"];
+    names.extend(fs_ns::FS_BUILTINS);
+    #[cfg(feature = "image")]
+    names.extend(image_ns::IMAGE_BUILTINS);
+    #[cfg(feature = "archive")]
+    names.extend(archive_ns::ARCHIVE_BUILTINS);
+    names
+}
+
+// Special forms that reach outside the env they are evaluated in: files, the
+// namespace registry and the test registry
+const DENIED_FORMS: [&str; 6] = [
+    "load-file",
+    "load-file-force",
+    "require",
+    "ns",
+    "deftest",
+    "run-tests",
+];
+
+thread_local! {
+    // Eval steps the innermost sandbox may still take, None outside of any
+    static STEPS_LEFT: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+// Restores the enclosing budget, charged with the steps spent inside, even when
+// the sandboxed eval panics
+struct Budget {
+    outer: Option<u64>,
+    limit: u64,
+}
+
+impl Drop for Budget {
+    fn drop(&mut self) {
+        let left = STEPS_LEFT.with(|steps| steps.get()).unwrap_or(0);
+        let used = self.limit - left;
+        STEPS_LEFT.with(|steps| steps.set(self.outer.map(|outer| outer.saturating_sub(used))));
+    }
+}
+
+// Runs `f` with at most `steps` eval steps, fewer if an enclosing sandbox has less left
+pub fn run<T>(steps: u64, f: impl FnOnce() -> Result<T, LispyType>) -> Result<T, LispyType> {
+    let outer = STEPS_LEFT.with(|steps| steps.get());
+    let limit = outer.map_or(steps, |outer| outer.min(steps));
+    STEPS_LEFT.with(|steps| steps.set(Some(limit)));
+    let _budget = Budget { outer, limit };
+    f()
+}
+
+// Called by eval before every step
+pub fn step() -> Result<(), LispyType> {
+    STEPS_LEFT.with(|steps| match steps.get() {
+        None => Ok(()),
+        Some(0) => Err(LispyType::create_error(
+            "Sandboxed evaluation ran out of steps",
+            "STEP_LIMIT",
+        )),
+        Some(left) => {
+            steps.set(Some(left - 1));
+            Ok(())
+        }
+    })
+}
+
+// Called by builtins before allocating `items` values, only limited inside a sandbox
+pub fn check_size(items: usize) -> Result<(), LispyType> {
+    let active = STEPS_LEFT.with(|steps| steps.get().is_some());
+    if active && items > MAX_SANDBOX_ITEMS {
+        return Err(LispyType::create_error(
+            format!(
+                "Sandboxed evaluation may allocate at most {} items at once, {} requested",
+                MAX_SANDBOX_ITEMS, items
+            )
+            .as_str(),
+            "LIMIT_EXCEEDED",
+        ));
+    }
+    Ok(())
+}
+
+// Called by eval before dispatching the special form `name` in `env`. Only file
+// forms can be denied per machine, so other names skip the walk to the root.
+pub fn check_form(name: &str, env: &LispyEnv) -> Result<(), LispyType> {
+    let active = STEPS_LEFT.with(|steps| steps.get().is_some());
+    let message = if active && DENIED_FORMS.contains(&name) {
+        "is not available in a sandbox"
+    } else if FILE_FORMS.contains(&name) && env.is_denied(name) {
+        "is disabled for this machine"
+    } else {
+        return Ok(());
+    };
+    Err(LispyType::create_error(
+        format!("{} {}", name, message).as_str(),
+        "SANDBOXED",
+    ))
+}
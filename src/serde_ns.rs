@@ -0,0 +1,199 @@
+use crate::env::LispyEnv;
+use crate::types::{sorted_hash_entries, LispyType};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+
+// Largest whole number every f64 below it is exact for, these serialize as integers
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+// Data only: nil is unit, keywords and symbols are their names, lists and vectors
+// are sequences and hashes are maps in key order. Functions, resources and errors
+// can't be serialized.
+impl Serialize for LispyType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            LispyType::Nil { .. } => serializer.serialize_unit(),
+            LispyType::Bool { value, .. } => serializer.serialize_bool(*value),
+            LispyType::Number { value, .. } if value.fract() == 0.0 && value.abs() < MAX_SAFE_INTEGER => {
+                serializer.serialize_i64(*value as i64)
+            }
+            LispyType::Number { value, .. } => serializer.serialize_f64(*value),
+            LispyType::String { value, .. } | LispyType::Symbol { value, .. } => serializer.serialize_str(value),
+            LispyType::Keyword { value, .. } => serializer.serialize_str(value.trim_start_matches(':')),
+            LispyType::List { collection, .. } | LispyType::Vector { collection, .. } => {
+                let mut sequence = serializer.serialize_seq(Some(collection.len()))?;
+                for item in collection.iter() {
+                    sequence.serialize_element(item)?;
+                }
+                sequence.end()
+            }
+            LispyType::Hash { collection, .. } => {
+                let mut map = serializer.serialize_map(Some(collection.len()))?;
+                for (key, value) in sorted_hash_entries(collection) {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            LispyType::Atom { value, .. } => value.borrow().serialize(serializer),
+            _ => Err(ser::Error::custom(format!("{} can't be serialized", self))),
+        }
+    }
+}
+
+struct LispyVisitor;
+
+impl<'de> Visitor<'de> for LispyVisitor {
+    type Value = LispyType;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("nil, a bool, number, string, sequence or map")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<LispyType, E> {
+        Ok(LispyType::create_nil())
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<LispyType, E> {
+        Ok(LispyType::create_nil())
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<LispyType, D::Error> {
+        LispyType::deserialize(deserializer)
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<LispyType, E> {
+        Ok(LispyType::create_bool(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<LispyType, E> {
+        Ok(LispyType::create_number(value as f64))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<LispyType, E> {
+        Ok(LispyType::create_number(value as f64))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<LispyType, E> {
+        Ok(LispyType::create_number(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<LispyType, E> {
+        Ok(LispyType::create_string(value))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut sequence: A) -> Result<LispyType, A::Error> {
+        let mut items = vec![];
+        while let Some(item) = sequence.next_element::<LispyType>()? {
+            items.push(item);
+        }
+        Ok(LispyType::create_list(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<LispyType, A::Error> {
+        let mut collection = HashMap::new();
+        while let Some((key, value)) = map.next_entry::<LispyType, LispyType>()? {
+            if !key.is_hashable() {
+                return Err(de::Error::custom(format!("{} can't be a hash key", key)));
+            }
+            collection.insert(key, value);
+        }
+        Ok(LispyType::Hash {
+            collection: Box::new(collection),
+            meta: HashMap::new(),
+        })
+    }
+}
+
+// Sequences become lists and maps hashes, JSON object keys stay strings
+impl<'de> Deserialize<'de> for LispyType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(LispyVisitor)
+    }
+}
+
+pub fn apply_serde_ns(env: &mut LispyEnv) {
+    env.set(
+        "json-encode",
+        LispyType::create_function(Some(1), |args| {
+            serde_json::to_string(&args[0])
+                .map(|json| LispyType::create_string(json.as_str()))
+                .map_err(|error| {
+                    LispyType::create_error(
+                        format!("Could not encode {} as JSON: {}", args[0], error).as_str(),
+                        "INCORRECT_TYPE",
+                    )
+                })
+        }),
+    );
+    env.set(
+        "json-decode",
+        LispyType::create_function(Some(1), |args| {
+            let source = args[0].as_string();
+            if source.is_none() {
+                return Err(LispyType::create_error(
+                    format!("json-decode expects a string. Received {}", args[0]).as_str(),
+                    "INCORRECT_TYPE",
+                ));
+            }
+            serde_json::from_str::<LispyType>(source.unwrap()).map_err(|error| {
+                LispyType::create_error(format!("Invalid JSON: {}", error).as_str(), "SYNTAX_ERROR")
+            })
+        }),
+    );
+}
+
+// Bytes are vectors of numbers 0-255, lispy has no bytes type of its own yet
+#[cfg(feature = "msgpack")]
+fn bytes_arg(value: &LispyType) -> Result<Vec<u8>, LispyType> {
+    let error = || {
+        LispyType::create_error(
+            format!("{} is not a sequence of bytes", value).as_str(),
+            "INCORRECT_TYPE",
+        )
+    };
+    let items = value.as_sequential();
+    if items.is_none() {
+        return Err(error());
+    }
+    items
+        .unwrap()
+        .iter()
+        .map(|item| match item.as_number() {
+            Some(byte) if byte.fract() == 0.0 && (0.0..=255.0).contains(byte) => Ok(*byte as u8),
+            _ => Err(error()),
+        })
+        .collect()
+}
+
+#[cfg(feature = "msgpack")]
+pub fn apply_msgpack_ns(env: &mut LispyEnv) {
+    env.set(
+        "msgpack/encode",
+        LispyType::create_function(Some(1), |args| {
+            rmp_serde::to_vec(&args[0])
+                .map(|bytes| {
+                    LispyType::create_vector(bytes.into_iter().map(|byte| LispyType::create_number(byte as f64)).collect())
+                })
+                .map_err(|error| {
+                    LispyType::create_error(
+                        format!("Could not encode {} as MessagePack: {}", args[0], error).as_str(),
+                        "INCORRECT_TYPE",
+                    )
+                })
+        }),
+    );
+    env.set(
+        "msgpack/decode",
+        LispyType::create_function(Some(1), |args| {
+            let bytes = bytes_arg(&args[0]);
+            if bytes.is_err() {
+                return Err(bytes.err().unwrap());
+            }
+            rmp_serde::from_slice::<LispyType>(&bytes.unwrap()).map_err(|error| {
+                LispyType::create_error(format!("Invalid MessagePack: {}", error).as_str(), "SYNTAX_ERROR")
+            })
+        }),
+    );
+}
@@ -0,0 +1,361 @@
+use crate::cancel::CancellationToken;
+use crate::compiler::compile_source_code_to_ast;
+use crate::env::LispyEnv;
+use crate::machine::LispyMachine;
+use crate::types::LispyType;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Frames larger than this are refused rather than allocated
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+// How often the watchdog looks for a request past its deadline
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(10);
+
+// Wire format, both directions: a 4 byte big endian length followed by that many
+// bytes of UTF-8. A request is source code, its response starts with a status line,
+// "ok" followed by the readable value of the last form, or "error" followed by the message.
+// A request whose first word is a session op is handled as that op instead:
+//   :clone-session        copies the current session, responds with the new id
+//   :use-session ID       evaluates the following requests of this connection in ID
+//   :close-session [ID]   drops ID, the current session by default
+//   :timeout MS           limits the following evals of this connection, 0 for no limit
+//   :interrupt            stops the eval of this connection that is running, if any
+fn read_frame(stream: &mut TcpStream) -> io::Result<Option<String>> {
+    let mut length = [0u8; 4];
+    match stream.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {} bytes exceeds the limit of {}", length, MAX_FRAME_SIZE),
+        ));
+    }
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload)?;
+    String::from_utf8(payload)
+        .map(Some)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Frame is not valid UTF-8"))
+}
+
+fn write_frame(stream: &mut TcpStream, payload: &str) -> io::Result<()> {
+    stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+    stream.write_all(payload.as_bytes())?;
+    stream.flush()
+}
+
+// What the connection threads report to the machine thread
+enum Event {
+    Connected(usize, TcpStream),
+    Request(usize, String),
+    // Whether an eval of the connection was running and got interrupted
+    Interrupted(usize, bool),
+    Disconnected(usize, Option<io::Error>),
+}
+
+// The eval in progress on the machine thread, shared with the connection threads
+// and the watchdog so they can cancel it while the machine thread is busy
+#[derive(Default)]
+struct Running {
+    client: Option<usize>,
+    deadline: Option<Instant>,
+    timed_out: bool,
+}
+
+#[derive(Clone)]
+struct Evaluation {
+    running: Arc<Mutex<Running>>,
+    token: CancellationToken,
+}
+
+impl Evaluation {
+    fn start(&self, client: usize, timeout: Option<Duration>) {
+        let mut running = self.running.lock().unwrap();
+        self.token.reset();
+        *running = Running {
+            client: Some(client),
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            timed_out: false,
+        };
+    }
+
+    // Whether the eval ran past its deadline
+    fn finish(&self) -> bool {
+        let mut running = self.running.lock().unwrap();
+        self.token.reset();
+        let timed_out = running.timed_out;
+        *running = Running::default();
+        timed_out
+    }
+
+    fn interrupt(&self, client: usize) -> bool {
+        let running = self.running.lock().unwrap();
+        let interrupted = running.client == Some(client);
+        if interrupted {
+            self.token.cancel();
+        }
+        interrupted
+    }
+
+    fn watch(&self) {
+        loop {
+            thread::sleep(WATCHDOG_INTERVAL);
+            let mut running = self.running.lock().unwrap();
+            if running.deadline.is_some() && Instant::now() >= running.deadline.unwrap() {
+                self.token.cancel();
+                running.deadline = None;
+                running.timed_out = true;
+            }
+        }
+    }
+}
+
+// Each connection is read on its own thread, evaluation stays on the thread owning the
+// machine. Interrupts are acted on right away, they can't wait for the eval they stop.
+fn read_requests(id: usize, mut stream: TcpStream, events: Sender<Event>, evaluation: Evaluation) {
+    loop {
+        let event = match read_frame(&mut stream) {
+            Ok(Some(source)) if source.split_whitespace().next() == Some(":interrupt") => {
+                Event::Interrupted(id, evaluation.interrupt(id))
+            }
+            Ok(Some(source)) => Event::Request(id, source),
+            Ok(None) => Event::Disconnected(id, None),
+            Err(error) => Event::Disconnected(id, Some(error)),
+        };
+        let done = matches!(event, Event::Disconnected(..));
+        if events.send(event).is_err() || done {
+            return;
+        }
+    }
+}
+
+fn accept_connections(listener: TcpListener, events: Sender<Event>, evaluation: Evaluation) {
+    for (id, stream) in listener.incoming().enumerate() {
+        let stream = match stream.and_then(|stream| stream.try_clone().map(|copy| (stream, copy))) {
+            Ok(streams) => streams,
+            Err(error) => {
+                eprintln!("Could not accept connection: {}", error);
+                continue;
+            }
+        };
+        if events.send(Event::Connected(id, stream.1)).is_err() {
+            return;
+        }
+        let events = events.clone();
+        let evaluation = evaluation.clone();
+        thread::spawn(move || read_requests(id, stream.0, events, evaluation));
+    }
+}
+
+// A connection starts in a fresh session, opened on its first eval
+struct Client {
+    stream: TcpStream,
+    current: Option<String>,
+    // The session opened implicitly for this connection, closed along with it
+    own: Option<String>,
+    timeout: Option<Duration>,
+}
+
+// Every session is a child env of the machine root: the stdlib is shared, definitions
+// are not. Cloned sessions outlive connections so several clients can work in the same one.
+struct Sessions {
+    next_id: u64,
+    envs: HashMap<String, LispyEnv>,
+}
+
+impl Sessions {
+    fn new() -> Self {
+        Self { next_id: 1, envs: HashMap::new() }
+    }
+
+    fn insert(&mut self, env: LispyEnv) -> String {
+        let id = self.next_id.to_string();
+        self.next_id += 1;
+        self.envs.insert(id.clone(), env);
+        id
+    }
+
+    fn open(&mut self, lispy_machine: &mut LispyMachine) -> String {
+        self.insert(LispyEnv::child(lispy_machine.get_env_mut()))
+    }
+}
+
+fn evaluate_request(
+    lispy_machine: &mut LispyMachine,
+    env: &mut LispyEnv,
+    source: &str,
+) -> Result<LispyType, String> {
+    let ast = compile_source_code_to_ast(source);
+    if ast.is_err() {
+        return Err(format!("Syntax error: {}", ast.err().unwrap()));
+    }
+    let ast = ast.unwrap();
+    // A panic in the machine fails this request, not the server
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut result = Ok(LispyType::create_nil());
+        for form in ast.iter() {
+            result = lispy_machine.evaluate_in(form, env);
+            if result.is_err() {
+                break;
+            }
+        }
+        result
+    }));
+    match outcome {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(error)) => Err(format!("{}", error)),
+        Err(_) => Err("Evaluation aborted".to_string()),
+    }
+}
+
+fn handle_request(
+    lispy_machine: &mut LispyMachine,
+    sessions: &mut Sessions,
+    client: &mut Client,
+    source: &str,
+) -> Result<String, String> {
+    let current = &mut client.current;
+    let mut words = source.split_whitespace();
+    let op = words.next().unwrap_or("");
+    let argument = words.next().map(|id| id.to_string());
+    let unknown_session = |id: &String| format!("Unknown session {}", id);
+
+    match op {
+        ":clone-session" => {
+            let env = current.as_ref().and_then(|id| sessions.envs.get(id));
+            match env {
+                Some(env) => {
+                    let copy = env.snapshot();
+                    Ok(sessions.insert(copy))
+                }
+                None => Ok(sessions.open(lispy_machine)),
+            }
+        }
+        ":use-session" => match argument {
+            Some(id) if sessions.envs.contains_key(&id) => {
+                *current = Some(id.clone());
+                Ok(id)
+            }
+            Some(id) => Err(unknown_session(&id)),
+            None => Err(":use-session expects a session id".to_string()),
+        },
+        ":timeout" => match argument.as_ref().and_then(|ms| ms.parse::<u64>().ok()) {
+            Some(ms) => {
+                client.timeout = Some(Duration::from_millis(ms)).filter(|timeout| !timeout.is_zero());
+                Ok(ms.to_string())
+            }
+            None => Err(":timeout expects milliseconds".to_string()),
+        },
+        ":close-session" => {
+            let id = argument.or_else(|| current.clone());
+            match id {
+                Some(id) if sessions.envs.remove(&id).is_some() => {
+                    if current.as_ref() == Some(&id) {
+                        *current = None;
+                    }
+                    Ok(id)
+                }
+                Some(id) => Err(unknown_session(&id)),
+                None => Err("No session to close".to_string()),
+            }
+        }
+        _ => {
+            let id = match current {
+                Some(id) => id.clone(),
+                None => {
+                    let id = sessions.open(lispy_machine);
+                    *current = Some(id.clone());
+                    client.own = Some(id.clone());
+                    id
+                }
+            };
+            // The session may have been closed by another client
+            let env = sessions.envs.get(&id);
+            if env.is_none() {
+                return Err(unknown_session(&id));
+            }
+            let mut env = env.unwrap().clone();
+            evaluate_request(lispy_machine, &mut env, source)
+                .map(|value| value.to_readable_string())
+        }
+    }
+}
+
+fn respond(client: &mut Client, response: Result<String, String>) {
+    let response = match response {
+        Ok(value) => format!("ok\n{}", value),
+        Err(message) => format!("error\n{}", message),
+    };
+    let written = write_frame(&mut client.stream, response.as_str());
+    if written.is_err() {
+        eprintln!("Could not respond: {}", written.err().unwrap());
+    }
+}
+
+// Serves eval requests on localhost against one machine. Connections are read
+// concurrently, their requests are evaluated one at a time in order of arrival.
+// `timeout` is the initial limit of every connection's evals.
+pub fn run_server(lispy_machine: &mut LispyMachine, port: u16, timeout: Option<Duration>) {
+    let listener = TcpListener::bind(("127.0.0.1", port)).unwrap_or_else(|error| {
+        eprintln!("Could not listen on port {}: {}", port, error);
+        std::process::exit(1);
+    });
+    eprintln!("Listening on 127.0.0.1:{}", port);
+
+    let evaluation = Evaluation {
+        running: Arc::new(Mutex::new(Running::default())),
+        token: lispy_machine.cancellation_token(),
+    };
+    let watchdog = evaluation.clone();
+    thread::spawn(move || watchdog.watch());
+    let (sender, events) = mpsc::channel();
+    let accepting = evaluation.clone();
+    thread::spawn(move || accept_connections(listener, sender, accepting));
+
+    let mut sessions = Sessions::new();
+    let mut clients: HashMap<usize, Client> = HashMap::new();
+    for event in events {
+        match event {
+            Event::Connected(id, stream) => {
+                clients.insert(id, Client { stream, current: None, own: None, timeout });
+            }
+            Event::Request(id, source) => {
+                let client = clients.get_mut(&id).unwrap();
+                evaluation.start(id, client.timeout);
+                let mut response = handle_request(lispy_machine, &mut sessions, client, source.as_str());
+                if evaluation.finish() {
+                    let limit = client.timeout.unwrap_or_default().as_millis();
+                    response = Err(format!("Evaluation timed out after {} ms", limit));
+                }
+                respond(client, response);
+            }
+            Event::Interrupted(id, interrupted) => {
+                let client = clients.get_mut(&id).unwrap();
+                let response = if interrupted {
+                    Ok("interrupted".to_string())
+                } else {
+                    Err("No eval of this connection is running".to_string())
+                };
+                respond(client, response);
+            }
+            Event::Disconnected(id, error) => {
+                if let Some(error) = error {
+                    eprintln!("Connection closed: {}", error);
+                }
+                let client = clients.remove(&id).unwrap();
+                if let Some(own) = client.own {
+                    sessions.envs.remove(&own);
+                }
+            }
+        }
+    }
+}
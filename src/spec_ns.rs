@@ -0,0 +1,165 @@
+use crate::env::LispyEnv;
+use crate::machine::apply_callable;
+use crate::types::LispyType;
+use std::collections::HashMap;
+
+const TYPE_KEYWORDS: [&str; 10] = [
+    ":any",
+    ":nil",
+    ":bool",
+    ":number",
+    ":string",
+    ":keyword",
+    ":symbol",
+    ":list",
+    ":hash",
+    ":function",
+];
+
+pub fn apply_spec_ns(env: &mut LispyEnv) {
+    env.set(
+        "spec",
+        LispyType::create_function(Some(1), |args| {
+            let checked = check_spec(&args[0]);
+            if checked.is_err() {
+                return Err(checked.err().unwrap());
+            }
+            Ok(args[0].clone())
+        }),
+    );
+    env.set(
+        "valid?",
+        LispyType::create_function(Some(2), |args| {
+            let violations = explain(&args[0], &args[1], &[]);
+            if violations.is_err() {
+                return Err(violations.err().unwrap());
+            }
+            Ok(LispyType::create_bool(violations.unwrap().is_empty()))
+        }),
+    );
+    env.set(
+        "explain",
+        LispyType::create_function(Some(2), |args| {
+            let violations = explain(&args[0], &args[1], &[]);
+            if violations.is_err() {
+                return Err(violations.err().unwrap());
+            }
+            Ok(LispyType::create_list(violations.unwrap()))
+        }),
+    );
+}
+
+// A spec is either a type keyword, a predicate function or a hash of specs.
+fn check_spec(spec: &LispyType) -> Result<(), LispyType> {
+    match spec {
+        LispyType::Keyword { value, .. } if TYPE_KEYWORDS.contains(&value.as_str()) => Ok(()),
+        LispyType::Function { .. } | LispyType::Lambda { .. } => Ok(()),
+        LispyType::Hash { collection, .. } => {
+            for value in collection.values() {
+                let checked = check_spec(value);
+                if checked.is_err() {
+                    return Err(checked.err().unwrap());
+                }
+            }
+            Ok(())
+        }
+        _ => Err(LispyType::create_error(
+            format!("{} is not a valid spec", spec).as_str(),
+            "INVALID_SPEC",
+        )),
+    }
+}
+
+fn matches_type(type_keyword: &str, value: &LispyType) -> bool {
+    match type_keyword {
+        ":any" => true,
+        ":nil" => value.is_nil(),
+        ":bool" => value.is_bool(),
+        ":number" => value.is_number(),
+        ":string" => value.is_string(),
+        ":keyword" => value.is_keyword(),
+        ":symbol" => value.is_symbol(),
+        ":list" => value.is_list(),
+        ":hash" => value.is_hash(),
+        ":function" => value.is_function(),
+        _ => false,
+    }
+}
+
+fn violation(path: &[LispyType], expected: LispyType, received: &LispyType) -> LispyType {
+    let mut collection = HashMap::new();
+    collection.insert(
+        LispyType::create_keyword(":path"),
+        LispyType::create_list(path.to_vec()),
+    );
+    collection.insert(LispyType::create_keyword(":expected"), expected);
+    collection.insert(LispyType::create_keyword(":received"), received.clone());
+    LispyType::Hash {
+        collection: Box::from(collection),
+        meta: HashMap::new(),
+    }
+}
+
+fn explain(
+    spec: &LispyType,
+    value: &LispyType,
+    path: &[LispyType],
+) -> Result<Vec<LispyType>, LispyType> {
+    match spec {
+        LispyType::Keyword { value: type_keyword, .. } => {
+            let checked = check_spec(spec);
+            if checked.is_err() {
+                return Err(checked.err().unwrap());
+            }
+            if matches_type(type_keyword, value) {
+                return Ok(vec![]);
+            }
+            Ok(vec![violation(path, spec.clone(), value)])
+        }
+        LispyType::Function { .. } | LispyType::Lambda { .. } => {
+            let result = apply_callable(spec, vec![value.clone()]);
+            if result.is_err() {
+                return Err(result.err().unwrap());
+            }
+            if result.unwrap().is_truthy() {
+                return Ok(vec![]);
+            }
+            Ok(vec![violation(path, spec.clone(), value)])
+        }
+        LispyType::Hash { collection, .. } => {
+            if !value.is_hash() {
+                return Ok(vec![violation(
+                    path,
+                    LispyType::create_keyword(":hash"),
+                    value,
+                )]);
+            }
+
+            let mut keys: Vec<&LispyType> = collection.keys().collect();
+            keys.sort_by_key(|key| format!("{}", key));
+
+            let mut violations = vec![];
+            for key in keys {
+                let received = value
+                    .as_hash()
+                    .unwrap()
+                    .get(key)
+                    .cloned()
+                    .unwrap_or_else(LispyType::create_nil);
+                let mut key_path = path.to_vec();
+                key_path.push(key.clone());
+
+                let nested = explain(collection.get(key).unwrap(), &received, &key_path);
+                if nested.is_err() {
+                    return Err(nested.err().unwrap());
+                }
+                violations.extend(nested.unwrap());
+            }
+            Ok(violations)
+        }
+        _ => Err(LispyType::create_error(
+            format!("{} is not a valid spec", spec).as_str(),
+            "INVALID_SPEC",
+        )),
+    }
+}
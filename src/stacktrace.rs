@@ -0,0 +1,144 @@
+use crate::types::{LispyType, TypeMeta};
+use std::cell::RefCell;
+
+// Where a form was read, see compiler::build_any_form
+#[derive(Clone, Default)]
+struct Position {
+    line: f64,
+    col: f64,
+    file: Option<String>,
+}
+
+impl Position {
+    // Copies only what is needed to render it later, frames are entered on every call
+    fn of(meta: &TypeMeta) -> Option<Self> {
+        let line = *meta.get("line")?.as_number()?;
+        let col = *meta.get("col")?.as_number()?;
+        let file = meta.get("file").and_then(|file| file.as_string()).cloned();
+        Some(Position { line, col, file })
+    }
+
+    fn render(&self) -> String {
+        match &self.file {
+            Some(file) => format!("{}:{}:{}", file, self.line, self.col),
+            None => format!("{}:{}", self.line, self.col),
+        }
+    }
+}
+
+// A function being applied: the name it was called by, or for anonymous functions
+// where their body was read, and where it was called from
+pub struct Frame {
+    name: Option<String>,
+    origin: Option<Position>,
+    site: Option<Position>,
+}
+
+impl Frame {
+    pub fn new(call: &LispyType, callee: &LispyType) -> Self {
+        let head = call.as_list().and_then(|items| items.first());
+        match head.and_then(|head| head.as_symbol()) {
+            Some(name) => Frame {
+                name: Some(name.clone()),
+                origin: None,
+                site: Position::of(call.meta()),
+            },
+            None => Frame::anonymous(callee, Position::of(call.meta())),
+        }
+    }
+
+    // Applied by a native like map, without a call form of its own
+    pub fn callback(callee: &LispyType) -> Self {
+        Frame::anonymous(callee, None)
+    }
+
+    fn anonymous(callee: &LispyType, site: Option<Position>) -> Self {
+        let origin = match callee {
+            LispyType::Lambda { to_eval, .. } => Position::of(to_eval.meta()),
+            _ => None,
+        };
+        Frame {
+            name: None,
+            origin,
+            site,
+        }
+    }
+
+    fn render(&self) -> String {
+        let name = match (&self.name, &self.origin) {
+            (Some(name), _) => name.clone(),
+            (None, Some(origin)) => format!("fn defined at {}", origin.render()),
+            (None, None) => "fn".to_string(),
+        };
+        match &self.site {
+            Some(site) => format!("{} ({})", name, site.render()),
+            None => name,
+        }
+    }
+}
+
+thread_local! {
+    // The lambdas being applied, innermost last. Natives get no frame while they run,
+    // only once they fail, see attach.
+    static CALL_STACK: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn depth() -> usize {
+    CALL_STACK.with(|stack| stack.borrow().len())
+}
+
+// Every eval owns at most the frame at its `depth`, a tail call replaces it
+// instead of growing the stack
+pub fn enter(depth: usize, frame: Frame) {
+    CALL_STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        stack.truncate(depth);
+        stack.push(frame);
+    });
+}
+
+pub fn leave(depth: usize) {
+    CALL_STACK.with(|stack| stack.borrow_mut().truncate(depth));
+}
+
+// The current frames rendered innermost first
+pub fn current() -> Vec<LispyType> {
+    CALL_STACK.with(|stack| {
+        stack
+            .borrow()
+            .iter()
+            .rev()
+            .map(|frame| LispyType::create_string(frame.render().as_str()))
+            .collect()
+    })
+}
+
+// Records the stack an error was raised in, once, by the innermost eval it leaves,
+// on top of it the native that raised it if any.
+// Unwinding to a block is not an error and gets no trace.
+pub fn attach(mut error: LispyType, raised_in: Option<Frame>) -> LispyType {
+    if let LispyType::Error { error_type, meta, .. } = &mut error {
+        if error_type != "RETURN_FROM" && !meta.contains_key("stacktrace") {
+            let mut trace: Vec<LispyType> = raised_in
+                .iter()
+                .map(|frame| LispyType::create_string(frame.render().as_str()))
+                .collect();
+            trace.extend(current());
+            meta.insert("stacktrace".to_string(), LispyType::create_list(trace));
+        }
+    }
+    error
+}
+
+// The trace of an error as printed under its message, empty without one
+pub fn format_trace(error: &LispyType) -> String {
+    let frames = error.meta().get("stacktrace").and_then(|trace| trace.as_list());
+    frames
+        .map(|frames| {
+            frames
+                .iter()
+                .map(|frame| format!("\n    at {}", frame.as_string().unwrap()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,56 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+pub const PATH_VARIABLE: &str = "LISPY_PATH";
+
+// Compiled into the binary so it runs from any directory. A file of the same name
+// on the search path takes precedence, e.g. to try stdlib changes without rebuilding.
+const EMBEDDED: [(&str, &str); 3] = [
+    ("core.lispy", include_str!("../lispy_std/core.lispy")),
+    ("errors.lispy", include_str!("../lispy_std/errors.lispy")),
+    ("math/matrix.lispy", include_str!("../lispy_std/math/matrix.lispy")),
+];
+
+// Directories searched for the stdlib and for namespaces passed to `require`: the
+// ones added through LispyMachineBuilder, then those listed in LISPY_PATH
+#[derive(Clone, Debug, Default)]
+pub struct SearchPath {
+    directories: Vec<PathBuf>,
+    from_env: Vec<PathBuf>,
+}
+
+impl SearchPath {
+    pub fn from_env() -> Self {
+        let from_env = env::var_os(PATH_VARIABLE)
+            .map(|paths| env::split_paths(&paths).filter(|path| !path.as_os_str().is_empty()).collect())
+            .unwrap_or_default();
+        Self { directories: vec![], from_env }
+    }
+
+    pub fn add(&mut self, directory: PathBuf) {
+        self.directories.push(directory);
+    }
+
+    // Every place `relative` could be, in search order
+    pub fn candidates(&self, relative: &str) -> Vec<PathBuf> {
+        self.directories
+            .iter()
+            .chain(self.from_env.iter())
+            .map(|directory| directory.join(relative))
+            .collect()
+    }
+
+    // Source of the stdlib file `name` and the file name positions in it report
+    pub fn stdlib_source(&self, name: &str) -> Option<(String, String)> {
+        for candidate in self.candidates(name) {
+            if let Ok(source) = fs::read_to_string(&candidate) {
+                return Some((source, candidate.to_string_lossy().to_string()));
+            }
+        }
+        EMBEDDED
+            .iter()
+            .find(|(embedded, _)| *embedded == name)
+            .map(|(embedded, source)| (source.to_string(), format!("lispy_std/{}", embedded)))
+    }
+}
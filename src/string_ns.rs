@@ -0,0 +1,437 @@
+use crate::env::LispyEnv;
+use crate::lispy_fn;
+use crate::sandbox;
+use crate::types::LispyType;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+fn string_arg(value: &LispyType) -> Result<&String, LispyType> {
+    match value.as_string() {
+        Some(string) => Ok(string),
+        None => Err(LispyType::create_error(
+            format!("{} is not a string", value).as_str(),
+            "INCORRECT_TYPE",
+        )),
+    }
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for i in 1..=a.len() {
+        current[0] = i;
+        for j in 1..=b.len() {
+            let substitution = previous[j - 1] + if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            current[j] = substitution.min(previous[j] + 1).min(current[j - 1] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+// 1.0 for identical strings down to 0.0 for completely different ones
+fn similarity(a: &str, b: &str) -> f64 {
+    let longest = a.chars().count().max(b.chars().count());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(a, b) as f64 / longest as f64
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+impl DiffOp {
+    fn keyword(&self) -> &'static str {
+        match self {
+            DiffOp::Equal => ":equal",
+            DiffOp::Delete => ":delete",
+            DiffOp::Insert => ":insert",
+        }
+    }
+}
+
+// Longest common subsequence walk over the two line lists
+fn diff_ops<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<(DiffOp, &'a str)> {
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            ops.push((DiffOp::Equal, a[i]));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            ops.push((DiffOp::Delete, a[i]));
+            i += 1;
+        } else {
+            ops.push((DiffOp::Insert, b[j]));
+            j += 1;
+        }
+    }
+    ops.extend(a[i..].iter().map(|line| (DiffOp::Delete, *line)));
+    ops.extend(b[j..].iter().map(|line| (DiffOp::Insert, *line)));
+    ops
+}
+
+fn hunk(op: DiffOp, a_line: usize, b_line: usize, lines: Vec<LispyType>) -> LispyType {
+    let mut collection = HashMap::new();
+    collection.insert(
+        LispyType::create_keyword(":op"),
+        LispyType::create_keyword(op.keyword()),
+    );
+    collection.insert(
+        LispyType::create_keyword(":a-line"),
+        LispyType::create_number(a_line as f64),
+    );
+    collection.insert(
+        LispyType::create_keyword(":b-line"),
+        LispyType::create_number(b_line as f64),
+    );
+    collection.insert(
+        LispyType::create_keyword(":lines"),
+        LispyType::create_list(lines),
+    );
+    LispyType::Hash {
+        collection: Box::from(collection),
+        meta: HashMap::new(),
+    }
+}
+
+// Groups consecutive operations of the same kind into hunks. `:a-line` and
+// `:b-line` are the 1-based positions the hunk starts at in each input.
+fn diff_lines(a: &str, b: &str) -> Vec<LispyType> {
+    let a: Vec<&str> = a.lines().collect();
+    let b: Vec<&str> = b.lines().collect();
+
+    let mut hunks = vec![];
+    let (mut a_line, mut b_line) = (1, 1);
+    let mut current: Option<(DiffOp, usize, usize, Vec<LispyType>)> = None;
+    for (op, line) in diff_ops(&a, &b) {
+        match current.as_mut() {
+            Some((current_op, _, _, lines)) if *current_op == op => {
+                lines.push(LispyType::create_string(line));
+            }
+            _ => {
+                if let Some((op, a_start, b_start, lines)) = current.take() {
+                    hunks.push(hunk(op, a_start, b_start, lines));
+                }
+                current = Some((op, a_line, b_line, vec![LispyType::create_string(line)]));
+            }
+        }
+        if op != DiffOp::Insert {
+            a_line += 1;
+        }
+        if op != DiffOp::Delete {
+            b_line += 1;
+        }
+    }
+    if let Some((op, a_start, b_start, lines)) = current {
+        hunks.push(hunk(op, a_start, b_start, lines));
+    }
+    hunks
+}
+
+// Columns a character occupies in a terminal: 0 for combining marks and other
+// zero-width characters, 2 for East Asian wide/fullwidth characters and emoji
+fn char_width(c: char) -> usize {
+    let code = c as u32;
+    match code {
+        0x0300..=0x036F
+        | 0x200B..=0x200F
+        | 0x20D0..=0x20FF
+        | 0xFE00..=0xFE0F
+        | 0xFE20..=0xFE2F => 0,
+        _ if c.is_control() => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+// Terminal width of `text`, ignoring ANSI escape sequences such as colors
+fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' && chars.peek() == Some(&'[') {
+            chars.next();
+            // CSI sequences end with a byte in the @..~ range
+            for next in chars.by_ref() {
+                if ('@'..='~').contains(&next) {
+                    break;
+                }
+            }
+            continue;
+        }
+        width += char_width(c);
+    }
+    width
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+fn pad(text: &str, width: usize, fill: &str, align: Align) -> String {
+    let missing = width.saturating_sub(display_width(text));
+    let (left, right) = match align {
+        Align::Left => (0, missing),
+        Align::Right => (missing, 0),
+        Align::Center => (missing / 2, missing - missing / 2),
+    };
+    format!("{}{}{}", fill.repeat(left), text, fill.repeat(right))
+}
+
+// (str/pad-x text width) or (str/pad-x text width fill), fill being a single column wide string
+fn pad_args(args: &[LispyType], align: Align) -> Result<LispyType, LispyType> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(LispyType::create_error(
+            format!("Expected arity 2 or 3, received {}", args.len()).as_str(),
+            "INCORRECT_ARITY",
+        ));
+    }
+    let text = string_arg(&args[0]);
+    if text.is_err() {
+        return Err(text.err().unwrap());
+    }
+    let width = match args[1].as_number() {
+        Some(width) if *width >= 0.0 => *width as usize,
+        _ => {
+            return Err(LispyType::create_error(
+                format!("{} is not a valid width", args[1]).as_str(),
+                "INCORRECT_TYPE",
+            ))
+        }
+    };
+    let sized = sandbox::check_size(width);
+    if sized.is_err() {
+        return Err(sized.err().unwrap());
+    }
+    let fill = match args.get(2) {
+        None => " ",
+        Some(fill) => match fill.as_string() {
+            Some(fill) if display_width(fill) == 1 => fill.as_str(),
+            _ => {
+                return Err(LispyType::create_error(
+                    format!("{} is not a single character fill", fill).as_str(),
+                    "INCORRECT_TYPE",
+                ))
+            }
+        },
+    };
+    Ok(LispyType::create_string(
+        pad(text.unwrap(), width, fill, align).as_str(),
+    ))
+}
+
+// Text shown for a value inside a table: strings and keywords without decoration,
+// nil as an empty cell
+fn cell_text(value: Option<&LispyType>) -> String {
+    match value {
+        None | Some(LispyType::Nil { .. }) => "".to_string(),
+        Some(LispyType::String { value, .. }) => value.clone(),
+        Some(LispyType::Keyword { value, .. }) => value.to_string(),
+        Some(value) => format!("{}", value),
+    }
+}
+
+fn compare_cells(a: Option<&LispyType>, b: Option<&LispyType>) -> Ordering {
+    match (a, b) {
+        (Some(LispyType::Number { value: a, .. }), Some(LispyType::Number { value: b, .. })) => {
+            a.partial_cmp(b).unwrap_or(Ordering::Equal)
+        }
+        (None, None) => Ordering::Equal,
+        (None, _) => Ordering::Greater,
+        (_, None) => Ordering::Less,
+        _ => cell_text(a).cmp(&cell_text(b)),
+    }
+}
+
+fn table_option<'a>(options: Option<&'a LispyType>, key: &str) -> Option<&'a LispyType> {
+    options
+        .and_then(|options| options.as_hash())
+        .and_then(|options| options.get(&LispyType::create_keyword(key)))
+        .filter(|value| !value.is_nil())
+}
+
+// Renders `rows` (a list of hashes) as an aligned ASCII table. Supported options:
+// :columns - keys to show, in order (defaults to every key, sorted)
+// :sort-by - key to order rows by
+// :desc    - reverse the :sort-by order
+fn render_table(rows: &LispyType, options: Option<&LispyType>) -> Result<String, LispyType> {
+    let rows = match rows.as_list() {
+        Some(rows) if rows.iter().all(|row| row.is_hash()) => rows,
+        _ => {
+            return Err(LispyType::create_error(
+                format!("{} is not a list of hashes", rows).as_str(),
+                "INCORRECT_TYPE",
+            ))
+        }
+    };
+    if let Some(options) = options.filter(|options| !options.is_hash()) {
+        return Err(LispyType::create_error(
+            format!("{} is not a hash of options", options).as_str(),
+            "INCORRECT_TYPE",
+        ));
+    }
+
+    let columns: Vec<LispyType> = match table_option(options, ":columns") {
+        Some(columns) => match columns.as_list() {
+            Some(columns) => columns.to_vec(),
+            None => {
+                return Err(LispyType::create_error(
+                    format!("{} is not a list of columns", columns).as_str(),
+                    "INCORRECT_TYPE",
+                ))
+            }
+        },
+        None => {
+            let mut columns: Vec<LispyType> = vec![];
+            for row in rows.iter() {
+                for key in row.as_hash().unwrap().keys() {
+                    if !columns.contains(key) {
+                        columns.push(key.clone());
+                    }
+                }
+            }
+            columns.sort_by_key(|column| cell_text(Some(column)));
+            columns
+        }
+    };
+
+    let mut rows: Vec<&LispyType> = rows.iter().collect();
+    if let Some(sort_key) = table_option(options, ":sort-by") {
+        rows.sort_by(|a, b| {
+            compare_cells(a.as_hash().unwrap().get(sort_key), b.as_hash().unwrap().get(sort_key))
+        });
+        if table_option(options, ":desc").is_some_and(|desc| desc.is_truthy()) {
+            rows.reverse();
+        }
+    }
+
+    let header: Vec<String> = columns.iter().map(|column| cell_text(Some(column))).collect();
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|column| cell_text(row.as_hash().unwrap().get(column)))
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|index| {
+            cells
+                .iter()
+                .map(|row| display_width(&row[index]))
+                .chain(std::iter::once(display_width(&header[index])))
+                .max()
+                .unwrap()
+        })
+        .collect();
+
+    let render_row = |row: &Vec<String>| {
+        let padded: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| pad(cell, *width, " ", Align::Left))
+            .collect();
+        format!("| {} |", padded.join(" | "))
+    };
+    let separator: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    let separator = format!("|-{}-|", separator.join("-+-"));
+
+    let mut lines = vec![render_row(&header), separator];
+    lines.extend(cells.iter().map(render_row));
+    Ok(lines.join("\n"))
+}
+
+#[lispy_fn(name = "str/levenshtein")]
+fn str_levenshtein(a: String, b: String) -> i64 {
+    levenshtein(&a, &b) as i64
+}
+
+#[lispy_fn(name = "str/similarity")]
+fn str_similarity(a: String, b: String) -> f64 {
+    similarity(&a, &b)
+}
+
+#[lispy_fn(name = "str/display-width")]
+fn str_display_width(text: String) -> i64 {
+    display_width(&text) as i64
+}
+
+#[lispy_fn(name = "diff-lines")]
+fn diff_lines_builtin(a: String, b: String) -> Vec<LispyType> {
+    diff_lines(&a, &b)
+}
+
+pub fn apply_string_ns(env: &mut LispyEnv) {
+    register_str_levenshtein(env);
+    register_str_similarity(env);
+    register_str_display_width(env);
+    env.set(
+        "str/pad-left",
+        LispyType::create_function(None, |args| pad_args(&args, Align::Right)),
+    );
+    env.set(
+        "str/pad-right",
+        LispyType::create_function(None, |args| pad_args(&args, Align::Left)),
+    );
+    env.set(
+        "str/center",
+        LispyType::create_function(None, |args| pad_args(&args, Align::Center)),
+    );
+    register_diff_lines_builtin(env);
+    // (print-table rows) or (print-table rows {:columns (..) :sort-by key :desc true})
+    env.set(
+        "print-table",
+        LispyType::create_function(None, |args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(LispyType::create_error(
+                    format!("Expected arity 1 or 2, received {}", args.len()).as_str(),
+                    "INCORRECT_ARITY",
+                ));
+            }
+            let table = render_table(&args[0], args.get(1));
+            if table.is_err() {
+                return Err(table.err().unwrap());
+            }
+            println!("{}", table.unwrap());
+            Ok(LispyType::create_nil())
+        }),
+    );
+}
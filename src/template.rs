@@ -0,0 +1,138 @@
+use crate::compiler::{compile_with_limits, runtime_limits};
+use crate::env::LispyEnv;
+use crate::machine::eval;
+use crate::sandbox;
+use crate::types::LispyType;
+use std::collections::HashMap;
+
+// Natives a template may call: pure functions over values. Anything reaching files,
+// the output, other threads or state shared with the rest of the program stays out.
+// Names a build lacks the feature for are skipped. Special forms doing so are denied
+// by the sandbox.
+const TEMPLATE_BUILTINS: [&str; 138] = [
+    "+", "-", "*", "/", "=", "approx=", "compare", ">", "<", ">=", "<=",
+    "nil?", "bool?", "symbol?", "number?", "nan?", "infinite?", "string?", "list?",
+    "vector?", "hash?", "function?", "resource?", "macro?", "error?", "queue?",
+    "str", "pr-str", "print-str", "list", "range", "count", "cons", "concat", "first",
+    "rest", "nth", "apply", "vector", "vec", "get", "conj", "queue", "push", "peek", "pop",
+    "to-string", "to-number", "to-bool", "to-list", "to-hash", "to-string-or-nil",
+    "to-number-or-nil", "to-bool-or-nil", "to-list-or-nil", "to-hash-or-nil",
+    "type-tag", "make-error", "error-type", "error-message", "error-data", "assert",
+    "assert=", "*features*", "feature?",
+    "walk", "prewalk", "postwalk", "map", "filter", "reduce", "bsearch", "insert-sorted",
+    "pipeline", "mapping", "filtering", "taking", "dropping",
+    "hash-map", "assoc", "dissoc", "keys", "vals", "sorted-map", "first-entry",
+    "last-entry", "contains?", "group-by", "index-by", "update", "update-keys",
+    "update-vals", "select-keys", "rename-keys", "merge-with", "deep-merge", "data-diff",
+    "str/pad-left", "str/pad-right", "str/center", "str/display-width", "str/levenshtein",
+    "str/similarity", "diff-lines",
+    "json/parse", "json/stringify", "json-encode", "json-decode", "msgpack/encode",
+    "msgpack/decode", "spec", "valid?", "explain",
+    "matrix?", "matrix/from-list", "matrix/to-list", "matrix/zeros", "matrix/identity",
+    "matrix/shape", "matrix/get", "matrix/add", "matrix/sub", "matrix/mul", "matrix/div",
+    "matrix/scale", "matrix/matmul", "matrix/transpose", "matrix/inverse",
+    "decimal", "decimal?", "decimal/add", "decimal/sub", "decimal/mul", "decimal/div",
+    "decimal/round", "decimal/compare", "decimal/format", "decimal/to-number",
+];
+
+fn template_error(message: &str) -> LispyType {
+    LispyType::create_error(message, "SYNTAX_ERROR")
+}
+
+// Byte length of the `(...)` form starting `source`, strings inside it may contain parens
+fn form_length(source: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, char) in source.char_indices() {
+        match char {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// A fresh root with only TEMPLATE_BUILTINS, plus `bindings` keyed by keyword, string or symbol
+fn sandbox(bindings: &HashMap<LispyType, LispyType>) -> Result<LispyEnv, LispyType> {
+    let builtins = LispyEnv::root();
+    let mut env = LispyEnv::empty();
+    for name in TEMPLATE_BUILTINS {
+        if let Some(value) = builtins.with_item(name, LispyType::clone) {
+            env.set(name, value);
+        }
+    }
+    for (key, value) in bindings.iter() {
+        let name = key
+            .as_keyword()
+            .map(|keyword| keyword.trim_start_matches(':'))
+            .or_else(|| key.as_string().map(String::as_str))
+            .or_else(|| key.as_symbol().map(String::as_str));
+        if name.is_none() {
+            return Err(LispyType::create_error(
+                format!("eval-template binding names must be keywords, strings or symbols. Received {}", key)
+                    .as_str(),
+                "INCORRECT_TYPE",
+            ));
+        }
+        env.set(name.unwrap(), value.clone());
+    }
+    Ok(env)
+}
+
+// Replaces every `~(...)` of `source` with the printed value of evaluating it against
+// `bindings` alone. The program's own definitions are out of reach and all segments
+// share one step budget, so config files can compute values without running
+// arbitrary code against the program.
+pub fn eval_template(source: &str, bindings: &HashMap<LispyType, LispyType>) -> Result<String, LispyType> {
+    sandbox::run(sandbox::DEFAULT_STEP_LIMIT, || expand(source, bindings))
+}
+
+fn expand(source: &str, bindings: &HashMap<LispyType, LispyType>) -> Result<String, LispyType> {
+    let mut env = None;
+    let mut result = String::new();
+    let mut rest = source;
+
+    while let Some(start) = rest.find("~(") {
+        result.push_str(&rest[..start]);
+        let segment = &rest[start + 1..];
+        let length = form_length(segment);
+        if length.is_none() {
+            return Err(template_error(
+                format!("Unterminated ~( at byte {} of template", source.len() - segment.len() - 1).as_str(),
+            ));
+        }
+        let length = length.unwrap();
+
+        let ast = compile_with_limits(&segment[..length], &runtime_limits());
+        if ast.is_err() {
+            return Err(ast.err().unwrap().to_lispy_error());
+        }
+        if env.is_none() {
+            let created = sandbox(bindings);
+            if created.is_err() {
+                return Err(created.err().unwrap());
+            }
+            env = created.ok();
+        }
+        for form in ast.unwrap().iter() {
+            let value = eval(form, env.as_mut().unwrap());
+            if value.is_err() {
+                return Err(value.err().unwrap());
+            }
+            result.push_str(&format!("{}", value.unwrap()));
+        }
+        rest = &segment[length..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
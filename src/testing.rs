@@ -0,0 +1,68 @@
+use crate::coverage;
+use crate::env::LispyEnv;
+use crate::machine::eval;
+use crate::types::LispyType;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    static TESTS: RefCell<Vec<(String, LispyType)>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn register_test(name: String, body: LispyType) {
+    TESTS.with(|tests| {
+        let mut tests = tests.borrow_mut();
+        tests.retain(|(existing, _)| existing != &name);
+        tests.push((name, body));
+    });
+}
+
+// Every test runs in a child of a snapshot of `env`, so its definitions are dropped
+// afterwards. Anything that still managed to change `env` is reported as a leak.
+pub fn run_tests(env: &mut LispyEnv) -> LispyType {
+    let tests = TESTS.with(|tests| tests.borrow().clone());
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for (name, body) in tests {
+        let before = env.snapshot();
+        let mut test_env = LispyEnv::child(env);
+
+        match eval(&body, &mut test_env) {
+            Ok(_) => {
+                passed += 1;
+                println!("PASS {}", name);
+            }
+            Err(error) => {
+                failed += 1;
+                println!("FAIL {}: {}", name, error);
+            }
+        }
+
+        let leaked = before.changed_keys(env);
+        if !leaked.is_empty() {
+            println!(
+                "WARN {} leaked global mutations: {}",
+                name,
+                leaked.join(", ")
+            );
+        }
+    }
+
+    let mut summary = HashMap::new();
+    summary.insert(
+        LispyType::create_keyword(":passed"),
+        LispyType::create_number(passed as f64),
+    );
+    summary.insert(
+        LispyType::create_keyword(":failed"),
+        LispyType::create_number(failed as f64),
+    );
+    if coverage::is_enabled() {
+        summary.insert(LispyType::create_keyword(":coverage"), coverage::summary());
+    }
+    LispyType::Hash {
+        collection: Box::from(summary),
+        meta: HashMap::new(),
+    }
+}
@@ -1,10 +1,14 @@
-use crate::env::LispyEnv;
+use crate::env::{LispyEnv, SymbolCache};
+use crate::interner::{intern, Interned};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::fmt::{Display, Formatter};
+use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::mem;
-use std::ops::{Add, Div, Mul, Sub};
+use std::ops::{Add, Deref, Div, Mul, Sub};
+use std::rc::Rc;
 
 fn integer_decode(val: f64) -> (u64, i16, i8) {
     let bits: u64 = unsafe { mem::transmute(val) };
@@ -22,6 +26,25 @@ fn integer_decode(val: f64) -> (u64, i16, i8) {
 
 pub type TypeMeta = HashMap<String, LispyType>;
 
+// Body of a native function. A closure rather than a plain fn so embedders can
+// register callbacks that capture their own state, see LispyMachine::register_fn.
+#[derive(Clone)]
+pub struct NativeFn(Rc<dyn Fn(Vec<LispyType>) -> Result<LispyType, LispyType>>);
+
+impl Debug for NativeFn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "NativeFn")
+    }
+}
+
+thread_local! {
+    // When set, numbers that are exactly a fraction print as `1/3`, see set_print_ratios
+    static PRINT_RATIOS: Cell<bool> = const { Cell::new(false) };
+}
+
+// Largest denominator the ratio printer looks for
+const MAX_RATIO_DENOMINATOR: i64 = 1_000_000;
+
 #[derive(Debug, Clone)]
 pub enum LispyType {
     Nil {
@@ -38,10 +61,12 @@ pub enum LispyType {
     },
     Symbol {
         value: String,
+        // Shared by every clone of the node, see LispyEnv::get_item_cached
+        cache: SymbolCache,
         meta: TypeMeta,
     },
     Keyword {
-        value: String,
+        value: Interned,
         meta: TypeMeta,
     },
     String {
@@ -53,6 +78,10 @@ pub enum LispyType {
         collection: Box<Vec<LispyType>>,
         meta: TypeMeta,
     },
+    Vector {
+        collection: Box<Vec<LispyType>>,
+        meta: TypeMeta,
+    },
     Hash {
         collection: Box<HashMap<LispyType, LispyType>>,
         meta: TypeMeta,
@@ -61,21 +90,36 @@ pub enum LispyType {
     Error {
         error_type: String,
         message: String,
+        // Whatever the thrower attached with make-error, read back with error-data
+        data: Option<Box<LispyType>>,
         meta: TypeMeta,
     },
 
     Function {
         arity: Option<i32>,
-        func: fn(args: Vec<LispyType>) -> Result<LispyType, LispyType>,
+        func: NativeFn,
         meta: TypeMeta,
     },
+    // Body and bindings are shared so that looking a lambda up (and caching it)
+    // does not copy its whole AST
     Lambda {
-        bindings: Box<Vec<LispyType>>,
-        to_eval: Box<LispyType>,
+        bindings: Rc<Vec<LispyType>>,
+        to_eval: Rc<LispyType>,
         env: Box<LispyEnv>,
         meta: TypeMeta,
         is_macro: bool,
     },
+
+    Resource {
+        kind: String,
+        handle: Rc<dyn Any>,
+        meta: TypeMeta,
+    },
+
+    Atom {
+        value: Rc<RefCell<LispyType>>,
+        meta: TypeMeta,
+    },
 }
 
 pub struct LispyErrorInternal {
@@ -141,6 +185,10 @@ impl LispyType {
         }
     }
 
+    pub fn is_vector(&self) -> bool {
+        matches!(self, LispyType::Vector { .. })
+    }
+
     pub fn is_hash(&self) -> bool {
         match self {
             LispyType::Hash { .. } => true,
@@ -176,6 +224,30 @@ impl LispyType {
             _ => false,
         }
     }
+
+    pub fn is_resource(&self) -> bool {
+        matches!(self, LispyType::Resource { .. })
+    }
+
+    pub fn is_atom(&self) -> bool {
+        matches!(self, LispyType::Atom { .. })
+    }
+
+    pub fn is_resource_of(&self, test: &str) -> bool {
+        matches!(self, LispyType::Resource { kind, .. } if kind == test)
+    }
+
+    pub fn is_hashable(&self) -> bool {
+        matches!(
+            self,
+            LispyType::Nil { .. }
+                | LispyType::Bool { .. }
+                | LispyType::Number { .. }
+                | LispyType::Symbol { .. }
+                | LispyType::Keyword { .. }
+                | LispyType::String { .. }
+        )
+    }
 }
 
 // as_? impls
@@ -203,7 +275,7 @@ impl LispyType {
 
     pub fn as_keyword(&self) -> Option<&String> {
         match self {
-            LispyType::Keyword { value, .. } => Some(value),
+            LispyType::Keyword { value, .. } => Some(value.deref()),
             _ => None,
         }
     }
@@ -222,6 +294,23 @@ impl LispyType {
         }
     }
 
+    pub fn as_vector(&self) -> Option<&Vec<LispyType>> {
+        match self {
+            LispyType::Vector { collection, .. } => Some(collection),
+            _ => None,
+        }
+    }
+
+    // Items of either a list or a vector
+    pub fn as_sequential(&self) -> Option<&Vec<LispyType>> {
+        match self {
+            LispyType::List { collection, .. } | LispyType::Vector { collection, .. } => {
+                Some(collection)
+            }
+            _ => None,
+        }
+    }
+
     pub fn as_hash(&self) -> Option<&Box<HashMap<LispyType, LispyType>>> {
         match self {
             LispyType::Hash { collection, .. } => Some(collection),
@@ -229,6 +318,20 @@ impl LispyType {
         }
     }
 
+    pub fn as_atom(&self) -> Option<&Rc<RefCell<LispyType>>> {
+        match self {
+            LispyType::Atom { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_resource<T: 'static>(&self) -> Option<&T> {
+        match self {
+            LispyType::Resource { handle, .. } => handle.downcast_ref::<T>(),
+            _ => None,
+        }
+    }
+
     pub fn as_error(&self) -> Option<LispyErrorInternal> {
         match self {
             LispyType::Error {
@@ -257,20 +360,26 @@ impl LispyType {
                             args.len()
                         ),
                         error_type: "INCORRECT_ARITY".to_string(),
+                        data: None,
                         meta: HashMap::new(),
                     });
                 }
-                (func)(args)
+                (func.0)(args)
             }
             _ => Err(LispyType::Error {
                 message: format!("{:?} is not a function", self).to_string(),
                 error_type: "NOT_A_FUNCTION".to_string(),
+                data: None,
                 meta: HashMap::new(),
             }),
         }
     }
 
-    pub fn apply_lambda(&self, args: Vec<LispyType>) -> Result<(LispyType, LispyEnv), LispyType> {
+    // Takes the argument vector by value, arguments are moved into the new env
+    pub fn apply_lambda(
+        &self,
+        mut args: Vec<LispyType>,
+    ) -> Result<(Rc<LispyType>, LispyEnv), LispyType> {
         match self {
             LispyType::Lambda {
                 env,
@@ -278,22 +387,107 @@ impl LispyType {
                 bindings,
                 ..
             } => {
-                let mut n_env = LispyEnv::child_lambda(env.clone());
-                if bindings.len() != args.len() {
+                let mut n_env = LispyEnv::child_lambda(env);
+
+                // `(a b & rest)`: everything after the fixed parameters is bound to `rest`.
+                // `(a &keys timeout (retries 3))`: an optional trailing hash binds its :timeout
+                // and :retries entries, a missing one binds its default or nil.
+                let mut spread = None;
+                let mut keys = None;
+                for (index, key) in bindings.iter().enumerate() {
+                    if let LispyType::Symbol { value, .. } = key {
+                        match value.as_str() {
+                            "&" => spread = Some(index),
+                            "&keys" => keys = Some(index),
+                            _ => {}
+                        }
+                    }
+                }
+                if spread.is_some() && keys.is_some() {
+                    return Err(LispyType::Error {
+                        message: format!("& and &keys can't be combined. Received {:?}", bindings),
+                        error_type: "INCORRECT_TYPE".to_string(),
+                        data: None,
+                        meta: HashMap::new(),
+                    });
+                }
+                let fixed = spread.or(keys).unwrap_or(bindings.len());
+                if spread.is_some() && spread.unwrap() + 2 != bindings.len() {
                     return Err(LispyType::Error {
                         message: format!(
-                            "Expected arity {}, received {}",
-                            bindings.len(),
+                            "& must be followed by exactly one rest binding. Received {:?}",
+                            bindings
+                        ),
+                        error_type: "INCORRECT_TYPE".to_string(),
+                        data: None,
+                        meta: HashMap::new(),
+                    });
+                }
+                if keys.is_some() && (args.len() < fixed || args.len() > fixed + 1) {
+                    return Err(LispyType::Error {
+                        message: format!(
+                            "Expected arity {} and an optional hash of keyword arguments, received {}",
+                            fixed,
                             args.len()
                         ),
                         error_type: "INCORRECT_ARITY".to_string(),
+                        data: None,
                         meta: HashMap::new(),
                     });
                 }
+                if (spread.is_none() && keys.is_none() && fixed != args.len())
+                    || (spread.is_some() && args.len() < fixed)
+                {
+                    return Err(LispyType::Error {
+                        message: format!(
+                            "Expected arity {}{}, received {}",
+                            if spread.is_some() { "at least " } else { "" },
+                            fixed,
+                            args.len()
+                        ),
+                        error_type: "INCORRECT_ARITY".to_string(),
+                        data: None,
+                        meta: HashMap::new(),
+                    });
+                }
+                if spread.is_some() {
+                    let rest = bindings.get(fixed + 1).unwrap();
+                    if !rest.is_symbol() {
+                        return Err(LispyType::Error {
+                            message: format!(
+                                "Bindings should consist of symbols. Received {}",
+                                rest
+                            ),
+                            error_type: "INCORRECT_TYPE".to_string(),
+                            data: None,
+                            meta: HashMap::new(),
+                        });
+                    }
+                    n_env.set_item(
+                        rest.as_symbol().unwrap().clone(),
+                        LispyType::create_list(args.split_off(fixed)),
+                    );
+                }
+
+                let mut to_eval = to_eval.clone();
+                if keys.is_some() {
+                    let options = if args.len() > fixed { args.pop().unwrap() } else { LispyType::create_nil() };
+                    let defaults = bind_keyword_args(&bindings[fixed + 1..], &options, &mut n_env);
+                    if defaults.is_err() {
+                        return Err(defaults.err().unwrap());
+                    }
+                    // Defaults are evaluated per call like the body, after the parameters are bound
+                    let defaults = defaults.unwrap();
+                    if !defaults.is_empty() {
+                        to_eval = Rc::new(LispyType::create_list(vec![
+                            LispyType::create_symbol("let*"),
+                            LispyType::create_list(defaults),
+                            LispyType::clone(&to_eval),
+                        ]));
+                    }
+                }
 
-                for i in 0..bindings.len() {
-                    let key = bindings.get(i).unwrap().clone();
-                    let value = args.get(i).unwrap().clone();
+                for (key, value) in bindings.iter().zip(args) {
                     if !key.is_symbol() {
                         return Err(LispyType::Error {
                             message: format!(
@@ -301,17 +495,19 @@ impl LispyType {
                                 key
                             ),
                             error_type: "INCORRECT_TYPE".to_string(),
+                            data: None,
                             meta: HashMap::new(),
                         });
                     }
                     n_env.set_item(key.as_symbol().unwrap().clone(), value);
                 }
 
-                Ok((*to_eval.clone(), n_env))
+                Ok((to_eval, n_env))
             }
             _ => Err(LispyType::Error {
                 message: format!("{:?} is not a function", self).to_string(),
                 error_type: "NOT_A_FUNCTION".to_string(),
+                data: None,
                 meta: HashMap::new(),
             }),
         }
@@ -335,6 +531,7 @@ impl LispyType {
             _ => LispyType::Error {
                 message: format!("{:?} is not a lambda", self).to_string(),
                 error_type: "NOT_A_FUNCTION".to_string(),
+                data: None,
                 meta: HashMap::new(),
             },
         }
@@ -343,16 +540,69 @@ impl LispyType {
 
 // truthiness
 impl LispyType {
+    // Only nil and false are falsy, everything else (including 0 and "") is truthy
     pub fn is_truthy(&self) -> bool {
         match self {
             LispyType::Nil { .. } => false,
             LispyType::Bool { value, .. } => *value,
-            LispyType::Number { value, .. } => *value != 0.0,
             _ => true,
         }
     }
 }
 
+// meta
+impl LispyType {
+    pub fn meta(&self) -> &TypeMeta {
+        match self {
+            LispyType::Nil { meta }
+            | LispyType::Bool { meta, .. }
+            | LispyType::Number { meta, .. }
+            | LispyType::Symbol { meta, .. }
+            | LispyType::Keyword { meta, .. }
+            | LispyType::String { meta, .. }
+            | LispyType::List { meta, .. }
+            | LispyType::Vector { meta, .. }
+            | LispyType::Hash { meta, .. }
+            | LispyType::Error { meta, .. }
+            | LispyType::Function { meta, .. }
+            | LispyType::Lambda { meta, .. }
+            | LispyType::Resource { meta, .. }
+            | LispyType::Atom { meta, .. } => meta,
+        }
+    }
+
+    pub fn meta_mut(&mut self) -> &mut TypeMeta {
+        match self {
+            LispyType::Nil { meta }
+            | LispyType::Bool { meta, .. }
+            | LispyType::Number { meta, .. }
+            | LispyType::Symbol { meta, .. }
+            | LispyType::Keyword { meta, .. }
+            | LispyType::String { meta, .. }
+            | LispyType::List { meta, .. }
+            | LispyType::Vector { meta, .. }
+            | LispyType::Hash { meta, .. }
+            | LispyType::Error { meta, .. }
+            | LispyType::Function { meta, .. }
+            | LispyType::Lambda { meta, .. }
+            | LispyType::Resource { meta, .. }
+            | LispyType::Atom { meta, .. } => meta,
+        }
+    }
+
+    // "file:line:col" where the compiler read this form, without the file for code
+    // that did not come from one
+    pub fn source_location(&self) -> Option<String> {
+        let meta = self.meta();
+        let line = meta.get("line")?.as_number()?;
+        let col = meta.get("col")?.as_number()?;
+        Some(match meta.get("file").and_then(|file| file.as_string()) {
+            Some(file) => format!("{}:{}:{}", file, line, col),
+            None => format!("{}:{}", line, col),
+        })
+    }
+}
+
 // Countable
 impl LispyType {
     pub fn len(&self) -> LispyType {
@@ -364,7 +614,7 @@ impl LispyType {
                     meta: HashMap::new(),
                 }
             }
-            LispyType::List { collection, .. } => {
+            LispyType::List { collection, .. } | LispyType::Vector { collection, .. } => {
                 let value = collection.len() as f64;
                 LispyType::Number {
                     value,
@@ -381,6 +631,7 @@ impl LispyType {
             _ => LispyType::Error {
                 message: format!("{} does not have length", self),
                 error_type: "INCORRECT_TYPE".to_string(),
+                data: None,
                 meta: HashMap::new(),
             },
         }
@@ -392,11 +643,11 @@ impl LispyType {
 impl LispyType {
     pub fn create_function(
         arity: Option<i32>,
-        func: fn(args: Vec<LispyType>) -> Result<LispyType, LispyType>,
+        func: impl Fn(Vec<LispyType>) -> Result<LispyType, LispyType> + 'static,
     ) -> Self {
         Self::Function {
             arity,
-            func,
+            func: NativeFn(Rc::new(func)),
             meta: HashMap::new(),
         }
     }
@@ -425,6 +676,16 @@ impl LispyType {
         Self::Error {
             message: message.to_string(),
             error_type: err_type.to_string(),
+            data: None,
+            meta: HashMap::new(),
+        }
+    }
+
+    pub fn create_error_with_data(message: &str, err_type: &str, data: LispyType) -> Self {
+        Self::Error {
+            message: message.to_string(),
+            error_type: err_type.to_string(),
+            data: Some(Box::new(data)),
             meta: HashMap::new(),
         }
     }
@@ -439,6 +700,14 @@ impl LispyType {
     pub fn create_symbol(value: &str) -> Self {
         Self::Symbol {
             value: value.to_string(),
+            cache: SymbolCache::default(),
+            meta: HashMap::new(),
+        }
+    }
+
+    pub fn create_keyword(value: &str) -> Self {
+        Self::Keyword {
+            value: intern(value),
             meta: HashMap::new(),
         }
     }
@@ -449,6 +718,28 @@ impl LispyType {
             meta: HashMap::new(),
         }
     }
+
+    pub fn create_vector(collection: Vec<LispyType>) -> Self {
+        Self::Vector {
+            collection: Box::from(collection),
+            meta: HashMap::new(),
+        }
+    }
+
+    pub fn create_atom(value: LispyType) -> Self {
+        Self::Atom {
+            value: Rc::new(RefCell::new(value)),
+            meta: HashMap::new(),
+        }
+    }
+
+    pub fn create_resource<T: 'static>(kind: &str, handle: T) -> Self {
+        Self::Resource {
+            kind: kind.to_string(),
+            handle: Rc::new(handle),
+            meta: HashMap::new(),
+        }
+    }
 }
 
 // iteration
@@ -462,7 +753,7 @@ impl LispyType {
                 }
                 Ok(LispyType::create_nil())
             }
-            LispyType::List { collection, .. } => {
+            LispyType::List { collection, .. } | LispyType::Vector { collection, .. } => {
                 let x = collection.first();
                 if x.is_some() {
                     return Ok(x.unwrap().clone());
@@ -485,7 +776,7 @@ impl LispyType {
                 }
                 Ok(LispyType::create_nil())
             }
-            LispyType::List { collection, .. } => {
+            LispyType::List { collection, .. } | LispyType::Vector { collection, .. } => {
                 let x = collection.get(1..collection.len());
 
                 if x.is_some() {
@@ -511,7 +802,7 @@ impl LispyType {
                 }
                 Ok(LispyType::create_nil())
             }
-            LispyType::List { collection, .. } => {
+            LispyType::List { collection, .. } | LispyType::Vector { collection, .. } => {
                 let x = collection.get(index);
                 if x.is_some() {
                     return Ok(x.unwrap().clone());
@@ -526,59 +817,354 @@ impl LispyType {
     }
 }
 
-impl Display for LispyType {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+// coercion
+impl LispyType {
+    fn coercion_error(&self, target: &str) -> LispyType {
+        LispyType::create_error(
+            format!("Could not coerce {} to {}", self, target).as_str(),
+            "COERCION_FAILED",
+        )
+    }
+
+    pub fn coerce_to_string(&self) -> Result<LispyType, LispyType> {
         match self {
-            LispyType::Nil { .. } => {
-                write!(f, "nil")
+            LispyType::String { .. } => Ok(self.clone()),
+            LispyType::Symbol { value, .. } => Ok(LispyType::create_string(value)),
+            LispyType::Keyword { value, .. } => Ok(LispyType::create_string(value)),
+            LispyType::Function { .. } | LispyType::Lambda { .. } => {
+                Err(self.coercion_error("string"))
             }
+            _ => Ok(LispyType::create_string(format!("{}", self).as_str())),
+        }
+    }
+
+    pub fn coerce_to_number(&self) -> Result<LispyType, LispyType> {
+        match self {
+            LispyType::Number { .. } => Ok(self.clone()),
             LispyType::Bool { value, .. } => {
-                write!(f, "{}", value)
-            }
-            LispyType::Number { value, .. } => {
-                write!(f, "{}", value)
+                Ok(LispyType::create_number(if *value { 1.0 } else { 0.0 }))
             }
-            LispyType::Symbol { value, .. } => {
-                write!(f, "Symbol<{}>", value)
-            }
-            LispyType::Keyword { value, .. } => {
-                write!(f, "Keyword<{}>", value)
+            LispyType::String { value, .. } => match value.trim().parse::<f64>() {
+                Ok(number) => Ok(LispyType::create_number(number)),
+                Err(_) => Err(self.coercion_error("number")),
+            },
+            _ => Err(self.coercion_error("number")),
+        }
+    }
+
+    pub fn coerce_to_bool(&self) -> Result<LispyType, LispyType> {
+        match self {
+            LispyType::Bool { .. } => Ok(self.clone()),
+            LispyType::Nil { .. } => Ok(LispyType::create_bool(false)),
+            LispyType::Number { value, .. } => Ok(LispyType::create_bool(*value != 0.0)),
+            LispyType::String { value, .. } => match value.trim() {
+                "true" => Ok(LispyType::create_bool(true)),
+                "false" => Ok(LispyType::create_bool(false)),
+                _ => Err(self.coercion_error("bool")),
+            },
+            _ => Err(self.coercion_error("bool")),
+        }
+    }
+
+    pub fn coerce_to_list(&self) -> Result<LispyType, LispyType> {
+        match self {
+            LispyType::List { .. } => Ok(self.clone()),
+            _ => self.iter_seq().map(LispyType::from_iter),
+        }
+    }
+
+    // The elements of a sequence the way `to-list` sees them: list and vector
+    // items, nothing for nil, one-char strings for a string and [key value]
+    // lists for a hash, in key order
+    pub fn iter_seq(&self) -> Result<SeqIter<'_>, LispyType> {
+        match self {
+            LispyType::List { collection, .. } | LispyType::Vector { collection, .. } => {
+                Ok(SeqIter::Items(collection.iter()))
             }
-            LispyType::String { value, .. } => {
-                write!(f, "{}", value)
+            LispyType::Nil { .. } => Ok(SeqIter::Items([].iter())),
+            LispyType::String { value, .. } => Ok(SeqIter::Chars(value.chars())),
+            LispyType::Hash { collection, .. } => {
+                Ok(SeqIter::Entries(sorted_hash_entries(collection).into_iter()))
             }
+            _ => Err(self.coercion_error("list")),
+        }
+    }
+
+    pub fn coerce_to_hash(&self) -> Result<LispyType, LispyType> {
+        match self {
+            LispyType::Hash { .. } => Ok(self.clone()),
+            LispyType::Nil { .. } => Ok(LispyType::Hash {
+                collection: Box::from(HashMap::new()),
+                meta: HashMap::new(),
+            }),
             LispyType::List { collection, .. } => {
-                let mut str = "".to_string();
-                if collection.len() > 0 {
-                    str += &format!("{}", collection.first().unwrap()).to_string();
-                    for index in 1..collection.len() {
-                        str += &format!(", {}", collection.get(index).unwrap());
+                let mut hash = HashMap::new();
+                for pair in collection.iter() {
+                    match pair.as_list() {
+                        Some(entry) if entry.len() == 2 && entry[0].is_hashable() => {
+                            hash.insert(entry[0].clone(), entry[1].clone());
+                        }
+                        _ => return Err(self.coercion_error("hash")),
                     }
                 }
-                write!(f, "[{}]", str)
+                Ok(LispyType::Hash {
+                    collection: Box::from(hash),
+                    meta: HashMap::new(),
+                })
             }
-            LispyType::Hash { collection, .. } => {
-                let mut str = "".to_string();
-
-                collection.iter().for_each(|(key, value)| {
-                    str += &format!("{} -> {}, ", key, value);
-                });
+            _ => Err(self.coercion_error("hash")),
+        }
+    }
+}
 
-                str = str.to_string()[0..str.len() - 2].to_string();
+// Binds the `&keys` parameters `specs`, each `name` or `(name default)`, from the
+// options hash passed last, nil when none was. Returns the `let*` bindings of
+// the defaults left to evaluate.
+fn bind_keyword_args(
+    specs: &[LispyType],
+    options: &LispyType,
+    env: &mut LispyEnv,
+) -> Result<Vec<LispyType>, LispyType> {
+    let empty = Box::new(HashMap::new());
+    let options = match options {
+        LispyType::Hash { collection, .. } => collection,
+        LispyType::Nil { .. } => &empty,
+        _ => {
+            return Err(LispyType::create_error(
+                format!("Keyword arguments must be passed as a hash. Received {}", options).as_str(),
+                "INCORRECT_TYPE",
+            ))
+        }
+    };
 
-                write!(f, "{{{}}}", str)
-            }
-            LispyType::Error { message, .. } => {
-                write!(f, "{}", message)
+    let mut defaults = vec![];
+    for spec in specs {
+        let (name, default) = match spec.as_list().map(|spec| spec.as_slice()) {
+            None if spec.is_symbol() => (spec, None),
+            Some([name, default]) if name.is_symbol() => (name, Some(default)),
+            _ => {
+                return Err(LispyType::create_error(
+                    format!("&keys parameters are symbols or (symbol default). Received {}", spec).as_str(),
+                    "INCORRECT_TYPE",
+                ))
             }
-            LispyType::Function { .. } => {
-                write!(f, "#<function>")
-            }
-            LispyType::Lambda { .. } => {
-                write!(f, "#<lambda-function>")
+        };
+        let name = name.as_symbol().unwrap();
+        let passed = options.get(&LispyType::create_keyword(format!(":{}", name).as_str()));
+        match (passed, default) {
+            (Some(value), _) => env.set_item(name.clone(), value.clone()),
+            (None, Some(default)) => {
+                defaults.push(LispyType::create_symbol(name));
+                defaults.push(default.clone());
             }
+            (None, None) => env.set_item(name.clone(), LispyType::create_nil()),
         }
     }
+    Ok(defaults)
+}
+
+// Prints the shortest decimal that parses back to exactly the same f64, never
+// switching to exponent notation, so the output is the same on every platform
+// and can always be read back by the lexer without losing precision.
+// Non-finite values use the ##NaN / ##Inf / ##-Inf literal syntax.
+pub fn format_number(value: f64) -> String {
+    if value.is_nan() {
+        return "##NaN".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "##Inf" } else { "##-Inf" }.to_string();
+    }
+    format!("{}", value)
+}
+
+pub fn set_print_ratios(enabled: bool) {
+    PRINT_RATIOS.with(|print_ratios| print_ratios.set(enabled));
+}
+
+// `numerator/denominator` when `value` is the float nearest to such a fraction, the
+// smallest denominator wins. Numbers are floats until the numeric tower lands, this
+// keeps the printed form of exact fractions exact and readable back by the reader.
+fn format_ratio(value: f64) -> Option<String> {
+    if !value.is_finite() || value.fract() == 0.0 {
+        return None;
+    }
+    // Convergents of the continued fraction of `value`, overflowing ones are too large anyway
+    let (mut numerator, mut previous_numerator) = (1i64, 0i64);
+    let (mut denominator, mut previous_denominator) = (0i64, 1i64);
+    let mut rest = value.abs();
+    loop {
+        let whole = rest.floor() as i64;
+        let next_denominator = whole.checked_mul(denominator)?.checked_add(previous_denominator)?;
+        if next_denominator > MAX_RATIO_DENOMINATOR {
+            return None;
+        }
+        (previous_numerator, numerator) = (numerator, whole.checked_mul(numerator)?.checked_add(previous_numerator)?);
+        (previous_denominator, denominator) = (denominator, next_denominator);
+        if numerator as f64 / denominator as f64 == value.abs() {
+            let sign = if value < 0.0 { "-" } else { "" };
+            return Some(format!("{}{}/{}", sign, numerator, denominator));
+        }
+        if rest.fract() == 0.0 {
+            return None;
+        }
+        rest = 1.0 / rest.fract();
+    }
+}
+
+fn key_rank(key: &LispyType) -> u8 {
+    match key {
+        LispyType::Nil { .. } => 0,
+        LispyType::Bool { .. } => 1,
+        LispyType::Number { .. } => 2,
+        LispyType::String { .. } => 3,
+        LispyType::Keyword { .. } => 4,
+        LispyType::Symbol { .. } => 5,
+        _ => 6,
+    }
+}
+
+// Returned by LispyType::iter_seq
+pub enum SeqIter<'a> {
+    Items(std::slice::Iter<'a, LispyType>),
+    Chars(std::str::Chars<'a>),
+    Entries(std::vec::IntoIter<(&'a LispyType, &'a LispyType)>),
+}
+
+impl Iterator for SeqIter<'_> {
+    type Item = LispyType;
+
+    fn next(&mut self) -> Option<LispyType> {
+        match self {
+            SeqIter::Items(items) => items.next().cloned(),
+            SeqIter::Chars(chars) => chars
+                .next()
+                .map(|char| LispyType::create_string(char.encode_utf8(&mut [0; 4]))),
+            SeqIter::Entries(entries) => entries
+                .next()
+                .map(|(key, value)| LispyType::create_list(vec![key.clone(), value.clone()])),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            SeqIter::Items(items) => items.size_hint(),
+            SeqIter::Chars(chars) => chars.size_hint(),
+            SeqIter::Entries(entries) => entries.size_hint(),
+        }
+    }
+}
+
+// Total order over hash keys: grouped by type, then numbers by value and text by content
+pub fn compare_keys(a: &LispyType, b: &LispyType) -> Ordering {
+    match (a, b) {
+        (LispyType::Number { value: a, .. }, LispyType::Number { value: b, .. }) => {
+            a.partial_cmp(b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+        }
+        (LispyType::Bool { value: a, .. }, LispyType::Bool { value: b, .. }) => a.cmp(b),
+        (LispyType::String { value: a, .. }, LispyType::String { value: b, .. })
+        | (LispyType::Symbol { value: a, .. }, LispyType::Symbol { value: b, .. }) => a.cmp(b),
+        (LispyType::Keyword { value: a, .. }, LispyType::Keyword { value: b, .. }) => a.cmp(b),
+        _ => key_rank(a).cmp(&key_rank(b)),
+    }
+}
+
+// Hash entries in key order. Every place that exposes the order of a hash (printing,
+// keys/vals, to-list) goes through this, so output is stable between runs.
+pub fn sorted_hash_entries(
+    collection: &HashMap<LispyType, LispyType>,
+) -> Vec<(&LispyType, &LispyType)> {
+    let mut entries: Vec<(&LispyType, &LispyType)> = collection.iter().collect();
+    entries.sort_by(|a, b| compare_keys(a.0, b.0));
+    entries
+}
+
+// Quotes a string so the reader turns it back into the same value
+pub fn escape_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for char in value.chars() {
+        match char {
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            '\0' => result.push_str("\\0"),
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            _ => result.push(char),
+        }
+    }
+    result.push('"');
+    result
+}
+
+// Shared printer behind Display (`str`, `println`) and `pr-str`. The readable form
+// is source the reader turns back into an equal value, the other one is for humans.
+fn print_value(value: &LispyType, readable: bool) -> String {
+    let join = |items: &mut dyn Iterator<Item = &LispyType>, separator: &str| {
+        items
+            .map(|item| print_value(item, readable))
+            .collect::<Vec<String>>()
+            .join(separator)
+    };
+    match value {
+        LispyType::Nil { .. } => "nil".to_string(),
+        LispyType::Bool { value, .. } => value.to_string(),
+        LispyType::Number { value, .. } if PRINT_RATIOS.with(|print_ratios| print_ratios.get()) => {
+            format_ratio(*value).unwrap_or_else(|| format_number(*value))
+        }
+        LispyType::Number { value, .. } => format_number(*value),
+        LispyType::Symbol { value, .. } if readable => value.clone(),
+        LispyType::Symbol { value, .. } => format!("Symbol<{}>", value),
+        LispyType::Keyword { value, .. } if readable => value.to_string(),
+        LispyType::Keyword { value, .. } => format!("Keyword<{}>", value),
+        LispyType::String { value, .. } if readable => escape_string(value),
+        LispyType::String { value, .. } => value.clone(),
+        LispyType::List { collection, .. } if readable => {
+            format!("({})", join(&mut collection.iter(), " "))
+        }
+        LispyType::List { collection, .. } => format!("[{}]", join(&mut collection.iter(), ", ")),
+        LispyType::Vector { collection, .. } if readable => {
+            format!("[{}]", join(&mut collection.iter(), " "))
+        }
+        LispyType::Vector { collection, .. } => {
+            format!("#[{}]", join(&mut collection.iter(), ", "))
+        }
+        LispyType::Hash { collection, .. } => {
+            let entries: Vec<String> = sorted_hash_entries(collection)
+                .into_iter()
+                .map(|(key, item)| {
+                    let key = print_value(key, readable);
+                    let item = print_value(item, readable);
+                    if readable {
+                        format!("{} {}", key, item)
+                    } else {
+                        format!("{} -> {}", key, item)
+                    }
+                })
+                .collect();
+            format!("{{{}}}", entries.join(if readable { " " } else { ", " }))
+        }
+        LispyType::Error { message, .. } => message.clone(),
+        LispyType::Function { .. } => "#<function>".to_string(),
+        LispyType::Lambda { .. } => "#<lambda-function>".to_string(),
+        LispyType::Resource { kind, .. } => format!("#<resource {}>", kind),
+        LispyType::Atom { value, .. } => {
+            format!("(atom {})", print_value(&value.borrow(), readable))
+        }
+    }
+}
+
+impl LispyType {
+    // The `pr-str` form, see print_value
+    pub fn to_readable_string(&self) -> String {
+        print_value(self, true)
+    }
+}
+
+impl Display for LispyType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", print_value(self, false))
+    }
 }
 
 impl PartialEq for LispyType {
@@ -588,20 +1174,22 @@ impl PartialEq for LispyType {
             LispyType::Bool { .. } => other.is_bool() && self.as_bool() == other.as_bool(),
             LispyType::Number { .. } => other.is_number() && self.as_number() == other.as_number(),
             LispyType::Symbol { .. } => other.is_symbol() && self.as_symbol() == other.as_symbol(),
-            LispyType::Keyword { .. } => {
-                other.is_keyword() && self.as_keyword() == other.as_keyword()
+            LispyType::Keyword { value, .. } => {
+                matches!(other, LispyType::Keyword { value: other, .. } if value == other)
             }
             LispyType::String { .. } => other.is_string() && self.as_string() == other.as_string(),
-            LispyType::List { .. } => {
-                if !other.is_list() {
+            LispyType::List { .. } | LispyType::Vector { .. } => {
+                if other.as_sequential().is_none() {
                     return false;
                 }
-                if other.as_list().unwrap().len() != self.as_list().unwrap().len() {
+                if other.as_sequential().unwrap().len() != self.as_sequential().unwrap().len() {
                     return false;
                 }
 
-                for index in 0..self.as_list().unwrap().len() - 1 {
-                    if self.as_list().unwrap().get(index) != other.as_list().unwrap().get(index) {
+                for index in 0..self.as_sequential().unwrap().len() {
+                    if self.as_sequential().unwrap().get(index)
+                        != other.as_sequential().unwrap().get(index)
+                    {
                         return false;
                     }
                 }
@@ -632,6 +1220,14 @@ impl PartialEq for LispyType {
             }
             LispyType::Function { .. } => false,
             LispyType::Lambda { .. } => false,
+            LispyType::Resource { handle, .. } => match other {
+                LispyType::Resource { handle: other, .. } => Rc::ptr_eq(handle, other),
+                _ => false,
+            },
+            LispyType::Atom { value, .. } => match other {
+                LispyType::Atom { value: other, .. } => Rc::ptr_eq(value, other),
+                _ => false,
+            },
         }
     }
 }
@@ -668,12 +1264,14 @@ impl Add for LispyType {
                 LispyType::Error {
                     message: "+ only works with number vars".to_string(),
                     error_type: "INCORRECT_TYPE".to_string(),
+                    data: None,
                     meta: HashMap::new(),
                 }
             }
             _ => LispyType::Error {
                 message: "+ only works with number vars".to_string(),
                 error_type: "INCORRECT_TYPE".to_string(),
+                data: None,
                 meta: HashMap::new(),
             },
         }
@@ -696,12 +1294,14 @@ impl Sub for LispyType {
                 LispyType::Error {
                     message: "+ only works with number vars".to_string(),
                     error_type: "INCORRECT_TYPE".to_string(),
+                    data: None,
                     meta: HashMap::new(),
                 }
             }
             _ => LispyType::Error {
                 message: "+ only works with number vars".to_string(),
                 error_type: "INCORRECT_TYPE".to_string(),
+                data: None,
                 meta: HashMap::new(),
             },
         }
@@ -724,12 +1324,14 @@ impl Mul for LispyType {
                 LispyType::Error {
                     message: "+ only works with number vars".to_string(),
                     error_type: "INCORRECT_TYPE".to_string(),
+                    data: None,
                     meta: HashMap::new(),
                 }
             }
             _ => LispyType::Error {
                 message: "+ only works with number vars".to_string(),
                 error_type: "INCORRECT_TYPE".to_string(),
+                data: None,
                 meta: HashMap::new(),
             },
         }
@@ -752,12 +1354,14 @@ impl Div for LispyType {
                 LispyType::Error {
                     message: "+ only works with number vars".to_string(),
                     error_type: "INCORRECT_TYPE".to_string(),
+                    data: None,
                     meta: HashMap::new(),
                 }
             }
             _ => LispyType::Error {
                 message: "+ only works with number vars".to_string(),
                 error_type: "INCORRECT_TYPE".to_string(),
+                data: None,
                 meta: HashMap::new(),
             },
         }
@@ -775,3 +1379,88 @@ impl PartialOrd for LispyType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::compile_source_code_to_ast;
+    use crate::types::{format_number, LispyType};
+    use std::collections::HashMap;
+
+    fn hash(entries: Vec<(LispyType, LispyType)>) -> LispyType {
+        LispyType::Hash {
+            collection: Box::from(entries.into_iter().collect::<HashMap<_, _>>()),
+            meta: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn only_nil_and_false_are_falsy() {
+        assert!(!LispyType::create_nil().is_truthy());
+        assert!(!LispyType::create_bool(false).is_truthy());
+        assert!(LispyType::create_bool(true).is_truthy());
+        assert!(LispyType::create_number(0.0).is_truthy());
+        assert!(LispyType::create_string("").is_truthy());
+        assert!(LispyType::create_list(vec![]).is_truthy());
+    }
+
+    #[test]
+    fn printed_numbers_read_back_equal() {
+        let values = [
+            0.1 + 0.2,
+            0.1,
+            -0.0,
+            1.0 / 3.0,
+            123456789.125,
+            1e-7,
+            1e21,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            5e-324,
+        ];
+        for value in values {
+            let printed = format_number(value);
+            assert!(!printed.contains('e'), "{} printed with an exponent", printed);
+            let read = compile_source_code_to_ast(&printed).unwrap();
+            assert_eq!(read[0], LispyType::create_number(value), "{} did not round-trip", printed);
+            assert_eq!(read[0].as_number().unwrap().to_bits(), value.to_bits());
+        }
+        assert_eq!(format_number(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(format_number(2.5), "2.5");
+    }
+
+    #[test]
+    fn prints_empty_collections() {
+        let empty = [
+            (LispyType::create_list(vec![]), "()", "[]"),
+            (LispyType::create_vector(vec![]), "[]", "#[]"),
+            (hash(vec![]), "{}", "{}"),
+        ];
+        for (value, readable, display) in empty {
+            assert_eq!(value.to_readable_string(), readable);
+            assert_eq!(value.to_string(), display);
+        }
+    }
+
+    #[test]
+    fn prints_single_element_collections() {
+        let one = LispyType::create_number(1.0);
+        let single = [
+            (LispyType::create_list(vec![one.clone()]), "(1)", "[1]"),
+            (LispyType::create_vector(vec![one.clone()]), "[1]", "#[1]"),
+            (
+                hash(vec![(LispyType::create_keyword(":a"), one.clone())]),
+                "{:a 1}",
+                "{Keyword<:a> -> 1}",
+            ),
+            (
+                LispyType::create_list(vec![LispyType::create_list(vec![])]),
+                "(())",
+                "[[]]",
+            ),
+        ];
+        for (value, readable, display) in single {
+            assert_eq!(value.to_readable_string(), readable);
+            assert_eq!(value.to_string(), display);
+        }
+    }
+}
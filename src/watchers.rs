@@ -0,0 +1,150 @@
+use crate::env::LispyEnv;
+use crate::machine::apply_callable;
+use crate::types::LispyType;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+pub type RedefinitionHook = Rc<dyn Fn(&str, &LispyType, &LispyType)>;
+
+// What watches the global bindings of one machine. Only bindings made directly in
+// its root env are reported, not locals, namespaces or sandboxes with a root of
+// their own, e.g. the ones of safe-eval and eval-template.
+#[derive(Clone)]
+pub struct Watchers {
+    inner: Rc<RefCell<Watches>>,
+}
+
+struct Watches {
+    root: LispyEnv,
+    // Symbols of `(watch! 'sym)`, whose changes are printed to stderr
+    printed: HashSet<String>,
+    // Callbacks of `(add-watch 'sym fn)` by symbol
+    callbacks: HashMap<String, Vec<LispyType>>,
+    // Hooks embedders registered on the machine for every symbol
+    hooks: Vec<RedefinitionHook>,
+}
+
+pub struct InstalledWatchers {
+    previous: Option<Watchers>,
+}
+
+impl Drop for InstalledWatchers {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+thread_local! {
+    // The watchers of the machine evaluating on this thread
+    static CURRENT: RefCell<Option<Watchers>> = const { RefCell::new(None) };
+}
+
+impl Watchers {
+    pub fn new(root: &LispyEnv) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(Watches {
+                root: root.clone(),
+                printed: HashSet::new(),
+                callbacks: HashMap::new(),
+                hooks: vec![],
+            })),
+        }
+    }
+
+    // Reports definitions to these watchers until the guard is dropped, which puts
+    // back whichever were installed before
+    pub fn install(&self) -> InstalledWatchers {
+        let previous = CURRENT.with(|current| current.borrow_mut().replace(self.clone()));
+        InstalledWatchers { previous }
+    }
+
+    pub fn add_hook(&self, hook: RedefinitionHook) {
+        self.inner.borrow_mut().hooks.push(hook);
+    }
+}
+
+fn current() -> Option<Watchers> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+pub fn watch(name: &str) {
+    if let Some(watchers) = current() {
+        watchers.inner.borrow_mut().printed.insert(name.to_string());
+    }
+}
+
+pub fn unwatch(name: &str) {
+    if let Some(watchers) = current() {
+        watchers.inner.borrow_mut().printed.remove(name);
+    }
+}
+
+pub fn add_watch(name: &str, callback: LispyType) {
+    if let Some(watchers) = current() {
+        let mut watches = watchers.inner.borrow_mut();
+        watches.callbacks.entry(name.to_string()).or_default().push(callback);
+    }
+}
+
+pub fn remove_watches(name: &str) -> bool {
+    current().is_some_and(|watchers| watchers.inner.borrow_mut().callbacks.remove(name).is_some())
+}
+
+// Whether a change of `name` in `env` has to be reported, checked before looking up
+// the previous value so unwatched definitions stay cheap
+pub fn is_watched(env: &LispyEnv, name: &str) -> bool {
+    let watchers = match current() {
+        Some(watchers) => watchers,
+        None => return false,
+    };
+    let watches = watchers.inner.borrow();
+    watches.root.ptr_eq(env)
+        && (watches.printed.contains(name) || watches.callbacks.contains_key(name) || !watches.hooks.is_empty())
+}
+
+// Called once `name` is bound to `value`, or removed by undef! when `value` is None.
+// Callbacks get the symbol, the previous value and the new one, nil standing in for
+// the missing one. They run in the order they were added, the first error stops the
+// rest and is returned to the definition.
+pub fn notify(name: &str, previous: Option<LispyType>, value: Option<LispyType>) -> Result<(), LispyType> {
+    let watchers = match current() {
+        Some(watchers) => watchers,
+        None => return Ok(()),
+    };
+    // Cloned so callbacks can add watches or redefine things themselves
+    let (printed, hooks, callbacks) = {
+        let watches = watchers.inner.borrow();
+        (
+            watches.printed.contains(name),
+            watches.hooks.clone(),
+            watches.callbacks.get(name).cloned(),
+        )
+    };
+    if printed {
+        match (&previous, &value) {
+            (Some(previous), Some(value)) => eprintln!("[watch] {} changed: {} -> {}", name, previous, value),
+            (None, Some(value)) => eprintln!("[watch] {} defined: {}", name, value),
+            (Some(previous), None) => eprintln!("[watch] {} removed: {}", name, previous),
+            (None, None) => {}
+        }
+    }
+
+    let previous = previous.unwrap_or_else(LispyType::create_nil);
+    let value = value.unwrap_or_else(LispyType::create_nil);
+    for hook in hooks.iter() {
+        hook(name, &previous, &value);
+    }
+
+    for callback in callbacks.unwrap_or_default() {
+        let called = apply_callable(
+            &callback,
+            vec![LispyType::create_symbol(name), previous.clone(), value.clone()],
+        );
+        if called.is_err() {
+            return Err(called.err().unwrap());
+        }
+    }
+    Ok(())
+}